@@ -19,8 +19,8 @@ struct TimerData {
     float TotalPassed;        // How much time has passed on the rendering clock since rendering started (including the current frame).
     float FrameDelta;         // How long a previous frame took in seconds.
     float TotalSimulatedTime; // How much time has passed in the simulation *excluding any steps in the current frame*.
-    // How much we're advancing the simulation for each step in the current frame.
-    // (This implicitly assumes that all steps are equally sized during a frame which may become invalid if we ever attempt a fully adaptive step!)
+    // Simulation step length at the start of the current frame.
+    // Steps are adaptive, so simulation shaders need to use StepDelta from the simulation properties instead!
     float SimulationDelta;
 };
 
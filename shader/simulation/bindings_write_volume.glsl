@@ -13,3 +13,5 @@ layout(set = 2, binding = 1, r32f) uniform restrict image3D VelocityVolumeX;
 layout(set = 2, binding = 2, r32f) uniform restrict image3D VelocityVolumeY;
 layout(set = 2, binding = 3, r32f) uniform restrict image3D VelocityVolumeZ;
 layout(set = 2, binding = 4) uniform texture3D PressureVolume;
+// Pressure in air cells next to fluid, only valid if surface tension is enabled.
+layout(set = 2, binding = 5) uniform texture3D SurfaceTensionPressureVolume;
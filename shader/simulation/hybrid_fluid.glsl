@@ -1,12 +1,171 @@
+#include "utilities.glsl"
+
 // Occupancy calculator: https://xmartlabs.github.io/cuda-calculator/
 #define COMPUTE_PASS_PARTICLES layout(local_size_x = 64, local_size_y = 1, local_size_z = 1) in;
 #define COMPUTE_PASS_VOLUME layout(local_size_x = 8, local_size_y = 8, local_size_z = 8) in;
 
 #ifndef NO_SIMPROPS
+#define TRANSFER_SCHEME_PIC 0
+#define TRANSFER_SCHEME_FLIP 1
+#define TRANSFER_SCHEME_APIC 2
+
 layout(set = 1, binding = 0) uniform SimulationProperties {
     vec3 GravityGridSpace;
     uint NumParticles;
+    uint TransferScheme;
+    float FlipBlendFactor; // 0 is pure PIC, 1 pure FLIP
+    float StepDelta;       // Length of the current simulation step in seconds. (steps may differ in length even within a frame)
+    float Viscosity;       // Kinematic viscosity in grid cells² per second.
+    // Surface tension coefficient divided by density in grid cells³ per second². Zero disables surface tension.
+    float SurfaceTension;
+    float VorticityConfinement; // Strength of the vorticity confinement force (relative to cell size). Zero disables vorticity confinement.
+};
+// Signed distance to static solids in grid cells at every cell center, negative inside.
+// (Domain walls are not part of this volume, they are handled by the marker pass)
+layout(set = 1, binding = 1) uniform texture3D SolidDistanceVolume;
+
+bool isStaticSolid(ivec3 gridCoord) { return texelFetch(SolidDistanceVolume, gridCoord, 0).x <= 0.0; }
+
+// Trilinear interpolated signed distance at a position in grid space.
+float sampleStaticSolidDistance(vec3 gridPosition) {
+    ivec3 gridSize = textureSize(SolidDistanceVolume, 0);
+    vec3 offsetPosition = clamp(gridPosition - vec3(0.5), vec3(0.0), vec3(gridSize - ivec3(1)));
+    ivec3 coordMin = ivec3(offsetPosition);
+    ivec3 coordMax = min(coordMin + ivec3(1), gridSize - ivec3(1));
+    vec3 interpolants = offsetPosition - vec3(coordMin);
+
+    float d_000 = texelFetch(SolidDistanceVolume, coordMin, 0).x;
+    float d_100 = texelFetch(SolidDistanceVolume, ivec3(coordMax.x, coordMin.yz), 0).x;
+    float d_010 = texelFetch(SolidDistanceVolume, ivec3(coordMin.x, coordMax.y, coordMin.z), 0).x;
+    float d_110 = texelFetch(SolidDistanceVolume, ivec3(coordMax.xy, coordMin.z), 0).x;
+    float d_001 = texelFetch(SolidDistanceVolume, ivec3(coordMin.xy, coordMax.z), 0).x;
+    float d_101 = texelFetch(SolidDistanceVolume, ivec3(coordMax.x, coordMin.y, coordMax.z), 0).x;
+    float d_011 = texelFetch(SolidDistanceVolume, ivec3(coordMin.x, coordMax.yz), 0).x;
+    float d_111 = texelFetch(SolidDistanceVolume, coordMax, 0).x;
+
+    return mix(mix(mix(d_000, d_100, interpolants.x), mix(d_010, d_110, interpolants.x), interpolants.y),
+               mix(mix(d_001, d_101, interpolants.x), mix(d_011, d_111, interpolants.x), interpolants.y), interpolants.z);
+}
+
+// Moving solids, evaluated analytically. All values are in grid space.
+#define MAX_NUM_DYNAMIC_SOLIDS 16
+#define DYNAMIC_SOLID_SHAPE_BOX 0
+#define DYNAMIC_SOLID_SHAPE_SPHERE 1
+#define DYNAMIC_SOLID_SHAPE_CAPSULE 2
+struct DynamicSolid {
+    vec3 Center;
+    uint Shape;
+    vec4 InverseRotation; // Quaternion
+    vec3 Extent;          // Half extent for boxes, half segment for capsules.
+    float Radius;
+    vec3 LinearVelocity;
+    float _padding0;
+    vec3 AngularVelocity;
+    float _padding1;
+};
+layout(set = 1, binding = 2) uniform DynamicSolids {
+    uint NumDynamicSolids;
+    DynamicSolid DynamicSolidList[MAX_NUM_DYNAMIC_SOLIDS];
 };
+// Gathered during every step and read back by the cpu, e.g. for choosing the length of the next step.
+layout(set = 1, binding = 3) buffer restrict SimulationStatistics {
+    // Largest particle speed in grid cells per second as float bits.
+    // (for positive floats the order of the bit patterns is the same as the order of the values, so we can use atomicMax)
+    uint MaxParticleSpeedBits;
+};
+
+vec3 rotateByQuaternion(vec4 quaternion, vec3 v) { return v + 2.0 * cross(quaternion.xyz, cross(quaternion.xyz, v) + quaternion.w * v); }
+
+float dynamicSolidDistance(uint solidIndex, vec3 gridPosition) {
+    DynamicSolid solid = DynamicSolidList[solidIndex];
+    vec3 localPosition = rotateByQuaternion(solid.InverseRotation, gridPosition - solid.Center);
+
+    if (solid.Shape == DYNAMIC_SOLID_SHAPE_BOX) {
+        vec3 q = abs(localPosition) - solid.Extent;
+        return length(max(q, vec3(0.0))) + min(max(q.x, max(q.y, q.z)), 0.0);
+    } else if (solid.Shape == DYNAMIC_SOLID_SHAPE_SPHERE) {
+        return length(localPosition) - solid.Radius;
+    } else {
+        vec3 segment = 2.0 * solid.Extent;
+        float t = saturate(dot(localPosition + solid.Extent, segment) / max(dot(segment, segment), 1e-8));
+        return length(localPosition + solid.Extent - segment * t) - solid.Radius;
+    }
+}
+
+// Distance to the closest dynamic solid, gives index of the solid as well (invalid if there are no dynamic solids).
+float closestDynamicSolid(vec3 gridPosition, out uint closestSolidIndex) {
+    float closestDistance = 1e30;
+    closestSolidIndex = 0;
+    for (uint i = 0; i < NumDynamicSolids; ++i) {
+        float solidDistance = dynamicSolidDistance(i, gridPosition);
+        if (solidDistance < closestDistance) {
+            closestDistance = solidDistance;
+            closestSolidIndex = i;
+        }
+    }
+    return closestDistance;
+}
+
+bool isDynamicSolid(ivec3 gridCoord) {
+    uint solidIndex;
+    return closestDynamicSolid(vec3(gridCoord) + vec3(0.5), solidIndex) <= 0.0;
+}
+
+// Position of the staggered velocity sample of a given component stored at gridCoord (on the positive wall of the cell).
+vec3 staggeredVelocityPosition(ivec3 gridCoord, int component) {
+    vec3 position = vec3(gridCoord) + vec3(0.5);
+    position[component] += 0.5;
+    return position;
+}
+
+// Velocity of the solid boundary at a given position (typically a staggered velocity sample position between a fluid and a solid cell)
+// Zero unless there is a dynamic solid close by.
+vec3 solidVelocity(vec3 gridPosition) {
+    uint solidIndex;
+    if (closestDynamicSolid(gridPosition, solidIndex) > 1.0)
+        return vec3(0.0);
+    DynamicSolid solid = DynamicSolidList[solidIndex];
+    return solid.LinearVelocity + cross(solid.AngularVelocity, gridPosition - solid.Center);
+}
+
+// Moves a particle that ended up inside a static solid back to the surface along the distance field gradient.
+vec3 pushOutOfStaticSolids(vec3 gridPosition) {
+    const float SurfaceMargin = 0.01;
+    float solidDistance = sampleStaticSolidDistance(gridPosition);
+    if (solidDistance >= SurfaceMargin)
+        return gridPosition;
+
+    const float h = 0.5;
+    vec3 gradient = vec3(sampleStaticSolidDistance(gridPosition + vec3(h, 0.0, 0.0)) - sampleStaticSolidDistance(gridPosition - vec3(h, 0.0, 0.0)),
+                         sampleStaticSolidDistance(gridPosition + vec3(0.0, h, 0.0)) - sampleStaticSolidDistance(gridPosition - vec3(0.0, h, 0.0)),
+                         sampleStaticSolidDistance(gridPosition + vec3(0.0, 0.0, h)) - sampleStaticSolidDistance(gridPosition - vec3(0.0, 0.0, h)));
+    float gradientLengthSq = dot(gradient, gradient);
+    if (gradientLengthSq < 1e-8)
+        return gridPosition;
+
+    return gridPosition + gradient * ((SurfaceMargin - solidDistance) * inversesqrt(gradientLengthSq));
+}
+
+// Moves a particle that ended up inside a dynamic solid back to its surface.
+vec3 pushOutOfDynamicSolids(vec3 gridPosition) {
+    const float SurfaceMargin = 0.01;
+    uint solidIndex;
+    float solidDistance = closestDynamicSolid(gridPosition, solidIndex);
+    if (solidDistance >= SurfaceMargin)
+        return gridPosition;
+
+    const float h = 0.01;
+    vec3 gradient = vec3(dynamicSolidDistance(solidIndex, gridPosition + vec3(h, 0.0, 0.0)) - dynamicSolidDistance(solidIndex, gridPosition - vec3(h, 0.0, 0.0)),
+                         dynamicSolidDistance(solidIndex, gridPosition + vec3(0.0, h, 0.0)) - dynamicSolidDistance(solidIndex, gridPosition - vec3(0.0, h, 0.0)),
+                         dynamicSolidDistance(solidIndex, gridPosition + vec3(0.0, 0.0, h)) - dynamicSolidDistance(solidIndex, gridPosition - vec3(0.0, 0.0, h)));
+    float gradientLengthSq = dot(gradient, gradient);
+    if (gradientLengthSq < 1e-12)
+        return gridPosition;
+
+    return gridPosition + gradient * ((SurfaceMargin - solidDistance) * inversesqrt(gradientLengthSq));
+}
+
+vec3 pushOutOfSolids(vec3 gridPosition) { return pushOutOfDynamicSolids(pushOutOfStaticSolids(gridPosition)); }
 #endif
 
 // Boundary is zero, so texel fetch outside of the domain always gives us boundary cells.
@@ -0,0 +1,101 @@
+// Resources for emitting, draining & reseeding particles.
+
+#include "per_frame_resources.glsl"
+#include "simulation/hybrid_fluid.glsl"
+#include "simulation/particles.glsl"
+
+// Needs to match ParticleCountBufferContent in emitter.rs
+// Leads with the indirect dispatch arguments for per-particle passes, followed by draw arguments for renderers.
+layout(set = 2, binding = 0) buffer restrict ParticleCountBuffer {
+    uint ParticleDispatchX;
+    uint ParticleDispatchY;
+    uint ParticleDispatchZ;
+    uint ParticleCount; // The actual number of particles. Copied to NumParticles in SimulationProperties after every update.
+    uint ParticleDrawVertexCount;
+    uint ParticleDrawInstanceCount;
+    uint ParticleDrawFirstVertex;
+    uint ParticleDrawFirstInstance;
+    uint NumRemovedParticles;
+    uint NumHoles;
+    uint NumMovers;
+    uint NumReseededParticles;
+};
+
+#define MAX_NUM_EMITTERS 8
+#define MAX_NUM_DRAINS 8
+#define EMITTER_SHAPE_BOX 0
+#define EMITTER_SHAPE_DISC 1
+struct Emitter {
+    vec3 Center;
+    uint Shape;
+    vec3 Extent; // Half extent for boxes, normal for discs.
+    float Radius;
+    vec3 Velocity;
+    uint FirstParticle; // Offset of the first particle emitted by this emitter within all particles emitted this step.
+    uint NumParticles;  // Number of particles to emit this step.
+};
+struct Drain {
+    vec3 Min;
+    vec3 Max;
+};
+layout(set = 2, binding = 1) uniform ParticleSources {
+    uint NumEmitters;
+    uint NumDrains;
+    uint NumEmittedParticles;
+    uint RandomSeed;
+    // Reseeding bounds for the number of particles in a linked list grid cell. Zero if reseeding is disabled.
+    uint MinParticlesPerCell;
+    uint MaxParticlesPerCell;
+    uint _padding0;
+    uint _padding1;
+    Emitter Emitters[MAX_NUM_EMITTERS];
+    Drain Drains[MAX_NUM_DRAINS];
+};
+
+layout(set = 2, binding = 2) buffer restrict ParticlePositionLlBuffer { ParticlePositionLl Particles[]; };
+layout(set = 2, binding = 3) buffer restrict ParticleBufferVx { vec4 ParticleBufferVelocityX[]; };
+layout(set = 2, binding = 4) buffer restrict ParticleBufferVy { vec4 ParticleBufferVelocityY[]; };
+layout(set = 2, binding = 5) buffer restrict ParticleBufferVz { vec4 ParticleBufferVelocityZ[]; };
+// Holes (indices of removed particles that need to be filled) from the front, movers (indices of particles that need to be moved) from the back.
+layout(set = 2, binding = 6) buffer restrict CompactionBuffer { uint CompactionIndices[]; };
+// State of the grid from the last step, only used by reseeding.
+layout(set = 2, binding = 7) uniform utexture3D LinkedListDualGrid;
+layout(set = 2, binding = 8) uniform texture3D MarkerVolume;
+layout(set = 2, binding = 9) uniform texture3D VelocityVolumeX;
+layout(set = 2, binding = 10) uniform texture3D VelocityVolumeY;
+layout(set = 2, binding = 11) uniform texture3D VelocityVolumeZ;
+
+// Reseeding marks surplus particles by replacing their (no longer needed) linked list pointer.
+// Real pointers are either particle indices or INVALID_LINKED_LIST_PTR, so this never collides.
+#define PARTICLE_MARKED_FOR_REMOVAL 0xFFFFFFFE
+
+uint maxNumParticles() { return uint(Particles.length()); }
+
+bool isInDrain(vec3 gridPosition) {
+    for (uint i = 0; i < NumDrains; ++i) {
+        if (all(greaterThanEqual(gridPosition, Drains[i].Min)) && all(lessThan(gridPosition, Drains[i].Max)))
+            return true;
+    }
+    return false;
+}
+
+// Whether a particle is removed by the drain passes, either because it is in a drain or because reseeding marked it.
+bool isRemoved(uint particleIndex) {
+    return Particles[particleIndex].LinkedListNext == PARTICLE_MARKED_FOR_REMOVAL || isInDrain(Particles[particleIndex].Position);
+}
+
+// Integer hash by Chris Wellons, https://nullprogram.com/blog/2018/07/31/
+uint hash(uint x) {
+    x ^= x >> 16;
+    x *= 0x7feb352dU;
+    x ^= x >> 15;
+    x *= 0x846ca68bU;
+    x ^= x >> 16;
+    return x;
+}
+
+// Random number in [0; 1)
+float random(inout uint state) {
+    state = hash(state);
+    return float(state >> 8) * (1.0 / 16777216.0);
+}
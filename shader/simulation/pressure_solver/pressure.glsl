@@ -10,11 +10,23 @@
 // 32 was distinctively slower, 16 about same as than 8, 4 clearly slower (gtx1070 ti)
 #define REDUCE_READS_PER_THREAD 16
 
+// Residual history in the reduce result & dispatch buffer (in floats), see PressureSolver::RESIDUAL_HISTORY_OFFSET
+// A length followed by (iteration, squared error) pairs.
+#define RESIDUAL_HISTORY_OFFSET 16
+#define RESIDUAL_HISTORY_MAX_LENGTH 128
+
 layout(set = 0, binding = 0) uniform texture3D MarkerVolume;
 layout(set = 1, binding = 0, r32f) uniform restrict image3D Pressure;
 layout(set = 1, binding = 1) uniform Config {
     float TargetMSE;
     uint MaxNumSolverIterations;
+    // The solved system is (IdentityScale * I + LaplaceScale * L) x = b with L being the (negative) laplacian.
+    // For pressure this is just L, for implicit viscosity it is (I + dt * viscosity * L).
+    float IdentityScale;
+    float LaplaceScale;
+    // Neighbors with this marker are left out of the stencil (zero gradient boundary).
+    // All other non-fluid neighbors are treated as zero valued (fixed value boundary).
+    float NeumannBoundaryMarker;
 };
 
 struct PcgScalars {
@@ -23,15 +35,23 @@ struct PcgScalars {
     vec2 _Dummy;
 };
 
+#ifdef MULTIGRID_PUSH_CONSTANTS
+layout(push_constant) uniform PushConstants_ {
+    uint Mode;  // Used to make adjustments to the shader invocation (which don't justify another shader instance)
+    uint Level; // Multigrid level, 0 is the full resolution grid
+}
+PushConstants;
+#else
 layout(push_constant) uniform PushConstants_ {
     uint Mode;             // Used to make adjustments to the shader invocation (which don't justify another shader instance)
     uint SourceBufferSize; // The size of the source buffer
 }
 PushConstants;
+#endif
 
-// Result of multiplication with coefficient matrix with a texture at gridCoord.
+// Result of multiplication with coefficient matrix with a texture at gridCoord, using a custom scale for the laplacian.
 // Only call if gridCoord is a fluid position!
-float MultiplyWithCoefficientMatrix(ivec3 gridCoord, texture3D texture, float valueAtGridCoord) {
+float MultiplyWithCoefficientMatrixScaled(ivec3 gridCoord, texture3D texture, float valueAtGridCoord, float laplaceScale) {
     float result = 0.0;
     float markerX0 = texelFetch(MarkerVolume, gridCoord - ivec3(1, 0, 0), 0).x;
     float markerX1 = texelFetch(MarkerVolume, gridCoord + ivec3(1, 0, 0), 0).x;
@@ -41,37 +61,46 @@ float MultiplyWithCoefficientMatrix(ivec3 gridCoord, texture3D texture, float va
     float markerZ1 = texelFetch(MarkerVolume, gridCoord + ivec3(0, 0, 1), 0).x;
 
     // This is the diagonal value of matrix A!
-    float numNonSolidNeighbors = 0.0;
-    numNonSolidNeighbors += abs(markerX0); // float(markerX0 != CELL_SOLID);
-    numNonSolidNeighbors += abs(markerX1); // float(markerX1 != CELL_SOLID);
-    numNonSolidNeighbors += abs(markerY0); // float(markerY0 != CELL_SOLID);
-    numNonSolidNeighbors += abs(markerY1); // float(markerY1 != CELL_SOLID);
-    numNonSolidNeighbors += abs(markerZ0); // float(markerZ0 != CELL_SOLID);
-    numNonSolidNeighbors += abs(markerZ1); // float(markerZ1 != CELL_SOLID);
+    float numStencilNeighbors = 0.0;
+    numStencilNeighbors += float(markerX0 != NeumannBoundaryMarker);
+    numStencilNeighbors += float(markerX1 != NeumannBoundaryMarker);
+    numStencilNeighbors += float(markerY0 != NeumannBoundaryMarker);
+    numStencilNeighbors += float(markerY1 != NeumannBoundaryMarker);
+    numStencilNeighbors += float(markerZ0 != NeumannBoundaryMarker);
+    numStencilNeighbors += float(markerZ1 != NeumannBoundaryMarker);
 
     // apply diagonal of A
-    if (numNonSolidNeighbors > 0.0) {
-        result += numNonSolidNeighbors * valueAtGridCoord;
+    float diagonal = IdentityScale + laplaceScale * numStencilNeighbors;
+    if (diagonal > 0.0) {
+        result += diagonal * valueAtGridCoord;
     }
 
     // apply other coefficients of A
+    float neighborSum = 0.0;
     if (markerX0 == CELL_FLUID) {
-        result -= texelFetch(texture, gridCoord - ivec3(1, 0, 0), 0).x;
+        neighborSum += texelFetch(texture, gridCoord - ivec3(1, 0, 0), 0).x;
     }
     if (markerX1 == CELL_FLUID) {
-        result -= texelFetch(texture, gridCoord + ivec3(1, 0, 0), 0).x;
+        neighborSum += texelFetch(texture, gridCoord + ivec3(1, 0, 0), 0).x;
     }
     if (markerY0 == CELL_FLUID) {
-        result -= texelFetch(texture, gridCoord - ivec3(0, 1, 0), 0).x;
+        neighborSum += texelFetch(texture, gridCoord - ivec3(0, 1, 0), 0).x;
     }
     if (markerY1 == CELL_FLUID) {
-        result -= texelFetch(texture, gridCoord + ivec3(0, 1, 0), 0).x;
+        neighborSum += texelFetch(texture, gridCoord + ivec3(0, 1, 0), 0).x;
     }
     if (markerZ0 == CELL_FLUID) {
-        result -= texelFetch(texture, gridCoord - ivec3(0, 0, 1), 0).x;
+        neighborSum += texelFetch(texture, gridCoord - ivec3(0, 0, 1), 0).x;
     }
     if (markerZ1 == CELL_FLUID) {
-        result -= texelFetch(texture, gridCoord + ivec3(0, 0, 1), 0).x;
+        neighborSum += texelFetch(texture, gridCoord + ivec3(0, 0, 1), 0).x;
     }
+    result -= laplaceScale * neighborSum;
     return result;
 }
+
+// Result of multiplication with coefficient matrix with a texture at gridCoord.
+// Only call if gridCoord is a fluid position!
+float MultiplyWithCoefficientMatrix(ivec3 gridCoord, texture3D texture, float valueAtGridCoord) {
+    return MultiplyWithCoefficientMatrixScaled(gridCoord, texture, valueAtGridCoord, LaplaceScale);
+}
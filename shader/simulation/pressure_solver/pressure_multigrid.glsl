@@ -0,0 +1,14 @@
+// Multigrid preconditioner, applies a single V-cycle to the residual (r) to yield the auxiliary vector (z).
+//
+// Level 0 is the full resolution grid, every coarser level halves the resolution in all directions.
+// Coarser levels solve the same system rediscretized on a coarsened marker volume.
+// Smoothing is red-black Gauss-Seidel, restriction averages and prolongation is piecewise constant.
+// Since the smoothing order is reversed on the way up, the V-cycle is a symmetric operator and can be used as a preconditioner for PCG.
+//
+// MarkerVolume (set 0) is the marker of the level a pass operates on. Passes between two levels operate on the finer one.
+
+#define MULTIGRID_PUSH_CONSTANTS
+#include "pressure.glsl"
+
+// The laplacian scales with the inverse squared cell size and cells on level n are 2^n times as large.
+float laplaceScaleOnLevel(uint level) { return LaplaceScale / float(1u << (2u * level)); }
@@ -0,0 +1,12 @@
+#include "per_frame_resources.glsl"
+#include "simulation/hybrid_fluid.glsl"
+#include "simulation/particles.glsl"
+#include "utilities.glsl"
+
+layout(set = 2, binding = 0) buffer restrict readonly ParticlePositionLlBuffer { ParticlePositionLl Particles[]; };
+layout(set = 2, binding = 1) uniform utexture3D LinkedListDualGrid;
+layout(set = 2, binding = 2) uniform texture3D MarkerVolume;
+// Signed distance to the fluid surface in grid cells, negative inside. Only accurate close to the surface.
+layout(set = 2, binding = 3, r32f) uniform restrict image3D LevelSetVolume;
+// Pressure jump caused by surface tension for air cells next to fluid, zero everywhere else. Same units as the pressure from velocity.
+layout(set = 2, binding = 4, r32f) uniform restrict writeonly image3D SurfaceTensionPressureVolume;
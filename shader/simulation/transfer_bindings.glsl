@@ -7,6 +7,8 @@ layout(set = 2, binding = 1) buffer restrict readonly ParticleComp { vec4 Partic
 layout(set = 2, binding = 2, r32ui) uniform restrict uimage3D LinkedListDualGrid;
 layout(set = 2, binding = 3, r8_snorm) uniform restrict image3D MarkerVolume;
 layout(set = 2, binding = 4, r32f) uniform restrict image3D VelocityComponentVolume;
+// Velocity before any forces are applied, needed to compute velocity change for FLIP.
+layout(set = 2, binding = 5, r32f) uniform restrict writeonly image3D VelocityComponentVolumeOld;
 layout(push_constant) uniform PushConstants { uint VelocityTransferComponent; };
 
 // Reading an image out of bounds returns 0, this is why all linked list pointers on the grid are offset by +1
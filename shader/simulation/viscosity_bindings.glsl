@@ -0,0 +1,33 @@
+#include "per_frame_resources.glsl"
+#include "simulation/hybrid_fluid.glsl"
+#include "utilities.glsl"
+
+layout(set = 2, binding = 0) uniform texture3D MarkerVolume;
+layout(set = 2, binding = 1, r32f) uniform restrict image3D VelocityVolumeX;
+layout(set = 2, binding = 2, r32f) uniform restrict image3D VelocityVolumeY;
+layout(set = 2, binding = 3, r32f) uniform restrict image3D VelocityVolumeZ;
+// Marker for the staggered velocity samples of the currently solved component, this is what the pressure solver uses as marker volume.
+layout(set = 2, binding = 4, r8_snorm) uniform restrict image3D ViscosityMarkerVolume;
+// Right hand side of the viscosity solve (shared with the pressure solver).
+layout(set = 2, binding = 5, r32f) uniform restrict image3D Residual;
+// Solution of the viscosity solve, initialized with the current velocity as first guess.
+layout(set = 2, binding = 6, r32f) uniform restrict image3D ViscousVelocity;
+layout(push_constant) uniform PushConstants { uint VelocityComponent; };
+
+float loadVelocity(ivec3 gridCoord) {
+    if (VelocityComponent == 0)
+        return imageLoad(VelocityVolumeX, gridCoord).x;
+    else if (VelocityComponent == 1)
+        return imageLoad(VelocityVolumeY, gridCoord).x;
+    else
+        return imageLoad(VelocityVolumeZ, gridCoord).x;
+}
+
+void storeVelocity(ivec3 gridCoord, float velocity) {
+    if (VelocityComponent == 0)
+        imageStore(VelocityVolumeX, gridCoord, velocity.xxxx);
+    else if (VelocityComponent == 1)
+        imageStore(VelocityVolumeY, gridCoord, velocity.xxxx);
+    else
+        imageStore(VelocityVolumeZ, gridCoord, velocity.xxxx);
+}
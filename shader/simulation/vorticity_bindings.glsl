@@ -0,0 +1,12 @@
+#include "per_frame_resources.glsl"
+#include "simulation/hybrid_fluid.glsl"
+#include "utilities.glsl"
+
+layout(set = 2, binding = 0) uniform texture3D MarkerVolume;
+layout(set = 2, binding = 1, r32f) uniform restrict image3D VelocityVolumeX;
+layout(set = 2, binding = 2, r32f) uniform restrict image3D VelocityVolumeY;
+layout(set = 2, binding = 3, r32f) uniform restrict image3D VelocityVolumeZ;
+// Vorticity at cell centers in xyz, its length in w. Zero for all non-fluid cells.
+layout(set = 2, binding = 4, rgba16f) uniform restrict image3D VorticityVolume;
+
+bool isFluid(ivec3 gridCoord) { return texelFetch(MarkerVolume, gridCoord, 0).x == CELL_FLUID; }
@@ -2,7 +2,8 @@ use super::timer::Timer;
 use super::wgpu_utils::uniformbuffer::*;
 use cgmath::prelude::*;
 use enumflags2::BitFlags;
-use winit::event::{DeviceEvent, ElementState, KeyboardInput, VirtualKeyCode, WindowEvent};
+use serde::{Deserialize, Serialize};
+use winit::event::{DeviceEvent, ElementState, KeyboardInput, MouseScrollDelta, VirtualKeyCode, WindowEvent};
 
 #[cfg_attr(rustfmt, rustfmt_skip)]
 const OPENGL_PROJECTION_TO_WGPU_PROJECTION: cgmath::Matrix4<f32> = cgmath::Matrix4::new(
@@ -12,7 +13,7 @@ const OPENGL_PROJECTION_TO_WGPU_PROJECTION: cgmath::Matrix4<f32> = cgmath::Matri
     0.0, 0.0, 0.5, 1.0,
 );
 
-const VERTICAL_FOV: cgmath::Deg<f32> = cgmath::Deg(80f32);
+const DEFAULT_VERTICAL_FOV: cgmath::Deg<f32> = cgmath::Deg(80f32);
 
 #[derive(BitFlags, Copy, Clone, Debug, PartialEq)]
 enum MoveCommands {
@@ -23,14 +24,53 @@ enum MoveCommands {
     SpeedUp = 0b1_0000,
 }
 
+// Camera placement as stored in scene files.
+#[derive(Clone, Copy, Serialize, Deserialize)]
+pub struct CameraConfig {
+    pub position: cgmath::Point3<f32>,
+    pub direction: cgmath::Vector3<f32>,
+    // In degrees.
+    #[serde(default = "default_vertical_fov")]
+    pub vertical_fov: f32,
+}
+
+fn default_vertical_fov() -> f32 {
+    DEFAULT_VERTICAL_FOV.0
+}
+
+// Named camera placement stored in scene files.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct CameraPresetConfig {
+    pub name: String,
+    #[serde(flatten)]
+    pub camera: CameraConfig,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, EnumIter, Serialize, Deserialize)]
+pub enum CameraMode {
+    // WASD to move, right mouse to look around.
+    Fly,
+    // Right mouse to rotate around the target, middle mouse to pan, scroll wheel to zoom.
+    Orbit,
+}
+
+#[derive(Clone)]
 pub struct Camera {
     pub position: cgmath::Point3<f32>,
     pub direction: cgmath::Vector3<f32>,
+    pub vertical_fov: cgmath::Deg<f32>,
     rotational_up: cgmath::Vector3<f32>,
 
+    mode: CameraMode,
+    // Orbit mode rotates around this point, position is always orbit_distance away from it.
+    orbit_target: cgmath::Point3<f32>,
+    orbit_distance: f32,
+
     movement_locked: bool,
+    panning: bool,
     active_move_commands: BitFlags<MoveCommands>,
     mouse_delta: (f64, f64),
+    scroll_delta: f32,
 
     translation_speed: f32,
     rotation_speed: f32,
@@ -42,17 +82,65 @@ impl Camera {
         Camera {
             position,
             direction: (cgmath::Point3::new(0f32, 0.0, 0.0) - position).normalize(),
+            vertical_fov: DEFAULT_VERTICAL_FOV,
             rotational_up: cgmath::Vector3::unit_y(),
 
+            mode: CameraMode::Fly,
+            orbit_target: cgmath::Point3::new(0.0, 0.0, 0.0),
+            orbit_distance: position.to_vec().magnitude(),
+
             movement_locked: true,
+            panning: false,
             active_move_commands: Default::default(),
             mouse_delta: (0.0, 0.0),
+            scroll_delta: 0.0,
 
             translation_speed: 0.5,
             rotation_speed: 0.001,
         }
     }
 
+    pub fn config(&self) -> CameraConfig {
+        CameraConfig {
+            position: self.position,
+            direction: self.direction,
+            vertical_fov: self.vertical_fov.0,
+        }
+    }
+
+    pub fn set_config(&mut self, config: &CameraConfig) {
+        self.position = config.position;
+        self.direction = config.direction.normalize();
+        self.vertical_fov = cgmath::Deg(config.vertical_fov);
+        self.orbit_target = self.position + self.direction * self.orbit_distance;
+    }
+
+    pub fn mode(&self) -> CameraMode {
+        self.mode
+    }
+
+    pub fn set_mode(&mut self, mode: CameraMode) {
+        // Position & direction are always up to date, so switching just means that we interpret them differently.
+        if mode == CameraMode::Orbit && self.mode != CameraMode::Orbit {
+            self.orbit_target = self.position + self.direction * self.orbit_distance;
+        }
+        self.mode = mode;
+    }
+
+    // Looks at the center of the given box (slightly from above) from a distance at which all of it is visible.
+    // Also makes the center the new orbit target.
+    pub fn frame_bounds(&mut self, min: cgmath::Point3<f32>, max: cgmath::Point3<f32>) {
+        let center = cgmath::EuclideanSpace::midpoint(min, max);
+        let bounding_radius = (max - min).magnitude() * 0.5;
+        // Bounding sphere fits vertically, usually leaves some room horizontally.
+        let distance = bounding_radius / (self.vertical_fov * 0.5).sin();
+
+        self.direction = cgmath::vec3(-0.4, -0.4, -1.0).normalize();
+        self.orbit_target = center;
+        self.orbit_distance = distance;
+        self.position = center - self.direction * distance;
+    }
+
     pub fn on_window_event(&mut self, event: &WindowEvent) {
         match event {
             WindowEvent::KeyboardInput {
@@ -79,10 +167,16 @@ impl Camera {
                     ElementState::Released => self.active_move_commands.remove(direction),
                 };
             }
-            WindowEvent::MouseInput { button, state, .. } => {
-                if *button == winit::event::MouseButton::Right {
-                    self.movement_locked = *state == ElementState::Released;
-                }
+            WindowEvent::MouseInput { button, state, .. } => match button {
+                winit::event::MouseButton::Right => self.movement_locked = *state == ElementState::Released,
+                winit::event::MouseButton::Middle => self.panning = *state == ElementState::Pressed,
+                _ => {}
+            },
+            WindowEvent::MouseWheel { delta, .. } => {
+                self.scroll_delta += match delta {
+                    MouseScrollDelta::LineDelta(_, lines) => *lines,
+                    MouseScrollDelta::PixelDelta(pixels) => pixels.y as f32 / 20.0,
+                };
             }
             _ => {}
         }
@@ -98,6 +192,28 @@ impl Camera {
     }
 
     pub fn update(&mut self, timer: &Timer) {
+        match self.mode {
+            CameraMode::Fly => self.update_fly(timer),
+            CameraMode::Orbit => self.update_orbit(),
+        }
+
+        self.mouse_delta = (0.0, 0.0);
+        self.scroll_delta = 0.0;
+    }
+
+    fn rotate_direction(&mut self) {
+        let right = self.direction.cross(self.rotational_up).normalize();
+        let rotation_updown = cgmath::Quaternion::from_axis_angle(right, cgmath::Rad(-self.mouse_delta.1 as f32 * self.rotation_speed));
+        let rotation_leftright =
+            cgmath::Quaternion::from_axis_angle(self.rotational_up, cgmath::Rad(-self.mouse_delta.0 as f32 * self.rotation_speed));
+        let new_direction = (rotation_updown + rotation_leftright).rotate_vector(self.direction).normalize();
+        // Looking straight up or down would flip the view.
+        if new_direction.dot(self.rotational_up).abs() < 0.99 {
+            self.direction = new_direction;
+        }
+    }
+
+    fn update_fly(&mut self, timer: &Timer) {
         if self.movement_locked == false {
             let right = self.direction.cross(self.rotational_up).normalize();
 
@@ -112,15 +228,24 @@ impl Camera {
                 translation *= 4.0;
             }
 
-            let rotation_updown = cgmath::Quaternion::from_axis_angle(right, cgmath::Rad(-self.mouse_delta.1 as f32 * self.rotation_speed));
-            let rotation_leftright =
-                cgmath::Quaternion::from_axis_angle(self.rotational_up, cgmath::Rad(-self.mouse_delta.0 as f32 * self.rotation_speed));
-            self.direction = (rotation_updown + rotation_leftright).rotate_vector(self.direction).normalize();
-
+            self.rotate_direction();
             self.position += translation;
         }
+    }
 
-        self.mouse_delta = (0.0, 0.0);
+    fn update_orbit(&mut self) {
+        if self.movement_locked == false {
+            self.rotate_direction();
+        }
+        if self.panning {
+            // Scaled with distance so that the target roughly sticks to the mouse cursor.
+            let right = self.direction.cross(self.rotational_up).normalize();
+            let up = right.cross(self.direction).normalize();
+            let pan_speed = self.orbit_distance * self.rotation_speed;
+            self.orbit_target += (-self.mouse_delta.0 as f32 * right + self.mouse_delta.1 as f32 * up) * pan_speed;
+        }
+        self.orbit_distance = (self.orbit_distance * 0.9f32.powf(self.scroll_delta)).max(0.01);
+        self.position = self.orbit_target - self.direction * self.orbit_distance;
     }
 
     pub fn fill_global_uniform_buffer(&self, aspect_ratio: f32) -> CameraUniformBufferContent {
@@ -128,7 +253,7 @@ impl Camera {
         let up = right.cross(self.direction).normalize();
 
         let view = cgmath::Matrix4::look_at_dir(self.position, self.direction, self.rotational_up);
-        let projection = OPENGL_PROJECTION_TO_WGPU_PROJECTION * cgmath::perspective(VERTICAL_FOV, aspect_ratio, 0.01, 1000.0);
+        let projection = OPENGL_PROJECTION_TO_WGPU_PROJECTION * cgmath::perspective(self.vertical_fov, aspect_ratio, 0.01, 1000.0);
         let view_projection = projection * view;
         let inverse_projection = projection.invert().unwrap();
         //let inverse_view_projection = view_projection.invert().unwrap();
@@ -143,8 +268,8 @@ impl Camera {
             up: up.into(),
             direction: self.direction.into(),
             ndc_camera_space_projected: ndc_camera_space_projected.into(),
-            tan_half_vertical_fov: (VERTICAL_FOV * 0.5).tan(),
-            inv_tan_half_vertical_fov: 1.0 / (VERTICAL_FOV * 0.5).tan(),
+            tan_half_vertical_fov: (self.vertical_fov * 0.5).tan(),
+            inv_tan_half_vertical_fov: 1.0 / (self.vertical_fov * 0.5).tan(),
         }
     }
 }
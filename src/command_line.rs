@@ -0,0 +1,192 @@
+use std::{path::PathBuf, time::Duration};
+
+pub const USAGE: &str = "\
+Usage: blub [OPTIONS]
+
+Options:
+    --scene <path>                 Scene file to load [default: first scene in the scene directory]
+    --scene-directory <directory>  Directory listing the scenes available in the ui [default: scenes]
+    --resolution <width>x<height>  Window resolution [default: 1980x1080]
+    --backend <name>               Graphics backend, one of primary, vulkan, metal, dx12, dx11, gl [default: primary]
+    --steps-per-second <n>         Number of simulation steps per simulated second
+    --max-cfl <number>             Adapt the simulation step length to keep the CFL number below the given bound
+    --paused                       Start with paused simulation
+    --record <fps>                 Start recording right away with the given frames per second
+    --stop-time <seconds>          Simulated time after which the simulation is paused (and recording stops)
+    --headless                     Run without window, see below
+    --validate-solver              Compare the GPU pressure solver against a CPU reference on test problems and exit
+    --compare-cpu                  Run the scene on GPU and CPU side by side and compare the particles every output interval,
+                                   requires --scene and --duration, takes the headless mode options
+    --help                         Print this message
+
+Headless mode (requires --scene and --duration):
+    --duration <seconds>           Simulated time after which the run stops
+    --output <directory>           Directory all results are written to [default: output]
+    --output-interval <seconds>    Simulated time between statistics samples (and particle dumps) [default: 0.1]
+    --dump-particles               Write all particles as ply file every output interval
+    --checkpoint-interval <seconds> Simulated time between checkpoints, a final checkpoint is always written
+    --cpu                          Simulate on the CPU instead, only for small scenes without emitters, obstacles etc.
+";
+
+pub struct CommandLineOptions {
+    pub help: bool,
+    pub scene: Option<PathBuf>,
+    pub scene_directory: PathBuf,
+    pub resolution: winit::dpi::LogicalSize<u32>,
+    pub backend: wgpu::BackendBit,
+    pub steps_per_second: Option<u64>,
+    pub max_cfl_number: Option<f32>,
+    pub start_paused: bool,
+    pub recording_fps: Option<f64>,
+    pub stop_time: Option<Duration>,
+
+    pub headless: bool,
+    pub validate_solver: bool,
+    pub compare_cpu: bool,
+    pub cpu: bool,
+    pub duration: Option<Duration>,
+    pub output_directory: PathBuf,
+    pub output_interval: Duration,
+    pub dump_particles: bool,
+    pub checkpoint_interval: Option<Duration>,
+}
+
+impl Default for CommandLineOptions {
+    fn default() -> Self {
+        CommandLineOptions {
+            help: false,
+            scene: None,
+            scene_directory: PathBuf::from("scenes"),
+            resolution: winit::dpi::LogicalSize::new(1980, 1080),
+            backend: wgpu::BackendBit::PRIMARY,
+            steps_per_second: None,
+            max_cfl_number: None,
+            start_paused: false,
+            recording_fps: None,
+            stop_time: None,
+
+            headless: false,
+            validate_solver: false,
+            compare_cpu: false,
+            cpu: false,
+            duration: None,
+            output_directory: PathBuf::from("output"),
+            output_interval: Duration::from_millis(100),
+            dump_particles: false,
+            checkpoint_interval: None,
+        }
+    }
+}
+
+fn parse_value<T: std::str::FromStr>(flag: &str, value: Option<String>) -> Result<T, String> {
+    let value = value.ok_or_else(|| format!("Missing value for {}", flag))?;
+    value.parse().map_err(|_| format!("Invalid value for {}: \"{}\"", flag, value))
+}
+
+fn parse_seconds(flag: &str, value: Option<String>) -> Result<Duration, String> {
+    let seconds: f64 = parse_value(flag, value)?;
+    if seconds <= 0.0 || !seconds.is_finite() {
+        return Err(format!("{} needs to be a positive number of seconds", flag));
+    }
+    Ok(Duration::from_secs_f64(seconds))
+}
+
+fn parse_resolution(flag: &str, value: Option<String>) -> Result<winit::dpi::LogicalSize<u32>, String> {
+    let value: String = parse_value(flag, value)?;
+    let invalid = || format!("Invalid value for {}: \"{}\", expected <width>x<height>", flag, value);
+    let mut parts = value.split('x');
+    let width: u32 = parts.next().and_then(|w| w.parse().ok()).ok_or_else(invalid)?;
+    let height: u32 = parts.next().and_then(|h| h.parse().ok()).ok_or_else(invalid)?;
+    if parts.next().is_some() || width == 0 || height == 0 {
+        return Err(invalid());
+    }
+    Ok(winit::dpi::LogicalSize::new(width, height))
+}
+
+fn parse_backend(flag: &str, value: Option<String>) -> Result<wgpu::BackendBit, String> {
+    let value: String = parse_value(flag, value)?;
+    match value.to_lowercase().as_str() {
+        "primary" => Ok(wgpu::BackendBit::PRIMARY),
+        "vulkan" => Ok(wgpu::BackendBit::VULKAN),
+        "metal" => Ok(wgpu::BackendBit::METAL),
+        "dx12" => Ok(wgpu::BackendBit::DX12),
+        "dx11" => Ok(wgpu::BackendBit::DX11),
+        "gl" => Ok(wgpu::BackendBit::GL),
+        _ => Err(format!("Unknown backend \"{}\"", value)),
+    }
+}
+
+impl CommandLineOptions {
+    // Expects the arguments without the executable name.
+    pub fn parse(mut args: impl Iterator<Item = String>) -> Result<Self, String> {
+        let mut options = CommandLineOptions::default();
+
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--help" | "-h" => options.help = true,
+                "--scene" => options.scene = Some(parse_value(&arg, args.next())?),
+                "--scene-directory" => options.scene_directory = parse_value(&arg, args.next())?,
+                "--resolution" => options.resolution = parse_resolution(&arg, args.next())?,
+                "--backend" => options.backend = parse_backend(&arg, args.next())?,
+                "--steps-per-second" => {
+                    let steps_per_second: u64 = parse_value(&arg, args.next())?;
+                    if steps_per_second < 1 {
+                        return Err("--steps-per-second needs to be at least 1".to_owned());
+                    }
+                    options.steps_per_second = Some(steps_per_second);
+                }
+                "--max-cfl" => {
+                    let max_cfl_number: f32 = parse_value(&arg, args.next())?;
+                    if max_cfl_number <= 0.0 || !max_cfl_number.is_finite() {
+                        return Err("--max-cfl needs to be a positive number".to_owned());
+                    }
+                    options.max_cfl_number = Some(max_cfl_number);
+                }
+                "--paused" => options.start_paused = true,
+                "--record" => {
+                    let fps: f64 = parse_value(&arg, args.next())?;
+                    if fps <= 0.0 || !fps.is_finite() {
+                        return Err("--record needs a positive number of frames per second".to_owned());
+                    }
+                    options.recording_fps = Some(fps);
+                }
+                "--stop-time" => options.stop_time = Some(parse_seconds(&arg, args.next())?),
+                "--headless" => options.headless = true,
+                "--validate-solver" => options.validate_solver = true,
+                "--compare-cpu" => options.compare_cpu = true,
+                "--cpu" => options.cpu = true,
+                "--duration" => options.duration = Some(parse_seconds(&arg, args.next())?),
+                "--output" => options.output_directory = parse_value(&arg, args.next())?,
+                "--output-interval" => options.output_interval = parse_seconds(&arg, args.next())?,
+                "--dump-particles" => options.dump_particles = true,
+                "--checkpoint-interval" => options.checkpoint_interval = Some(parse_seconds(&arg, args.next())?),
+                _ => return Err(format!("Unknown argument \"{}\"", arg)),
+            }
+        }
+
+        if options.start_paused && options.recording_fps.is_some() {
+            return Err("--paused and --record can't be combined".to_owned());
+        }
+        if options.headless {
+            if options.scene.is_none() {
+                return Err("--headless requires --scene".to_owned());
+            }
+            if options.duration.is_none() {
+                return Err("--headless requires --duration".to_owned());
+            }
+        }
+        if options.cpu && !options.headless {
+            return Err("--cpu requires --headless".to_owned());
+        }
+        if options.compare_cpu {
+            if options.scene.is_none() {
+                return Err("--compare-cpu requires --scene".to_owned());
+            }
+            if options.duration.is_none() {
+                return Err("--compare-cpu requires --duration".to_owned());
+            }
+        }
+
+        Ok(options)
+    }
+}
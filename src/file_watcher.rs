@@ -0,0 +1,51 @@
+use notify::Watcher;
+use std::{
+    path::Path,
+    sync::atomic::{AtomicBool, Ordering},
+    sync::Arc,
+};
+
+// Watches a set of individual files for changes.
+// Watches the directories containing them, since many editors save by replacing the file which would end a watch on the file itself.
+pub struct FileWatcher {
+    #[allow(dead_code)]
+    watcher: notify::RecommendedWatcher,
+    detected_change: Arc<AtomicBool>,
+}
+
+impl FileWatcher {
+    pub fn new(files: &[&Path]) -> notify::Result<FileWatcher> {
+        // Event paths are based on the watched directory, so canonicalize everything to compare paths reliably.
+        let mut watched_files = Vec::new();
+        let mut watched_directories = Vec::new();
+        for file in files {
+            let file = file.canonicalize()?;
+            let directory = file.parent().unwrap().to_path_buf();
+            if !watched_directories.contains(&directory) {
+                watched_directories.push(directory);
+            }
+            watched_files.push(file);
+        }
+
+        let detected_change = Arc::new(AtomicBool::new(false));
+        let detected_change_evt_ref = detected_change.clone();
+        let mut watcher: notify::RecommendedWatcher = notify::Watcher::new_immediate(move |res: notify::Result<notify::Event>| match res {
+            Ok(event) => {
+                if event.paths.iter().any(|path| watched_files.contains(path)) {
+                    detected_change_evt_ref.store(true, Ordering::Relaxed);
+                }
+            }
+            Err(e) => error!("File watcher error: {:?}", e),
+        })?;
+        for directory in watched_directories.iter() {
+            watcher.watch(directory, notify::RecursiveMode::NonRecursive)?;
+        }
+
+        Ok(FileWatcher { watcher, detected_change })
+    }
+
+    // Checks if any of the watched files changed since the last call.
+    pub fn detected_change(&self) -> bool {
+        self.detected_change.swap(false, Ordering::Relaxed)
+    }
+}
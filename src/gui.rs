@@ -1,40 +1,63 @@
 use crate::renderer::{FluidRenderingMode, SceneRenderer, VolumeVisualizationMode};
 use crate::simulation_controller::{SimulationController, SimulationControllerStatus};
 use crate::{
+    camera::{Camera, CameraMode, CameraPresetConfig},
     render_output::screen::Screen,
     scene::Scene,
-    simulation::{HybridFluid, SolverConfig, SolverStatisticSample},
+    simulation::{
+        HybridFluid, Preconditioner, SolverConfig, SolverConvergence, SolverMethod, SolverStatisticSample, TransferScheme, MAX_PARTICLES_PER_CELL,
+    },
     ApplicationEvent,
 };
-use imgui::im_str;
-use std::{borrow::Cow, collections::VecDeque, path::PathBuf, time::Duration};
+use imgui::{im_str, ImString};
+use std::{
+    borrow::Cow,
+    collections::VecDeque,
+    path::{Path, PathBuf},
+    time::Duration,
+};
 use strum::IntoEnumIterator;
 use winit::event_loop::EventLoopProxy;
 
-const SCENE_DIRECTORY: &str = "scenes";
+const CHECKPOINT_DIRECTORY: &str = "checkpoints";
 
-fn list_scene_files() -> Vec<PathBuf> {
-    let files: Vec<PathBuf> = std::fs::read_dir(SCENE_DIRECTORY)
-        .expect(&format!("Scene directory \"{}\" not present!", SCENE_DIRECTORY))
+fn list_scene_files(scene_directory: &Path) -> Vec<PathBuf> {
+    let entries = match std::fs::read_dir(scene_directory) {
+        Ok(entries) => entries,
+        Err(error) => {
+            warn!("Failed to list scene directory {:?}: {}", scene_directory, error);
+            return Vec::new();
+        }
+    };
+    entries
         .map(|entry| entry.unwrap())
         .filter(|entry| entry.file_type().unwrap().is_file())
         .map(|entry| entry.path())
         .filter(|path| path.extension().unwrap_or_default() == "json")
-        .collect();
+        .collect()
+}
 
-    if files.len() == 0 {
-        panic!("No scene files found scene directory \"{}\"", SCENE_DIRECTORY);
+fn is_same_file(a: &Path, b: &Path) -> bool {
+    match (a.canonicalize(), b.canonicalize()) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => a == b,
     }
-
-    files
 }
 
 pub struct GUIState {
     fast_forward_length_seconds: f32,
     video_fps: i32,
     selected_scene_idx: usize,
+    scene_directory: PathBuf,
     known_scene_files: Vec<PathBuf>,
     wait_for_vblank: bool,
+    hot_reload_scene: bool,
+    save_scene_name: ImString,
+    camera_preset_name: ImString,
+    follow_camera_path_while_recording: bool,
+    preview_camera_path: bool,
+    // Shown in a dialog until dismissed or a scene is loaded successfully.
+    scene_error: Option<String>,
 }
 pub struct GUI {
     imgui_context: imgui::Context,
@@ -45,7 +68,15 @@ pub struct GUI {
 }
 
 impl GUI {
-    pub fn new(device: &wgpu::Device, window: &winit::window::Window, command_queue: &mut wgpu::Queue) -> Self {
+    // The initial scene is selected in the scene list (and added to it if it's not in the scene directory).
+    // If there is none, the first scene in the scene directory is selected.
+    pub fn new(
+        device: &wgpu::Device,
+        window: &winit::window::Window,
+        command_queue: &mut wgpu::Queue,
+        scene_directory: &Path,
+        initial_scene: Option<&Path>,
+    ) -> Self {
         let mut imgui_context = imgui::Context::create();
 
         let mut imgui_platform = imgui_winit_support::WinitPlatform::init(&mut imgui_context);
@@ -67,6 +98,27 @@ impl GUI {
 
         let imgui_renderer = imgui_wgpu::Renderer::new(&mut imgui_context, device, command_queue, Screen::FORMAT_BACKBUFFER, None);
 
+        let mut known_scene_files = list_scene_files(scene_directory);
+        let mut selected_scene_idx = 0;
+        if let Some(initial_scene) = initial_scene {
+            selected_scene_idx = match known_scene_files.iter().position(|path| is_same_file(path, initial_scene)) {
+                Some(idx) => idx,
+                None => {
+                    known_scene_files.push(initial_scene.to_path_buf());
+                    known_scene_files.len() - 1
+                }
+            };
+        }
+        if known_scene_files.len() == 0 {
+            panic!("No scene files found in scene directory {:?}", scene_directory);
+        }
+
+        let mut save_scene_name = ImString::with_capacity(256);
+        save_scene_name.push_str(&format!(
+            "{}_saved.json",
+            known_scene_files[selected_scene_idx].file_stem().unwrap_or_default().to_string_lossy()
+        ));
+
         GUI {
             imgui_context,
             imgui_platform,
@@ -75,9 +127,16 @@ impl GUI {
             state: GUIState {
                 fast_forward_length_seconds: 5.0,
                 video_fps: 60,
-                selected_scene_idx: 0,
-                known_scene_files: list_scene_files(),
+                selected_scene_idx,
+                scene_directory: scene_directory.to_path_buf(),
+                known_scene_files,
                 wait_for_vblank: Screen::DEFAULT_PRESENT_MODE == wgpu::PresentMode::Fifo,
+                hot_reload_scene: true,
+                save_scene_name,
+                camera_preset_name: ImString::with_capacity(64),
+                follow_camera_path_while_recording: true,
+                preview_camera_path: false,
+                scene_error: None,
             },
         }
     }
@@ -86,6 +145,54 @@ impl GUI {
         &self.state.known_scene_files[self.state.selected_scene_idx]
     }
 
+    // Whether the scene (and background config) should be reloaded whenever its file changes.
+    pub fn hot_reload_scene(&self) -> bool {
+        self.state.hot_reload_scene
+    }
+
+    pub fn set_scene_error(&mut self, error: Option<String>) {
+        self.state.scene_error = error;
+    }
+
+    // Whether the camera should be driven by the scene's camera path.
+    pub fn follow_camera_path(&self, recording: bool) -> bool {
+        self.state.preview_camera_path || (recording && self.state.follow_camera_path_while_recording)
+    }
+
+    // Makes newly saved scenes available in the scene list.
+    pub fn on_scene_saved(&mut self, scene_path: &Path) {
+        if !self.state.known_scene_files.iter().any(|path| is_same_file(path, scene_path)) {
+            self.state.known_scene_files.push(scene_path.to_path_buf());
+        }
+    }
+
+    // Every scene has a single checkpoint slot.
+    fn checkpoint_path(state: &GUIState) -> PathBuf {
+        let scene_name = state.known_scene_files[state.selected_scene_idx].file_stem().unwrap_or_default();
+        Path::new(CHECKPOINT_DIRECTORY).join(scene_name).with_extension("checkpoint")
+    }
+
+    // Exports are numbered like screenshots and kept next to the scene's checkpoint, earlier ones are never overwritten.
+    fn solver_convergence_export_path(state: &GUIState) -> PathBuf {
+        let scene_name = state.known_scene_files[state.selected_scene_idx].file_stem().unwrap_or_default();
+        (0..)
+            .map(|i| Path::new(CHECKPOINT_DIRECTORY).join(format!("{}_solver_convergence{}.csv", scene_name.to_string_lossy(), i)))
+            .find(|path| !path.exists())
+            .unwrap()
+    }
+
+    // Saved scenes always go into the scene directory, so names can't point anywhere else.
+    fn save_scene_path(state: &GUIState) -> Result<PathBuf, &'static str> {
+        let name = state.save_scene_name.to_str();
+        if name.is_empty() {
+            Err("enter a name to save the scene")
+        } else if name.contains(std::path::is_separator) || name.contains("..") {
+            Err("name can't contain path separators or \"..\"")
+        } else {
+            Ok(state.scene_directory.join(name).with_extension("json"))
+        }
+    }
+
     const DEFAULT_BUTTON_HEIGHT: f32 = 19.0;
 
     fn setup_ui_timer(
@@ -133,7 +240,13 @@ impl GUI {
         ));
     }
 
-    fn setup_ui_solver_stats(ui: &imgui::Ui, stats: &VecDeque<SolverStatisticSample>, max_iterations: i32, target_mse: f32) {
+    fn setup_ui_solver_stats(
+        ui: &imgui::Ui,
+        stats: &VecDeque<SolverStatisticSample>,
+        convergence: &VecDeque<SolverConvergence>,
+        max_iterations: i32,
+        target_mse: f32,
+    ) {
         let newest_sample = match stats.back() {
             Some(&sample) => sample,
             None => Default::default(),
@@ -155,6 +268,24 @@ impl GUI {
         .scale_max(max_iterations as f32)
         .graph_size([300.0, 40.0])
         .build();
+
+        // Residual over the error checks of the last solve, log scale since it spans orders of magnitude.
+        let newest_convergence = convergence.back().cloned().unwrap_or_default();
+        let log_mse = newest_convergence
+            .residuals
+            .iter()
+            .map(|residual| residual.mse.max(f32::MIN_POSITIVE).log10())
+            .collect::<Vec<f32>>();
+        ui.plot_lines(
+            &im_str!(
+                "log10 mse per check - target {:.1}, {:.3}s",
+                target_mse.log10(),
+                newest_convergence.timestamp.as_secs_f64()
+            ),
+            &log_mse,
+        )
+        .graph_size([300.0, 60.0])
+        .build();
     }
 
     fn setup_ui_solver_config(ui: &imgui::Ui, config: &mut SolverConfig) {
@@ -178,15 +309,106 @@ impl GUI {
         {
             config.mse_check_frequency = mse_check_frequency as i32;
         }
+        let mut current_method = config.method as usize;
+        imgui::ComboBox::new(im_str!("method")).build_simple(
+            ui,
+            &mut current_method,
+            &SolverMethod::iter().collect::<Vec<SolverMethod>>(),
+            &|value| Cow::from(im_str!("{:?}", *value)),
+        );
+        config.method = SolverMethod::iter().nth(current_method).unwrap();
+        if config.method == SolverMethod::Pcg {
+            let mut current_preconditioner = config.preconditioner as usize;
+            imgui::ComboBox::new(im_str!("preconditioner")).build_simple(
+                ui,
+                &mut current_preconditioner,
+                &Preconditioner::iter().collect::<Vec<Preconditioner>>(),
+                &|value| Cow::from(im_str!("{:?}", *value)),
+            );
+            config.preconditioner = Preconditioner::iter().nth(current_preconditioner).unwrap();
+        }
     }
 
-    fn setup_ui_solver(ui: &imgui::Ui, fluid: &mut HybridFluid) {
+    fn setup_ui_solver(ui: &imgui::Ui, state: &GUIState, fluid: &mut HybridFluid, event_loop_proxy: &EventLoopProxy<ApplicationEvent>) {
+        {
+            let mut current_transfer_scheme = fluid.transfer_scheme() as usize;
+            imgui::ComboBox::new(im_str!("Transfer Scheme")).build_simple(
+                ui,
+                &mut current_transfer_scheme,
+                &TransferScheme::iter().collect::<Vec<TransferScheme>>(),
+                &|value| Cow::from(im_str!("{:?}", *value)),
+            );
+            fluid.set_transfer_scheme(TransferScheme::iter().skip(current_transfer_scheme).next().unwrap());
+            if fluid.transfer_scheme() == TransferScheme::Flip {
+                let mut flip_blend_factor = fluid.flip_blend_factor();
+                imgui::Drag::new(im_str!("FLIP blend factor"))
+                    .range(0.0..=1.0)
+                    .speed(0.01)
+                    .display_format(im_str!("%.2f"))
+                    .build(ui, &mut flip_blend_factor);
+                fluid.set_flip_blend_factor(flip_blend_factor);
+            }
+            let mut viscosity = fluid.viscosity_grid();
+            if ui
+                .input_float(im_str!("viscosity (grid cells²/s)"), &mut viscosity)
+                .step(1.0)
+                .enter_returns_true(true)
+                .build()
+            {
+                fluid.set_viscosity_grid(viscosity);
+            }
+            let mut surface_tension = fluid.surface_tension_grid();
+            if ui
+                .input_float(im_str!("surface tension (grid cells³/s²)"), &mut surface_tension)
+                .step(1.0)
+                .enter_returns_true(true)
+                .build()
+            {
+                fluid.set_surface_tension_grid(surface_tension);
+            }
+            let mut vorticity_confinement = fluid.vorticity_confinement();
+            if imgui::Drag::new(im_str!("vorticity confinement"))
+                .range(0.0..=2.0)
+                .speed(0.01)
+                .display_format(im_str!("%.2f"))
+                .build(ui, &mut vorticity_confinement)
+            {
+                fluid.set_vorticity_confinement(vorticity_confinement);
+            }
+            let mut reseeding_enabled = fluid.reseeding().is_some();
+            let mut reseeding = fluid.reseeding().unwrap_or_default();
+            let mut changed = ui.checkbox(im_str!("particle reseeding"), &mut reseeding_enabled);
+            if reseeding_enabled {
+                let mut min_particles_per_cell = reseeding.min_particles_per_cell as i32;
+                let mut max_particles_per_cell = reseeding.max_particles_per_cell as i32;
+                changed |= ui
+                    .input_int(im_str!("min particles per cell"), &mut min_particles_per_cell)
+                    .enter_returns_true(true)
+                    .build();
+                changed |= ui
+                    .input_int(im_str!("max particles per cell"), &mut max_particles_per_cell)
+                    .enter_returns_true(true)
+                    .build();
+                reseeding.min_particles_per_cell = min_particles_per_cell.max(1).min(MAX_PARTICLES_PER_CELL as i32) as u32;
+                reseeding.max_particles_per_cell = max_particles_per_cell.max(1).min(MAX_PARTICLES_PER_CELL as i32) as u32;
+            }
+            if changed {
+                fluid.set_reseeding(if reseeding_enabled { Some(reseeding) } else { None });
+            }
+        }
+        ui.separator();
         let stack_token = ui.push_id(1);
         {
             ui.text(im_str!("pressure solver, primary (from velocity)"));
             let max_num_iterations = fluid.pressure_solver_config_velocity().max_num_iterations;
             let target_mse = fluid.pressure_solver_config_velocity().target_mse;
-            Self::setup_ui_solver_stats(ui, fluid.pressure_solver_stats_velocity(), max_num_iterations, target_mse);
+            Self::setup_ui_solver_stats(
+                ui,
+                fluid.pressure_solver_stats_velocity(),
+                fluid.pressure_solver_convergence_velocity(),
+                max_num_iterations,
+                target_mse,
+            );
             Self::setup_ui_solver_config(ui, fluid.pressure_solver_config_velocity());
         }
         stack_token.pop(ui);
@@ -195,9 +417,37 @@ impl GUI {
             ui.text(im_str!("pressure solver, secondary (from density)"));
             let max_num_iterations = fluid.pressure_solver_config_density().max_num_iterations;
             let target_mse = fluid.pressure_solver_config_density().target_mse;
-            Self::setup_ui_solver_stats(ui, fluid.pressure_solver_stats_density(), max_num_iterations, target_mse);
+            Self::setup_ui_solver_stats(
+                ui,
+                fluid.pressure_solver_stats_density(),
+                fluid.pressure_solver_convergence_density(),
+                max_num_iterations,
+                target_mse,
+            );
             Self::setup_ui_solver_config(ui, fluid.pressure_solver_config_density());
         }
+        if fluid.viscosity_grid() > 0.0 {
+            ui.separator();
+            let stack_token = ui.push_id(2);
+            ui.text(im_str!("viscosity solver (one sample per component)"));
+            let max_num_iterations = fluid.viscosity_solver_config().max_num_iterations;
+            let target_mse = fluid.viscosity_solver_config().target_mse;
+            Self::setup_ui_solver_stats(
+                ui,
+                fluid.viscosity_solver_stats(),
+                fluid.viscosity_solver_convergence(),
+                max_num_iterations,
+                target_mse,
+            );
+            Self::setup_ui_solver_config(ui, fluid.viscosity_solver_config());
+            stack_token.pop(ui);
+        }
+        ui.separator();
+        if ui.button(im_str!("Export Solver Convergence"), [208.0, Self::DEFAULT_BUTTON_HEIGHT]) {
+            event_loop_proxy
+                .send_event(ApplicationEvent::ExportSolverConvergence(Self::solver_convergence_export_path(state)))
+                .unwrap();
+        }
     }
 
     fn setup_ui_simulation_control(
@@ -240,6 +490,36 @@ impl GUI {
             simulation_controller.time_scale = simulation_controller.time_scale.max(0.01).min(100.0);
         }
 
+        {
+            let mut adaptive_time_step = simulation_controller.adaptive_time_step();
+            let mut changed = ui.checkbox(im_str!("adaptive time step (CFL)"), &mut adaptive_time_step.enabled);
+            if adaptive_time_step.enabled {
+                changed |= ui
+                    .input_float(im_str!("max CFL number"), &mut adaptive_time_step.max_cfl_number)
+                    .step(0.1)
+                    .enter_returns_true(true)
+                    .build();
+                let mut max_simulation_steps_per_second = adaptive_time_step.max_simulation_steps_per_second as i32;
+                if ui
+                    .input_int(im_str!("max simulation steps per second"), &mut max_simulation_steps_per_second)
+                    .step(10)
+                    .enter_returns_true(true)
+                    .build()
+                {
+                    adaptive_time_step.max_simulation_steps_per_second = max_simulation_steps_per_second.max(20).min(60 * 100) as u64;
+                    changed = true;
+                }
+                ui.text(im_str!(
+                    "current step length: {:.2}ms",
+                    simulation_controller.timer().simulation_delta().as_secs_f64() * 1000.0
+                ));
+            }
+            if changed {
+                adaptive_time_step.max_cfl_number = adaptive_time_step.max_cfl_number.max(0.05).min(10.0);
+                simulation_controller.set_adaptive_time_step(adaptive_time_step);
+            }
+        }
+
         {
             if ui.button(im_str!("Reset"), [50.0, Self::DEFAULT_BUTTON_HEIGHT]) {
                 event_loop_proxy.send_event(ApplicationEvent::ResetScene).unwrap();
@@ -333,12 +613,68 @@ impl GUI {
         ui.checkbox(im_str!("Show Fluid Domain Bounds"), &mut scene_renderer.enable_box_lines);
     }
 
+    fn setup_ui_camera(ui: &imgui::Ui, state: &mut GUIState, scene: &mut Scene, camera: &mut Camera) {
+        {
+            let mut current_camera_mode = camera.mode() as usize;
+            imgui::ComboBox::new(im_str!("Camera Mode")).build_simple(
+                ui,
+                &mut current_camera_mode,
+                &CameraMode::iter().collect::<Vec<CameraMode>>(),
+                &|value| Cow::from(im_str!("{:?}", *value)),
+            );
+            camera.set_mode(CameraMode::iter().skip(current_camera_mode).next().unwrap());
+        }
+        if ui.button(im_str!("Frame Fluid Domain"), [150.0, Self::DEFAULT_BUTTON_HEIGHT]) {
+            let (min, max) = scene.config().fluid.domain_bounds();
+            camera.frame_bounds(min, max);
+        }
+        imgui::Drag::new(im_str!("Vertical FOV"))
+            .range(10.0..=150.0)
+            .speed(0.1)
+            .display_format(im_str!("%.1f"))
+            .build(&ui, &mut camera.vertical_fov.0);
+
+        ui.separator();
+        for (i, preset) in scene.config().camera_presets.iter().enumerate() {
+            if i % 4 != 0 {
+                ui.same_line(0.0);
+            }
+            let id_token = ui.push_id(i as i32);
+            if ui.button(&im_str!("{}", preset.name), [0.0, Self::DEFAULT_BUTTON_HEIGHT]) {
+                camera.set_config(&preset.camera);
+            }
+            id_token.pop(ui);
+        }
+        ui.set_next_item_width(150.0);
+        ui.input_text(im_str!("##camera preset name"), &mut state.camera_preset_name).build();
+        ui.same_line(0.0);
+        if ui.button(im_str!("Add Preset"), [120.0, Self::DEFAULT_BUTTON_HEIGHT]) && !state.camera_preset_name.is_empty() {
+            scene.add_camera_preset(CameraPresetConfig {
+                name: state.camera_preset_name.to_str().to_owned(),
+                camera: camera.config(),
+            });
+            state.camera_preset_name.clear();
+        }
+
+        ui.separator();
+        if scene.config().camera_path.is_empty() {
+            ui.text_disabled(im_str!("scene has no camera path"));
+        } else {
+            ui.checkbox(
+                im_str!("Follow Camera Path while Recording"),
+                &mut state.follow_camera_path_while_recording,
+            );
+            ui.checkbox(im_str!("Preview Camera Path"), &mut state.preview_camera_path);
+        }
+    }
+
     fn setup_ui(
         ui: &imgui::Ui,
         state: &mut GUIState,
         simulation_controller: &mut SimulationController,
         scene_renderer: &mut SceneRenderer,
         scene: &mut Scene,
+        camera: &mut Camera,
         event_loop_proxy: &EventLoopProxy<ApplicationEvent>,
     ) {
         let window = imgui::Window::new(im_str!("Blub"));
@@ -349,7 +685,7 @@ impl GUI {
                 Self::setup_ui_timer(ui, state, simulation_controller, event_loop_proxy);
 
                 if imgui::CollapsingHeader::new(im_str!("Solver")).build(&ui) {
-                    Self::setup_ui_solver(ui, scene.fluid_mut());
+                    Self::setup_ui_solver(ui, state, scene.fluid_mut(), event_loop_proxy);
                 }
                 if imgui::CollapsingHeader::new(im_str!("Simulation Controller & Recording"))
                     .default_open(true)
@@ -359,21 +695,72 @@ impl GUI {
                 }
                 if imgui::CollapsingHeader::new(im_str!("Scene Settings")).build(&ui) {
                     ui.set_next_item_width(150.0);
+                    let scene_directory = &state.scene_directory;
                     if imgui::ComboBox::new(im_str!("Load Scene")).build_simple(
                         ui,
                         &mut state.selected_scene_idx,
                         &state.known_scene_files,
-                        &|path| Cow::from(im_str!("{:?}", path.strip_prefix(SCENE_DIRECTORY).unwrap_or(path))),
+                        &|path| Cow::from(im_str!("{:?}", path.strip_prefix(scene_directory).unwrap_or(path))),
                     ) {
                         event_loop_proxy
                             .send_event(ApplicationEvent::LoadScene(state.known_scene_files[state.selected_scene_idx].clone()))
                             .unwrap();
                     }
+                    ui.checkbox(im_str!("Reload Scene on File Change"), &mut state.hot_reload_scene);
+                    ui.set_next_item_width(150.0);
+                    ui.input_text(im_str!("##save scene name"), &mut state.save_scene_name).build();
+                    ui.same_line(0.0);
+                    match Self::save_scene_path(state) {
+                        Ok(scene_path) => {
+                            // Overwriting needs the changed button label as confirmation.
+                            let label = if scene_path.exists() {
+                                im_str!("Overwrite Scene")
+                            } else {
+                                im_str!("Save Scene As")
+                            };
+                            if ui.button(label, [120.0, Self::DEFAULT_BUTTON_HEIGHT]) {
+                                event_loop_proxy.send_event(ApplicationEvent::SaveScene(scene_path)).unwrap();
+                            }
+                        }
+                        Err(problem) => ui.text_disabled(problem),
+                    }
+                    if ui.button(im_str!("Save Checkpoint"), [120.0, Self::DEFAULT_BUTTON_HEIGHT]) {
+                        event_loop_proxy
+                            .send_event(ApplicationEvent::SaveCheckpoint(Self::checkpoint_path(state)))
+                            .unwrap();
+                    }
+                    ui.same_line(0.0);
+                    let checkpoint_path = Self::checkpoint_path(state);
+                    if checkpoint_path.exists() {
+                        if ui.button(im_str!("Load Checkpoint"), [120.0, Self::DEFAULT_BUTTON_HEIGHT]) {
+                            event_loop_proxy.send_event(ApplicationEvent::LoadCheckpoint(checkpoint_path)).unwrap();
+                        }
+                    } else {
+                        ui.text_disabled(im_str!("no checkpoint saved"));
+                    }
                 }
                 if imgui::CollapsingHeader::new(im_str!("Rendering Settings")).build(&ui) {
                     Self::setup_ui_rendersettings(ui, scene_renderer);
                 }
+                if imgui::CollapsingHeader::new(im_str!("Camera")).build(&ui) {
+                    Self::setup_ui_camera(ui, state, scene, camera);
+                }
             });
+
+        if let Some(error) = state.scene_error.as_ref() {
+            let mut dismissed = false;
+            imgui::Window::new(im_str!("Scene Error"))
+                .position([400.0, 100.0], imgui::Condition::FirstUseEver)
+                .always_auto_resize(true)
+                .build(&ui, || {
+                    ui.text(im_str!("{}", error));
+                    ui.text_disabled(im_str!("The previous scene keeps running."));
+                    dismissed = ui.button(im_str!("Dismiss"), [80.0, Self::DEFAULT_BUTTON_HEIGHT]);
+                });
+            if dismissed {
+                state.scene_error = None;
+            }
+        }
     }
 
     pub fn draw(
@@ -386,6 +773,7 @@ impl GUI {
         simulation_controller: &mut SimulationController,
         scene_renderer: &mut SceneRenderer,
         scene: &mut Scene,
+        camera: &mut Camera,
         event_loop_proxy: &EventLoopProxy<ApplicationEvent>,
     ) {
         let context = &mut self.imgui_context;
@@ -395,7 +783,7 @@ impl GUI {
             .prepare_frame(context.io_mut(), window)
             .expect("Failed to prepare imgui frame");
         let ui = context.frame();
-        Self::setup_ui(&ui, state, simulation_controller, scene_renderer, scene, event_loop_proxy);
+        Self::setup_ui(&ui, state, simulation_controller, scene_renderer, scene, camera, event_loop_proxy);
         self.imgui_platform.prepare_render(&ui, &window);
         self.imgui_renderer
             .render(ui.render(), &device, encoder, queue, view)
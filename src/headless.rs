@@ -0,0 +1,449 @@
+// Runs a scene without window or any rendering and writes results to disk.
+// Meant for long unattended simulation runs, e.g. parameter studies on machines without display.
+// Also hosts the pressure solver validation and the CPU reference simulation which likewise run without window.
+
+use crate::{
+    command_line::CommandLineOptions,
+    per_frame_resources::PerFrameResources,
+    scene::{Scene, SceneConfig},
+    simulation::{
+        validate_pressure_solver, FluidBackend, GpuStepContext, HybridFluid, ParticleComparison, SolverStatisticSample,
+        MAX_RELATIVE_PRESSURE_DIFFERENCE,
+    },
+    simulation_controller::{AdaptiveTimeStepConfig, SimulationController},
+    wgpu_utils::{pipelines::PipelineManager, shader::ShaderDirectory},
+};
+use cgmath::EuclideanSpace;
+use std::{
+    fs::File,
+    io::{self, BufWriter, Write},
+    path::Path,
+    time::{Duration, Instant},
+};
+
+// Writes particles in world space as binary ply point cloud with velocity attributes.
+fn write_particles_ply(path: &Path, config: &SceneConfig, particles: &[(cgmath::Point3<f32>, cgmath::Vector3<f32>)]) -> io::Result<()> {
+    let origin = config.fluid.world_position;
+    let scale = config.fluid.grid_to_world_scale;
+
+    let mut writer = BufWriter::new(File::create(path)?);
+    write!(
+        writer,
+        "ply\nformat binary_little_endian 1.0\nelement vertex {}\n\
+         property float x\nproperty float y\nproperty float z\n\
+         property float vx\nproperty float vy\nproperty float vz\nend_header\n",
+        particles.len()
+    )?;
+    for (position, velocity) in particles.iter() {
+        let position = origin + position.to_vec() * scale;
+        let velocity = *velocity * scale;
+        for value in [position.x, position.y, position.z, velocity.x, velocity.y, velocity.z].iter() {
+            writer.write_all(&value.to_le_bytes())?;
+        }
+    }
+    writer.flush()
+}
+
+struct StatisticsWriter {
+    writer: BufWriter<File>,
+    start_time: Instant,
+}
+
+impl StatisticsWriter {
+    fn new(path: &Path) -> io::Result<Self> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        writeln!(
+            writer,
+            "simulated_time,num_steps,num_particles,\
+             velocity_solver_iterations,velocity_solver_mse,density_solver_iterations,density_solver_mse,wall_clock_time,simulation_delta"
+        )?;
+        Ok(StatisticsWriter {
+            writer,
+            start_time: Instant::now(),
+        })
+    }
+
+    fn write_sample(&mut self, simulation_controller: &SimulationController, scene: &Scene) -> io::Result<()> {
+        let fluid = scene.fluid();
+        self.write_row(
+            simulation_controller.timer().total_simulated_time(),
+            simulation_controller.timer().num_simulation_steps_performed(),
+            fluid.num_particles(),
+            fluid.pressure_solver_stats_velocity().back().cloned().unwrap_or_default(),
+            fluid.pressure_solver_stats_density().back().cloned().unwrap_or_default(),
+            simulation_controller.timer().simulation_delta(),
+        )
+    }
+
+    fn write_row(
+        &mut self,
+        simulated_time: Duration,
+        num_steps: u32,
+        num_particles: u32,
+        velocity_solver: SolverStatisticSample,
+        density_solver: SolverStatisticSample,
+        simulation_delta: Duration,
+    ) -> io::Result<()> {
+        let sample_columns = |sample: SolverStatisticSample| format!("{},{}", sample.iteration_count, sample.mse);
+        writeln!(
+            self.writer,
+            "{},{},{},{},{},{},{}",
+            simulated_time.as_secs_f64(),
+            num_steps,
+            num_particles,
+            sample_columns(velocity_solver),
+            sample_columns(density_solver),
+            self.start_time.elapsed().as_secs_f64(),
+            simulation_delta.as_secs_f64(),
+        )?;
+        // Make results available right away, runs can take hours.
+        self.writer.flush()
+    }
+}
+
+// Appends the residual history of every solve retrieved since the last output, see HybridFluid::write_solver_convergence_rows
+struct SolverConvergenceWriter {
+    writer: BufWriter<File>,
+    num_written: [u64; 3],
+}
+
+impl SolverConvergenceWriter {
+    fn new(path: &Path) -> io::Result<Self> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        writeln!(writer, "{}", HybridFluid::SOLVER_CONVERGENCE_CSV_HEADER)?;
+        Ok(SolverConvergenceWriter { writer, num_written: [0; 3] })
+    }
+
+    fn write_new_solves(&mut self, fluid: &HybridFluid) -> io::Result<()> {
+        let num_skipped = fluid.write_solver_convergence_rows(&mut self.writer, &mut self.num_written)?;
+        if num_skipped > 0 {
+            // Only the most recent solves are kept, see PressureField::convergence
+            warn!(
+                "{} solves dropped out of the convergence history before they could be written, use a shorter output interval",
+                num_skipped
+            );
+        }
+        self.writer.flush()
+    }
+}
+
+pub fn run(options: &CommandLineOptions) -> io::Result<()> {
+    futures::executor::block_on(run_async(options))
+}
+
+async fn request_headless_device(options: &CommandLineOptions) -> io::Result<(wgpu::Device, wgpu::Queue)> {
+    let wgpu_instance = wgpu::Instance::new(options.backend);
+    let adapter = wgpu_instance
+        .request_adapter(&wgpu::RequestAdapterOptions {
+            power_preference: wgpu::PowerPreference::HighPerformance,
+            compatible_surface: None,
+        })
+        .await
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "No suitable graphics adapter found"))?;
+    Ok(crate::request_device(&adapter).await)
+}
+
+// Runs all pressure solver validation cases and logs the results. Returns false if any case failed.
+pub fn validate_solver(options: &CommandLineOptions) -> io::Result<bool> {
+    let (device, queue) = futures::executor::block_on(request_headless_device(options))?;
+    let shader_dir = ShaderDirectory::new(Path::new("shader"));
+    let mut pipeline_manager = PipelineManager::new();
+
+    let results = validate_pressure_solver(&device, &queue, &shader_dir, &mut pipeline_manager)
+        .map_err(|_| io::Error::new(io::ErrorKind::Other, "failed to read back solver results"))?;
+
+    let mut all_passed = true;
+    for result in results.iter() {
+        let message = format!(
+            "{}: relative difference {:e} (max {:e}, rms {:e}), gpu mse {:e} after {} iterations (recomputed on cpu {:e}), reference mse {:e} after {} iterations",
+            result.name,
+            result.comparison.relative_difference(),
+            result.comparison.max_abs_difference,
+            result.comparison.rms_difference,
+            result.gpu.mse,
+            result.gpu.iteration_count,
+            result.gpu_solution_error,
+            result.reference.mse,
+            result.reference.iteration_count,
+        );
+        if result.passed() {
+            info!("passed - {}", message);
+        } else {
+            error!("FAILED - {}", message);
+            all_passed = false;
+        }
+    }
+    if all_passed {
+        info!(
+            "All solver validation cases within relative difference of {}",
+            MAX_RELATIVE_PRESSURE_DIFFERENCE
+        );
+    }
+    Ok(all_passed)
+}
+
+async fn run_async(options: &CommandLineOptions) -> io::Result<()> {
+    let scene_path = options.scene.as_ref().expect("Headless mode requires a scene");
+    let duration = options.duration.expect("Headless mode requires a duration");
+
+    let (device, queue) = request_headless_device(options).await?;
+
+    let shader_dir = ShaderDirectory::new(Path::new("shader"));
+    let mut pipeline_manager = PipelineManager::new();
+    let mut per_frame_resources = PerFrameResources::new(&device);
+    let mut scene = Scene::new(
+        scene_path,
+        &device,
+        &queue,
+        &shader_dir,
+        &mut pipeline_manager,
+        per_frame_resources.bind_group_layout(),
+    )?;
+    let mut simulation_controller = SimulationController::new();
+    if let Some(settings) = scene.config().settings.as_ref() {
+        simulation_controller.set_simulation_steps_per_second(settings.simulation_steps_per_second);
+        simulation_controller.set_adaptive_time_step(settings.adaptive_time_step);
+    }
+    if let Some(steps_per_second) = options.steps_per_second {
+        simulation_controller.set_simulation_steps_per_second(steps_per_second);
+    }
+    if let Some(max_cfl_number) = options.max_cfl_number {
+        simulation_controller.set_adaptive_time_step(AdaptiveTimeStepConfig {
+            enabled: true,
+            max_cfl_number,
+            ..simulation_controller.adaptive_time_step()
+        });
+    }
+
+    std::fs::create_dir_all(&options.output_directory)?;
+    let mut statistics = StatisticsWriter::new(&options.output_directory.join("statistics.csv"))?;
+    let mut solver_convergence = SolverConvergenceWriter::new(&options.output_directory.join("solver_convergence.csv"))?;
+    let mut num_outputs = 0;
+    let mut next_checkpoint_time = options.checkpoint_interval.unwrap_or(Duration::from_secs(u64::MAX));
+
+    info!(
+        "Simulating {:?} of scene {:?}, writing results to {:?}",
+        duration, scene_path, options.output_directory
+    );
+
+    loop {
+        let simulated_time = simulation_controller.timer().total_simulated_time();
+
+        statistics.write_sample(&simulation_controller, &scene)?;
+        solver_convergence.write_new_solves(scene.fluid())?;
+        if options.dump_particles {
+            let particles = scene.fluid().read_particles(&device, &queue)?;
+            write_particles_ply(
+                &options.output_directory.join(format!("particles_{:05}.ply", num_outputs)),
+                scene.config(),
+                &particles,
+            )?;
+        }
+        num_outputs += 1;
+
+        if simulated_time >= duration {
+            break;
+        }
+        if simulated_time >= next_checkpoint_time {
+            scene.save_checkpoint(
+                &device,
+                &queue,
+                &options
+                    .output_directory
+                    .join(format!("checkpoint_{:.2}s.checkpoint", simulated_time.as_secs_f64())),
+            )?;
+            next_checkpoint_time += options.checkpoint_interval.unwrap();
+        }
+
+        // Only the time part of the per frame data is relevant for the simulation, it changes only with the simulation step length.
+        per_frame_resources.update_gpu_data_time_only(&queue, simulation_controller.timer().fill_global_uniform_buffer());
+        let jump_length = options.output_interval.min(duration - simulated_time);
+        simulation_controller.fast_forward_steps(
+            jump_length,
+            &device,
+            &queue,
+            &mut scene,
+            &pipeline_manager,
+            per_frame_resources.bind_group(),
+        );
+    }
+
+    scene.save_checkpoint(&device, &queue, &options.output_directory.join("final.checkpoint"))?;
+    info!(
+        "Finished simulating {:?} after {:?}",
+        simulation_controller.timer().total_simulated_time(),
+        statistics.start_time.elapsed()
+    );
+    Ok(())
+}
+
+// Largest tolerated distance (in grid cells) between a particle on the GPU and its counterpart in the CPU reference.
+// Floating point order and the pressure solvers (which stop at a target error) keep the two from ever matching exactly,
+// and the differences grow over time, so comparisons are meant for short runs of small scenes.
+const MAX_PARTICLE_POSITION_DIFFERENCE: f32 = 0.05;
+
+// Fixed step length for runs without SimulationController, adaptive time steps are not supported there.
+fn fixed_simulation_delta(options: &CommandLineOptions, config: &SceneConfig) -> Duration {
+    let steps_per_second = options
+        .steps_per_second
+        .or_else(|| config.settings.as_ref().map(|settings| settings.simulation_steps_per_second))
+        .unwrap_or(120);
+    Duration::from_secs_f64(1.0 / steps_per_second as f64)
+}
+
+// Number of steps of the given length to reach the next output, at least one.
+fn num_steps_until(simulation_delta: Duration, jump_length: Duration) -> u32 {
+    ((jump_length.as_secs_f64() / simulation_delta.as_secs_f64()).round() as u32).max(1)
+}
+
+fn step_fluid<'a, F: FluidBackend<'a>>(fluid: &mut F, context: F::StepContext, simulation_delta: Duration, num_steps: u32) {
+    for _ in 0..num_steps {
+        fluid.step(context, simulation_delta);
+    }
+}
+
+// Runs a scene with the CPU reference simulation (no GPU needed), writes the same statistics and particle files as the GPU headless mode.
+pub fn run_cpu(options: &CommandLineOptions) -> io::Result<()> {
+    let scene_path = options.scene.as_ref().expect("Headless mode requires a scene");
+    let duration = options.duration.expect("Headless mode requires a duration");
+
+    let config = SceneConfig::load(scene_path)?;
+    let mut fluid = Scene::create_cpu_fluid(&config)?;
+    let simulation_delta = fixed_simulation_delta(options, &config);
+
+    std::fs::create_dir_all(&options.output_directory)?;
+    let mut statistics = StatisticsWriter::new(&options.output_directory.join("statistics.csv"))?;
+    let mut num_outputs = 0;
+    let mut num_steps = 0;
+    let mut simulated_time = Duration::from_secs(0);
+
+    info!(
+        "Simulating {:?} of scene {:?} on the CPU with steps of {:?}, writing results to {:?}",
+        duration, scene_path, simulation_delta, options.output_directory
+    );
+
+    loop {
+        statistics.write_row(
+            simulated_time,
+            num_steps,
+            fluid.num_particles(),
+            fluid.pressure_solver_stats_velocity(),
+            fluid.pressure_solver_stats_density(),
+            simulation_delta,
+        )?;
+        if options.dump_particles {
+            write_particles_ply(
+                &options.output_directory.join(format!("particles_{:05}.ply", num_outputs)),
+                &config,
+                &fluid.read_particles(())?,
+            )?;
+        }
+        num_outputs += 1;
+
+        if simulated_time >= duration {
+            break;
+        }
+        let num_jump_steps = num_steps_until(simulation_delta, options.output_interval.min(duration - simulated_time));
+        step_fluid(&mut fluid, (), simulation_delta, num_jump_steps);
+        num_steps += num_jump_steps;
+        simulated_time += simulation_delta * num_jump_steps;
+        info!(
+            "{:.3}s simulated, max particle speed {} cells/s",
+            simulated_time.as_secs_f64(),
+            fluid.max_particle_speed_grid()
+        );
+    }
+
+    write_particles_ply(&options.output_directory.join("final.ply"), &config, &fluid.read_particles(())?)?;
+    info!("Finished simulating {:?} after {:?}", simulated_time, statistics.start_time.elapsed());
+    Ok(())
+}
+
+// Runs a scene on the GPU and with the CPU reference side by side and compares the particles every output interval.
+// Returns false if the particles drifted further apart than MAX_PARTICLE_POSITION_DIFFERENCE.
+pub fn compare_with_cpu(options: &CommandLineOptions) -> io::Result<bool> {
+    let scene_path = options.scene.as_ref().expect("Comparison requires a scene");
+    let duration = options.duration.expect("Comparison requires a duration");
+
+    let config = SceneConfig::load(scene_path)?;
+    let mut cpu_fluid = Scene::create_cpu_fluid(&config)?;
+    let simulation_delta = fixed_simulation_delta(options, &config);
+
+    let (device, queue) = futures::executor::block_on(request_headless_device(options))?;
+    let shader_dir = ShaderDirectory::new(Path::new("shader"));
+    let mut pipeline_manager = PipelineManager::new();
+    let per_frame_resources = PerFrameResources::new(&device);
+    let mut scene = Scene::new(
+        scene_path,
+        &device,
+        &queue,
+        &shader_dir,
+        &mut pipeline_manager,
+        per_frame_resources.bind_group_layout(),
+    )?;
+    // Scenes the CPU can run have neither obstacles nor rigid bodies, so stepping the fluid alone is the same as stepping the scene.
+    let gpu_context = GpuStepContext {
+        device: &device,
+        queue: &queue,
+        pipeline_manager: &pipeline_manager,
+        per_frame_bind_group: per_frame_resources.bind_group(),
+    };
+
+    std::fs::create_dir_all(&options.output_directory)?;
+    let mut writer = BufWriter::new(File::create(options.output_directory.join("comparison.csv"))?);
+    writeln!(
+        writer,
+        "simulated_time,num_steps,num_particles_gpu,num_particles_cpu,max_position_difference,rms_position_difference,max_velocity_difference"
+    )?;
+
+    info!(
+        "Comparing {:?} of scene {:?} on GPU and CPU with steps of {:?}, tolerating particle position differences of up to {} cells",
+        duration, scene_path, simulation_delta, MAX_PARTICLE_POSITION_DIFFERENCE
+    );
+
+    let mut num_steps = 0;
+    let mut simulated_time = Duration::from_secs(0);
+    let mut all_within_tolerance = true;
+    loop {
+        let comparison = ParticleComparison::new(&FluidBackend::read_particles(scene.fluid(), gpu_context)?, &cpu_fluid.read_particles(())?);
+        writeln!(
+            writer,
+            "{},{},{},{},{},{},{}",
+            simulated_time.as_secs_f64(),
+            num_steps,
+            comparison.num_particles[0],
+            comparison.num_particles[1],
+            comparison.max_position_difference,
+            comparison.rms_position_difference,
+            comparison.max_velocity_difference,
+        )?;
+        writer.flush()?;
+
+        let message = format!(
+            "{:.3}s: max position difference {} (rms {}), max velocity difference {}, {} gpu / {} cpu particles",
+            simulated_time.as_secs_f64(),
+            comparison.max_position_difference,
+            comparison.rms_position_difference,
+            comparison.max_velocity_difference,
+            comparison.num_particles[0],
+            comparison.num_particles[1],
+        );
+        if comparison.within(MAX_PARTICLE_POSITION_DIFFERENCE) {
+            info!("{}", message);
+        } else {
+            error!("{}", message);
+            all_within_tolerance = false;
+        }
+
+        if simulated_time >= duration {
+            break;
+        }
+        let num_jump_steps = num_steps_until(simulation_delta, options.output_interval.min(duration - simulated_time));
+        step_fluid(scene.fluid_mut(), gpu_context, simulation_delta, num_jump_steps);
+        step_fluid(&mut cpu_fluid, (), simulation_delta, num_jump_steps);
+        num_steps += num_jump_steps;
+        simulated_time += simulation_delta * num_jump_steps;
+    }
+
+    Ok(all_within_tolerance)
+}
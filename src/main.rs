@@ -8,19 +8,25 @@ extern crate strum_macros;
 mod wgpu_utils;
 
 mod camera;
+mod command_line;
+mod file_watcher;
 mod gui;
+mod headless;
+mod mesh;
 mod per_frame_resources;
 mod render_output;
 mod renderer;
 mod scene;
+mod scene_validation;
 mod simulation;
 mod simulation_controller;
 mod timer;
 
+use file_watcher::FileWatcher;
 use per_frame_resources::*;
 use render_output::{hdr_backbuffer::HdrBackbuffer, screen::Screen, screenshot_recorder::ScreenshotRecorder};
 use renderer::SceneRenderer;
-use simulation_controller::SimulationControllerStatus;
+use simulation_controller::{AdaptiveTimeStepConfig, SimulationControllerStatus};
 use std::{
     path::{Path, PathBuf},
     time::Duration,
@@ -36,12 +42,43 @@ use winit::{
 #[derive(Debug, Clone)]
 pub enum ApplicationEvent {
     LoadScene(PathBuf),
+    SaveScene(PathBuf),
     ResetScene,
+    SaveCheckpoint(PathBuf),
+    LoadCheckpoint(PathBuf),
+    ExportSolverConvergence(PathBuf),
     FastForwardSimulation(Duration),
     ResetAndStartRecording { recording_fps: f64 }, // to stop recording, pause the simulation controller.
     ChangePresentMode(wgpu::PresentMode),
 }
 
+fn watch_file(path: &Path) -> Option<FileWatcher> {
+    match FileWatcher::new(&[path]) {
+        Ok(watcher) => Some(watcher),
+        Err(error) => {
+            warn!("Failed to watch {:?} for changes: {:?}", path, error);
+            None
+        }
+    }
+}
+
+// Settings stored in the scene file that aren't part of the simulation.
+// (solver settings are applied by the scene itself)
+fn apply_scene_settings(
+    scene: &scene::Scene,
+    simulation_controller: &mut simulation_controller::SimulationController,
+    scene_renderer: &mut SceneRenderer,
+    camera: &mut camera::Camera,
+) {
+    if let Some(settings) = scene.config().settings.as_ref() {
+        simulation_controller.set_simulation_steps_per_second(settings.simulation_steps_per_second);
+        simulation_controller.time_scale = settings.time_scale;
+        simulation_controller.set_adaptive_time_step(settings.adaptive_time_step);
+        scene_renderer.set_rendering_config(&settings.rendering);
+        camera.set_config(&settings.camera);
+    }
+}
+
 struct Application {
     window: Window,
     window_surface: wgpu::Surface,
@@ -55,21 +92,25 @@ struct Application {
     shader_dir: shader::ShaderDirectory,
     pipeline_manager: pipelines::PipelineManager,
     scene: scene::Scene,
+    scene_watcher: Option<FileWatcher>,
+    background_watcher: Option<FileWatcher>,
     scene_renderer: SceneRenderer,
     simulation_controller: simulation_controller::SimulationController,
     gui: gui::GUI,
 
     camera: camera::Camera,
+    // Render time at which the last recording started, camera paths are played back relative to it.
+    camera_path_start_time: Duration,
     per_frame_resources: PerFrameResources,
 }
 
 impl Application {
-    async fn new(event_loop: &EventLoop<ApplicationEvent>) -> Application {
-        let wgpu_instance = wgpu::Instance::new(wgpu::BackendBit::PRIMARY); //wgpu::BackendBit::DX12);
+    async fn new(event_loop: &EventLoop<ApplicationEvent>, options: &command_line::CommandLineOptions) -> Application {
+        let wgpu_instance = wgpu::Instance::new(options.backend);
         let window = WindowBuilder::new()
             .with_title("Blub")
             .with_resizable(true)
-            .with_inner_size(winit::dpi::LogicalSize::new(1980, 1080))
+            .with_inner_size(options.resolution)
             .build(&event_loop)
             .unwrap();
 
@@ -82,20 +123,7 @@ impl Application {
             .await
             .unwrap();
 
-        let (device, mut command_queue) = adapter
-            .request_device(
-                &wgpu::DeviceDescriptor {
-                    features: wgpu::Features::PUSH_CONSTANTS,
-                    limits: wgpu::Limits {
-                        max_push_constant_size: 8,
-                        ..Default::default()
-                    },
-                    shader_validation: true,
-                },
-                None, //Some(Path::new("C:/dev/blub/trace")),
-            )
-            .await
-            .unwrap();
+        let (device, mut command_queue) = request_device(&adapter).await;
 
         let shader_dir = shader::ShaderDirectory::new(Path::new("shader"));
         let mut pipeline_manager = pipelines::PipelineManager::new();
@@ -103,7 +131,10 @@ impl Application {
         let screen = Screen::new(&device, &window_surface, Screen::DEFAULT_PRESENT_MODE, window.inner_size(), &shader_dir);
         let hdr_backbuffer = HdrBackbuffer::new(&device, screen.resolution(), &shader_dir);
         let per_frame_resources = PerFrameResources::new(&device);
-        let simulation_controller = simulation_controller::SimulationController::new();
+        let mut simulation_controller = simulation_controller::SimulationController::new();
+        if let Some(stop_time) = options.stop_time {
+            simulation_controller.simulation_stop_time = stop_time;
+        }
         let mut scene_renderer = SceneRenderer::new(
             &device,
             &command_queue,
@@ -112,26 +143,64 @@ impl Application {
             per_frame_resources.bind_group_layout(),
             &hdr_backbuffer,
         );
-        let gui = gui::GUI::new(&device, &window, &mut command_queue);
+        let gui = gui::GUI::new(
+            &device,
+            &window,
+            &mut command_queue,
+            &options.scene_directory,
+            options.scene.as_ref().map(|path| path.as_path()),
+        );
 
-        // Load initial scene. Gui already needs to list all scenes, so we go there to grab the default selected.
-        let scene = scene::Scene::new(
+        // Load initial scene. Gui already needs to list all scenes, so we go there to grab the selected one.
+        // Without a scene there is nothing to show, so a broken scene (e.g. passed with --scene) ends the application right away.
+        let scene = match scene::Scene::new(
             gui.selected_scene(),
             &device,
             &command_queue,
             &shader_dir,
             &mut pipeline_manager,
             per_frame_resources.bind_group_layout(),
-        )
-        .unwrap();
+        ) {
+            Ok(scene) => scene,
+            Err(error) => {
+                error!("Failed to load scene {:?}: {}", gui.selected_scene(), error);
+                std::process::exit(1);
+            }
+        };
         scene_renderer.on_new_scene(&command_queue, &scene);
+        let mut camera = camera::Camera::new();
+        let (domain_min, domain_max) = scene.config().fluid.domain_bounds();
+        camera.frame_bounds(domain_min, domain_max);
+        apply_scene_settings(&scene, &mut simulation_controller, &mut scene_renderer, &mut camera);
+        // Command line takes precedence over scene settings.
+        if let Some(steps_per_second) = options.steps_per_second {
+            simulation_controller.set_simulation_steps_per_second(steps_per_second);
+        }
+        if let Some(max_cfl_number) = options.max_cfl_number {
+            simulation_controller.set_adaptive_time_step(AdaptiveTimeStepConfig {
+                enabled: true,
+                max_cfl_number,
+                ..simulation_controller.adaptive_time_step()
+            });
+        }
+        let scene_watcher = watch_file(gui.selected_scene());
+        let background_watcher = watch_file(&SceneRenderer::background_config_path());
+
+        let mut screenshot_recorder = ScreenshotRecorder::new();
+        let camera_path_start_time = simulation_controller.timer().total_render_time();
+        if let Some(recording_fps) = options.recording_fps {
+            simulation_controller.start_recording_with_fixed_frame_length(recording_fps);
+            screenshot_recorder.start_next_recording();
+        } else if options.start_paused {
+            simulation_controller.pause_or_resume();
+        }
 
         Application {
             window,
             window_surface,
             screen,
             hdr_backbuffer,
-            screenshot_recorder: ScreenshotRecorder::new(),
+            screenshot_recorder,
 
             device,
             command_queue,
@@ -139,16 +208,46 @@ impl Application {
             shader_dir,
             pipeline_manager,
             scene,
+            scene_watcher,
+            background_watcher,
             scene_renderer,
             simulation_controller,
             gui,
 
-            camera: camera::Camera::new(),
+            camera,
+            camera_path_start_time,
             per_frame_resources,
         }
     }
 
-    pub fn load_scene(&mut self, scene_path: &Path) {
+    // Gathers all settings that are stored with a scene and writes the scene to a new file.
+    fn save_scene(&mut self, scene_path: &Path) {
+        let settings = scene::SettingsConfig {
+            pressure_solver_velocity: *self.scene.fluid_mut().pressure_solver_config_velocity(),
+            pressure_solver_density: *self.scene.fluid_mut().pressure_solver_config_density(),
+            simulation_steps_per_second: self.simulation_controller.simulation_steps_per_second(),
+            time_scale: self.simulation_controller.time_scale,
+            adaptive_time_step: self.simulation_controller.adaptive_time_step(),
+            viscosity_solver: Some(*self.scene.fluid_mut().viscosity_solver_config()),
+            rendering: self.scene_renderer.rendering_config(),
+            camera: self.camera.config(),
+        };
+        match self.scene.save(scene_path, settings) {
+            Ok(()) => {
+                info!("Saved scene to {:?}", scene_path);
+                // Overwriting the active scene shouldn't trigger a hot reload. Events for our own write are lost by restarting the watcher.
+                self.scene_watcher = watch_file(self.gui.selected_scene());
+                self.gui.on_scene_saved(scene_path);
+            }
+            Err(error) => error!("Failed to save scene to {:?}: {:?}", scene_path, error),
+        }
+    }
+
+    // Returns false if the scene couldn't be loaded, in which case the previous scene stays active.
+    pub fn load_scene(&mut self, scene_path: &Path) -> bool {
+        // Watch the new file even if loading fails, fixing it should trigger a reload.
+        self.scene_watcher = watch_file(scene_path);
+
         let new_scene = scene::Scene::new(
             scene_path,
             &self.device,
@@ -162,9 +261,17 @@ impl Application {
             Ok(scene) => {
                 self.scene = scene;
                 self.scene_renderer.on_new_scene(&self.command_queue, &self.scene);
+                let (domain_min, domain_max) = self.scene.config().fluid.domain_bounds();
+                self.camera.frame_bounds(domain_min, domain_max);
+                apply_scene_settings(&self.scene, &mut self.simulation_controller, &mut self.scene_renderer, &mut self.camera);
+                self.gui.set_scene_error(None);
+                true
             }
             Err(error) => {
-                error!("Failed to load scene from {:?}: {:?}", scene_path, error);
+                error!("Failed to load scene from {:?}: {}", scene_path, error);
+                self.gui
+                    .set_scene_error(Some(format!("Failed to load scene from {:?}\n{}", scene_path, error)));
+                false
             }
         }
     }
@@ -180,9 +287,11 @@ impl Application {
             match &event {
                 Event::UserEvent(event) => match event {
                     ApplicationEvent::LoadScene(scene_path) => {
-                        self.load_scene(scene_path);
-                        self.simulation_controller.restart();
+                        if self.load_scene(scene_path) {
+                            self.simulation_controller.restart();
+                        }
                     }
+                    ApplicationEvent::SaveScene(scene_path) => self.save_scene(scene_path),
                     ApplicationEvent::ResetScene => {
                         self.scene.reset(
                             &self.device,
@@ -193,6 +302,28 @@ impl Application {
                         );
                         self.simulation_controller.restart();
                     }
+                    ApplicationEvent::SaveCheckpoint(checkpoint_path) => {
+                        if let Err(error) = self.scene.save_checkpoint(&self.device, &self.command_queue, checkpoint_path) {
+                            error!("Failed to save checkpoint to {:?}: {:?}", checkpoint_path, error);
+                        }
+                    }
+                    ApplicationEvent::ExportSolverConvergence(path) => match self.scene.fluid().write_solver_convergence_csv(path) {
+                        Ok(()) => info!("Wrote solver convergence history to {:?}", path),
+                        Err(error) => error!("Failed to write solver convergence history to {:?}: {:?}", path, error),
+                    },
+                    ApplicationEvent::LoadCheckpoint(checkpoint_path) => {
+                        match self.scene.load_checkpoint(
+                            checkpoint_path,
+                            &self.device,
+                            &self.command_queue,
+                            &self.shader_dir,
+                            &mut self.pipeline_manager,
+                            self.per_frame_resources.bind_group_layout(),
+                        ) {
+                            Ok(()) => self.simulation_controller.restart(),
+                            Err(error) => error!("Failed to load checkpoint from {:?}: {:?}", checkpoint_path, error),
+                        }
+                    }
                     ApplicationEvent::FastForwardSimulation(simulation_jump_length) => {
                         self.simulation_controller.fast_forward_steps(
                             *simulation_jump_length,
@@ -214,6 +345,7 @@ impl Application {
                         self.simulation_controller.restart();
                         self.simulation_controller.start_recording_with_fixed_frame_length(*recording_fps);
                         self.screenshot_recorder.start_next_recording();
+                        self.camera_path_start_time = self.simulation_controller.timer().total_render_time();
                     }
                     ApplicationEvent::ChangePresentMode(present_mode) => {
                         self.screen = Screen::new(
@@ -286,15 +418,29 @@ impl Application {
             info!("reloading shaders...");
             self.pipeline_manager.reload_all(&self.device, &self.shader_dir);
         }
+        // Always query the watchers so that changes made while hot reloading was disabled don't trigger a reload later on.
+        let scene_changed = self.scene_watcher.as_ref().map_or(false, FileWatcher::detected_change);
+        let background_changed = self.background_watcher.as_ref().map_or(false, FileWatcher::detected_change);
+        if self.gui.hot_reload_scene() {
+            if scene_changed {
+                info!("reloading scene...");
+                // On failure (e.g. file saved with syntax errors) we keep the current scene running.
+                // Keep looking at whatever we were looking at while editing.
+                let camera = self.camera.clone();
+                if self.load_scene(&self.gui.selected_scene().clone()) {
+                    self.camera = camera;
+                    self.simulation_controller.restart();
+                }
+            }
+            if background_changed {
+                info!("reloading background config...");
+                self.scene_renderer.reload_background_config(&self.command_queue);
+            }
+        }
         self.camera.update(self.simulation_controller.timer());
 
-        self.per_frame_resources.update_gpu_data(
-            &self.command_queue,
-            self.camera.fill_global_uniform_buffer(self.screen.aspect_ratio()),
-            self.simulation_controller.timer().fill_global_uniform_buffer(),
-            self.scene_renderer.fill_global_uniform_buffer(&self.scene),
-            self.screen.fill_global_uniform_buffer(),
-        );
+        // Timings as of the start of the frame, simulation steps don't read the per frame data, so it's uploaded only afterwards.
+        let frame_time = self.simulation_controller.timer().fill_global_uniform_buffer();
         self.simulation_controller.frame_steps(
             &mut self.scene,
             &self.device,
@@ -303,6 +449,31 @@ impl Application {
             self.per_frame_resources.bind_group(),
         );
 
+        // Recordings force their fixed frame length only when stepping, sampling the camera path before would add the real frame duration.
+        let recording = matches!(
+            self.simulation_controller.status(),
+            SimulationControllerStatus::RecordingWithFixedFrameLength(_)
+        );
+        if self.gui.follow_camera_path(recording) {
+            let path_time = self
+                .simulation_controller
+                .timer()
+                .total_render_time()
+                .checked_sub(self.camera_path_start_time)
+                .unwrap_or_default();
+            if let Some(camera) = self.scene.config().camera_path_at(path_time.as_secs_f32()) {
+                self.camera.set_config(&camera);
+            }
+        }
+
+        self.per_frame_resources.update_gpu_data(
+            &self.command_queue,
+            self.camera.fill_global_uniform_buffer(self.screen.aspect_ratio()),
+            frame_time,
+            self.scene_renderer.fill_global_uniform_buffer(&self.scene),
+            self.screen.fill_global_uniform_buffer(),
+        );
+
         if self.simulation_controller.status() == SimulationControllerStatus::Paused {
             self.screenshot_recorder.stop_recording();
         }
@@ -351,6 +522,7 @@ impl Application {
             &mut self.simulation_controller,
             &mut self.scene_renderer,
             &mut self.scene,
+            &mut self.camera,
             event_loop_proxy,
         );
 
@@ -361,10 +533,72 @@ impl Application {
     }
 }
 
+pub async fn request_device(adapter: &wgpu::Adapter) -> (wgpu::Device, wgpu::Queue) {
+    adapter
+        .request_device(
+            &wgpu::DeviceDescriptor {
+                features: wgpu::Features::PUSH_CONSTANTS,
+                limits: wgpu::Limits {
+                    max_push_constant_size: 8,
+                    ..Default::default()
+                },
+                shader_validation: true,
+            },
+            None, //Some(Path::new("C:/dev/blub/trace")),
+        )
+        .await
+        .unwrap()
+}
+
 fn main() {
     // Silence warnings from `naga::front::spirv` for now since as of writing it doesn't know enough spirv yet.
     env_logger::init_from_env(env_logger::Env::default().filter_or(env_logger::DEFAULT_FILTER_ENV, "warn,blub=info"));
+
+    let options = match command_line::CommandLineOptions::parse(std::env::args().skip(1)) {
+        Ok(options) => options,
+        Err(message) => {
+            eprintln!("{}\n\n{}", message, command_line::USAGE);
+            std::process::exit(1);
+        }
+    };
+    if options.help {
+        println!("{}", command_line::USAGE);
+        return;
+    }
+    if options.validate_solver {
+        match headless::validate_solver(&options) {
+            Ok(true) => return,
+            Ok(false) => std::process::exit(1),
+            Err(error) => {
+                error!("Solver validation failed: {:?}", error);
+                std::process::exit(1);
+            }
+        }
+    }
+    if options.compare_cpu {
+        match headless::compare_with_cpu(&options) {
+            Ok(true) => return,
+            Ok(false) => std::process::exit(1),
+            Err(error) => {
+                error!("CPU comparison failed: {:?}", error);
+                std::process::exit(1);
+            }
+        }
+    }
+    if options.headless {
+        let result = if options.cpu {
+            headless::run_cpu(&options)
+        } else {
+            headless::run(&options)
+        };
+        if let Err(error) = result {
+            error!("Headless run failed: {:?}", error);
+            std::process::exit(1);
+        }
+        return;
+    }
+
     let event_loop = EventLoop::<ApplicationEvent>::with_user_event();
-    let application = futures::executor::block_on(Application::new(&event_loop));
+    let application = futures::executor::block_on(Application::new(&event_loop, &options));
     application.run(event_loop);
 }
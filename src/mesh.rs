@@ -0,0 +1,425 @@
+use cgmath::{EuclideanSpace, InnerSpace};
+use std::{
+    ffi::OsStr,
+    fs::File,
+    io::{self, BufRead, BufReader, Read},
+    path::Path,
+};
+
+// Triangle soup as loaded from an OBJ or STL file. Only geometry is loaded, everything else is ignored.
+pub struct TriangleMesh {
+    pub vertices: Vec<cgmath::Point3<f32>>,
+    pub triangles: Vec<[u32; 3]>,
+}
+
+fn invalid_data(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+fn parse_vector(mut tokens: std::str::SplitWhitespace, line: &str) -> Result<cgmath::Point3<f32>, io::Error> {
+    let mut parse_next = || -> Result<f32, io::Error> {
+        tokens
+            .next()
+            .and_then(|token| token.parse().ok())
+            .ok_or_else(|| invalid_data(format!("Expected three numbers in line \"{}\"", line)))
+    };
+    Ok(cgmath::point3(parse_next()?, parse_next()?, parse_next()?))
+}
+
+impl TriangleMesh {
+    pub fn load(path: &Path) -> Result<Self, io::Error> {
+        let extension = path.extension().and_then(OsStr::to_str).map(str::to_lowercase);
+        let mesh = match extension.as_deref() {
+            Some("obj") => Self::load_obj(BufReader::new(File::open(path)?))?,
+            Some("stl") => {
+                let mut content = Vec::new();
+                File::open(path)?.read_to_end(&mut content)?;
+                Self::load_stl(&content)?
+            }
+            _ => return Err(invalid_data(format!("Unsupported mesh format {:?}, expected .obj or .stl", path))),
+        };
+        mesh.check(path)
+    }
+
+    // Rejects meshes the signed distance computation can't handle.
+    fn check(self, path: &Path) -> Result<Self, io::Error> {
+        if self.triangles.is_empty() {
+            return Err(invalid_data(format!("Mesh {:?} does not contain any triangles", path)));
+        }
+        if let Some(vertex) = self.vertices.iter().find(|v| !(v.x.is_finite() && v.y.is_finite() && v.z.is_finite())) {
+            return Err(invalid_data(format!(
+                "Mesh {:?} contains a vertex that is not finite: {:?}",
+                path, vertex
+            )));
+        }
+        info!("Loaded mesh {:?} with {} triangles", path, self.triangles.len());
+        Ok(self)
+    }
+
+    // Reads vertices & faces, polygons are triangulated as fans.
+    fn load_obj(reader: impl BufRead) -> Result<Self, io::Error> {
+        let mut mesh = TriangleMesh {
+            vertices: Vec::new(),
+            triangles: Vec::new(),
+        };
+
+        for line in reader.lines() {
+            let line = line?;
+            let mut tokens = line.split_whitespace();
+            match tokens.next() {
+                Some("v") => mesh.vertices.push(parse_vector(tokens, &line)?),
+                Some("f") => {
+                    let mut indices = Vec::new();
+                    for token in tokens {
+                        // Face elements are of the form v, v/vt, v//vn or v/vt/vn, we only care about v. Negative indices are relative.
+                        let index: i64 = token
+                            .split('/')
+                            .next()
+                            .and_then(|index| index.parse().ok())
+                            .ok_or_else(|| invalid_data(format!("Invalid face element in line \"{}\"", line)))?;
+                        let index = if index < 0 { mesh.vertices.len() as i64 + index } else { index - 1 };
+                        if index < 0 || index >= mesh.vertices.len() as i64 {
+                            return Err(invalid_data(format!("Face references unknown vertex in line \"{}\"", line)));
+                        }
+                        indices.push(index as u32);
+                    }
+                    for i in 2..indices.len() {
+                        mesh.triangles.push([indices[0], indices[i - 1], indices[i]]);
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        Ok(mesh)
+    }
+
+    fn load_stl(content: &[u8]) -> Result<Self, io::Error> {
+        // ASCII files are supposed to start with "solid", but so do many binary files. Size of a binary file is fully determined by its header.
+        const BINARY_HEADER_SIZE: usize = 84;
+        const BINARY_TRIANGLE_SIZE: usize = 50;
+        if content.len() >= BINARY_HEADER_SIZE {
+            let num_triangles = u32::from_le_bytes([content[80], content[81], content[82], content[83]]) as usize;
+            if content.len() == BINARY_HEADER_SIZE + num_triangles * BINARY_TRIANGLE_SIZE {
+                let read_f32 = |offset: usize| f32::from_le_bytes([content[offset], content[offset + 1], content[offset + 2], content[offset + 3]]);
+                let mut mesh = TriangleMesh {
+                    vertices: Vec::with_capacity(num_triangles * 3),
+                    triangles: Vec::with_capacity(num_triangles),
+                };
+                for triangle in 0..num_triangles {
+                    // Each triangle is a normal, three vertices and a two byte attribute.
+                    let triangle_offset = BINARY_HEADER_SIZE + triangle * BINARY_TRIANGLE_SIZE + 12;
+                    for vertex in 0..3 {
+                        let offset = triangle_offset + vertex * 12;
+                        mesh.vertices
+                            .push(cgmath::point3(read_f32(offset), read_f32(offset + 4), read_f32(offset + 8)));
+                    }
+                    let first = (triangle * 3) as u32;
+                    mesh.triangles.push([first, first + 1, first + 2]);
+                }
+                return Ok(mesh);
+            }
+        }
+
+        let mut mesh = TriangleMesh {
+            vertices: Vec::new(),
+            triangles: Vec::new(),
+        };
+        for line in content.lines() {
+            let line = line?;
+            let mut tokens = line.split_whitespace();
+            if tokens.next() == Some("vertex") {
+                mesh.vertices.push(parse_vector(tokens, &line)?);
+                if mesh.vertices.len() % 3 == 0 {
+                    let first = mesh.vertices.len() as u32 - 3;
+                    mesh.triangles.push([first, first + 1, first + 2]);
+                }
+            }
+        }
+        Ok(mesh)
+    }
+
+    pub fn transform(&mut self, transform: impl Fn(cgmath::Point3<f32>) -> cgmath::Point3<f32>) {
+        for vertex in self.vertices.iter_mut() {
+            *vertex = transform(*vertex);
+        }
+    }
+
+    fn triangle(&self, index: usize) -> [cgmath::Point3<f32>; 3] {
+        let indices = self.triangles[index];
+        [
+            self.vertices[indices[0] as usize],
+            self.vertices[indices[1] as usize],
+            self.vertices[indices[2] as usize],
+        ]
+    }
+
+    // Computes the signed distance (negative inside) at every cell center of a grid, mesh is expected to be in grid space.
+    // Distances are exact within `band` cells of the surface and clamped to +/-band elsewhere.
+    // Inside/outside is determined by counting ray crossings along the x axis, so the mesh should be closed.
+    pub fn signed_distance_field(&self, dimension: cgmath::Point3<u32>, band: f32) -> Vec<f32> {
+        let cell_index = |x: u32, y: u32, z: u32| (x + (y + z * dimension.y) * dimension.x) as usize;
+        let mut distances = vec![band; (dimension.x * dimension.y * dimension.z) as usize];
+        let mut crossings = vec![Vec::new(); (dimension.y * dimension.z) as usize];
+
+        let cell_range = |min: f32, max: f32, size: u32| -> std::ops::Range<u32> {
+            let start = (min.floor().max(0.0) as u32).min(size);
+            let end = ((max.ceil() + 1.0).max(0.0) as u32).min(size);
+            start..end
+        };
+
+        for triangle_index in 0..self.triangles.len() {
+            let [a, b, c] = self.triangle(triangle_index);
+            let min = cgmath::point3(a.x.min(b.x).min(c.x), a.y.min(b.y).min(c.y), a.z.min(b.z).min(c.z));
+            let max = cgmath::point3(a.x.max(b.x).max(c.x), a.y.max(b.y).max(c.y), a.z.max(b.z).max(c.z));
+
+            // Unsigned distance within the band around the triangle.
+            for z in cell_range(min.z - band - 0.5, max.z + band - 0.5, dimension.z) {
+                for y in cell_range(min.y - band - 0.5, max.y + band - 0.5, dimension.y) {
+                    for x in cell_range(min.x - band - 0.5, max.x + band - 0.5, dimension.x) {
+                        let cell_center = cgmath::point3(x as f32 + 0.5, y as f32 + 0.5, z as f32 + 0.5);
+                        let distance = (cell_center - closest_point_on_triangle(cell_center, a, b, c)).magnitude();
+                        let index = cell_index(x, y, z);
+                        distances[index] = distances[index].min(distance);
+                    }
+                }
+            }
+
+            // Crossings of rays along x through the cell centers of every row.
+            for z in cell_range(min.z - 0.5, max.z - 0.5, dimension.z) {
+                for y in cell_range(min.y - 0.5, max.y - 0.5, dimension.y) {
+                    if let Some(x) = intersect_row(y as f32 + 0.5, z as f32 + 0.5, a, b, c) {
+                        crossings[(y + z * dimension.y) as usize].push(x);
+                    }
+                }
+            }
+        }
+
+        for z in 0..dimension.z {
+            for y in 0..dimension.y {
+                let row = &mut crossings[(y + z * dimension.y) as usize];
+                // Degenerate triangles (e.g. after transforming with a huge scale) can produce crossings that are not a number.
+                row.retain(|x| x.is_finite());
+                row.sort_by(|a, b| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal));
+                let mut num_crossings = 0;
+                for x in 0..dimension.x {
+                    let cell_center_x = x as f32 + 0.5;
+                    while num_crossings < row.len() && row[num_crossings] < cell_center_x {
+                        num_crossings += 1;
+                    }
+                    if num_crossings % 2 == 1 {
+                        let index = cell_index(x, y, z);
+                        distances[index] = -distances[index];
+                    }
+                }
+            }
+        }
+
+        distances
+    }
+}
+
+// Closest point on a triangle, see Real-Time Collision Detection (Christer Ericson), chapter 5.1.5
+fn closest_point_on_triangle(p: cgmath::Point3<f32>, a: cgmath::Point3<f32>, b: cgmath::Point3<f32>, c: cgmath::Point3<f32>) -> cgmath::Point3<f32> {
+    let ab = b - a;
+    let ac = c - a;
+    let ap = p - a;
+    let d1 = ab.dot(ap);
+    let d2 = ac.dot(ap);
+    if d1 <= 0.0 && d2 <= 0.0 {
+        return a;
+    }
+
+    let bp = p - b;
+    let d3 = ab.dot(bp);
+    let d4 = ac.dot(bp);
+    if d3 >= 0.0 && d4 <= d3 {
+        return b;
+    }
+
+    let vc = d1 * d4 - d3 * d2;
+    if vc <= 0.0 && d1 >= 0.0 && d3 <= 0.0 {
+        return a + ab * (d1 / (d1 - d3));
+    }
+
+    let cp = p - c;
+    let d5 = ab.dot(cp);
+    let d6 = ac.dot(cp);
+    if d6 >= 0.0 && d5 <= d6 {
+        return c;
+    }
+
+    let vb = d5 * d2 - d1 * d6;
+    if vb <= 0.0 && d2 >= 0.0 && d6 <= 0.0 {
+        return a + ac * (d2 / (d2 - d6));
+    }
+
+    let va = d3 * d6 - d5 * d4;
+    if va <= 0.0 && (d4 - d3) >= 0.0 && (d5 - d6) >= 0.0 {
+        return b + (c - b) * ((d4 - d3) / ((d4 - d3) + (d5 - d6)));
+    }
+
+    let denom = 1.0 / (va + vb + vc);
+    a + ab * (vb * denom) + ac * (vc * denom)
+}
+
+// Intersects a ray along x at (y, z) with a triangle and returns the x coordinate of the hit.
+// Edges use a top-left style tie breaking rule so that rays hitting an edge shared by two triangles are counted exactly once.
+fn intersect_row(y: f32, z: f32, a: cgmath::Point3<f32>, b: cgmath::Point3<f32>, c: cgmath::Point3<f32>) -> Option<f32> {
+    let orientation = (b.y - a.y) * (c.z - a.z) - (b.z - a.z) * (c.y - a.y);
+    if orientation == 0.0 {
+        return None; // Triangle is parallel to the ray.
+    }
+    let (b, c) = if orientation > 0.0 { (b, c) } else { (c, b) };
+
+    let edge_function = |p0: cgmath::Point3<f32>, p1: cgmath::Point3<f32>| -> Option<f32> {
+        let (dy, dz) = (p1.y - p0.y, p1.z - p0.z);
+        let value = dy * (z - p0.z) - dz * (y - p0.y);
+        if value > 0.0 || (value == 0.0 && (dz < 0.0 || (dz == 0.0 && dy > 0.0))) {
+            Some(value)
+        } else {
+            None
+        }
+    };
+    let weight_a = edge_function(b, c)?;
+    let weight_b = edge_function(c, a)?;
+    let weight_c = edge_function(a, b)?;
+    let total = weight_a + weight_b + weight_c;
+    Some((a.to_vec() * weight_a + b.to_vec() * weight_b + c.to_vec() * weight_c).x / total)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Quads of all kinds of face element notations, mixed with lines that are to be ignored.
+    const UNIT_CUBE_OBJ: &str = "\
+# unit cube
+v 0 0 0
+v 1 0 0
+v 1 1 0
+v 0 1 0
+v 0 0 1
+v 1 0 1
+v 1 1 1
+v 0 1 1
+vt 0 0
+vn 0 0 1
+f 1 4 3 2
+f 5/1 6/1 7/1 8/1
+f 1//1 2//1 6//1 5//1
+f 4/1/1 8/1/1 7/1/1 3/1/1
+f -8 -4 -1 -5
+f 2 3 7 6
+";
+
+    fn unit_cube() -> TriangleMesh {
+        TriangleMesh::load_obj(UNIT_CUBE_OBJ.as_bytes()).unwrap()
+    }
+
+    fn triangle_vertices(mesh: &TriangleMesh) -> Vec<[cgmath::Point3<f32>; 3]> {
+        (0..mesh.triangles.len()).map(|i| mesh.triangle(i)).collect()
+    }
+
+    fn to_ascii_stl(mesh: &TriangleMesh) -> String {
+        let mut stl = String::from("solid cube\n");
+        for [a, b, c] in triangle_vertices(mesh) {
+            stl += "  facet normal 0 0 0\n    outer loop\n";
+            for vertex in [a, b, c].iter() {
+                stl += &format!("      vertex {} {} {}\n", vertex.x, vertex.y, vertex.z);
+            }
+            stl += "    endloop\n  endfacet\n";
+        }
+        stl + "endsolid cube\n"
+    }
+
+    fn to_binary_stl(mesh: &TriangleMesh) -> Vec<u8> {
+        // Binary files starting with "solid" are common, this must not throw off the format detection.
+        let mut stl = b"solid but actually binary".to_vec();
+        stl.resize(80, 0);
+        stl.extend_from_slice(&(mesh.triangles.len() as u32).to_le_bytes());
+        for triangle in triangle_vertices(mesh) {
+            stl.extend_from_slice(&[0; 12]);
+            for vertex in triangle.iter() {
+                for value in [vertex.x, vertex.y, vertex.z].iter() {
+                    stl.extend_from_slice(&value.to_le_bytes());
+                }
+            }
+            stl.extend_from_slice(&[0; 2]);
+        }
+        stl
+    }
+
+    #[test]
+    fn obj_unit_cube() {
+        let mesh = unit_cube();
+        assert_eq!(mesh.vertices.len(), 8);
+        assert_eq!(mesh.triangles.len(), 12);
+        assert_eq!(mesh.triangles[8], [0, 4, 7]);
+        assert_eq!(mesh.triangles[9], [0, 7, 3]);
+    }
+
+    #[test]
+    fn obj_invalid_faces() {
+        assert!(TriangleMesh::load_obj("v 0 0 0\nf 1 2 1\n".as_bytes()).is_err());
+        assert!(TriangleMesh::load_obj("v 0 0 0\nf 1 a 1\n".as_bytes()).is_err());
+        assert!(TriangleMesh::load_obj("v 0 0\n".as_bytes()).is_err());
+    }
+
+    #[test]
+    fn stl_unit_cube() {
+        let cube = triangle_vertices(&unit_cube());
+        let ascii = TriangleMesh::load_stl(to_ascii_stl(&unit_cube()).as_bytes()).unwrap();
+        assert_eq!(triangle_vertices(&ascii), cube);
+        let binary = TriangleMesh::load_stl(&to_binary_stl(&unit_cube())).unwrap();
+        assert_eq!(triangle_vertices(&binary), cube);
+    }
+
+    #[test]
+    fn rejects_non_finite_vertices() {
+        let path = Path::new("test.obj");
+        assert!(unit_cube().check(path).is_ok());
+        for value in ["nan", "inf", "-inf"].iter() {
+            let obj = UNIT_CUBE_OBJ.replacen("v 1 1 1", &format!("v 1 {} 1", value), 1);
+            assert!(TriangleMesh::load_obj(obj.as_bytes()).unwrap().check(path).is_err());
+        }
+        let empty = TriangleMesh::load_obj("v 0 0 0\n".as_bytes()).unwrap();
+        assert!(empty.check(path).is_err());
+    }
+
+    #[test]
+    fn signed_distance_field_of_cube() {
+        // Cube from 2 to 6 in a 8³ grid. Rays through cell centers pass right through the diagonals of the faces.
+        let mut mesh = unit_cube();
+        mesh.transform(|vertex| cgmath::point3(2.0, 2.0, 2.0) + vertex.to_vec() * 4.0);
+        const BAND: f32 = 3.0;
+        let dimension = cgmath::point3(8, 8, 8);
+        let distances = mesh.signed_distance_field(dimension, BAND);
+
+        let distance_at = |x: u32, y: u32, z: u32| distances[(x + (y + z * dimension.y) * dimension.x) as usize];
+        let assert_distance = |x: u32, y: u32, z: u32, expected: f32| {
+            let distance = distance_at(x, y, z);
+            assert!(
+                (distance - expected).abs() < 1.0e-5,
+                "distance {} at ({}, {}, {}), expected {}",
+                distance,
+                x,
+                y,
+                z,
+                expected
+            );
+        };
+        for z in 0..dimension.z {
+            for y in 0..dimension.y {
+                for x in 0..dimension.x {
+                    let inside = [x, y, z].iter().all(|c| *c >= 2 && *c < 6);
+                    assert_eq!(distance_at(x, y, z) < 0.0, inside, "wrong sign at ({}, {}, {})", x, y, z);
+                }
+            }
+        }
+        assert_distance(4, 4, 4, -1.5);
+        assert_distance(2, 4, 4, -0.5);
+        assert_distance(6, 4, 4, 0.5);
+        assert_distance(0, 4, 4, 1.5);
+    }
+}
@@ -85,6 +85,13 @@ impl PerFrameResources {
         );
     }
 
+    // For running the simulation without any rendering, everything but the time is left zeroed.
+    pub fn update_gpu_data_time_only(&mut self, queue: &wgpu::Queue, time: timer::FrameTimeUniformBufferContent) {
+        let mut content: PerFrameUniformBufferContent = bytemuck::Zeroable::zeroed();
+        content.time = time;
+        self.ubo.update_content(queue, content);
+    }
+
     pub fn bind_group(&self) -> &wgpu::BindGroup {
         &self.bind_group
     }
@@ -28,6 +28,7 @@ type LightingAndBackgroundUniformBuffer = UniformBuffer<LightingAndBackgroundUni
 
 pub struct Background {
     pipeline: RenderPipelineHandle,
+    ubo: LightingAndBackgroundUniformBuffer,
     bind_group_layout: wgpu::BindGroupLayout,
     bind_group: wgpu::BindGroup,
 }
@@ -102,7 +103,19 @@ fn load_cubemap(path: &Path, device: &wgpu::Device, queue: &wgpu::Queue) -> Resu
     }))
 }
 
+fn load_config(path: &Path) -> Result<LightingAndBackgroundUniformBufferContent, io::Error> {
+    let file = File::open(path.join(Background::CONFIG_FILENAME))?;
+    let reader = BufReader::new(file);
+    let config: BackgroundConfig = serde_json::from_reader(reader)?;
+    Ok(LightingAndBackgroundUniformBufferContent {
+        dir_light_direction: config.dir_light_direction.into(),
+        dir_light_radiance: config.dir_light_radiance.into(),
+    })
+}
+
 impl Background {
+    pub const CONFIG_FILENAME: &'static str = "config.json";
+
     pub fn new(
         path: &Path,
         device: &wgpu::Device,
@@ -111,17 +124,7 @@ impl Background {
         pipeline_manager: &mut PipelineManager,
         per_frame_bind_group_layout: &wgpu::BindGroupLayout,
     ) -> Result<Self, io::Error> {
-        let file = File::open(path.join("config.json"))?;
-        let reader = BufReader::new(file);
-        let config: BackgroundConfig = serde_json::from_reader(reader)?;
-
-        let ubo = LightingAndBackgroundUniformBuffer::new_with_data(
-            &device,
-            &LightingAndBackgroundUniformBufferContent {
-                dir_light_direction: config.dir_light_direction.into(),
-                dir_light_radiance: config.dir_light_radiance.into(),
-            },
-        );
+        let ubo = LightingAndBackgroundUniformBuffer::new_with_data(&device, &load_config(path)?);
 
         let cubemap_view = load_cubemap(path, device, queue)?;
 
@@ -156,11 +159,18 @@ impl Background {
 
         Ok(Background {
             pipeline: pipeline_manager.create_render_pipeline(device, shader_dir, render_pipeline_desc),
+            ubo,
             bind_group_layout: bind_group_layout.layout,
             bind_group,
         })
     }
 
+    // Re-reads the lighting config. The cubemap stays as is.
+    pub fn reload_config(&mut self, path: &Path, queue: &wgpu::Queue) -> Result<(), io::Error> {
+        self.ubo.update_content(queue, load_config(path)?);
+        Ok(())
+    }
+
     pub fn draw<'a>(&'a self, rpass: &mut wgpu::RenderPass<'a>, pipeline_manager: &'a PipelineManager) {
         wgpu_scope!(rpass, "CubemapRenderer.draw");
         rpass.set_bind_group(1, &self.bind_group, &[]);
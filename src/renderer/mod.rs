@@ -7,5 +7,6 @@ mod volume_renderer;
 
 pub use scene_renderer::FluidRenderingMode;
 pub use scene_renderer::GlobalRenderSettingsUniformBufferContent;
+pub use scene_renderer::RenderingConfig;
 pub use scene_renderer::SceneRenderer;
 pub use volume_renderer::VolumeVisualizationMode;
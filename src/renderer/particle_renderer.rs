@@ -41,6 +41,6 @@ impl ParticleRenderer {
         wgpu_scope!(rpass, "ParticleRenderer.draw");
         rpass.set_pipeline(pipeline_manager.get_render(&self.render_pipeline));
         rpass.set_bind_group(1, fluid.bind_group_renderer(), &[]);
-        rpass.draw(0..4, 0..fluid.num_particles());
+        fluid.draw_particles(rpass);
     }
 }
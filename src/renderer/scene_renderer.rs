@@ -1,4 +1,4 @@
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
 use super::background::Background;
 use super::particle_renderer::ParticleRenderer;
@@ -11,14 +11,25 @@ use crate::{
     simulation::HybridFluid,
     wgpu_utils::{pipelines::PipelineManager, shader::ShaderDirectory},
 };
-use cgmath::EuclideanSpace;
-#[derive(Clone, Copy, Debug, EnumIter)]
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Copy, Debug, EnumIter, Serialize, Deserialize)]
 pub enum FluidRenderingMode {
     None,
     ScreenSpaceFluid,
     Particles,
 }
 
+// Render settings as stored in scene files.
+#[derive(Clone, Copy, Serialize, Deserialize)]
+pub struct RenderingConfig {
+    pub fluid_rendering_mode: FluidRenderingMode,
+    pub volume_visualization: VolumeVisualizationMode,
+    pub particle_radius_factor: f32,
+    pub enable_box_lines: bool,
+    pub velocity_visualization_scale: f32,
+}
+
 #[repr(C)]
 #[derive(Clone, Copy)]
 pub struct GlobalRenderSettingsUniformBufferContent {
@@ -46,6 +57,8 @@ pub struct SceneRenderer {
 }
 
 impl SceneRenderer {
+    pub const BACKGROUND_DIRECTORY: &'static str = "background";
+
     pub fn new(
         device: &wgpu::Device,
         queue: &wgpu::Queue,
@@ -57,7 +70,7 @@ impl SceneRenderer {
         let fluid_renderer_group_layout = &HybridFluid::get_or_create_group_layout_renderer(device).layout;
 
         let background = Background::new(
-            Path::new("background"),
+            Path::new(Self::BACKGROUND_DIRECTORY),
             device,
             queue,
             shader_dir,
@@ -101,12 +114,38 @@ impl SceneRenderer {
         }
     }
 
+    pub fn background_config_path() -> PathBuf {
+        Path::new(Self::BACKGROUND_DIRECTORY).join(Background::CONFIG_FILENAME)
+    }
+
+    pub fn reload_background_config(&mut self, queue: &wgpu::Queue) {
+        if let Err(error) = self.background.reload_config(Path::new(Self::BACKGROUND_DIRECTORY), queue) {
+            error!("Failed to reload background config: {:?}", error);
+        }
+    }
+
+    pub fn rendering_config(&self) -> RenderingConfig {
+        RenderingConfig {
+            fluid_rendering_mode: self.fluid_rendering_mode,
+            volume_visualization: self.volume_visualization,
+            particle_radius_factor: self.particle_radius_factor,
+            enable_box_lines: self.enable_box_lines,
+            velocity_visualization_scale: self.velocity_visualization_scale,
+        }
+    }
+
+    pub fn set_rendering_config(&mut self, config: &RenderingConfig) {
+        self.fluid_rendering_mode = config.fluid_rendering_mode;
+        self.volume_visualization = config.volume_visualization;
+        self.particle_radius_factor = config.particle_radius_factor;
+        self.enable_box_lines = config.enable_box_lines;
+        self.velocity_visualization_scale = config.velocity_visualization_scale;
+    }
+
     // Needs to be called whenever immutable scene properties change.
     pub fn on_new_scene(&mut self, queue: &wgpu::Queue, scene: &Scene) {
         let line_color = cgmath::vec3(0.0, 0.0, 0.0);
-        let grid_extent = scene.config().fluid.grid_dimension;
-        let min = scene.config().fluid.world_position;
-        let max = min + grid_extent.cast().unwrap().to_vec() * scene.config().fluid.grid_to_world_scale;
+        let (min, max) = scene.config().fluid.domain_bounds();
 
         self.bounds_line_renderer.clear_lines();
         self.bounds_line_renderer.add_lines(
@@ -365,7 +365,7 @@ impl ScreenSpaceFluid {
             rpass.set_bind_group(0, &per_frame_bind_group, &[]);
             rpass.set_bind_group(1, fluid.bind_group_renderer(), &[]);
             rpass.set_pipeline(pipeline_manager.get_render(&self.screen_independent.pipeline_render_particles));
-            rpass.draw(0..4, 0..fluid.num_particles());
+            fluid.draw_particles(&mut rpass);
         });
 
         wgpu_scope!(encoder, "clear intermediate blur targets", || {
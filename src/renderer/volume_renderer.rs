@@ -4,9 +4,10 @@ use crate::{
     simulation::HybridFluid,
     wgpu_utils::pipelines::*,
 };
+use serde::{Deserialize, Serialize};
 use std::{path::Path, rc::Rc};
 
-#[derive(Clone, Copy, Debug, EnumIter)]
+#[derive(Clone, Copy, Debug, EnumIter, Serialize, Deserialize)]
 pub enum VolumeVisualizationMode {
     None,
     Velocity,
@@ -1,44 +1,653 @@
 use crate::{
-    simulation::HybridFluid,
+    camera::{CameraConfig, CameraPresetConfig},
+    mesh::TriangleMesh,
+    renderer::RenderingConfig,
+    scene_validation::{self, SceneLoadError, ValidationProblem},
+    simulation::{
+        CpuFluid, DynamicSolid, DynamicSolidShape, EmitterShape, FluidBackend, HybridFluid, ParticleDrain, ParticleEmitter, ParticleReseeding,
+        RigidBody, RigidBodyShape, SolverConfig, TransferScheme,
+    },
+    simulation_controller::AdaptiveTimeStepConfig,
     wgpu_utils::{pipelines::PipelineManager, shader::ShaderDirectory},
 };
 
-use serde::Deserialize;
+use cgmath::EuclideanSpace;
+use serde::{Deserialize, Serialize};
 use std::{
-    fs::File,
-    io::{self, BufReader},
-    path::Path,
+    io,
+    path::{Path, PathBuf},
     time::Duration,
 };
 
-#[derive(Deserialize)]
+#[derive(Clone, Serialize, Deserialize)]
 pub struct Box {
     pub min: cgmath::Point3<f32>,
     pub max: cgmath::Point3<f32>,
 }
 
+// Static solid obstacle shapes. Coordinates use the same convention as fluid cubes.
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(tag = "type")]
+pub enum SolidShape {
+    Box {
+        min: cgmath::Point3<f32>,
+        max: cgmath::Point3<f32>,
+    },
+    Sphere {
+        center: cgmath::Point3<f32>,
+        radius: f32,
+    },
+    Capsule {
+        start: cgmath::Point3<f32>,
+        end: cgmath::Point3<f32>,
+        radius: f32,
+    },
+    // Triangle mesh from an OBJ or STL file (path relative to the scene file), placed with its origin at `position`.
+    // Should be closed, otherwise inside/outside can't be determined reliably.
+    Mesh {
+        path: PathBuf,
+        position: cgmath::Point3<f32>,
+        #[serde(default = "default_mesh_scale")]
+        scale: f32,
+    },
+}
+
+fn default_mesh_scale() -> f32 {
+    1.0
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct SolidConfig {
+    #[serde(flatten)]
+    pub shape: SolidShape,
+    // Euler angles in degrees, applied around the center of the shape.
+    #[serde(default)]
+    pub rotation: Option<cgmath::Vector3<f32>>,
+}
+
+impl SolidShape {
+    fn center(&self) -> cgmath::Point3<f32> {
+        match *self {
+            SolidShape::Box { min, max } => cgmath::EuclideanSpace::midpoint(min, max),
+            SolidShape::Sphere { center, .. } => center,
+            SolidShape::Capsule { start, end, .. } => cgmath::EuclideanSpace::midpoint(start, end),
+            SolidShape::Mesh { position, .. } => position,
+        }
+    }
+
+    // Signed distance to the (unrotated) shape, negative inside. None for meshes, they are voxelized instead.
+    fn signed_distance(&self, p: cgmath::Point3<f32>) -> Option<f32> {
+        use cgmath::InnerSpace;
+        Some(match *self {
+            SolidShape::Mesh { .. } => return None,
+            SolidShape::Box { min, max } => {
+                let center = cgmath::EuclideanSpace::midpoint(min, max);
+                let half_extent = (max - min) * 0.5;
+                let q = cgmath::vec3(
+                    (p.x - center.x).abs() - half_extent.x,
+                    (p.y - center.y).abs() - half_extent.y,
+                    (p.z - center.z).abs() - half_extent.z,
+                );
+                let outside = cgmath::vec3(q.x.max(0.0), q.y.max(0.0), q.z.max(0.0)).magnitude();
+                let inside = q.x.max(q.y).max(q.z).min(0.0);
+                outside + inside
+            }
+            SolidShape::Sphere { center, radius } => (p - center).magnitude() - radius,
+            SolidShape::Capsule { start, end, radius } => {
+                let segment = end - start;
+                let t = ((p - start).dot(segment) / segment.magnitude2().max(std::f32::EPSILON)).max(0.0).min(1.0);
+                (p - (start + segment * t)).magnitude() - radius
+            }
+        })
+    }
+
+    // Radius of a sphere around the center that fully contains the shape. None for meshes.
+    fn bounding_radius(&self) -> Option<f32> {
+        use cgmath::InnerSpace;
+        match *self {
+            SolidShape::Mesh { .. } => None,
+            SolidShape::Box { min, max } => Some((max - min).magnitude() * 0.5),
+            SolidShape::Sphere { radius, .. } => Some(radius),
+            SolidShape::Capsule { start, end, radius } => Some((end - start).magnitude() * 0.5 + radius),
+        }
+    }
+}
+
+impl SolidConfig {
+    fn rotation(&self) -> cgmath::Matrix3<f32> {
+        match self.rotation {
+            Some(angles) => cgmath::Euler::new(cgmath::Deg(angles.x), cgmath::Deg(angles.y), cgmath::Deg(angles.z)).into(),
+            None => cgmath::SquareMatrix::identity(),
+        }
+    }
+
+    fn inverse_rotation(&self) -> cgmath::Matrix3<f32> {
+        cgmath::Matrix::transpose(&self.rotation())
+    }
+
+    // Signed distance to the shape including its rotation, negative inside. None for meshes.
+    pub fn signed_distance(&self, p: cgmath::Point3<f32>) -> Option<f32> {
+        let center = self.shape.center();
+        self.shape.signed_distance(center + self.inverse_rotation() * (p - center))
+    }
+
+    // Axis aligned bounds that contain the shape regardless of its rotation. None for meshes.
+    pub fn bounds(&self) -> Option<(cgmath::Point3<f32>, cgmath::Point3<f32>)> {
+        let center = self.shape.center();
+        let radius = self.shape.bounding_radius()?;
+        Some((
+            center - cgmath::vec3(radius, radius, radius),
+            center + cgmath::vec3(radius, radius, radius),
+        ))
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Keyframe<T> {
+    // In seconds. Simulation time for obstacle tracks, render time for camera paths.
+    pub time: f32,
+    pub value: T,
+}
+
+// Shapes of kinematic obstacles, i.e. all solid shapes that can be evaluated analytically in the shaders.
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(tag = "type")]
+pub enum ObstacleShape {
+    Box {
+        min: cgmath::Point3<f32>,
+        max: cgmath::Point3<f32>,
+    },
+    Sphere {
+        center: cgmath::Point3<f32>,
+        radius: f32,
+    },
+    Capsule {
+        start: cgmath::Point3<f32>,
+        end: cgmath::Point3<f32>,
+        radius: f32,
+    },
+}
+
+impl ObstacleShape {
+    fn center(&self) -> cgmath::Point3<f32> {
+        match *self {
+            ObstacleShape::Box { min, max } => cgmath::EuclideanSpace::midpoint(min, max),
+            ObstacleShape::Sphere { center, .. } => center,
+            ObstacleShape::Capsule { start, end, .. } => cgmath::EuclideanSpace::midpoint(start, end),
+        }
+    }
+}
+
+// Solid that moves along keyframed tracks over simulation time. Keyframes are linearly interpolated.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct KinematicObstacleConfig {
+    #[serde(flatten)]
+    pub shape: ObstacleShape,
+    // Positions of the shape's center. If empty, the shape stays where it was defined.
+    #[serde(default)]
+    pub position_track: Vec<Keyframe<cgmath::Point3<f32>>>,
+    // Euler angles in degrees.
+    #[serde(default)]
+    pub rotation_track: Vec<Keyframe<cgmath::Vector3<f32>>>,
+    // If true, all tracks start over once the last keyframe of the longest track is reached.
+    #[serde(default)]
+    pub looping: bool,
+}
+
+// Finds keyframe values before and after a given time, the interpolant between them and the duration of the segment.
+fn sample_track<T: Copy>(track: &[Keyframe<T>], time: f32) -> Option<(T, T, f32, f32)> {
+    let first = track.first()?;
+    if time <= first.time {
+        return Some((first.value, first.value, 0.0, 0.0));
+    }
+    for segment in track.windows(2) {
+        let (start, end) = (&segment[0], &segment[1]);
+        if time < end.time {
+            let duration = end.time - start.time;
+            return Some((start.value, end.value, (time - start.time) / duration, duration));
+        }
+    }
+    let last = track.last()?;
+    Some((last.value, last.value, 0.0, 0.0))
+}
+
+fn euler_degrees_to_quaternion(angles: cgmath::Vector3<f32>) -> cgmath::Quaternion<f32> {
+    cgmath::Euler::new(cgmath::Deg(angles.x), cgmath::Deg(angles.y), cgmath::Deg(angles.z)).into()
+}
+
+impl KinematicObstacleConfig {
+    fn duration(&self) -> f32 {
+        let last_position = self.position_track.last().map_or(0.0, |keyframe| keyframe.time);
+        let last_rotation = self.rotation_track.last().map_or(0.0, |keyframe| keyframe.time);
+        last_position.max(last_rotation)
+    }
+
+    // Evaluates position & velocity of the obstacle at a given simulation time and converts it to grid space.
+    pub fn evaluate(&self, time: f32, grid_to_world_scale: f32) -> DynamicSolid {
+        use cgmath::InnerSpace;
+
+        let duration = self.duration();
+        let time = if self.looping && duration > 0.0 { time % duration } else { time };
+
+        let (center, linear_velocity) = match sample_track(&self.position_track, time) {
+            Some((start, end, interpolant, segment_duration)) => (
+                start + (end - start) * interpolant,
+                if segment_duration > 0.0 {
+                    (end - start) / segment_duration
+                } else {
+                    cgmath::vec3(0.0, 0.0, 0.0)
+                },
+            ),
+            None => (self.shape.center(), cgmath::vec3(0.0, 0.0, 0.0)),
+        };
+
+        let (rotation, angular_velocity) = match sample_track(&self.rotation_track, time) {
+            Some((start, end, interpolant, segment_duration)) => {
+                let start = euler_degrees_to_quaternion(start);
+                let end = euler_degrees_to_quaternion(end);
+                let rotation = start.slerp(end, interpolant);
+
+                let mut delta = end * start.conjugate();
+                if delta.s < 0.0 {
+                    delta = -delta;
+                }
+                let angle = 2.0 * delta.s.min(1.0).acos();
+                let angular_velocity = if segment_duration > 0.0 && angle > 1e-6 {
+                    delta.v.normalize() * (angle / segment_duration)
+                } else {
+                    cgmath::vec3(0.0, 0.0, 0.0)
+                };
+                (rotation, angular_velocity)
+            }
+            None => (cgmath::Quaternion::new(1.0, 0.0, 0.0, 0.0), cgmath::vec3(0.0, 0.0, 0.0)),
+        };
+
+        let shape = match self.shape {
+            ObstacleShape::Box { min, max } => DynamicSolidShape::Box {
+                half_extent: (max - min) * 0.5 / grid_to_world_scale,
+            },
+            ObstacleShape::Sphere { radius, .. } => DynamicSolidShape::Sphere {
+                radius: radius / grid_to_world_scale,
+            },
+            ObstacleShape::Capsule { start, end, radius } => DynamicSolidShape::Capsule {
+                half_segment: (end - start) * 0.5 / grid_to_world_scale,
+                radius: radius / grid_to_world_scale,
+            },
+        };
+
+        DynamicSolid {
+            shape,
+            center: center / grid_to_world_scale,
+            rotation,
+            linear_velocity: linear_velocity / grid_to_world_scale,
+            angular_velocity,
+        }
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(tag = "type")]
+pub enum RigidBodyShapeConfig {
+    Box { min: cgmath::Point3<f32>, max: cgmath::Point3<f32> },
+    Sphere { center: cgmath::Point3<f32>, radius: f32 },
+}
+
+fn default_rigid_body_damping() -> f32 {
+    0.2
+}
+
+// Free moving body that is coupled with the fluid.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct RigidBodyConfig {
+    #[serde(flatten)]
+    pub shape: RigidBodyShapeConfig,
+    // In kg/m³, i.e. bodies with a lower density than the fluid float.
+    pub density: f32,
+    // Euler angles in degrees.
+    #[serde(default)]
+    pub rotation: Option<cgmath::Vector3<f32>>,
+    // Fraction of linear & angular velocity that is lost every second.
+    // Fluid forces arrive a few steps late which adds energy to the system, without any damping bodies tend to bob up and down forever.
+    #[serde(default = "default_rigid_body_damping")]
+    pub damping: f32,
+}
+
+impl RigidBodyConfig {
+    fn create_body(&self) -> RigidBody {
+        let (shape, center) = match self.shape {
+            RigidBodyShapeConfig::Box { min, max } => (
+                RigidBodyShape::Box {
+                    half_extent: (max - min) * 0.5,
+                },
+                cgmath::EuclideanSpace::midpoint(min, max),
+            ),
+            RigidBodyShapeConfig::Sphere { center, radius } => (RigidBodyShape::Sphere { radius }, center),
+        };
+        let rotation = euler_degrees_to_quaternion(self.rotation.unwrap_or(cgmath::vec3(0.0, 0.0, 0.0)));
+        RigidBody::new(shape, self.density, self.damping, center, rotation)
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(tag = "type")]
+pub enum EmitterShapeConfig {
+    Box {
+        min: cgmath::Point3<f32>,
+        max: cgmath::Point3<f32>,
+    },
+    Disc {
+        center: cgmath::Point3<f32>,
+        normal: cgmath::Vector3<f32>,
+        radius: f32,
+    },
+}
+
+// Continuous source of fluid.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct EmitterConfig {
+    #[serde(flatten)]
+    pub shape: EmitterShapeConfig,
+    // Initial velocity of the emitted fluid (in world space).
+    pub velocity: cgmath::Vector3<f32>,
+    // Volume of fluid emitted per second in m³/s
+    pub rate: f32,
+}
+
+impl EmitterConfig {
+    fn to_grid_space(&self, grid_to_world_scale: f32) -> ParticleEmitter {
+        use cgmath::InnerSpace;
+        let (shape, center) = match self.shape {
+            EmitterShapeConfig::Box { min, max } => (
+                EmitterShape::Box {
+                    half_extent: (max - min) * 0.5 / grid_to_world_scale,
+                },
+                cgmath::EuclideanSpace::midpoint(min, max),
+            ),
+            EmitterShapeConfig::Disc { center, normal, radius } => (
+                EmitterShape::Disc {
+                    normal: normal.normalize(),
+                    radius: radius / grid_to_world_scale,
+                },
+                center,
+            ),
+        };
+        ParticleEmitter {
+            shape,
+            center: center / grid_to_world_scale,
+            velocity: self.velocity / grid_to_world_scale,
+            rate: self.rate / grid_to_world_scale.powi(3) * HybridFluid::PARTICLES_PER_GRID_CELL as f32,
+        }
+    }
+}
+
+// Shapes of the fluid volumes a scene starts with. Coordinates are in world space.
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(tag = "type")]
+pub enum FluidShape {
+    Box {
+        min: cgmath::Point3<f32>,
+        max: cgmath::Point3<f32>,
+    },
+    Sphere {
+        center: cgmath::Point3<f32>,
+        radius: f32,
+    },
+    Cylinder {
+        // Centers of the two caps.
+        start: cgmath::Point3<f32>,
+        end: cgmath::Point3<f32>,
+        radius: f32,
+    },
+    // Interior of a closed triangle mesh from an OBJ or STL file (path relative to the scene file), placed with its origin at `position`.
+    Mesh {
+        path: PathBuf,
+        position: cgmath::Point3<f32>,
+        #[serde(default = "default_mesh_scale")]
+        scale: f32,
+    },
+}
+
+impl FluidShape {
+    // Axis aligned bounds of all analytic shapes. None for meshes, their bounds aren't known before voxelization.
+    pub fn bounds(&self) -> Option<(cgmath::Point3<f32>, cgmath::Point3<f32>)> {
+        Some(match *self {
+            FluidShape::Mesh { .. } => return None,
+            FluidShape::Box { min, max } => (min, max),
+            FluidShape::Sphere { center, radius } => (
+                center - cgmath::vec3(radius, radius, radius),
+                center + cgmath::vec3(radius, radius, radius),
+            ),
+            FluidShape::Cylinder { start, end, radius } => (
+                cgmath::point3(start.x.min(end.x), start.y.min(end.y), start.z.min(end.z)) - cgmath::vec3(radius, radius, radius),
+                cgmath::point3(start.x.max(end.x), start.y.max(end.y), start.z.max(end.z)) + cgmath::vec3(radius, radius, radius),
+            ),
+        })
+    }
+
+    // Inside test of all analytic shapes. None for meshes, they are voxelized instead.
+    fn contains(&self, p: cgmath::Point3<f32>) -> Option<bool> {
+        use cgmath::InnerSpace;
+        Some(match *self {
+            FluidShape::Mesh { .. } => return None,
+            FluidShape::Box { min, max } => p.x >= min.x && p.y >= min.y && p.z >= min.z && p.x <= max.x && p.y <= max.y && p.z <= max.z,
+            FluidShape::Sphere { center, radius } => (p - center).magnitude2() <= radius * radius,
+            FluidShape::Cylinder { start, end, radius } => {
+                let axis = end - start;
+                let t = (p - start).dot(axis) / axis.magnitude2().max(std::f32::EPSILON);
+                (0.0..=1.0).contains(&t) && (p - (start + axis * t)).magnitude2() <= radius * radius
+            }
+        })
+    }
+}
+
+fn default_fluid_velocity() -> cgmath::Vector3<f32> {
+    cgmath::vec3(0.0, 0.0, 0.0)
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct FluidVolumeConfig {
+    #[serde(flatten)]
+    pub shape: FluidShape,
+    // Initial velocity of all fluid in the volume (in world space).
+    #[serde(default = "default_fluid_velocity")]
+    pub velocity: cgmath::Vector3<f32>,
+}
+
+// Shorthand for box shaped fluid volumes, filled up to whole grid cells.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct FluidCubeConfig {
+    pub min: cgmath::Point3<f32>,
+    pub max: cgmath::Point3<f32>,
+    // Initial velocity of all fluid in the cube (in world space).
+    #[serde(default = "default_fluid_velocity")]
+    pub velocity: cgmath::Vector3<f32>,
+}
+
 // Data describing a fluid in the scene.
-#[derive(Deserialize)]
+#[derive(Clone, Serialize, Deserialize)]
 pub struct FluidConfig {
     pub world_position: cgmath::Point3<f32>,
     pub grid_to_world_scale: f32,
     pub grid_dimension: cgmath::Point3<u32>,
     pub max_num_particles: u32,
-    pub fluid_cubes: Vec<Box>,
+    #[serde(default)]
+    pub fluid_cubes: Vec<FluidCubeConfig>,
+    #[serde(default)]
+    pub fluid_volumes: Vec<FluidVolumeConfig>,
+    #[serde(default)]
+    pub emitters: Vec<EmitterConfig>,
+    // Particles entering any of these boxes are removed.
+    #[serde(default)]
+    pub drains: Vec<Box>,
+    // In kg/m³, only relevant for the interaction with rigid bodies and for surface tension.
+    #[serde(default = "default_fluid_density")]
+    pub density: f32,
+    #[serde(default)]
+    pub transfer_scheme: TransferScheme,
+    // Only used with the FLIP transfer scheme, 0 is pure PIC, 1 pure FLIP.
+    #[serde(default = "default_flip_blend_factor")]
+    pub flip_blend_factor: f32,
+    // Kinematic viscosity in m²/s. Water is about 1e-6 which is negligible at typical grid resolutions,
+    // honey-like fluids are somewhere around 1e-2.
+    #[serde(default)]
+    pub viscosity: f32,
+    // Surface tension coefficient in N/m, water is about 0.073. Only noticeable for small scale scenes.
+    #[serde(default)]
+    pub surface_tension: f32,
+    // Strength of the vorticity confinement force, counteracts numerical dissipation of swirls.
+    // Relative to the grid cell size, useful values are somewhere around 0.05-0.5.
+    #[serde(default)]
+    pub vorticity_confinement: f32,
+    // Keeps the number of particles per grid cell within bounds by spawning & removing particles. Disabled if not present.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reseeding: Option<ParticleReseeding>,
+}
+
+impl FluidConfig {
+    // World space bounds of the simulation grid.
+    pub fn domain_bounds(&self) -> (cgmath::Point3<f32>, cgmath::Point3<f32>) {
+        let extent = cgmath::vec3(self.grid_dimension.x as f32, self.grid_dimension.y as f32, self.grid_dimension.z as f32);
+        (self.world_position, self.world_position + extent * self.grid_to_world_scale)
+    }
+}
+
+fn default_fluid_density() -> f32 {
+    1000.0
+}
+
+fn default_flip_blend_factor() -> f32 {
+    0.95
+}
+
+// Settings that are usually tuned in the ui, written by Scene::save.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct SettingsConfig {
+    pub pressure_solver_velocity: SolverConfig,
+    pub pressure_solver_density: SolverConfig,
+    pub simulation_steps_per_second: u64,
+    pub time_scale: f32,
+    #[serde(default)]
+    pub adaptive_time_step: AdaptiveTimeStepConfig,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub viscosity_solver: Option<SolverConfig>,
+    pub rendering: RenderingConfig,
+    pub camera: CameraConfig,
 }
 
 // Data describing a scene.
-#[derive(Deserialize)]
+#[derive(Clone, Serialize, Deserialize)]
 pub struct SceneConfig {
     // global gravity (in world space)
     pub gravity: cgmath::Vector3<f32>,
     pub fluid: FluidConfig,
+    // static obstacles (same coordinate convention as fluid cubes)
+    #[serde(default)]
+    pub solids: Vec<SolidConfig>,
+    // moving obstacles (same coordinate convention as fluid cubes)
+    #[serde(default)]
+    pub obstacles: Vec<KinematicObstacleConfig>,
+    // bodies moved by the fluid (same coordinate convention as fluid cubes)
+    #[serde(default)]
+    pub rigid_bodies: Vec<RigidBodyConfig>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub camera_presets: Vec<CameraPresetConfig>,
+    // Camera animation used while recording. Keyframes are interpolated with a Catmull-Rom spline.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub camera_path: Vec<Keyframe<CameraConfig>>,
+    // If not present, defaults are used.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub settings: Option<SettingsConfig>,
+}
+
+impl SceneConfig {
+    // Reads a scene file, makes mesh paths relative to it and validates the result.
+    pub fn load(scene_path: &Path) -> Result<SceneConfig, SceneLoadError> {
+        let json = std::fs::read_to_string(scene_path)?;
+        let mut config: SceneConfig =
+            serde_json::from_str(&json).map_err(|error| SceneLoadError::Invalid(vec![scene_validation::problem_from_json_error(&json, &error)]))?;
+
+        let scene_directory = scene_path.parent().unwrap_or(Path::new(""));
+        for solid in config.solids.iter_mut() {
+            if let SolidShape::Mesh { path, .. } = &mut solid.shape {
+                *path = scene_directory.join(&path);
+            }
+        }
+        for volume in config.fluid.fluid_volumes.iter_mut() {
+            if let FluidShape::Mesh { path, .. } = &mut volume.shape {
+                *path = scene_directory.join(&path);
+            }
+        }
+        let problems = scene_validation::validate(&config);
+        if !problems.is_empty() {
+            return Err(SceneLoadError::Invalid(problems));
+        }
+        Ok(config)
+    }
+
+    fn grid_dimension(&self) -> wgpu::Extent3d {
+        wgpu::Extent3d {
+            width: self.fluid.grid_dimension.x,
+            height: self.fluid.grid_dimension.y,
+            depth: self.fluid.grid_dimension.z,
+        }
+    }
+
+    // Evaluates the camera path at a given render time. None if there is no camera path.
+    pub fn camera_path_at(&self, time: f32) -> Option<CameraConfig> {
+        use cgmath::{InnerSpace, Rotation};
+
+        // Everything that is interpolated as spline, treated the same component wise.
+        // Directions are slerped instead, a spline through unit vectors can pass arbitrarily close to zero.
+        let values = |camera: &CameraConfig| [camera.position.x, camera.position.y, camera.position.z, camera.vertical_fov];
+
+        let path = &self.camera_path;
+        let last = path.len().checked_sub(1)?;
+        let segment = path.iter().position(|keyframe| keyframe.time > time).unwrap_or(path.len());
+        if segment == 0 || segment > last {
+            return Some(path[segment.min(last)].value);
+        }
+
+        // Hermite segment between keyframe i and i+1, tangents via finite differences (Catmull-Rom for non-uniform keyframe times).
+        let i = segment - 1;
+        let tangent = |k: usize, component: usize| {
+            let (before, after) = (k.saturating_sub(1), (k + 1).min(last));
+            (values(&path[after].value)[component] - values(&path[before].value)[component]) / (path[after].time - path[before].time)
+        };
+        let duration = path[i + 1].time - path[i].time;
+        let t = (time - path[i].time) / duration;
+        let (t2, t3) = (t * t, t * t * t);
+        let (h00, h10, h01, h11) = (2.0 * t3 - 3.0 * t2 + 1.0, t3 - 2.0 * t2 + t, -2.0 * t3 + 3.0 * t2, t3 - t2);
+
+        let (start, end) = (values(&path[i].value), values(&path[i + 1].value));
+        let mut result = [0.0; 4];
+        for (component, value) in result.iter_mut().enumerate() {
+            *value =
+                h00 * start[component] + h10 * duration * tangent(i, component) + h01 * end[component] + h11 * duration * tangent(i + 1, component);
+        }
+
+        let start_direction = path[i].value.direction.normalize();
+        let end_direction = path[i + 1].value.direction.normalize();
+        let rotation = cgmath::Quaternion::new(1.0, 0.0, 0.0, 0.0).slerp(cgmath::Quaternion::from_arc(start_direction, end_direction, None), t);
+
+        Some(CameraConfig {
+            position: cgmath::point3(result[0], result[1], result[2]),
+            direction: rotation.rotate_vector(start_direction),
+            vertical_fov: result[3],
+        })
+    }
 }
 
 // Scene data & simulation.
 pub struct Scene {
     hybrid_fluid: HybridFluid,
     config: SceneConfig,
+    // Voxelizing solids can be slow, so keep the result around for resets.
+    solid_distance_field: Vec<f32>,
+    // Same for the meshes of fluid volumes, one distance field for each in order of appearance.
+    fluid_mesh_distance_fields: Vec<Vec<f32>>,
+    // Total simulated time since the scene was (re)started.
+    simulation_time: Duration,
+    rigid_bodies: Vec<RigidBody>,
 }
 
 impl Scene {
@@ -49,22 +658,45 @@ impl Scene {
         shader_dir: &ShaderDirectory,
         pipeline_manager: &mut PipelineManager,
         per_frame_bind_group_layout: &wgpu::BindGroupLayout,
-    ) -> Result<Self, io::Error> {
-        let file = File::open(scene_path)?;
-        let reader = BufReader::new(file);
-        let config: SceneConfig = serde_json::from_reader(reader)?;
+    ) -> Result<Self, SceneLoadError> {
+        let config = SceneConfig::load(scene_path)?;
+        let solid_distance_field = Self::compute_solid_distance_field(&config)?;
+        let fluid_mesh_distance_fields = Self::compute_fluid_mesh_distance_fields(&config)?;
 
-        let hybrid_fluid = Self::create_fluid_from_config(&config, device, queue, shader_dir, pipeline_manager, per_frame_bind_group_layout);
+        let hybrid_fluid = Self::create_fluid_from_config(
+            &config,
+            &solid_distance_field,
+            &fluid_mesh_distance_fields,
+            device,
+            queue,
+            shader_dir,
+            pipeline_manager,
+            per_frame_bind_group_layout,
+        );
 
-        Ok(Scene { hybrid_fluid, config })
+        Ok(Scene {
+            hybrid_fluid,
+            solid_distance_field,
+            fluid_mesh_distance_fields,
+            simulation_time: Duration::from_secs(0),
+            rigid_bodies: config.rigid_bodies.iter().map(RigidBodyConfig::create_body).collect(),
+            config,
+        })
     }
 
     pub fn config(&self) -> &SceneConfig {
         &self.config
     }
 
+    // Presets are part of the config, i.e. they are only persisted when the scene is saved.
+    pub fn add_camera_preset(&mut self, preset: CameraPresetConfig) {
+        self.config.camera_presets.push(preset);
+    }
+
     fn create_fluid_from_config(
         config: &SceneConfig,
+        solid_distance_field: &[f32],
+        fluid_mesh_distance_fields: &[Vec<f32>],
         device: &wgpu::Device,
         queue: &wgpu::Queue,
         shader_dir: &ShaderDirectory,
@@ -73,31 +705,193 @@ impl Scene {
     ) -> HybridFluid {
         let mut hybrid_fluid = HybridFluid::new(
             device,
-            wgpu::Extent3d {
-                width: config.fluid.grid_dimension.x,
-                height: config.fluid.grid_dimension.y,
-                depth: config.fluid.grid_dimension.z,
-            },
+            config.grid_dimension(),
             config.fluid.max_num_particles,
             shader_dir,
             pipeline_manager,
             per_frame_bind_group_layout,
         );
+        Self::setup_fluid(config, solid_distance_field, fluid_mesh_distance_fields, &mut hybrid_fluid, queue);
 
-        for cube in config.fluid.fluid_cubes.iter() {
-            hybrid_fluid.add_fluid_cube(
-                queue,
-                cube.min / config.fluid.grid_to_world_scale,
-                cube.max / config.fluid.grid_to_world_scale,
-            );
+        let scale = config.fluid.grid_to_world_scale;
+        hybrid_fluid.set_emitters(
+            &config
+                .fluid
+                .emitters
+                .iter()
+                .map(|emitter| emitter.to_grid_space(scale))
+                .collect::<Vec<_>>(),
+        );
+        hybrid_fluid.set_drains(
+            &config
+                .fluid
+                .drains
+                .iter()
+                .map(|drain| ParticleDrain {
+                    min: drain.min / scale,
+                    max: drain.max / scale,
+                })
+                .collect::<Vec<_>>(),
+        );
+        hybrid_fluid.set_viscosity_grid(config.fluid.viscosity / (scale * scale));
+        hybrid_fluid.set_vorticity_confinement(config.fluid.vorticity_confinement);
+        hybrid_fluid.set_reseeding(config.fluid.reseeding);
+        hybrid_fluid.set_surface_tension_grid(config.fluid.surface_tension / (config.fluid.density * scale.powi(3)));
+        if let Some(viscosity_solver) = config.settings.as_ref().and_then(|settings| settings.viscosity_solver) {
+            *hybrid_fluid.viscosity_solver_config() = viscosity_solver;
         }
-        hybrid_fluid.set_gravity_grid(config.gravity / config.fluid.grid_to_world_scale);
 
         // Creating the fluid is quite heavy, make sure we're done with all the buffer book-keeping before we move on.
         device.poll(wgpu::Maintain::Wait);
         hybrid_fluid
     }
 
+    // Everything of the scene config that is supported by all fluid backends: particles, static solids and the core simulation settings.
+    fn setup_fluid<'a, F: FluidBackend<'a>>(
+        config: &SceneConfig,
+        solid_distance_field: &[f32],
+        fluid_mesh_distance_fields: &[Vec<f32>],
+        fluid: &mut F,
+        context: F::SetupContext,
+    ) {
+        let scale = config.fluid.grid_to_world_scale;
+        for cube in config.fluid.fluid_cubes.iter() {
+            fluid.add_fluid_volume(context, cube.min / scale, cube.max / scale, cube.velocity / scale, &|_| true);
+        }
+        let mut fluid_mesh_distance_fields = fluid_mesh_distance_fields.iter();
+        for volume in config.fluid.fluid_volumes.iter() {
+            if let FluidShape::Mesh { .. } = volume.shape {
+                let distance_field = fluid_mesh_distance_fields.next().unwrap();
+                let dimension = config.fluid.grid_dimension;
+                fluid.add_fluid_volume(
+                    context,
+                    cgmath::point3(0.0, 0.0, 0.0),
+                    dimension.cast().unwrap(),
+                    volume.velocity / scale,
+                    &|p| distance_field[(p.x as u32 + (p.y as u32 + p.z as u32 * dimension.y) * dimension.x) as usize] < 0.0,
+                );
+            } else if let Some((min, max)) = volume.shape.bounds() {
+                fluid.add_fluid_volume(context, min / scale, max / scale, volume.velocity / scale, &|p| {
+                    volume.shape.contains(p * scale) == Some(true)
+                });
+            }
+        }
+        // Need to upload solids even if there are none since the solid volume is not zero initialized.
+        fluid.set_static_solids(context, solid_distance_field);
+        fluid.set_gravity_grid(config.gravity / scale);
+        fluid.set_transfer_scheme(config.fluid.transfer_scheme);
+        fluid.set_flip_blend_factor(config.fluid.flip_blend_factor);
+        if let Some(settings) = config.settings.as_ref() {
+            *fluid.pressure_solver_config_velocity() = settings.pressure_solver_velocity;
+            *fluid.pressure_solver_config_density() = settings.pressure_solver_density;
+        }
+    }
+
+    // Sets up the CPU reference for a scene, fails for anything it doesn't simulate (see cpu_fluid.rs)
+    pub fn create_cpu_fluid(config: &SceneConfig) -> Result<CpuFluid, SceneLoadError> {
+        let mut problems = Vec::new();
+        let mut unsupported = |json_path: &str, is_used: bool| {
+            if is_used {
+                problems.push(ValidationProblem {
+                    json_path: json_path.to_owned(),
+                    reason: "not supported by the CPU simulation".to_owned(),
+                });
+            }
+        };
+        unsupported("fluid.emitters", !config.fluid.emitters.is_empty());
+        unsupported("fluid.drains", !config.fluid.drains.is_empty());
+        unsupported("fluid.viscosity", config.fluid.viscosity != 0.0);
+        unsupported("fluid.surface_tension", config.fluid.surface_tension != 0.0);
+        unsupported("fluid.vorticity_confinement", config.fluid.vorticity_confinement != 0.0);
+        unsupported("fluid.reseeding", config.fluid.reseeding.is_some());
+        unsupported("obstacles", !config.obstacles.is_empty());
+        unsupported("rigid_bodies", !config.rigid_bodies.is_empty());
+        if !problems.is_empty() {
+            return Err(SceneLoadError::Invalid(problems));
+        }
+
+        let solid_distance_field = Self::compute_solid_distance_field(config)?;
+        let fluid_mesh_distance_fields = Self::compute_fluid_mesh_distance_fields(config)?;
+        let mut cpu_fluid = CpuFluid::new(config.grid_dimension(), config.fluid.max_num_particles);
+        Self::setup_fluid(config, &solid_distance_field, &fluid_mesh_distance_fields, &mut cpu_fluid, ());
+        Ok(cpu_fluid)
+    }
+
+    // Computes the signed distance (in grid cells, negative inside) to the closest static solid for every grid cell center.
+    // Distances are exact only close to the surface which is all we need for marking cells and pushing particles out.
+    fn compute_solid_distance_field(config: &SceneConfig) -> Result<Vec<f32>, io::Error> {
+        const BAND: f32 = 4.0;
+
+        let dimension = config.fluid.grid_dimension;
+        let scale = config.fluid.grid_to_world_scale;
+        let mut distance_field = vec![BAND; (dimension.x * dimension.y * dimension.z) as usize];
+
+        for solid in config.solids.iter() {
+            if let SolidShape::Mesh {
+                ref path,
+                position,
+                scale: mesh_scale,
+            } = solid.shape
+            {
+                let mut mesh = TriangleMesh::load(path)?;
+                let rotation = solid.rotation();
+                mesh.transform(|vertex| (position + rotation * (vertex.to_vec() * mesh_scale)) / scale);
+                for (distance, mesh_distance) in distance_field.iter_mut().zip(mesh.signed_distance_field(dimension, BAND)) {
+                    *distance = distance.min(mesh_distance);
+                }
+                continue;
+            }
+
+            let (min, max) = match solid.bounds() {
+                Some(bounds) => bounds,
+                None => continue,
+            };
+            let min_cell = cgmath::point3(
+                ((min.x / scale - BAND).floor().max(0.0) as u32).min(dimension.x),
+                ((min.y / scale - BAND).floor().max(0.0) as u32).min(dimension.y),
+                ((min.z / scale - BAND).floor().max(0.0) as u32).min(dimension.z),
+            );
+            let max_cell = cgmath::point3(
+                ((max.x / scale + BAND).ceil().max(0.0) as u32).min(dimension.x),
+                ((max.y / scale + BAND).ceil().max(0.0) as u32).min(dimension.y),
+                ((max.z / scale + BAND).ceil().max(0.0) as u32).min(dimension.z),
+            );
+
+            for z in min_cell.z..max_cell.z {
+                for y in min_cell.y..max_cell.y {
+                    for x in min_cell.x..max_cell.x {
+                        let cell_center = cgmath::point3(x as f32 + 0.5, y as f32 + 0.5, z as f32 + 0.5) * scale;
+                        let distance = &mut distance_field[(x + (y + z * dimension.y) * dimension.x) as usize];
+                        if let Some(solid_distance) = solid.signed_distance(cell_center) {
+                            *distance = distance.min((solid_distance / scale).max(-BAND));
+                        }
+                    }
+                }
+            }
+        }
+
+        Ok(distance_field)
+    }
+
+    // Voxelizes the meshes of all mesh shaped fluid volumes, see compute_solid_distance_field.
+    fn compute_fluid_mesh_distance_fields(config: &SceneConfig) -> Result<Vec<Vec<f32>>, io::Error> {
+        let scale = config.fluid.grid_to_world_scale;
+        let mut distance_fields = Vec::new();
+        for volume in config.fluid.fluid_volumes.iter() {
+            if let FluidShape::Mesh {
+                ref path,
+                position,
+                scale: mesh_scale,
+            } = volume.shape
+            {
+                let mut mesh = TriangleMesh::load(path)?;
+                mesh.transform(|vertex| (position + vertex.to_vec() * mesh_scale) / scale);
+                distance_fields.push(mesh.signed_distance_field(config.fluid.grid_dimension, 1.0));
+            }
+        }
+        Ok(distance_fields)
+    }
+
     pub fn reset(
         &mut self,
         device: &wgpu::Device,
@@ -106,7 +900,116 @@ impl Scene {
         pipeline_manager: &mut PipelineManager,
         per_frame_bind_group_layout: &wgpu::BindGroupLayout,
     ) {
-        self.hybrid_fluid = Self::create_fluid_from_config(&self.config, device, queue, shader_dir, pipeline_manager, per_frame_bind_group_layout);
+        self.hybrid_fluid = Self::create_fluid_from_config(
+            &self.config,
+            &self.solid_distance_field,
+            &self.fluid_mesh_distance_fields,
+            device,
+            queue,
+            shader_dir,
+            pipeline_manager,
+            per_frame_bind_group_layout,
+        );
+        self.simulation_time = Duration::from_secs(0);
+        self.rigid_bodies = self.config.rigid_bodies.iter().map(RigidBodyConfig::create_body).collect();
+    }
+
+    // Writes the scene config with the current fluid parameters and the given settings to a scene file.
+    // Mesh paths are rewritten to be relative to the new file where possible.
+    pub fn save(&self, path: &Path, settings: SettingsConfig) -> Result<(), io::Error> {
+        let mut config = self.config.clone();
+        config.fluid.transfer_scheme = self.hybrid_fluid.transfer_scheme();
+        config.fluid.flip_blend_factor = self.hybrid_fluid.flip_blend_factor();
+        config.fluid.viscosity = self.hybrid_fluid.viscosity_grid() * config.fluid.grid_to_world_scale * config.fluid.grid_to_world_scale;
+        config.fluid.vorticity_confinement = self.hybrid_fluid.vorticity_confinement();
+        config.fluid.reseeding = self.hybrid_fluid.reseeding();
+        config.fluid.surface_tension = self.hybrid_fluid.surface_tension_grid() * config.fluid.density * config.fluid.grid_to_world_scale.powi(3);
+        config.settings = Some(settings);
+
+        let current_directory = std::env::current_dir()?;
+        let scene_directory = match path.parent() {
+            Some(directory) if !directory.as_os_str().is_empty() => directory.canonicalize()?,
+            _ => current_directory.clone(),
+        };
+        let relative_mesh_path = |mesh_path: &mut PathBuf| {
+            // Meshes may have been moved or deleted since the scene was loaded, that shouldn't keep the settings from being saved.
+            let absolute_path = mesh_path.canonicalize().unwrap_or_else(|error| {
+                warn!(
+                    "Failed to resolve mesh path {:?}, saving it without resolving links: {}",
+                    mesh_path, error
+                );
+                current_directory.join(&mesh_path)
+            });
+            *mesh_path = match absolute_path.strip_prefix(&scene_directory) {
+                Ok(relative_path) => relative_path.to_path_buf(),
+                Err(_) => absolute_path,
+            };
+        };
+        for solid in config.solids.iter_mut() {
+            if let SolidShape::Mesh { path, .. } = &mut solid.shape {
+                relative_mesh_path(path);
+            }
+        }
+        for volume in config.fluid.fluid_volumes.iter_mut() {
+            if let FluidShape::Mesh { path, .. } = &mut volume.shape {
+                relative_mesh_path(path);
+            }
+        }
+
+        let writer = std::io::BufWriter::new(std::fs::File::create(path)?);
+        serde_json::to_writer_pretty(writer, &config)?;
+        Ok(())
+    }
+
+    // Writes the fluid state together with the current simulation time and rigid body state to a checkpoint file.
+    pub fn save_checkpoint(&self, device: &wgpu::Device, queue: &wgpu::Queue, path: &Path) -> Result<(), io::Error> {
+        self.hybrid_fluid
+            .save_checkpoint(device, queue, path, self.simulation_time, &self.rigid_bodies)
+    }
+
+    // Restarts the scene from a checkpoint written by save_checkpoint. Leaves the scene untouched on failure.
+    pub fn load_checkpoint(
+        &mut self,
+        path: &Path,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        shader_dir: &ShaderDirectory,
+        pipeline_manager: &mut PipelineManager,
+        per_frame_bind_group_layout: &wgpu::BindGroupLayout,
+    ) -> Result<(), io::Error> {
+        let mut hybrid_fluid = Self::create_fluid_from_config(
+            &self.config,
+            &self.solid_distance_field,
+            &self.fluid_mesh_distance_fields,
+            device,
+            queue,
+            shader_dir,
+            pipeline_manager,
+            per_frame_bind_group_layout,
+        );
+        let mut rigid_bodies: Vec<RigidBody> = self.config.rigid_bodies.iter().map(RigidBodyConfig::create_body).collect();
+        self.simulation_time = hybrid_fluid.load_checkpoint(queue, path, &mut rigid_bodies)?;
+        self.hybrid_fluid = hybrid_fluid;
+        self.rigid_bodies = rigid_bodies;
+        Ok(())
+    }
+
+    // Applies the latest fluid forces to all rigid bodies and moves them forward in time.
+    fn step_rigid_bodies(&mut self, simulation_delta: Duration) {
+        let scale = self.config.fluid.grid_to_world_scale;
+        if let Some(forces) = self.hybrid_fluid.retrieve_dynamic_solid_forces() {
+            // Rigid bodies come after the kinematic obstacles in the list of dynamic solids.
+            for (body, force) in self.rigid_bodies.iter_mut().zip(forces.iter().skip(self.config.obstacles.len())) {
+                body.set_fluid_force(force, self.config.fluid.density, scale);
+            }
+        }
+
+        // Outermost cells are always solid.
+        let domain_min = cgmath::point3(scale, scale, scale);
+        let domain_max = (self.config.fluid.grid_dimension.cast::<f32>().unwrap() - cgmath::vec3(1.0, 1.0, 1.0)) * scale;
+        for body in self.rigid_bodies.iter_mut() {
+            body.integrate(simulation_delta.as_secs_f32(), self.config.gravity, domain_min, domain_max);
+        }
     }
 
     pub fn step(
@@ -120,6 +1023,20 @@ impl Scene {
         // Poll device to update mapped buffers which may feed back into what a step does.
         device.poll(wgpu::Maintain::Poll);
 
+        self.step_rigid_bodies(simulation_delta);
+
+        // Obstacles are evaluated in the middle of the step so that their velocity matches the movement during the step.
+        let obstacle_time = (self.simulation_time + simulation_delta / 2).as_secs_f32();
+        let scale = self.config.fluid.grid_to_world_scale;
+        let dynamic_solids: Vec<DynamicSolid> = self
+            .config
+            .obstacles
+            .iter()
+            .map(|obstacle| obstacle.evaluate(obstacle_time, scale))
+            .chain(self.rigid_bodies.iter().map(|body| body.dynamic_solid(scale)))
+            .collect();
+        self.hybrid_fluid.set_dynamic_solids(&dynamic_solids);
+
         let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
             label: Some("Encoder: Scene Step"),
         });
@@ -127,6 +1044,7 @@ impl Scene {
             .step(simulation_delta, &mut encoder, pipeline_manager, queue, per_frame_bind_group);
         queue.submit(Some(encoder.finish()));
         self.hybrid_fluid.update_statistics();
+        self.simulation_time += simulation_delta;
     }
 
     pub fn fluid(&self) -> &HybridFluid {
@@ -137,3 +1055,76 @@ impl Scene {
         &mut self.hybrid_fluid
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simulation::ParticleComparison;
+
+    const SIMULATION_DELTA_SECONDS: f64 = 1.0 / 120.0;
+
+    fn single_cell_debug_scene() -> SceneConfig {
+        SceneConfig::load(&Path::new(env!("CARGO_MANIFEST_DIR")).join("scenes/single_cell_debug.json")).unwrap()
+    }
+
+    // Runs the scene on the CPU and returns all particles after every step.
+    fn run_on_cpu(config: &SceneConfig, num_steps: usize) -> Vec<Vec<(cgmath::Point3<f32>, cgmath::Vector3<f32>)>> {
+        let mut fluid = Scene::create_cpu_fluid(config).unwrap();
+
+        let mut particles = vec![fluid.read_particles(()).unwrap()];
+        for _ in 0..num_steps {
+            fluid.step((), Duration::from_secs_f64(SIMULATION_DELTA_SECONDS));
+            particles.push(fluid.read_particles(()).unwrap());
+        }
+        particles
+    }
+
+    #[test]
+    fn cpu_fluid_steps_single_cell_debug_scene() {
+        const NUM_STEPS: usize = 5;
+        let config = single_cell_debug_scene();
+        let particles = run_on_cpu(&config, NUM_STEPS);
+        let domain = config.fluid.grid_dimension.cast::<f32>().unwrap();
+
+        let num_particles = particles[0].len();
+        assert!(num_particles > 0);
+        for step_particles in particles.iter() {
+            assert_eq!(step_particles.len(), num_particles);
+            for (position, velocity) in step_particles.iter() {
+                assert!(position.x.is_finite() && position.y.is_finite() && position.z.is_finite());
+                assert!(velocity.x.is_finite() && velocity.y.is_finite() && velocity.z.is_finite());
+                assert!(
+                    position.x >= 0.0
+                        && position.y >= 0.0
+                        && position.z >= 0.0
+                        && position.x <= domain.x
+                        && position.y <= domain.y
+                        && position.z <= domain.z,
+                    "particle left the domain: {:?}",
+                    position
+                );
+            }
+        }
+
+        // Surrounded by air there is nothing to hold the cell up, so it falls freely.
+        let final_particles = &particles[NUM_STEPS];
+        let mean_y = |step_particles: &[(cgmath::Point3<f32>, cgmath::Vector3<f32>)]| {
+            step_particles.iter().map(|(position, _)| position.y).sum::<f32>() / step_particles.len() as f32
+        };
+        assert!(mean_y(final_particles) < mean_y(&particles[0]));
+        let velocity_y = final_particles.iter().map(|(_, velocity)| velocity.y).sum::<f32>() / num_particles as f32;
+        let expected_velocity_y = config.gravity.y / config.fluid.grid_to_world_scale * (NUM_STEPS as f64 * SIMULATION_DELTA_SECONDS) as f32;
+        assert!(
+            (velocity_y - expected_velocity_y).abs() < 0.1 * expected_velocity_y.abs(),
+            "mean velocity {} after {} steps, expected free fall with {}",
+            velocity_y,
+            NUM_STEPS,
+            expected_velocity_y
+        );
+
+        let repeated_particles = run_on_cpu(&config, NUM_STEPS);
+        for (step_particles, repeated_step_particles) in particles.iter().zip(repeated_particles.iter()) {
+            assert!(ParticleComparison::new(step_particles, repeated_step_particles).within(0.0));
+        }
+    }
+}
@@ -0,0 +1,513 @@
+// Checks scene configs for values the simulation can't handle and reports them with the JSON path of the offending value.
+// Scene::new refuses to load any scene with problems, so everything past it can rely on a sane config.
+
+use crate::{
+    camera::CameraConfig,
+    scene::{EmitterShapeConfig, FluidShape, ObstacleShape, RigidBodyShapeConfig, SceneConfig, SolidShape},
+    simulation::{HybridFluid, SolverConfig, MAX_NUM_DRAINS, MAX_NUM_DYNAMIC_SOLIDS, MAX_NUM_EMITTERS, MAX_PARTICLES_PER_CELL},
+};
+use std::{fmt, io};
+
+pub struct ValidationProblem {
+    // Path of the offending value, e.g. "fluid.fluid_cubes[1].max".
+    pub json_path: String,
+    pub reason: String,
+}
+
+impl fmt::Display for ValidationProblem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.json_path.is_empty() {
+            write!(f, "(root): {}", self.reason)
+        } else {
+            write!(f, "{}: {}", self.json_path, self.reason)
+        }
+    }
+}
+
+pub enum SceneLoadError {
+    Io(io::Error),
+    // Either a json syntax/type error (as single problem) or the list of everything validation found.
+    Invalid(Vec<ValidationProblem>),
+}
+
+impl fmt::Display for SceneLoadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SceneLoadError::Io(error) => write!(f, "{}", error),
+            SceneLoadError::Invalid(problems) => {
+                write!(f, "{} problem(s) in scene file:", problems.len())?;
+                for problem in problems.iter() {
+                    write!(f, "\n  {}", problem)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl fmt::Debug for SceneLoadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
+
+impl From<io::Error> for SceneLoadError {
+    fn from(error: io::Error) -> Self {
+        SceneLoadError::Io(error)
+    }
+}
+
+impl From<SceneLoadError> for io::Error {
+    fn from(error: SceneLoadError) -> Self {
+        match error {
+            SceneLoadError::Io(error) => error,
+            error @ SceneLoadError::Invalid(_) => io::Error::new(io::ErrorKind::InvalidData, error.to_string()),
+        }
+    }
+}
+
+// Turns a serde_json error into a problem located at the value the parser was looking at.
+pub fn problem_from_json_error(json: &str, error: &serde_json::Error) -> ValidationProblem {
+    ValidationProblem {
+        json_path: json_path_at(json, error.line(), error.column()),
+        reason: error.to_string(),
+    }
+}
+
+// Determines the path of the innermost value at the given position (1-based line & column, as reported by serde_json).
+// serde_json counts columns in bytes, so this works on bytes as well. All JSON syntax is ASCII, multi-byte characters only occur within strings.
+fn json_path_at(json: &str, line: usize, column: usize) -> String {
+    enum Frame {
+        Object { key: Option<String>, expecting_key: bool },
+        Array { index: usize },
+    }
+    let mut stack: Vec<Frame> = Vec::new();
+
+    let mut current_line = 1;
+    let mut current_column = 0;
+    let mut bytes = json.bytes();
+    while let Some(c) = bytes.next() {
+        if c == b'\n' {
+            current_line += 1;
+            current_column = 0;
+        } else {
+            current_column += 1;
+        }
+        // Process the character at the error position as well, errors about incomplete objects point at their closing bracket.
+        if current_line > line || (current_line == line && current_column > column) {
+            break;
+        }
+
+        match c {
+            b'{' => stack.push(Frame::Object {
+                key: None,
+                expecting_key: true,
+            }),
+            b'[' => stack.push(Frame::Array { index: 0 }),
+            b'}' | b']' => {
+                stack.pop();
+            }
+            b',' => match stack.last_mut() {
+                Some(Frame::Object { key, expecting_key }) => {
+                    *key = None;
+                    *expecting_key = true;
+                }
+                Some(Frame::Array { index }) => *index += 1,
+                None => {}
+            },
+            b'"' => {
+                let mut string = Vec::new();
+                let mut escaped = false;
+                for c in &mut bytes {
+                    current_column += 1;
+                    match c {
+                        b'"' if !escaped => break,
+                        b'\\' if !escaped => escaped = true,
+                        _ => {
+                            escaped = false;
+                            string.push(c);
+                        }
+                    }
+                }
+                if let Some(Frame::Object { key, expecting_key }) = stack.last_mut() {
+                    if *expecting_key {
+                        *key = Some(String::from_utf8_lossy(&string).into_owned());
+                        *expecting_key = false;
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    let mut path = String::new();
+    for frame in stack.iter() {
+        match frame {
+            Frame::Object { key: Some(key), .. } => {
+                if !path.is_empty() {
+                    path.push('.');
+                }
+                path.push_str(key);
+            }
+            Frame::Object { key: None, .. } => {}
+            Frame::Array { index } => path.push_str(&format!("[{}]", index)),
+        }
+    }
+    path
+}
+
+struct Validator {
+    problems: Vec<ValidationProblem>,
+}
+
+impl Validator {
+    fn report(&mut self, json_path: impl Into<String>, reason: impl Into<String>) {
+        self.problems.push(ValidationProblem {
+            json_path: json_path.into(),
+            reason: reason.into(),
+        });
+    }
+
+    fn check(&mut self, condition: bool, json_path: impl Into<String>, reason: impl Into<String>) {
+        if !condition {
+            self.report(json_path, reason);
+        }
+    }
+
+    fn check_positive(&mut self, value: f32, json_path: impl Into<String>) {
+        self.check(value > 0.0 && value.is_finite(), json_path, format!("needs to be positive, is {}", value));
+    }
+
+    fn check_box(&mut self, min: cgmath::Point3<f32>, max: cgmath::Point3<f32>, json_path: &str) {
+        self.check(
+            min.x < max.x && min.y < max.y && min.z < max.z,
+            json_path,
+            format!("min {:?} needs to be smaller than max {:?} in all components", min, max),
+        );
+    }
+
+    // Checks that a world space box lies within the fluid domain.
+    fn check_in_domain(&mut self, min: cgmath::Point3<f32>, max: cgmath::Point3<f32>, domain_max: cgmath::Point3<f32>, json_path: &str) {
+        self.check(
+            min.x >= 0.0 && min.y >= 0.0 && min.z >= 0.0 && max.x <= domain_max.x && max.y <= domain_max.y && max.z <= domain_max.z,
+            json_path,
+            format!(
+                "bounds {:?} - {:?} are not within the fluid domain (0, 0, 0) - {:?}",
+                min, max, domain_max
+            ),
+        );
+    }
+}
+
+// Returns all problems found, empty if the config is fine.
+// Expects mesh paths to be already resolved relative to the scene file.
+pub fn validate(config: &SceneConfig) -> Vec<ValidationProblem> {
+    let mut v = Validator { problems: Vec::new() };
+    let fluid = &config.fluid;
+
+    v.check(
+        fluid.grid_dimension.x > 1 && fluid.grid_dimension.y > 1 && fluid.grid_dimension.z > 1,
+        "fluid.grid_dimension",
+        format!(
+            "needs to be at least 2 in every dimension (first cell is reserved for the boundary), is {:?}",
+            fluid.grid_dimension
+        ),
+    );
+    v.check_positive(fluid.grid_to_world_scale, "fluid.grid_to_world_scale");
+    v.check(fluid.max_num_particles > 0, "fluid.max_num_particles", "needs to be at least 1");
+    v.check_positive(fluid.density, "fluid.density");
+    v.check(
+        (0.0..=1.0).contains(&fluid.flip_blend_factor),
+        "fluid.flip_blend_factor",
+        format!("needs to be between 0 and 1, is {}", fluid.flip_blend_factor),
+    );
+    v.check(
+        fluid.viscosity >= 0.0,
+        "fluid.viscosity",
+        format!("can't be negative, is {}", fluid.viscosity),
+    );
+    v.check(
+        fluid.surface_tension >= 0.0,
+        "fluid.surface_tension",
+        format!("can't be negative, is {}", fluid.surface_tension),
+    );
+    v.check(
+        fluid.vorticity_confinement >= 0.0,
+        "fluid.vorticity_confinement",
+        format!("can't be negative, is {}", fluid.vorticity_confinement),
+    );
+    if let Some(reseeding) = fluid.reseeding.as_ref() {
+        v.check(
+            reseeding.min_particles_per_cell >= 1,
+            "fluid.reseeding.min_particles_per_cell",
+            "needs to be at least 1".to_string(),
+        );
+        v.check(
+            reseeding.max_particles_per_cell >= reseeding.min_particles_per_cell,
+            "fluid.reseeding.max_particles_per_cell",
+            format!(
+                "needs to be at least min_particles_per_cell ({}), is {}",
+                reseeding.min_particles_per_cell, reseeding.max_particles_per_cell
+            ),
+        );
+        v.check(
+            reseeding.max_particles_per_cell <= MAX_PARTICLES_PER_CELL,
+            "fluid.reseeding.max_particles_per_cell",
+            format!("can be at most {}, is {}", MAX_PARTICLES_PER_CELL, reseeding.max_particles_per_cell),
+        );
+    }
+    if !v.problems.is_empty() {
+        // Everything below depends on a valid grid.
+        return v.problems;
+    }
+
+    let scale = fluid.grid_to_world_scale;
+    let domain_max = cgmath::point3(
+        fluid.grid_dimension.x as f32 * scale,
+        fluid.grid_dimension.y as f32 * scale,
+        fluid.grid_dimension.z as f32 * scale,
+    );
+    let particles_per_volume = HybridFluid::PARTICLES_PER_GRID_CELL as f32 / scale.powi(3);
+    let mut estimated_num_particles = 0.0;
+
+    for (i, cube) in fluid.fluid_cubes.iter().enumerate() {
+        let path = format!("fluid.fluid_cubes[{}]", i);
+        v.check_box(cube.min, cube.max, &path);
+        v.check_in_domain(cube.min, cube.max, domain_max, &path);
+        // Cubes are filled up to whole cells, first cell excluded (see HybridFluid::add_fluid_volume).
+        let num_cells = |min: f32, max: f32, dimension: u32| {
+            let to_cell = |coordinate: f32| ((coordinate / scale) as u32).min(dimension).max(1);
+            to_cell(max).saturating_sub(to_cell(min)) as f32
+        };
+        estimated_num_particles += num_cells(cube.min.x, cube.max.x, fluid.grid_dimension.x)
+            * num_cells(cube.min.y, cube.max.y, fluid.grid_dimension.y)
+            * num_cells(cube.min.z, cube.max.z, fluid.grid_dimension.z)
+            * HybridFluid::PARTICLES_PER_GRID_CELL as f32;
+    }
+    for (i, volume) in fluid.fluid_volumes.iter().enumerate() {
+        let path = format!("fluid.fluid_volumes[{}]", i);
+        match volume.shape {
+            FluidShape::Box { min, max } => {
+                v.check_box(min, max, &path);
+                let extent = max - min;
+                estimated_num_particles += extent.x.max(0.0) * extent.y.max(0.0) * extent.z.max(0.0) * particles_per_volume;
+            }
+            FluidShape::Sphere { radius, .. } => {
+                v.check_positive(radius, format!("{}.radius", path));
+                estimated_num_particles += 4.0 / 3.0 * std::f32::consts::PI * radius.powi(3) * particles_per_volume;
+            }
+            FluidShape::Cylinder { start, end, radius } => {
+                v.check_positive(radius, format!("{}.radius", path));
+                v.check(start != end, format!("{}.end", path), "needs to differ from start");
+                let length = cgmath::MetricSpace::distance(start, end);
+                estimated_num_particles += std::f32::consts::PI * radius * radius * length * particles_per_volume;
+            }
+            FluidShape::Mesh {
+                path: ref mesh_path, scale, ..
+            } => {
+                v.check(mesh_path.is_file(), format!("{}.path", path), format!("{:?} is not a file", mesh_path));
+                v.check_positive(scale, format!("{}.scale", path));
+                // Volume and bounds of a mesh aren't known before voxelization, voxelization discards everything outside the domain.
+            }
+        }
+        if let Some((min, max)) = volume.shape.bounds() {
+            v.check_in_domain(min, max, domain_max, &path);
+        }
+    }
+    v.check(
+        estimated_num_particles <= fluid.max_num_particles as f32,
+        "fluid.max_num_particles",
+        format!(
+            "initial fluid needs about {:.0} particles, but max_num_particles is only {}",
+            estimated_num_particles, fluid.max_num_particles
+        ),
+    );
+
+    v.check(
+        fluid.emitters.len() <= MAX_NUM_EMITTERS,
+        "fluid.emitters",
+        format!("at most {} emitters are supported, got {}", MAX_NUM_EMITTERS, fluid.emitters.len()),
+    );
+    for (i, emitter) in fluid.emitters.iter().enumerate() {
+        let path = format!("fluid.emitters[{}]", i);
+        v.check(
+            emitter.rate >= 0.0 && emitter.rate.is_finite(),
+            format!("{}.rate", path),
+            format!("needs to be zero or positive, is {}", emitter.rate),
+        );
+        match emitter.shape {
+            EmitterShapeConfig::Box { min, max } => {
+                v.check_box(min, max, &path);
+                v.check_in_domain(min, max, domain_max, &path);
+            }
+            EmitterShapeConfig::Disc { center, normal, radius } => {
+                v.check_positive(radius, format!("{}.radius", path));
+                v.check(normal != cgmath::vec3(0.0, 0.0, 0.0), format!("{}.normal", path), "can't be zero");
+                let extent = cgmath::vec3(radius, radius, radius);
+                v.check_in_domain(center - extent, center + extent, domain_max, &path);
+            }
+        }
+    }
+    v.check(
+        fluid.drains.len() <= MAX_NUM_DRAINS,
+        "fluid.drains",
+        format!("at most {} drains are supported, got {}", MAX_NUM_DRAINS, fluid.drains.len()),
+    );
+    for (i, drain) in fluid.drains.iter().enumerate() {
+        v.check_box(drain.min, drain.max, &format!("fluid.drains[{}]", i));
+    }
+
+    for (i, solid) in config.solids.iter().enumerate() {
+        validate_solid_shape(&mut v, &solid.shape, &format!("solids[{}]", i));
+    }
+    for (i, obstacle) in config.obstacles.iter().enumerate() {
+        let path = format!("obstacles[{}]", i);
+        match obstacle.shape {
+            ObstacleShape::Box { min, max } => v.check_box(min, max, &path),
+            ObstacleShape::Sphere { radius, .. } | ObstacleShape::Capsule { radius, .. } => v.check_positive(radius, format!("{}.radius", path)),
+        }
+        for (track_name, times) in [
+            ("position_track", obstacle.position_track.iter().map(|k| k.time).collect::<Vec<_>>()),
+            ("rotation_track", obstacle.rotation_track.iter().map(|k| k.time).collect::<Vec<_>>()),
+        ]
+        .iter()
+        {
+            v.check(
+                times.windows(2).all(|pair| pair[0] < pair[1]),
+                format!("{}.{}", path, track_name),
+                "keyframe times need to be strictly increasing",
+            );
+        }
+    }
+    for (i, body) in config.rigid_bodies.iter().enumerate() {
+        let path = format!("rigid_bodies[{}]", i);
+        match body.shape {
+            RigidBodyShapeConfig::Box { min, max } => v.check_box(min, max, &path),
+            RigidBodyShapeConfig::Sphere { radius, .. } => v.check_positive(radius, format!("{}.radius", path)),
+        }
+        v.check_positive(body.density, format!("{}.density", path));
+        v.check(
+            body.damping >= 0.0 && body.damping.is_finite(),
+            format!("{}.damping", path),
+            format!("needs to be zero or positive, is {}", body.damping),
+        );
+    }
+    let num_dynamic_solids = config.obstacles.len() + config.rigid_bodies.len();
+    v.check(
+        num_dynamic_solids <= MAX_NUM_DYNAMIC_SOLIDS,
+        "obstacles",
+        format!(
+            "at most {} obstacles and rigid bodies together are supported, got {}",
+            MAX_NUM_DYNAMIC_SOLIDS, num_dynamic_solids
+        ),
+    );
+
+    for (i, preset) in config.camera_presets.iter().enumerate() {
+        validate_camera(&mut v, &preset.camera, &format!("camera_presets[{}]", i));
+    }
+    for (i, keyframe) in config.camera_path.iter().enumerate() {
+        validate_camera(&mut v, &keyframe.value, &format!("camera_path[{}].value", i));
+    }
+    v.check(
+        config.camera_path.windows(2).all(|pair| pair[0].time < pair[1].time),
+        "camera_path",
+        "keyframe times need to be strictly increasing",
+    );
+
+    if let Some(settings) = config.settings.as_ref() {
+        v.check(
+            settings.simulation_steps_per_second > 0,
+            "settings.simulation_steps_per_second",
+            "needs to be at least 1",
+        );
+        v.check_positive(settings.time_scale, "settings.time_scale");
+        v.check_positive(settings.adaptive_time_step.max_cfl_number, "settings.adaptive_time_step.max_cfl_number");
+        v.check(
+            settings.adaptive_time_step.max_simulation_steps_per_second >= settings.simulation_steps_per_second,
+            "settings.adaptive_time_step.max_simulation_steps_per_second",
+            "can't be lower than settings.simulation_steps_per_second",
+        );
+        validate_solver_config(&mut v, &settings.pressure_solver_velocity, "settings.pressure_solver_velocity");
+        validate_solver_config(&mut v, &settings.pressure_solver_density, "settings.pressure_solver_density");
+        if let Some(viscosity_solver) = settings.viscosity_solver.as_ref() {
+            validate_solver_config(&mut v, viscosity_solver, "settings.viscosity_solver");
+        }
+        validate_camera(&mut v, &settings.camera, "settings.camera");
+    }
+
+    v.problems
+}
+
+fn validate_camera(v: &mut Validator, camera: &CameraConfig, path: &str) {
+    v.check(
+        camera.direction != cgmath::vec3(0.0, 0.0, 0.0),
+        format!("{}.direction", path),
+        "can't be zero",
+    );
+    v.check(
+        camera.vertical_fov > 0.0 && camera.vertical_fov < 180.0,
+        format!("{}.vertical_fov", path),
+        format!("needs to be between 0 and 180 degrees, is {}", camera.vertical_fov),
+    );
+}
+
+fn validate_solver_config(v: &mut Validator, config: &SolverConfig, path: &str) {
+    v.check_positive(config.target_mse, format!("{}.target_mse", path));
+    v.check(
+        config.max_num_iterations > 0,
+        format!("{}.max_num_iterations", path),
+        "needs to be at least 1",
+    );
+    v.check(
+        config.mse_check_frequency > 0,
+        format!("{}.mse_check_frequency", path),
+        "needs to be at least 1",
+    );
+}
+
+fn validate_solid_shape(v: &mut Validator, shape: &SolidShape, path: &str) {
+    match *shape {
+        SolidShape::Box { min, max } => v.check_box(min, max, path),
+        SolidShape::Sphere { radius, .. } => v.check_positive(radius, format!("{}.radius", path)),
+        SolidShape::Capsule { radius, .. } => v.check_positive(radius, format!("{}.radius", path)),
+        SolidShape::Mesh {
+            path: ref mesh_path, scale, ..
+        } => {
+            v.check(mesh_path.is_file(), format!("{}.path", path), format!("{:?} is not a file", mesh_path));
+            v.check_positive(scale, format!("{}.scale", path));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Path at the first byte of `needle`, with line & column given the way serde_json counts them (1-based, columns in bytes).
+    fn path_at(json: &str, needle: &str) -> String {
+        let offset = json.find(needle).unwrap();
+        let line_start = json[..offset].rfind('\n').map_or(0, |newline| newline + 1);
+        json_path_at(json, json[..offset].matches('\n').count() + 1, offset - line_start + 1)
+    }
+
+    #[test]
+    fn nested_objects_and_arrays() {
+        let json = r#"{"fluid": {"cubes": [{"min": 1}, {"max": 2}]}, "gravity": 3}"#;
+        assert_eq!(path_at(json, "1"), "fluid.cubes[0].min");
+        assert_eq!(path_at(json, "2"), "fluid.cubes[1].max");
+        assert_eq!(path_at(json, "3"), "gravity");
+    }
+
+    #[test]
+    fn brackets_quotes_and_escapes_in_strings() {
+        let json = r#"{"a": "{[\"\\", "b": [1, 2], "we\"ird": {"x": 3}}"#;
+        assert_eq!(path_at(json, "2"), "b[1]");
+        assert_eq!(path_at(json, "3"), "we\"ird.x");
+    }
+
+    #[test]
+    fn columns_are_counted_in_bytes() {
+        let json = "{\n  \"name\": \"Überlauf ☃\", \"value\": [true, 3]\n}";
+        assert_eq!(path_at(json, "3"), "value[1]");
+    }
+}
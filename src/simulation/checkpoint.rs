@@ -0,0 +1,149 @@
+// On-disk format of fluid checkpoints, see HybridFluid::save_checkpoint & HybridFluid::load_checkpoint.
+//
+// A fixed size header is followed by the raw contents of the particle buffers (only the used part) and the pressure volumes.
+// Scene elements with state on the CPU (emitters and rigid bodies) come last, so that a checkpoint restarts the scene exactly where it left off.
+// Everything is stored in the native byte order, i.e. little endian on all platforms we care about.
+
+use super::hybrid_fluid::ParticlePositionLl;
+use std::{
+    fs::File,
+    io::{self, BufReader, BufWriter, Read, Write},
+    path::Path,
+};
+
+const MAGIC: [u8; 8] = *b"BLUBCKPT";
+// Bump whenever the layout of the header or the meaning of any stored data changes.
+const VERSION: u32 = 5;
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub(super) struct CheckpointHeader {
+    magic: [u8; 8],
+    version: u32,
+    pub grid_dimension: [u32; 3],
+    pub num_particles: u32,
+    pub num_steps: u32,
+    pub transfer_scheme: u32,
+    pub flip_blend_factor: f32,
+    pub gravity_grid: [f32; 3],
+    pub viscosity_grid: f32,
+    pub simulation_time_nanos: u64,
+    pub num_emitters: u32,
+    pub num_rigid_bodies: u32,
+    pub surface_tension_grid: f32,
+    pub vorticity_confinement: f32,
+    // Both zero if reseeding is disabled.
+    pub reseeding_min_particles_per_cell: u32,
+    pub reseeding_max_particles_per_cell: u32,
+}
+unsafe impl bytemuck::Pod for CheckpointHeader {}
+unsafe impl bytemuck::Zeroable for CheckpointHeader {}
+
+impl CheckpointHeader {
+    pub fn new() -> Self {
+        CheckpointHeader {
+            magic: MAGIC,
+            version: VERSION,
+            ..bytemuck::Zeroable::zeroed()
+        }
+    }
+}
+
+// Everything needed to continue a rigid body's motion, in world space. See RigidBody::checkpoint.
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub(super) struct RigidBodyCheckpoint {
+    pub position: [f32; 3],
+    pub rotation: [f32; 4],
+    pub linear_velocity: [f32; 3],
+    pub angular_velocity: [f32; 3],
+    pub fluid_force: [f32; 3],
+    pub fluid_torque: [f32; 3],
+}
+unsafe impl bytemuck::Pod for RigidBodyCheckpoint {}
+unsafe impl bytemuck::Zeroable for RigidBodyCheckpoint {}
+
+pub(super) struct Checkpoint {
+    pub header: CheckpointHeader,
+    pub particles_position_llindex: Vec<u8>,
+    pub particles_velocity: [Vec<u8>; 3],
+    pub pressure_from_velocity: Vec<u8>,
+    pub pressure_from_density: Vec<u8>,
+    // Particles each emitter has accumulated but not yet emitted.
+    pub emitter_fractional_particles: Vec<f32>,
+    pub rigid_bodies: Vec<RigidBodyCheckpoint>,
+}
+
+impl Checkpoint {
+    pub fn particle_buffer_sizes(header: &CheckpointHeader) -> (usize, usize) {
+        let num_particles = header.num_particles as usize;
+        (
+            num_particles * std::mem::size_of::<ParticlePositionLl>(),
+            num_particles * std::mem::size_of::<cgmath::Vector4<f32>>(),
+        )
+    }
+
+    pub fn volume_size(header: &CheckpointHeader) -> usize {
+        header.grid_dimension.iter().map(|d| *d as usize).product::<usize>() * std::mem::size_of::<f32>()
+    }
+
+    pub fn write(&self, path: &Path) -> io::Result<()> {
+        if let Some(directory) = path.parent() {
+            std::fs::create_dir_all(directory)?;
+        }
+        let mut writer = BufWriter::new(File::create(path)?);
+        writer.write_all(bytemuck::bytes_of(&self.header))?;
+        writer.write_all(&self.particles_position_llindex)?;
+        for velocity in self.particles_velocity.iter() {
+            writer.write_all(velocity)?;
+        }
+        writer.write_all(&self.pressure_from_velocity)?;
+        writer.write_all(&self.pressure_from_density)?;
+        writer.write_all(bytemuck::cast_slice(&self.emitter_fractional_particles))?;
+        writer.write_all(bytemuck::cast_slice(&self.rigid_bodies))?;
+        writer.flush()
+    }
+
+    pub fn read(path: &Path) -> io::Result<Self> {
+        let mut reader = BufReader::new(File::open(path)?);
+
+        let mut header: CheckpointHeader = bytemuck::Zeroable::zeroed();
+        reader.read_exact(bytemuck::bytes_of_mut(&mut header))?;
+        if header.magic != MAGIC {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "not a fluid checkpoint file"));
+        }
+        if header.version != VERSION {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("checkpoint has version {}, expected version {}", header.version, VERSION),
+            ));
+        }
+
+        let mut read_block = |size: usize| -> io::Result<Vec<u8>> {
+            let mut block = vec![0; size];
+            reader.read_exact(&mut block)?;
+            Ok(block)
+        };
+        let (position_size, velocity_size) = Self::particle_buffer_sizes(&header);
+        let volume_size = Self::volume_size(&header);
+        let particles_position_llindex = read_block(position_size)?;
+        let particles_velocity = [read_block(velocity_size)?, read_block(velocity_size)?, read_block(velocity_size)?];
+        let pressure_from_velocity = read_block(volume_size)?;
+        let pressure_from_density = read_block(volume_size)?;
+
+        let mut emitter_fractional_particles = vec![0.0; header.num_emitters as usize];
+        reader.read_exact(bytemuck::cast_slice_mut(&mut emitter_fractional_particles))?;
+        let mut rigid_bodies = vec![bytemuck::Zeroable::zeroed(); header.num_rigid_bodies as usize];
+        reader.read_exact(bytemuck::cast_slice_mut(&mut rigid_bodies))?;
+
+        Ok(Checkpoint {
+            header,
+            particles_position_llindex,
+            particles_velocity,
+            pressure_from_velocity,
+            pressure_from_density,
+            emitter_fractional_particles,
+            rigid_bodies,
+        })
+    }
+}
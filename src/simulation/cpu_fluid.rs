@@ -0,0 +1,812 @@
+// CPU reference for the simulation step of HybridFluid, runs without any GPU.
+// Mirrors the shaders in shader/simulation pass by pass (same linked lists, same boundary handling, same clamping),
+// so that differences to the GPU are down to floating point order and the pressure solver. The pressure solves use CpuPressureSystem, i.e. always PCG no matter the configured method.
+//
+// Only the core APIC/FLIP/PIC step is covered: no dynamic solids, emitters, drains, reseeding, viscosity, surface tension or vorticity confinement.
+// Volumes use the same memory layout as the GPU textures (x fastest, then y, then z).
+
+use super::cpu_pressure_solver::CpuPressureSystem;
+use super::fluid_backend::{fluid_volume_particles, FluidBackend};
+use super::hybrid_fluid::{HybridFluid, TransferScheme};
+use super::pressure_solver::{SolverConfig, SolverStatisticSample, SolverSystem, CELL_AIR, CELL_FLUID, CELL_SOLID};
+use cgmath::InnerSpace;
+use std::{io, time::Duration};
+
+// End of a particle linked list, see transfer_bindings.glsl
+const INVALID_LINKED_LIST_PTR: u32 = 0xFFFFFFFF;
+// The gather passes on the GPU walk at most this many particles of every linked list.
+const MAX_GATHERED_PARTICLES_PER_LIST: usize = 12;
+
+// Normal density of a cell with PARTICLES_PER_GRID_CELL particles, see density_projection_gather_error.comp
+const DENSITY0: f32 = HybridFluid::PARTICLES_PER_GRID_CELL as f32;
+const SOLID_NEIGHBOR_DENSITY_CONTRIBUTION: f32 = 0.77;
+
+const NEIGHBOR_OFFSETS: [[i32; 3]; 6] = [[1, 0, 0], [0, 1, 0], [0, 0, 1], [-1, 0, 0], [0, -1, 0], [0, 0, -1]];
+
+fn mix(a: f32, b: f32, t: f32) -> f32 {
+    a + (b - a) * t
+}
+
+fn saturate(x: f32) -> f32 {
+    x.max(0.0).min(1.0)
+}
+
+fn fract(x: f32) -> f32 {
+    x - x.floor()
+}
+
+// Corner values are indexed by bits, 1 is x, 2 is y, 4 is z.
+fn interpolate_trilinear(corners: &[f32; 8], interpolants: [f32; 3]) -> f32 {
+    let [x, y, z] = interpolants;
+    mix(
+        mix(mix(corners[0], corners[1], x), mix(corners[2], corners[3], x), y),
+        mix(mix(corners[4], corners[5], x), mix(corners[6], corners[7], x), y),
+        z,
+    )
+}
+
+// Differences between the opposing faces of the trilinear cell along each axis, the affine part of APIC. See advect_particles.comp
+fn trilinear_differences(corners: &[f32; 8], interpolants: [f32; 3]) -> [f32; 3] {
+    let [x, y, z] = interpolants;
+    let v_x00 = mix(corners[0], corners[1], x);
+    let v_x10 = mix(corners[2], corners[3], x);
+    let v_x01 = mix(corners[4], corners[5], x);
+    let v_x11 = mix(corners[6], corners[7], x);
+    let v_xy0 = mix(v_x00, v_x10, y);
+    let v_xy1 = mix(v_x01, v_x11, y);
+    [
+        mix(mix(corners[1], corners[3], y), mix(corners[5], corners[7], y), z)
+            - mix(mix(corners[0], corners[2], y), mix(corners[4], corners[6], y), z),
+        mix(v_x10, v_x11, z) - mix(v_x00, v_x01, z),
+        v_xy1 - v_xy0,
+    ]
+}
+
+fn interpolate_bilinear(p_00: f32, p_10: f32, p_01: f32, p_11: f32, interpolants: [f32; 2]) -> f32 {
+    mix(mix(p_00, p_10, interpolants[0]), mix(p_01, p_11, interpolants[0]), interpolants[1])
+}
+
+// Trilinear hat weight of a particle for a sample position, see transfer_gather_velocity.comp
+fn particle_weight(to_sample_position: cgmath::Vector3<f32>) -> f32 {
+    saturate(1.0 - to_sample_position.x.abs()) * saturate(1.0 - to_sample_position.y.abs()) * saturate(1.0 - to_sample_position.z.abs())
+}
+
+// Particles in linked lists per (dual) grid cell, built the same way as with imageAtomicExchange on the GPU.
+// The order within a list is arbitrary on the GPU, here the particle added last comes first.
+struct ParticleLinkedLists {
+    heads: Vec<u32>,
+    next: Vec<u32>,
+}
+
+impl ParticleLinkedLists {
+    fn new(num_cells: usize) -> Self {
+        ParticleLinkedLists {
+            heads: vec![INVALID_LINKED_LIST_PTR; num_cells],
+            next: Vec::new(),
+        }
+    }
+
+    fn clear(&mut self) {
+        for head in self.heads.iter_mut() {
+            *head = INVALID_LINKED_LIST_PTR;
+        }
+        self.next.clear();
+    }
+
+    fn push(&mut self, cell_index: usize, particle_index: usize) {
+        if self.next.len() <= particle_index {
+            self.next.resize(particle_index + 1, INVALID_LINKED_LIST_PTR);
+        }
+        self.next[particle_index] = self.heads[cell_index];
+        self.heads[cell_index] = particle_index as u32;
+    }
+
+    // Particles of a cell as far as the gather passes look at them.
+    fn iter(&self, cell_index: usize) -> impl Iterator<Item = usize> + '_ {
+        let mut particle_index = self.heads[cell_index];
+        std::iter::from_fn(move || {
+            if particle_index == INVALID_LINKED_LIST_PTR {
+                return None;
+            }
+            let current = particle_index as usize;
+            particle_index = self.next[current];
+            Some(current)
+        })
+        .take(MAX_GATHERED_PARTICLES_PER_LIST)
+    }
+}
+
+pub struct CpuFluid {
+    grid_dimension: wgpu::Extent3d,
+    max_num_particles: u32,
+
+    // Grid space, just like on the GPU.
+    particle_positions: Vec<cgmath::Point3<f32>>,
+    // One buffer per velocity component, same layout as on the GPU: row of the affine velocity matrix (APIC) in xyz, velocity in w.
+    particle_velocities: [Vec<cgmath::Vector4<f32>>; 3],
+    linked_lists: ParticleLinkedLists,
+
+    solid_distance: Vec<f32>,
+    marker: Vec<f32>,
+    // Staggered, component i of a cell is stored on its positive wall along axis i.
+    velocity: [Vec<f32>; 3],
+    // Velocity before forces were applied, only written with FLIP.
+    velocity_old: [Vec<f32>; 3],
+    // Kept from step to step as initial guess for the solver, like PressureField.
+    pressure_from_velocity: Vec<f32>,
+    pressure_from_density: Vec<f32>,
+
+    gravity_grid: cgmath::Vector3<f32>,
+    transfer_scheme: TransferScheme,
+    flip_blend_factor: f32,
+    pressure_solver_config_velocity: SolverConfig,
+    pressure_solver_config_density: SolverConfig,
+    pressure_solver_stats_velocity: SolverStatisticSample,
+    pressure_solver_stats_density: SolverStatisticSample,
+    max_particle_speed: f32,
+}
+
+impl CpuFluid {
+    pub fn new(grid_dimension: wgpu::Extent3d, max_num_particles: u32) -> Self {
+        let num_cells = (grid_dimension.width * grid_dimension.height * grid_dimension.depth) as usize;
+        let volume = || vec![0.0; num_cells];
+        CpuFluid {
+            grid_dimension,
+            max_num_particles,
+
+            particle_positions: Vec::new(),
+            particle_velocities: [Vec::new(), Vec::new(), Vec::new()],
+            linked_lists: ParticleLinkedLists::new(num_cells),
+
+            // Far away from any solid, see Scene::compute_solid_distance_field
+            solid_distance: vec![4.0; num_cells],
+            marker: vec![CELL_AIR; num_cells],
+            velocity: [volume(), volume(), volume()],
+            velocity_old: [volume(), volume(), volume()],
+            pressure_from_velocity: volume(),
+            pressure_from_density: volume(),
+
+            gravity_grid: cgmath::vec3(0.0, -9.81, 0.0),
+            transfer_scheme: TransferScheme::default(),
+            flip_blend_factor: 0.95,
+            pressure_solver_config_velocity: HybridFluid::DEFAULT_PRESSURE_SOLVER_CONFIG_VELOCITY,
+            pressure_solver_config_density: HybridFluid::DEFAULT_PRESSURE_SOLVER_CONFIG_DENSITY,
+            pressure_solver_stats_velocity: Default::default(),
+            pressure_solver_stats_density: Default::default(),
+            max_particle_speed: 0.0,
+        }
+    }
+
+    // Solver statistics of the last step.
+    pub fn pressure_solver_stats_velocity(&self) -> SolverStatisticSample {
+        self.pressure_solver_stats_velocity
+    }
+
+    pub fn pressure_solver_stats_density(&self) -> SolverStatisticSample {
+        self.pressure_solver_stats_density
+    }
+
+    // Largest particle speed of the last step in grid cells per second.
+    pub fn max_particle_speed_grid(&self) -> f32 {
+        self.max_particle_speed
+    }
+
+    fn grid_size(&self) -> cgmath::Vector3<f32> {
+        cgmath::vec3(
+            self.grid_dimension.width as f32,
+            self.grid_dimension.height as f32,
+            self.grid_dimension.depth as f32,
+        )
+    }
+
+    fn grid_coords(&self) -> impl Iterator<Item = [i32; 3]> {
+        let (width, height, depth) = (
+            self.grid_dimension.width as i32,
+            self.grid_dimension.height as i32,
+            self.grid_dimension.depth as i32,
+        );
+        (0..depth).flat_map(move |z| (0..height).flat_map(move |y| (0..width).map(move |x| [x, y, z])))
+    }
+
+    // None if outside of the grid.
+    fn cell_index(&self, coord: [i32; 3]) -> Option<usize> {
+        let [x, y, z] = coord;
+        if x < 0
+            || y < 0
+            || z < 0
+            || x >= self.grid_dimension.width as i32
+            || y >= self.grid_dimension.height as i32
+            || z >= self.grid_dimension.depth as i32
+        {
+            None
+        } else {
+            Some((x as u32 + self.grid_dimension.width * (y as u32 + self.grid_dimension.height * z as u32)) as usize)
+        }
+    }
+
+    // Cell containing a position, positions are always within the grid.
+    fn cell_index_at(&self, position: cgmath::Point3<f32>) -> usize {
+        self.cell_index([position.x as i32, position.y as i32, position.z as i32]).unwrap()
+    }
+
+    // Reading out of bounds on the GPU gives zero, which is a solid cell and a zero velocity.
+    fn marker_at(&self, coord: [i32; 3]) -> f32 {
+        self.cell_index(coord).map_or(CELL_SOLID, |index| self.marker[index])
+    }
+
+    fn sample_volume(&self, volume: &[f32], coord: [i32; 3]) -> f32 {
+        self.cell_index(coord).map_or(0.0, |index| volume[index])
+    }
+
+    // Trilinear interpolated signed distance to static solids, see sampleStaticSolidDistance in hybrid_fluid.glsl
+    fn sample_static_solid_distance(&self, position: cgmath::Point3<f32>) -> f32 {
+        let max_coord = [
+            self.grid_dimension.width as i32 - 1,
+            self.grid_dimension.height as i32 - 1,
+            self.grid_dimension.depth as i32 - 1,
+        ];
+        let offset_position = [position.x - 0.5, position.y - 0.5, position.z - 0.5];
+        let mut coord_min = [0; 3];
+        let mut coord_max = [0; 3];
+        let mut interpolants = [0.0; 3];
+        for axis in 0..3 {
+            let offset_position = offset_position[axis].max(0.0).min(max_coord[axis] as f32);
+            coord_min[axis] = offset_position as i32;
+            coord_max[axis] = (coord_min[axis] + 1).min(max_coord[axis]);
+            interpolants[axis] = offset_position - coord_min[axis] as f32;
+        }
+        let mut corners = [0.0; 8];
+        for (corner, value) in corners.iter_mut().enumerate() {
+            let coord = [
+                if corner & 1 != 0 { coord_max[0] } else { coord_min[0] },
+                if corner & 2 != 0 { coord_max[1] } else { coord_min[1] },
+                if corner & 4 != 0 { coord_max[2] } else { coord_min[2] },
+            ];
+            *value = self.sample_volume(&self.solid_distance, coord);
+        }
+        interpolate_trilinear(&corners, interpolants)
+    }
+
+    // Moves a position inside a static solid back to the surface, see pushOutOfStaticSolids in hybrid_fluid.glsl
+    fn push_out_of_solids(&self, position: cgmath::Point3<f32>) -> cgmath::Point3<f32> {
+        const SURFACE_MARGIN: f32 = 0.01;
+        let solid_distance = self.sample_static_solid_distance(position);
+        if solid_distance >= SURFACE_MARGIN {
+            return position;
+        }
+
+        const H: f32 = 0.5;
+        let gradient = cgmath::vec3(
+            self.sample_static_solid_distance(position + cgmath::vec3(H, 0.0, 0.0))
+                - self.sample_static_solid_distance(position - cgmath::vec3(H, 0.0, 0.0)),
+            self.sample_static_solid_distance(position + cgmath::vec3(0.0, H, 0.0))
+                - self.sample_static_solid_distance(position - cgmath::vec3(0.0, H, 0.0)),
+            self.sample_static_solid_distance(position + cgmath::vec3(0.0, 0.0, H))
+                - self.sample_static_solid_distance(position - cgmath::vec3(0.0, 0.0, H)),
+        );
+        let gradient_length_sq = gradient.magnitude2();
+        if gradient_length_sq < 1e-8 {
+            return position;
+        }
+        position + gradient * ((SURFACE_MARGIN - solid_distance) / gradient_length_sq.sqrt())
+    }
+
+    // Keeps particles out of the solid boundary layer at zero and inside the grid.
+    fn clamp_to_domain(&self, position: cgmath::Point3<f32>) -> cgmath::Point3<f32> {
+        let max = self.grid_size() - cgmath::vec3(0.0001, 0.0001, 0.0001);
+        cgmath::point3(
+            position.x.max(1.0001).min(max.x),
+            position.y.max(1.0001).min(max.y),
+            position.z.max(1.0001).min(max.z),
+        )
+    }
+
+    // Dual grid cell of a particle for velocity component i (or cell centers if None).
+    fn dual_cell_index(&self, position: cgmath::Point3<f32>, component: Option<usize>) -> usize {
+        let mut offset = cgmath::vec3(0.5, 0.5, 0.5);
+        if let Some(component) = component {
+            offset[component] = 1.0;
+        }
+        self.cell_index_at(position - offset)
+    }
+
+    // Everything at zero and inside static solids is solid, all other cells stay as they are. See transfer_set_boundary_marker.comp
+    fn set_boundary_marker(&mut self) {
+        for (index, coord) in self.grid_coords().enumerate() {
+            if coord.contains(&0) || self.solid_distance[index] <= 0.0 {
+                self.marker[index] = CELL_SOLID;
+            }
+        }
+    }
+
+    // Marks all cells with particles as fluid and sets up the linked lists for velocity component i. See transfer_build_linkedlist.comp
+    fn build_linked_lists(&mut self, component: usize) {
+        self.linked_lists.clear();
+        for particle_index in 0..self.particle_positions.len() {
+            let position = self.particle_positions[particle_index];
+            if component == 0 {
+                let cell_index = self.cell_index_at(position);
+                self.marker[cell_index] = CELL_FLUID;
+            }
+            let dual_cell_index = self.dual_cell_index(position, Some(component));
+            self.linked_lists.push(dual_cell_index, particle_index);
+        }
+    }
+
+    // Weighted sum over the particles in the 2x2x2 dual cells around a sample position, see transfer_gather_velocity.comp
+    fn gather(&self, coord: [i32; 3], mut contribution: impl FnMut(usize)) {
+        for offset in 0..8 {
+            let dual_coord = [coord[0] - (offset & 1), coord[1] - (offset >> 1 & 1), coord[2] - (offset >> 2 & 1)];
+            if let Some(dual_cell_index) = self.cell_index(dual_coord) {
+                for particle_index in self.linked_lists.iter(dual_cell_index) {
+                    contribution(particle_index);
+                }
+            }
+        }
+    }
+
+    // Particle to grid transfer of velocity component i including gravity and solid walls. See transfer_gather_velocity.comp
+    fn gather_velocity(&mut self, component: usize, simulation_delta: f32) {
+        let mut offset = [0; 3];
+        offset[component] = 1;
+
+        for (index, coord) in self.grid_coords().enumerate() {
+            let marker_a = self.marker[index];
+            let marker_b = self.marker_at([coord[0] + offset[0], coord[1] + offset[1], coord[2] + offset[2]]);
+            let writes_fluid = marker_a == CELL_FLUID || marker_b == CELL_FLUID;
+            // FLIP needs the old velocity everywhere particles might sample it.
+            let gathers = writes_fluid || self.transfer_scheme == TransferScheme::Flip;
+            if !gathers {
+                continue;
+            }
+
+            let mut sample_position = cgmath::point3(coord[0] as f32 + 0.5, coord[1] as f32 + 0.5, coord[2] as f32 + 0.5);
+            sample_position[component] += 0.5;
+            let mut velocity = 0.0;
+            let mut velocity_weight = 0.0;
+            self.gather(coord, |particle_index| {
+                let to_sample_position = sample_position - self.particle_positions[particle_index];
+                let weight = particle_weight(to_sample_position);
+                velocity += weight * self.particle_velocities[component][particle_index].dot(to_sample_position.extend(1.0));
+                velocity_weight += weight;
+            });
+            if velocity_weight > 0.0 {
+                velocity /= velocity_weight;
+            }
+            if self.transfer_scheme == TransferScheme::Flip {
+                self.velocity_old[component][index] = velocity;
+            }
+
+            if writes_fluid {
+                velocity += self.gravity_grid[component] * simulation_delta;
+                // Don't flow into solid. (there are no moving solids, so walls are at rest)
+                if marker_a == CELL_SOLID {
+                    velocity = velocity.max(0.0);
+                } else if marker_b == CELL_SOLID {
+                    velocity = velocity.min(0.0);
+                }
+                self.velocity[component][index] = velocity;
+            }
+        }
+    }
+
+    // Right hand side of the pressure solve, see divergence_compute.comp
+    fn compute_divergence(&self) -> Vec<f32> {
+        self.grid_coords()
+            .enumerate()
+            .map(|(index, coord)| {
+                if self.marker[index] != CELL_FLUID {
+                    return 0.0;
+                }
+                let mut divergence = 0.0;
+                for component in 0..3 {
+                    let mut neighbor_coord = coord;
+                    neighbor_coord[component] -= 1;
+                    let velocity_positive = self.velocity[component][index];
+                    let velocity_negative = self.sample_volume(&self.velocity[component], neighbor_coord);
+                    divergence += velocity_positive - velocity_negative;
+
+                    // Walls at rest.
+                    if self.marker_at(neighbor_coord) == CELL_SOLID {
+                        divergence += velocity_negative;
+                    }
+                    neighbor_coord[component] += 2;
+                    if self.marker_at(neighbor_coord) == CELL_SOLID {
+                        divergence -= velocity_positive;
+                    }
+                }
+                divergence
+            })
+            .collect()
+    }
+
+    fn solve_pressure(&self, rhs: &[f32], pressure: &mut [f32], config: &SolverConfig, simulation_delta: Duration) -> SolverStatisticSample {
+        CpuPressureSystem::new(self.grid_dimension, &self.marker, SolverSystem::Pressure, simulation_delta).solve_pcg(rhs, pressure, config)
+    }
+
+    // Subtracts the pressure gradient, velocities not next to any fluid become NaN. See divergence_remove.comp
+    fn remove_divergence(&mut self) {
+        let pressure_at = |marker: f32, index: Option<usize>, pressure: &[f32]| match index {
+            Some(index) if marker == CELL_FLUID => pressure[index],
+            _ => 0.0,
+        };
+        for (index, coord) in self.grid_coords().enumerate() {
+            let center_marker = self.marker[index];
+            let center_pressure = pressure_at(center_marker, Some(index), &self.pressure_from_velocity);
+            for component in 0..3 {
+                let mut neighbor_coord = coord;
+                neighbor_coord[component] += 1;
+                let neighbor_marker = self.marker_at(neighbor_coord);
+                let neighbor_pressure = pressure_at(neighbor_marker, self.cell_index(neighbor_coord), &self.pressure_from_velocity);
+
+                let velocity = &mut self.velocity[component][index];
+                if center_marker == CELL_FLUID || neighbor_marker == CELL_FLUID {
+                    if neighbor_marker == CELL_SOLID {
+                        *velocity = velocity.min(0.0);
+                    } else if center_marker == CELL_SOLID {
+                        *velocity = velocity.max(0.0);
+                    } else {
+                        *velocity -= center_pressure - neighbor_pressure;
+                    }
+                } else {
+                    *velocity = f32::NAN;
+                }
+            }
+        }
+    }
+
+    // Fills velocities that aren't next to fluid with the average of valid ones in the same plane. See extrapolate_velocity.comp
+    // Only ever reads valid velocities and only writes invalid ones, so this can work in place.
+    fn extrapolate_velocity(&mut self) {
+        for (index, coord) in self.grid_coords().enumerate() {
+            if self.marker[index] == CELL_FLUID {
+                continue;
+            }
+            for component in 0..3 {
+                let is_valid_velocity = |mut coord: [i32; 3]| {
+                    if self.marker_at(coord) == CELL_FLUID {
+                        return true;
+                    }
+                    coord[component] += 1;
+                    self.marker_at(coord) == CELL_FLUID
+                };
+
+                let mut opposite_coord = coord;
+                opposite_coord[component] += 1;
+                if self.marker_at(opposite_coord) == CELL_FLUID {
+                    continue;
+                }
+
+                let (axis_a, axis_b) = ((component + 1) % 3, (component + 2) % 3);
+                let mut num_valid = 0.0;
+                let mut sum = 0.0;
+                for a in -1..=1 {
+                    for b in -1..=1 {
+                        if a == 0 && b == 0 {
+                            continue;
+                        }
+                        let mut neighbor_coord = coord;
+                        neighbor_coord[axis_a] += a;
+                        neighbor_coord[axis_b] += b;
+                        if is_valid_velocity(neighbor_coord) {
+                            num_valid += 1.0;
+                            sum += self.sample_volume(&self.velocity[component], neighbor_coord);
+                        }
+                    }
+                }
+                if num_valid > 0.0 {
+                    self.velocity[component][index] = sum / num_valid;
+                }
+            }
+        }
+    }
+
+    // Samples the 8 velocity values around a particle for every component.
+    // Returns them along with the interpolants, see advect_particles.comp
+    fn velocity_corners(&self, volumes: &[Vec<f32>; 3], position: cgmath::Point3<f32>) -> ([[f32; 8]; 3], [[f32; 3]; 3]) {
+        let max_coord = [
+            self.grid_dimension.width as i32 - 1,
+            self.grid_dimension.height as i32 - 1,
+            self.grid_dimension.depth as i32 - 1,
+        ];
+        let mut corners = [[0.0; 8]; 3];
+        let mut interpolants = [[0.0; 3]; 3];
+        for component in 0..3 {
+            let mut offset_position = position - cgmath::vec3(0.5, 0.5, 0.5);
+            offset_position[component] -= 0.5;
+            let mut coord_min = [0; 3];
+            let mut coord_max = [0; 3];
+            for axis in 0..3 {
+                let offset_position = offset_position[axis].max(0.0);
+                coord_min[axis] = offset_position as i32;
+                coord_max[axis] = (coord_min[axis] + 1).min(max_coord[axis]);
+                interpolants[component][axis] = fract(offset_position);
+            }
+            for (corner, value) in corners[component].iter_mut().enumerate() {
+                let coord = [
+                    if corner & 1 != 0 { coord_max[0] } else { coord_min[0] },
+                    if corner & 2 != 0 { coord_max[1] } else { coord_min[1] },
+                    if corner & 4 != 0 { coord_max[2] } else { coord_min[2] },
+                ];
+                *value = self.sample_volume(&volumes[component], coord);
+            }
+        }
+        (corners, interpolants)
+    }
+
+    // Grid to particle transfer and RK4 advection, returns the largest speed involved. See advect_particles.comp
+    fn advect_particle(&mut self, particle_index: usize, simulation_delta: f32) -> f32 {
+        let original_position = self.particle_positions[particle_index];
+        let (corners, interpolants) = self.velocity_corners(&self.velocity, original_position);
+
+        let mut new_velocity = cgmath::vec3(0.0, 0.0, 0.0);
+        let mut affine = [[0.0; 3]; 3];
+        for component in 0..3 {
+            new_velocity[component] = interpolate_trilinear(&corners[component], interpolants[component]);
+            affine[component] = trilinear_differences(&corners[component], interpolants[component]);
+        }
+
+        let mut particle_velocity = new_velocity;
+        if self.transfer_scheme == TransferScheme::Flip {
+            let (old_corners, _) = self.velocity_corners(&self.velocity_old, original_position);
+            for component in 0..3 {
+                let old_velocity = interpolate_trilinear(&old_corners[component], interpolants[component]);
+                let previous_particle_velocity = self.particle_velocities[component][particle_index].w;
+                particle_velocity[component] = mix(
+                    new_velocity[component],
+                    previous_particle_velocity + new_velocity[component] - old_velocity,
+                    self.flip_blend_factor,
+                );
+            }
+        }
+
+        // Runge Kutta 4 confined to the cell sampled above.
+        // Like the shader, the movement along axis i offsets all interpolants of velocity component i.
+        let velocity_at_offset = |step: cgmath::Vector3<f32>| {
+            let mut velocity = cgmath::vec3(0.0, 0.0, 0.0);
+            for component in 0..3 {
+                let mut offset_interpolants = interpolants[component];
+                for interpolant in offset_interpolants.iter_mut() {
+                    *interpolant = saturate(*interpolant + step[component]);
+                }
+                velocity[component] = interpolate_trilinear(&corners[component], offset_interpolants);
+            }
+            velocity
+        };
+        let k1 = new_velocity;
+        let k2 = velocity_at_offset(simulation_delta * 0.5 * k1);
+        let k3 = velocity_at_offset(simulation_delta * 0.5 * k2);
+        let k4 = velocity_at_offset(simulation_delta * k3);
+        let total_movement = simulation_delta * (1.0 / 6.0) * (k1 + 2.0 * (k2 + k3) + k4);
+        let new_position = self.clamp_to_domain(self.push_out_of_solids(original_position + total_movement));
+
+        // New marker & linked lists for the density projection.
+        let cell_index = self.cell_index_at(new_position);
+        self.marker[cell_index] = CELL_FLUID;
+        let dual_cell_index = self.dual_cell_index(new_position, None);
+        self.linked_lists.push(dual_cell_index, particle_index);
+
+        self.particle_positions[particle_index] = new_position;
+        for component in 0..3 {
+            self.particle_velocities[component][particle_index] = if self.transfer_scheme == TransferScheme::Apic {
+                // affine[i] holds the derivatives of velocity component i along x/y/z.
+                cgmath::vec4(affine[component][0], affine[component][1], affine[component][2], new_velocity[component])
+            } else {
+                cgmath::vec4(0.0, 0.0, 0.0, particle_velocity[component])
+            };
+        }
+
+        new_velocity.magnitude().max(particle_velocity.magnitude())
+    }
+
+    // Deviation from the normal particle density in all fluid cells, right hand side of the density projection. See density_projection_gather_error.comp
+    fn gather_density_error(&self) -> Vec<f32> {
+        self.grid_coords()
+            .enumerate()
+            .map(|(index, coord)| {
+                if self.marker[index] != CELL_FLUID {
+                    return 0.0;
+                }
+
+                let sample_position = cgmath::point3(coord[0] as f32 + 0.5, coord[1] as f32 + 0.5, coord[2] as f32 + 0.5);
+                let mut density = 0.0;
+                self.gather(coord, |particle_index| {
+                    density += particle_weight(sample_position - self.particle_positions[particle_index]);
+                });
+
+                let mut has_air_neighbor = false;
+                for offset in NEIGHBOR_OFFSETS.iter() {
+                    let neighbor_marker = self.marker_at([coord[0] + offset[0], coord[1] + offset[1], coord[2] + offset[2]]);
+                    if neighbor_marker == CELL_SOLID {
+                        density += SOLID_NEIGHBOR_DENSITY_CONTRIBUTION;
+                    }
+                    has_air_neighbor |= neighbor_marker == CELL_AIR;
+                }
+                if has_air_neighbor {
+                    density = density.max(DENSITY0);
+                }
+                density = density.max(DENSITY0 * 0.5).min(DENSITY0 * 1.5);
+
+                DENSITY0 - density
+            })
+            .collect()
+    }
+
+    // Moves particles along the gradient of the pressure from the density error. See density_projection_correct_particles.comp
+    fn correct_particle_density_error(&mut self, particle_index: usize, simulation_delta: f32) {
+        let max_coord = [
+            self.grid_dimension.width as i32 - 1,
+            self.grid_dimension.height as i32 - 1,
+            self.grid_dimension.depth as i32 - 1,
+        ];
+        let original_position = self.particle_positions[particle_index];
+        let offset_position = original_position - cgmath::vec3(0.5, 0.5, 0.5);
+
+        // Clamped to 1, the boundary at zero is solid.
+        let mut coord_min = [0; 3];
+        let mut coord_max = [0; 3];
+        let mut interpolants = [0.0; 3];
+        for axis in 0..3 {
+            coord_min[axis] = offset_position[axis].max(1.0) as i32;
+            coord_max[axis] = (coord_min[axis] + 1).min(max_coord[axis]);
+            interpolants[axis] = fract(offset_position[axis]);
+        }
+        let mut p = [0.0; 8];
+        for (corner, value) in p.iter_mut().enumerate() {
+            let coord = [
+                if corner & 1 != 0 { coord_max[0] } else { coord_min[0] },
+                if corner & 2 != 0 { coord_max[1] } else { coord_min[1] },
+                if corner & 4 != 0 { coord_max[2] } else { coord_min[2] },
+            ];
+            let index = self.cell_index(coord).unwrap();
+            *value = if self.marker[index] == CELL_FLUID {
+                self.pressure_from_density[index]
+            } else {
+                0.0
+            };
+        }
+
+        let [x, y, z] = interpolants;
+        let gradient = cgmath::vec3(
+            interpolate_bilinear(p[1], p[3], p[5], p[7], [y, z]) - interpolate_bilinear(p[0], p[2], p[4], p[6], [y, z]),
+            interpolate_bilinear(p[2], p[3], p[6], p[7], [x, z]) - interpolate_bilinear(p[0], p[1], p[4], p[5], [x, z]),
+            interpolate_bilinear(p[4], p[5], p[6], p[7], [x, y]) - interpolate_bilinear(p[0], p[1], p[2], p[3], [x, y]),
+        );
+        let new_position = original_position + simulation_delta * gradient;
+        self.particle_positions[particle_index] = self.clamp_to_domain(self.push_out_of_solids(new_position));
+    }
+}
+
+impl<'a> FluidBackend<'a> for CpuFluid {
+    type SetupContext = ();
+    type StepContext = ();
+
+    fn grid_dimension(&self) -> wgpu::Extent3d {
+        self.grid_dimension
+    }
+
+    fn num_particles(&self) -> u32 {
+        self.particle_positions.len() as u32
+    }
+
+    fn add_fluid_volume(
+        &mut self,
+        _: (),
+        min_grid: cgmath::Point3<f32>,
+        max_grid: cgmath::Point3<f32>,
+        velocity: cgmath::Vector3<f32>,
+        contains: &dyn Fn(cgmath::Point3<f32>) -> bool,
+    ) {
+        let mut new_particles = fluid_volume_particles(self.grid_dimension, self.num_particles(), min_grid, max_grid, contains);
+        let num_particles = self.num_particles();
+        if self.max_num_particles < new_particles.len() as u32 + num_particles {
+            error!(
+                "Can't add {} particles, max is {}, current is {}",
+                new_particles.len(),
+                self.max_num_particles,
+                num_particles
+            );
+            new_particles.truncate((self.max_num_particles - num_particles) as usize);
+        }
+        info!("Adding {} new particles", new_particles.len());
+
+        for component in 0..3 {
+            self.particle_velocities[component].resize(
+                self.particle_positions.len() + new_particles.len(),
+                cgmath::vec4(0.0, 0.0, 0.0, velocity[component]),
+            );
+        }
+        self.particle_positions.extend(new_particles);
+        self.max_particle_speed = self.max_particle_speed.max(velocity.magnitude());
+    }
+
+    fn set_static_solids(&mut self, _: (), distance_field: &[f32]) {
+        assert_eq!(distance_field.len(), self.solid_distance.len());
+        self.solid_distance.copy_from_slice(distance_field);
+    }
+
+    fn set_gravity_grid(&mut self, gravity: cgmath::Vector3<f32>) {
+        self.gravity_grid = gravity;
+    }
+
+    fn set_transfer_scheme(&mut self, transfer_scheme: TransferScheme) {
+        self.transfer_scheme = transfer_scheme;
+    }
+
+    fn set_flip_blend_factor(&mut self, flip_blend_factor: f32) {
+        self.flip_blend_factor = flip_blend_factor.max(0.0).min(1.0);
+    }
+
+    fn pressure_solver_config_velocity(&mut self) -> &mut SolverConfig {
+        &mut self.pressure_solver_config_velocity
+    }
+
+    fn pressure_solver_config_density(&mut self) -> &mut SolverConfig {
+        &mut self.pressure_solver_config_density
+    }
+
+    // Same passes in the same order as HybridFluid::step
+    fn step(&mut self, _: (), simulation_delta: Duration) {
+        let step_delta = simulation_delta.as_secs_f32();
+
+        // Transfer particle velocity to grid.
+        for component in 0..3 {
+            if component == 0 {
+                for marker in self.marker.iter_mut() {
+                    *marker = CELL_AIR;
+                }
+            }
+            self.build_linked_lists(component);
+            if component == 0 {
+                self.set_boundary_marker();
+            }
+            self.gather_velocity(component, step_delta);
+        }
+
+        let divergence = self.compute_divergence();
+        let mut pressure = std::mem::take(&mut self.pressure_from_velocity);
+        self.pressure_solver_stats_velocity =
+            self.solve_pressure(&divergence, &mut pressure, &self.pressure_solver_config_velocity, simulation_delta);
+        self.pressure_from_velocity = pressure;
+        self.remove_divergence();
+        self.extrapolate_velocity();
+
+        // Advect particles & write new marker and linked lists.
+        for marker in self.marker.iter_mut() {
+            *marker = CELL_AIR;
+        }
+        self.linked_lists.clear();
+        self.max_particle_speed = 0.0;
+        for particle_index in 0..self.particle_positions.len() {
+            let speed = self.advect_particle(particle_index, step_delta);
+            self.max_particle_speed = self.max_particle_speed.max(speed);
+        }
+
+        // Density projection.
+        self.set_boundary_marker();
+        let density_error = self.gather_density_error();
+        let mut pressure = std::mem::take(&mut self.pressure_from_density);
+        self.pressure_solver_stats_density =
+            self.solve_pressure(&density_error, &mut pressure, &self.pressure_solver_config_density, simulation_delta);
+        self.pressure_from_density = pressure;
+        for particle_index in 0..self.particle_positions.len() {
+            self.correct_particle_density_error(particle_index, step_delta);
+        }
+    }
+
+    fn read_particles(&self, _: ()) -> io::Result<Vec<(cgmath::Point3<f32>, cgmath::Vector3<f32>)>> {
+        Ok(self
+            .particle_positions
+            .iter()
+            .enumerate()
+            .map(|(i, position)| {
+                let velocity = cgmath::vec3(
+                    self.particle_velocities[0][i].w,
+                    self.particle_velocities[1][i].w,
+                    self.particle_velocities[2][i].w,
+                );
+                (*position, velocity)
+            })
+            .collect())
+    }
+}
@@ -0,0 +1,425 @@
+// CPU reference for the linear systems solved by the PressureSolver.
+// Works on plain arrays in the same memory layout as the volumes (x fastest, then y, then z) and doesn't need a GPU.
+// Follows the shaders in shader/simulation/pressure_solver closely, but computes in double precision
+// so its results can be trusted when looking for errors in the GPU solver.
+
+use super::pressure_solver::{SolverConfig, SolverStatisticSample, SolverSystem, CELL_FLUID, CELL_SOLID};
+use std::time::Duration;
+
+const NEIGHBOR_OFFSETS: [[i32; 3]; 6] = [[-1, 0, 0], [1, 0, 0], [0, -1, 0], [0, 1, 0], [0, 0, -1], [0, 0, 1]];
+
+// The system (IdentityScale * I + LaplaceScale * L) x = b on a marker volume, see pressure.glsl
+pub struct CpuPressureSystem<'a> {
+    grid_dimension: wgpu::Extent3d,
+    marker: &'a [f32],
+    identity_scale: f64,
+    laplace_scale: f64,
+    neumann_boundary_marker: f32,
+    // Factor from the error we compute with to the user facing target error, see SolverSystem::error_scale
+    error_scale: f64,
+}
+
+// Differences between two pressure volumes, only fluid cells are taken into account.
+#[derive(Default, Debug, Clone, Copy)]
+pub struct PressureComparison {
+    pub max_abs_difference: f32,
+    pub rms_difference: f32,
+    pub max_abs_pressure: f32,
+}
+
+impl PressureComparison {
+    pub fn relative_difference(&self) -> f32 {
+        if self.max_abs_pressure > 0.0 {
+            self.max_abs_difference / self.max_abs_pressure
+        } else {
+            self.max_abs_difference
+        }
+    }
+}
+
+impl<'a> CpuPressureSystem<'a> {
+    pub(super) fn new(grid_dimension: wgpu::Extent3d, marker: &'a [f32], system: SolverSystem, simulation_delta: Duration) -> Self {
+        assert_eq!(
+            marker.len(),
+            (grid_dimension.width * grid_dimension.height * grid_dimension.depth) as usize
+        );
+        let (identity_scale, laplace_scale, neumann_boundary_marker) = system.coefficients(simulation_delta);
+        CpuPressureSystem {
+            grid_dimension,
+            marker,
+            identity_scale: identity_scale as f64,
+            laplace_scale: laplace_scale as f64,
+            neumann_boundary_marker,
+            error_scale: system.error_scale(simulation_delta) as f64,
+        }
+    }
+
+    pub fn num_cells(&self) -> usize {
+        self.marker.len()
+    }
+
+    pub fn is_fluid(&self, index: usize) -> bool {
+        self.marker[index] == CELL_FLUID
+    }
+
+    fn grid_coord(&self, index: usize) -> [i32; 3] {
+        let index = index as u32;
+        let x = index % self.grid_dimension.width;
+        let y = index / self.grid_dimension.width % self.grid_dimension.height;
+        let z = index / (self.grid_dimension.width * self.grid_dimension.height);
+        [x as i32, y as i32, z as i32]
+    }
+
+    // Index of the neighbor of a cell, None if outside of the grid.
+    fn neighbor(&self, index: usize, offset: [i32; 3]) -> Option<usize> {
+        let coord = self.grid_coord(index);
+        let (x, y, z) = (coord[0] + offset[0], coord[1] + offset[1], coord[2] + offset[2]);
+        if x < 0
+            || y < 0
+            || z < 0
+            || x >= self.grid_dimension.width as i32
+            || y >= self.grid_dimension.height as i32
+            || z >= self.grid_dimension.depth as i32
+        {
+            None
+        } else {
+            Some((x as u32 + self.grid_dimension.width * (y as u32 + self.grid_dimension.height * z as u32)) as usize)
+        }
+    }
+
+    // Like texelFetch on the marker volume, everything outside of the grid is solid.
+    fn neighbor_marker(&self, index: usize, offset: [i32; 3]) -> f32 {
+        self.neighbor(index, offset).map_or(CELL_SOLID, |neighbor| self.marker[neighbor])
+    }
+
+    fn fluid_neighbor(&self, index: usize, offset: [i32; 3]) -> Option<usize> {
+        self.neighbor(index, offset).filter(|neighbor| self.is_fluid(*neighbor))
+    }
+
+    // Diagonal entry of the coefficient matrix of a fluid cell.
+    fn diagonal(&self, index: usize) -> f64 {
+        let num_stencil_neighbors = NEIGHBOR_OFFSETS
+            .iter()
+            .filter(|offset| self.neighbor_marker(index, **offset) != self.neumann_boundary_marker)
+            .count();
+        self.identity_scale + self.laplace_scale * num_stencil_neighbors as f64
+    }
+
+    // Result of multiplying the coefficient matrix with a vector, zero outside of the fluid. See MultiplyWithCoefficientMatrix
+    fn apply_coefficient_matrix(&self, x: &[f64], result: &mut [f64]) {
+        for (index, result) in result.iter_mut().enumerate() {
+            *result = 0.0;
+            if !self.is_fluid(index) {
+                continue;
+            }
+            let diagonal = self.diagonal(index);
+            if diagonal > 0.0 {
+                *result += diagonal * x[index];
+            }
+            let neighbor_sum: f64 = NEIGHBOR_OFFSETS
+                .iter()
+                .filter_map(|offset| self.fluid_neighbor(index, *offset))
+                .map(|neighbor| x[neighbor])
+                .sum();
+            *result -= self.laplace_scale * neighbor_sum;
+        }
+    }
+
+    // Incomplete Poisson preconditioner in its symmetric form M^-1 = (I - L D^-1)(I - D^-1 L^T) with L being the strictly lower part of A.
+    // The shader applies a cheaper variant, so iterates differ but PCG converges to the same solution.
+    fn apply_preconditioner(&self, r: &[f64], z: &mut [f64]) {
+        let inverse_diagonal = |index: usize| {
+            let diagonal = self.diagonal(index);
+            if diagonal > 0.0 {
+                1.0 / diagonal
+            } else {
+                0.0
+            }
+        };
+
+        // t = (I - D^-1 L^T) r, L^T contains all upper neighbors with -LaplaceScale.
+        let mut t = vec![0.0; r.len()];
+        for index in (0..r.len()).filter(|index| self.is_fluid(*index)) {
+            let upper_neighbor_sum: f64 = NEIGHBOR_OFFSETS[1..]
+                .iter()
+                .step_by(2)
+                .filter_map(|offset| self.fluid_neighbor(index, *offset))
+                .map(|neighbor| r[neighbor])
+                .sum();
+            t[index] = r[index] + self.laplace_scale * inverse_diagonal(index) * upper_neighbor_sum;
+        }
+        // z = (I - L D^-1) t
+        for (index, z) in z.iter_mut().enumerate() {
+            *z = 0.0;
+            if !self.is_fluid(index) {
+                continue;
+            }
+            let lower_neighbor_sum: f64 = NEIGHBOR_OFFSETS
+                .iter()
+                .step_by(2)
+                .filter_map(|offset| self.fluid_neighbor(index, *offset))
+                .map(|neighbor| t[neighbor] * inverse_diagonal(neighbor))
+                .sum();
+            *z = t[index] + self.laplace_scale * lower_neighbor_sum;
+        }
+    }
+
+    fn dot(a: &[f64], b: &[f64]) -> f64 {
+        a.iter().zip(b.iter()).map(|(a, b)| a * b).sum()
+    }
+
+    // Division by dotproduct results as in pressure_reduce.comp, avoids NaN once everything is solved.
+    fn divide_by_dot(numerator: f64, dot: f64) -> f64 {
+        const EPSILON: f64 = 1e-10;
+        numerator / (dot + if dot < 0.0 { -EPSILON } else { EPSILON })
+    }
+
+    // Residual b - A x of a given solution, zero outside of the fluid. See pressure_init.comp
+    fn residual_f64(&self, rhs: &[f32], pressure: &[f64]) -> Vec<f64> {
+        let mut pressure_times_a = vec![0.0; pressure.len()];
+        self.apply_coefficient_matrix(pressure, &mut pressure_times_a);
+        (0..self.num_cells())
+            .map(|index| {
+                if self.is_fluid(index) {
+                    rhs[index] as f64 - pressure_times_a[index]
+                } else {
+                    0.0
+                }
+            })
+            .collect()
+    }
+
+    pub fn residual(&self, rhs: &[f32], pressure: &[f32]) -> Vec<f32> {
+        let pressure: Vec<f64> = pressure.iter().map(|p| *p as f64).collect();
+        self.residual_f64(rhs, &pressure).iter().map(|r| *r as f32).collect()
+    }
+
+    // Squared error of a given solution in the same units as SolverStatisticSample::mse
+    pub fn solution_error(&self, rhs: &[f32], pressure: &[f32]) -> f32 {
+        let pressure: Vec<f64> = pressure.iter().map(|p| *p as f64).collect();
+        let squared_error: f64 = self.residual_f64(rhs, &pressure).iter().map(|r| r * r).sum();
+        (squared_error * self.error_scale) as f32
+    }
+
+    // Preconditioned Conjugate Gradient with the same iteration control as the GPU solver, see pcg_solver.rs
+    // Pressure is the initial guess and is set to zero outside of the fluid.
+    pub fn solve_pcg(&self, rhs: &[f32], pressure: &mut [f32], config: &SolverConfig) -> SolverStatisticSample {
+        assert_eq!(rhs.len(), self.num_cells());
+        assert_eq!(pressure.len(), self.num_cells());
+
+        for (index, pressure) in pressure.iter_mut().enumerate() {
+            if !self.is_fluid(index) {
+                *pressure = 0.0;
+            }
+        }
+        let target_squared_error = config.target_mse as f64 / self.error_scale;
+
+        let mut p: Vec<f64> = pressure.iter().map(|p| *p as f64).collect();
+        let mut r = self.residual_f64(rhs, &p);
+        let mut z = vec![0.0; r.len()];
+        self.apply_preconditioner(&r, &mut z);
+        let mut s = z.clone();
+        let mut sigma = Self::dot(&z, &r);
+
+        let mut sample = SolverStatisticSample::default();
+        let mut i = 0;
+        loop {
+            self.apply_coefficient_matrix(&s, &mut z);
+            let alpha = Self::divide_by_dot(sigma, Self::dot(&s, &z));
+            for index in 0..p.len() {
+                p[index] += alpha * s[index];
+                r[index] -= alpha * z[index];
+            }
+
+            if config.is_mse_check_iteration(i) {
+                let squared_error = Self::dot(&r, &r);
+                if squared_error < target_squared_error || config.max_num_iterations == i {
+                    sample.mse = (squared_error * self.error_scale) as f32;
+                    sample.iteration_count = i;
+                    break;
+                }
+            }
+
+            self.apply_preconditioner(&r, &mut z);
+            let sigma_new = Self::dot(&z, &r);
+            let beta = Self::divide_by_dot(sigma_new, sigma);
+            for index in 0..s.len() {
+                s[index] = z[index] + beta * s[index];
+            }
+            sigma = sigma_new;
+            i += 1;
+        }
+
+        for (pressure, p) in pressure.iter_mut().zip(p.iter()) {
+            *pressure = *p as f32;
+        }
+        sample
+    }
+
+    pub fn compare(&self, pressure_a: &[f32], pressure_b: &[f32]) -> PressureComparison {
+        let mut comparison = PressureComparison::default();
+        let mut squared_difference_sum = 0.0;
+        let mut num_fluid_cells = 0;
+        for index in (0..self.num_cells()).filter(|index| self.is_fluid(*index)) {
+            let difference = (pressure_a[index] - pressure_b[index]).abs();
+            comparison.max_abs_difference = comparison.max_abs_difference.max(difference);
+            comparison.max_abs_pressure = comparison.max_abs_pressure.max(pressure_a[index].abs()).max(pressure_b[index].abs());
+            squared_difference_sum += difference as f64 * difference as f64;
+            num_fluid_cells += 1;
+        }
+        if num_fluid_cells > 0 {
+            comparison.rms_difference = (squared_difference_sum / num_fluid_cells as f64).sqrt() as f32;
+        }
+        comparison
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simulation::pressure_solver::{Preconditioner, SolverMethod, CELL_AIR};
+
+    const GRID_DIMENSION: wgpu::Extent3d = wgpu::Extent3d {
+        width: 10,
+        height: 8,
+        depth: 9,
+    };
+    const SIMULATION_DELTA: Duration = Duration::from_secs(1);
+
+    fn num_cells() -> usize {
+        (GRID_DIMENSION.width * GRID_DIMENSION.height * GRID_DIMENSION.depth) as usize
+    }
+
+    // Small version of the pool in pressure_solver_validation.rs: fluid with a wavy surface, air above and a solid block inside.
+    fn pool_marker() -> Vec<f32> {
+        let mut marker = Vec::new();
+        for z in 0..GRID_DIMENSION.depth {
+            for y in 0..GRID_DIMENSION.height {
+                for x in 0..GRID_DIMENSION.width {
+                    let surface_height = 5 + (x + z) % 2;
+                    marker.push(if (3..6).contains(&x) && (1..3).contains(&y) && (3..6).contains(&z) {
+                        CELL_SOLID
+                    } else if y < surface_height {
+                        CELL_FLUID
+                    } else {
+                        CELL_AIR
+                    });
+                }
+            }
+        }
+        marker
+    }
+
+    // Deterministic values in [-1, 1] for every cell.
+    fn pseudo_random_values(seed: usize) -> Vec<f32> {
+        (0..num_cells())
+            .map(|index| ((index * 7919 + seed * 104729) % 201) as f32 / 100.0 - 1.0)
+            .collect()
+    }
+
+    fn solver_config(target_mse: f32) -> SolverConfig {
+        SolverConfig {
+            target_mse,
+            max_num_iterations: 1000,
+            mse_check_frequency: 4,
+            preconditioner: Preconditioner::IncompletePoisson,
+            method: SolverMethod::Pcg,
+        }
+    }
+
+    #[test]
+    fn coefficient_matrix_is_symmetric() {
+        let marker = pool_marker();
+        for system in [SolverSystem::Pressure, SolverSystem::Viscosity(0.7)].iter() {
+            let cpu_system = CpuPressureSystem::new(GRID_DIMENSION, &marker, *system, SIMULATION_DELTA);
+            let x: Vec<f64> = pseudo_random_values(1).iter().map(|v| *v as f64).collect();
+            let y: Vec<f64> = pseudo_random_values(2).iter().map(|v| *v as f64).collect();
+            let mut ax = vec![0.0; num_cells()];
+            let mut ay = vec![0.0; num_cells()];
+            cpu_system.apply_coefficient_matrix(&x, &mut ax);
+            cpu_system.apply_coefficient_matrix(&y, &mut ay);
+
+            // Restricted to the fluid cells, which is the only part the matrix is defined on.
+            let fluid_dot = |a: &[f64], b: &[f64]| -> f64 { (0..num_cells()).filter(|i| cpu_system.is_fluid(*i)).map(|i| a[i] * b[i]).sum() };
+            let (xay, yax) = (fluid_dot(&x, &ay), fluid_dot(&y, &ax));
+            assert!(
+                (xay - yax).abs() <= 1e-9 * xay.abs().max(1.0),
+                "{:?}: x·Ay = {}, y·Ax = {}",
+                system,
+                xay,
+                yax
+            );
+        }
+    }
+
+    #[test]
+    fn pcg_reaches_target_error() {
+        let marker = pool_marker();
+        let rhs: Vec<f32> = pseudo_random_values(3)
+            .iter()
+            .zip(marker.iter())
+            .map(|(value, marker)| if *marker == CELL_FLUID { *value } else { 0.0 })
+            .collect();
+        let system = CpuPressureSystem::new(GRID_DIMENSION, &marker, SolverSystem::Pressure, SIMULATION_DELTA);
+        let config = solver_config(1.0e-7);
+
+        let mut pressure = vec![0.0; num_cells()];
+        let sample = system.solve_pcg(&rhs, &mut pressure, &config);
+
+        assert!(sample.iteration_count < config.max_num_iterations);
+        assert!(
+            sample.mse <= config.target_mse,
+            "mse {} after {} iterations",
+            sample.mse,
+            sample.iteration_count
+        );
+        let max_residual = system.residual(&rhs, &pressure).iter().fold(0.0f32, |max, r| max.max(r.abs()));
+        assert!(max_residual < 1.0e-3, "max residual {}", max_residual);
+        assert!(pressure.iter().all(|p| p.is_finite()));
+    }
+
+    #[test]
+    fn system_coefficients() {
+        let delta = Duration::from_millis(500);
+        assert_eq!(SolverSystem::Pressure.coefficients(delta), (0.0, 1.0, CELL_SOLID));
+        assert_eq!(SolverSystem::Pressure.error_scale(delta), 0.25);
+        assert_eq!(SolverSystem::Viscosity(4.0).coefficients(delta), (1.0, 2.0, CELL_AIR));
+        assert_eq!(SolverSystem::Viscosity(4.0).error_scale(delta), 1.0);
+
+        // Single fluid cell between air (-x) and solid (+x), everything outside of the grid is solid as well.
+        let dimension = wgpu::Extent3d {
+            width: 3,
+            height: 1,
+            depth: 1,
+        };
+        let marker = [CELL_AIR, CELL_FLUID, CELL_SOLID];
+        let diagonal = |system: SolverSystem| {
+            let mut result = [0.0; 3];
+            CpuPressureSystem::new(dimension, &marker, system, delta).apply_coefficient_matrix(&[0.0, 1.0, 0.0], &mut result);
+            result
+        };
+        // Pressure: zero gradient towards solids, so only the air neighbor is part of the stencil.
+        assert_eq!(diagonal(SolverSystem::Pressure), [0.0, 1.0, 0.0]);
+        // Viscosity: zero gradient towards air, the five solid neighbors are part of the stencil.
+        assert_eq!(diagonal(SolverSystem::Viscosity(4.0)), [0.0, 1.0 + 2.0 * 5.0, 0.0]);
+    }
+
+    #[test]
+    fn no_fluid() {
+        for fill in [CELL_AIR, CELL_SOLID].iter() {
+            let marker = vec![*fill; num_cells()];
+            let rhs = pseudo_random_values(4);
+            let system = CpuPressureSystem::new(GRID_DIMENSION, &marker, SolverSystem::Pressure, SIMULATION_DELTA);
+            let config = solver_config(1.0e-7);
+
+            let mut pressure = vec![1.0; num_cells()];
+            let sample = system.solve_pcg(&rhs, &mut pressure, &config);
+
+            assert_eq!(sample.mse, 0.0);
+            assert!(sample.iteration_count < config.max_num_iterations);
+            assert!(pressure.iter().all(|p| *p == 0.0));
+            assert!(system.residual(&rhs, &pressure).iter().all(|r| *r == 0.0));
+            assert_eq!(system.solution_error(&rhs, &pressure), 0.0);
+        }
+    }
+}
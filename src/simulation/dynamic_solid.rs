@@ -0,0 +1,123 @@
+// Moving solids that act as boundary for the fluid, e.g. keyframed obstacles.
+// Unlike static solids these are not voxelized but evaluated analytically in the shaders every step.
+
+use std::time::Duration;
+
+#[derive(Clone, Copy)]
+pub enum DynamicSolidShape {
+    Box { half_extent: cgmath::Vector3<f32> },
+    Sphere { radius: f32 },
+    // Line segment from -half_segment to half_segment (in local space) with a radius around it.
+    Capsule { half_segment: cgmath::Vector3<f32>, radius: f32 },
+}
+
+// All quantities are in grid space.
+#[derive(Clone, Copy)]
+pub struct DynamicSolid {
+    pub shape: DynamicSolidShape,
+    pub center: cgmath::Point3<f32>,
+    pub rotation: cgmath::Quaternion<f32>,
+    pub linear_velocity: cgmath::Vector3<f32>,
+    // Angular velocity around the center (axis scaled with radians per second)
+    pub angular_velocity: cgmath::Vector3<f32>,
+}
+
+pub const MAX_NUM_DYNAMIC_SOLIDS: usize = 16;
+
+// Need to match the defines in hybrid_fluid.glsl
+const SHAPE_BOX: u32 = 0;
+const SHAPE_SPHERE: u32 = 1;
+const SHAPE_CAPSULE: u32 = 2;
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct DynamicSolidUniformContent {
+    center: cgmath::Point3<f32>,
+    shape: u32,
+    inverse_rotation: cgmath::Vector4<f32>,
+    extent: cgmath::Vector3<f32>,
+    radius: f32,
+    linear_velocity: cgmath::Vector3<f32>,
+    padding0: f32,
+    angular_velocity: cgmath::Vector3<f32>,
+    padding1: f32,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub(super) struct DynamicSolidsUniformBufferContent {
+    num_solids: u32,
+    padding: [u32; 3],
+    solids: [DynamicSolidUniformContent; MAX_NUM_DYNAMIC_SOLIDS],
+}
+unsafe impl bytemuck::Pod for DynamicSolidsUniformBufferContent {}
+unsafe impl bytemuck::Zeroable for DynamicSolidsUniformBufferContent {}
+
+impl DynamicSolidsUniformBufferContent {
+    pub fn new(solids: &[DynamicSolid]) -> Self {
+        if solids.len() > MAX_NUM_DYNAMIC_SOLIDS {
+            warn!(
+                "{} dynamic solids given, but only up to {} are supported. Ignoring the rest.",
+                solids.len(),
+                MAX_NUM_DYNAMIC_SOLIDS
+            );
+        }
+
+        let mut content: Self = bytemuck::Zeroable::zeroed();
+        for (target, solid) in content.solids.iter_mut().zip(solids.iter()) {
+            let (shape, extent, radius) = match solid.shape {
+                DynamicSolidShape::Box { half_extent } => (SHAPE_BOX, half_extent, 0.0),
+                DynamicSolidShape::Sphere { radius } => (SHAPE_SPHERE, cgmath::vec3(0.0, 0.0, 0.0), radius),
+                DynamicSolidShape::Capsule { half_segment, radius } => (SHAPE_CAPSULE, half_segment, radius),
+            };
+            let inverse_rotation = solid.rotation.conjugate();
+            *target = DynamicSolidUniformContent {
+                center: solid.center,
+                shape,
+                inverse_rotation: inverse_rotation.v.extend(inverse_rotation.s),
+                extent,
+                radius,
+                linear_velocity: solid.linear_velocity,
+                padding0: 0.0,
+                angular_velocity: solid.angular_velocity,
+                padding1: 0.0,
+            };
+        }
+        content.num_solids = solids.len().min(MAX_NUM_DYNAMIC_SOLIDS) as u32;
+        content
+    }
+
+    pub fn num_solids(&self) -> u32 {
+        self.num_solids
+    }
+}
+
+// Force & torque the fluid pressure exerts on a dynamic solid.
+// In grid space, scaled with 1/density, i.e. multiply with density * grid_to_world_scale^4 (force) or ^5 (torque) to get to world units.
+#[derive(Clone, Copy)]
+pub struct FluidForce {
+    pub force: cgmath::Vector3<f32>,
+    pub torque: cgmath::Vector3<f32>,
+}
+
+// Meta data for a force read-back.
+pub(super) struct ForceReadbackInfo {
+    pub num_solids: u32,
+    pub simulation_delta: Duration,
+}
+
+// Size of the buffer dynamic_solid_forces.comp writes into: linear & angular impulse as vec4 for each solid.
+pub(super) const FORCE_BUFFER_SIZE: u64 = (MAX_NUM_DYNAMIC_SOLIDS * 2 * std::mem::size_of::<cgmath::Vector4<f32>>()) as u64;
+
+// Converts the read-back pressure impulses into forces.
+pub(super) fn forces_from_impulse_buffer(content: &[u8], info: &ForceReadbackInfo) -> Vec<FluidForce> {
+    let impulses: &[f32] = bytemuck::cast_slice(content);
+    let inv_delta = 1.0 / info.simulation_delta.as_secs_f32();
+    let read_vector = |offset: usize| cgmath::vec3(impulses[offset], impulses[offset + 1], impulses[offset + 2]) * inv_delta;
+    (0..info.num_solids as usize)
+        .map(|i| FluidForce {
+            force: read_vector(i * 8),
+            torque: read_vector(i * 8 + 4),
+        })
+        .collect()
+}
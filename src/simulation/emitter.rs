@@ -0,0 +1,219 @@
+use serde::{Deserialize, Serialize};
+
+// Emitters continuously add particles to the fluid, drains remove them.
+// Both run entirely on the GPU, the CPU only decides how many particles each emitter spawns in a step.
+// Reseeding shares the same passes to keep the number of particles per cell within bounds.
+
+#[derive(Clone, Copy)]
+pub enum EmitterShape {
+    // Particles are spawned uniformly within the box.
+    Box { half_extent: cgmath::Vector3<f32> },
+    // Particles are spawned on a disc (slightly spread out along the normal by the distance they travel in a step).
+    Disc { normal: cgmath::Vector3<f32>, radius: f32 },
+}
+
+// All quantities are in grid space.
+#[derive(Clone, Copy)]
+pub struct ParticleEmitter {
+    pub shape: EmitterShape,
+    pub center: cgmath::Point3<f32>,
+    // Initial velocity of all new particles.
+    pub velocity: cgmath::Vector3<f32>,
+    // Number of particles per second.
+    pub rate: f32,
+}
+
+// Removes all particles within a box. Coordinates are in grid space.
+#[derive(Clone, Copy)]
+pub struct ParticleDrain {
+    pub min: cgmath::Point3<f32>,
+    pub max: cgmath::Point3<f32>,
+}
+
+// Bounds for the number of particles per (linked list) grid cell.
+// Cells inside the fluid with fewer particles get new ones, cells with more lose the surplus.
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+pub struct ParticleReseeding {
+    pub min_particles_per_cell: u32,
+    pub max_particles_per_cell: u32,
+}
+
+impl Default for ParticleReseeding {
+    fn default() -> Self {
+        // Initial fluid volumes spawn 8 particles per cell.
+        ParticleReseeding {
+            min_particles_per_cell: 4,
+            max_particles_per_cell: 16,
+        }
+    }
+}
+
+// Reseeding only looks at this many particles per cell. Needs to match MAX_WALKED_PARTICLES in particle_sources_reseed.comp
+pub const MAX_PARTICLES_PER_CELL: u32 = 64;
+
+pub const MAX_NUM_EMITTERS: usize = 8;
+pub const MAX_NUM_DRAINS: usize = 8;
+
+// Need to match the defines in particle_sources.glsl
+const EMITTER_SHAPE_BOX: u32 = 0;
+const EMITTER_SHAPE_DISC: u32 = 1;
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct EmitterUniformContent {
+    center: cgmath::Point3<f32>,
+    shape: u32,
+    extent: cgmath::Vector3<f32>, // Half extent for boxes, normal for discs.
+    radius: f32,
+    velocity: cgmath::Vector3<f32>,
+    first_particle: u32,
+    num_particles: u32,
+    padding: [u32; 3],
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct DrainUniformContent {
+    min: cgmath::Point3<f32>,
+    padding0: f32,
+    max: cgmath::Point3<f32>,
+    padding1: f32,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub(super) struct ParticleSourcesUniformBufferContent {
+    num_emitters: u32,
+    num_drains: u32,
+    num_emitted_particles: u32,
+    random_seed: u32,
+    min_particles_per_cell: u32,
+    max_particles_per_cell: u32,
+    padding: [u32; 2],
+    emitters: [EmitterUniformContent; MAX_NUM_EMITTERS],
+    drains: [DrainUniformContent; MAX_NUM_DRAINS],
+}
+unsafe impl bytemuck::Pod for ParticleSourcesUniformBufferContent {}
+unsafe impl bytemuck::Zeroable for ParticleSourcesUniformBufferContent {}
+
+// Layout of the particle count buffer, needs to match ParticleCountBuffer in particle_sources.glsl
+// Leads with the indirect dispatch arguments for all per-particle passes, followed by the particle count and the draw arguments for renderers.
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub(super) struct ParticleCountBufferContent {
+    dispatch_x: u32,
+    dispatch_y: u32,
+    dispatch_z: u32,
+    num_particles: u32,
+    draw_vertex_count: u32,
+    draw_instance_count: u32,
+    draw_first_vertex: u32,
+    draw_first_instance: u32,
+    num_removed_particles: u32,
+    num_holes: u32,
+    num_movers: u32,
+    num_reseeded_particles: u32,
+}
+unsafe impl bytemuck::Pod for ParticleCountBufferContent {}
+unsafe impl bytemuck::Zeroable for ParticleCountBufferContent {}
+
+impl ParticleCountBufferContent {
+    pub const DISPATCH_ARGS_OFFSET: u64 = 0;
+    pub const NUM_PARTICLES_OFFSET: u64 = 12;
+    pub const DRAW_ARGS_OFFSET: u64 = 16;
+
+    pub fn new(num_particles: u32, compute_local_size_particles: u32) -> Self {
+        ParticleCountBufferContent {
+            dispatch_x: (num_particles + compute_local_size_particles - 1) / compute_local_size_particles,
+            dispatch_y: 1,
+            dispatch_z: 1,
+            num_particles,
+            draw_vertex_count: 4,
+            draw_instance_count: num_particles,
+            draw_first_vertex: 0,
+            draw_first_instance: 0,
+            num_removed_particles: 0,
+            num_holes: 0,
+            num_movers: 0,
+            num_reseeded_particles: 0,
+        }
+    }
+}
+
+// Keeps track of fractional particles between steps so that low rates and small time steps still emit the right amount.
+pub(super) struct EmitterState {
+    emitter: ParticleEmitter,
+    pub fractional_particles: f32,
+}
+
+impl EmitterState {
+    pub fn new(emitter: ParticleEmitter) -> Self {
+        EmitterState {
+            emitter,
+            fractional_particles: 0.0,
+        }
+    }
+}
+
+impl ParticleSourcesUniformBufferContent {
+    pub fn new(
+        emitters: &mut [EmitterState],
+        drains: &[ParticleDrain],
+        reseeding: Option<ParticleReseeding>,
+        simulation_delta: std::time::Duration,
+        random_seed: u32,
+    ) -> Self {
+        let mut content: Self = bytemuck::Zeroable::zeroed();
+
+        for (target, state) in content.emitters.iter_mut().zip(emitters.iter_mut()) {
+            state.fractional_particles += state.emitter.rate * simulation_delta.as_secs_f32();
+            let num_particles = state.fractional_particles.floor();
+            state.fractional_particles -= num_particles;
+
+            let (shape, extent, radius) = match state.emitter.shape {
+                EmitterShape::Box { half_extent } => (EMITTER_SHAPE_BOX, half_extent, 0.0),
+                EmitterShape::Disc { normal, radius } => (EMITTER_SHAPE_DISC, normal, radius),
+            };
+            *target = EmitterUniformContent {
+                center: state.emitter.center,
+                shape,
+                extent,
+                radius,
+                velocity: state.emitter.velocity,
+                first_particle: content.num_emitted_particles,
+                num_particles: num_particles as u32,
+                padding: [0; 3],
+            };
+            content.num_emitted_particles += num_particles as u32;
+        }
+        for (target, drain) in content.drains.iter_mut().zip(drains.iter()) {
+            *target = DrainUniformContent {
+                min: drain.min,
+                padding0: 0.0,
+                max: drain.max,
+                padding1: 0.0,
+            };
+        }
+
+        content.num_emitters = emitters.len().min(MAX_NUM_EMITTERS) as u32;
+        content.num_drains = drains.len().min(MAX_NUM_DRAINS) as u32;
+        content.random_seed = random_seed;
+        if let Some(reseeding) = reseeding {
+            content.min_particles_per_cell = reseeding.min_particles_per_cell;
+            content.max_particles_per_cell = reseeding.max_particles_per_cell;
+        }
+        content
+    }
+
+    pub fn num_emitted_particles(&self) -> u32 {
+        self.num_emitted_particles
+    }
+
+    pub fn num_drains(&self) -> u32 {
+        self.num_drains
+    }
+
+    pub fn is_reseeding(&self) -> bool {
+        self.max_particles_per_cell > 0
+    }
+}
@@ -0,0 +1,309 @@
+// Interface shared by the GPU simulation (HybridFluid) and its CPU reference (CpuFluid).
+// Covers everything needed to set up a fluid, step it and look at the result, so the same scene can run on either and the results can be compared.
+// All coordinates are in grid space.
+
+use super::hybrid_fluid::{HybridFluid, TransferScheme};
+use super::pressure_solver::SolverConfig;
+use crate::wgpu_utils::pipelines::PipelineManager;
+use rand::prelude::*;
+use std::{io, time::Duration};
+
+pub trait FluidBackend<'a> {
+    // Resources needed to add particles and solids. The GPU needs a queue, the CPU nothing.
+    type SetupContext: Copy;
+    // Resources needed to step the fluid and read back particles.
+    type StepContext: Copy;
+
+    fn grid_dimension(&self) -> wgpu::Extent3d;
+    fn num_particles(&self) -> u32;
+
+    // Adds particles to all cells between min_grid and max_grid for which `contains` is true at the particle position, see HybridFluid::add_fluid_volume
+    fn add_fluid_volume(
+        &mut self,
+        context: Self::SetupContext,
+        min_grid: cgmath::Point3<f32>,
+        max_grid: cgmath::Point3<f32>,
+        velocity: cgmath::Vector3<f32>,
+        contains: &dyn Fn(cgmath::Point3<f32>) -> bool,
+    );
+    // Signed distance (in grid cells, negative inside) to the closest static solid for every grid cell center.
+    fn set_static_solids(&mut self, context: Self::SetupContext, distance_field: &[f32]);
+
+    fn set_gravity_grid(&mut self, gravity: cgmath::Vector3<f32>);
+    fn set_transfer_scheme(&mut self, transfer_scheme: TransferScheme);
+    fn set_flip_blend_factor(&mut self, flip_blend_factor: f32);
+    fn pressure_solver_config_velocity(&mut self) -> &mut SolverConfig;
+    fn pressure_solver_config_density(&mut self) -> &mut SolverConfig;
+
+    fn step(&mut self, context: Self::StepContext, simulation_delta: Duration);
+    // Positions and velocities (grid cells per second) of all particles, in the order they were added.
+    fn read_particles(&self, context: Self::StepContext) -> io::Result<Vec<(cgmath::Point3<f32>, cgmath::Vector3<f32>)>>;
+}
+
+#[derive(Clone, Copy)]
+pub struct GpuStepContext<'a> {
+    pub device: &'a wgpu::Device,
+    pub queue: &'a wgpu::Queue,
+    pub pipeline_manager: &'a PipelineManager,
+    pub per_frame_bind_group: &'a wgpu::BindGroup,
+}
+
+impl<'a> FluidBackend<'a> for HybridFluid {
+    type SetupContext = &'a wgpu::Queue;
+    type StepContext = GpuStepContext<'a>;
+
+    fn grid_dimension(&self) -> wgpu::Extent3d {
+        HybridFluid::grid_dimension(self)
+    }
+
+    fn num_particles(&self) -> u32 {
+        HybridFluid::num_particles(self)
+    }
+
+    fn add_fluid_volume(
+        &mut self,
+        queue: &'a wgpu::Queue,
+        min_grid: cgmath::Point3<f32>,
+        max_grid: cgmath::Point3<f32>,
+        velocity: cgmath::Vector3<f32>,
+        contains: &dyn Fn(cgmath::Point3<f32>) -> bool,
+    ) {
+        HybridFluid::add_fluid_volume(self, queue, min_grid, max_grid, velocity, contains);
+    }
+
+    fn set_static_solids(&mut self, queue: &'a wgpu::Queue, distance_field: &[f32]) {
+        HybridFluid::set_static_solids(self, queue, distance_field);
+    }
+
+    fn set_gravity_grid(&mut self, gravity: cgmath::Vector3<f32>) {
+        HybridFluid::set_gravity_grid(self, gravity);
+    }
+
+    fn set_transfer_scheme(&mut self, transfer_scheme: TransferScheme) {
+        HybridFluid::set_transfer_scheme(self, transfer_scheme);
+    }
+
+    fn set_flip_blend_factor(&mut self, flip_blend_factor: f32) {
+        HybridFluid::set_flip_blend_factor(self, flip_blend_factor);
+    }
+
+    fn pressure_solver_config_velocity(&mut self) -> &mut SolverConfig {
+        HybridFluid::pressure_solver_config_velocity(self)
+    }
+
+    fn pressure_solver_config_density(&mut self) -> &mut SolverConfig {
+        HybridFluid::pressure_solver_config_density(self)
+    }
+
+    // Submits the step right away.
+    fn step(&mut self, context: GpuStepContext<'a>, simulation_delta: Duration) {
+        let mut encoder = context.device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Encoder: Fluid Step"),
+        });
+        HybridFluid::step(
+            self,
+            simulation_delta,
+            &mut encoder,
+            context.pipeline_manager,
+            context.queue,
+            context.per_frame_bind_group,
+        );
+        context.queue.submit(Some(encoder.finish()));
+        self.update_statistics();
+    }
+
+    fn read_particles(&self, context: GpuStepContext<'a>) -> io::Result<Vec<(cgmath::Point3<f32>, cgmath::Vector3<f32>)>> {
+        HybridFluid::read_particles(self, context.device, context.queue)
+    }
+}
+
+// Positions of the particles for filling all cells between min_grid and max_grid for which `contains` is true at the particle position.
+// Deterministic for a given number of existing particles, so all backends start out with the very same particles.
+pub(super) fn fluid_volume_particles(
+    grid_dimension: wgpu::Extent3d,
+    num_existing_particles: u32,
+    min_grid: cgmath::Point3<f32>,
+    max_grid: cgmath::Point3<f32>,
+    contains: impl Fn(cgmath::Point3<f32>) -> bool,
+) -> Vec<cgmath::Point3<f32>> {
+    // Due to the design of the grid, the 0-1 range is reserved by solid cells and can't be filled.
+    let clamp_to_grid = |grid_cor: cgmath::Point3<f32>| {
+        cgmath::Point3::new(
+            grid_dimension.width.min(grid_cor.x as u32).max(1),
+            grid_dimension.height.min(grid_cor.y as u32).max(1),
+            grid_dimension.depth.min(grid_cor.z as u32).max(1),
+        )
+    };
+    // align to whole cells for simplicity.
+    let min_grid = clamp_to_grid(min_grid);
+    let max_grid = clamp_to_grid(max_grid);
+
+    let mut rng: rand::rngs::SmallRng = rand::SeedableRng::seed_from_u64(num_existing_particles as u64);
+    let mut positions = Vec::new();
+    for z in min_grid.z..max_grid.z {
+        for y in min_grid.y..max_grid.y {
+            for x in min_grid.x..max_grid.x {
+                let cell = cgmath::point3(x as f32, y as f32, z as f32);
+                for sample_idx in 0..HybridFluid::PARTICLES_PER_GRID_CELL {
+                    // pure random
+                    // let offset = rng.gen::<cgmath::Vector3<f32>>();
+                    // pure regular
+                    // let offset = cgmath::vec3(
+                    //     (sample_idx % 2) as f32 + 0.5,
+                    //     (sample_idx / 2 % 2) as f32 + 0.5,
+                    //     (sample_idx / 4 % 2) as f32 + 0.5,
+                    // ) * 0.5;
+                    // stratified
+                    let offset = cgmath::vec3((sample_idx % 2) as f32, (sample_idx / 2 % 2) as f32, (sample_idx / 4 % 2) as f32) * 0.5
+                        + rng.gen::<cgmath::Vector3<f32>>() * 0.5;
+                    let position = cell + offset;
+                    if contains(position) {
+                        positions.push(position);
+                    }
+                }
+            }
+        }
+    }
+    positions
+}
+
+// Differences between the particles of two fluids. Particles are matched by index.
+#[derive(Default, Debug, Clone, Copy)]
+pub struct ParticleComparison {
+    pub num_particles: [usize; 2],
+    // In grid cells.
+    pub max_position_difference: f32,
+    pub rms_position_difference: f32,
+    // In grid cells per second.
+    pub max_velocity_difference: f32,
+}
+
+impl ParticleComparison {
+    pub fn new(particles_a: &[(cgmath::Point3<f32>, cgmath::Vector3<f32>)], particles_b: &[(cgmath::Point3<f32>, cgmath::Vector3<f32>)]) -> Self {
+        use cgmath::InnerSpace;
+
+        let mut comparison = ParticleComparison {
+            num_particles: [particles_a.len(), particles_b.len()],
+            ..Default::default()
+        };
+        // f32::max ignores NaN, but a particle that went astray should never go unnoticed.
+        let difference = |difference: f32| if difference.is_nan() { f32::INFINITY } else { difference };
+        let mut squared_difference_sum = 0.0;
+        for ((position_a, velocity_a), (position_b, velocity_b)) in particles_a.iter().zip(particles_b.iter()) {
+            let position_difference = difference((position_a - position_b).magnitude());
+            comparison.max_position_difference = comparison.max_position_difference.max(position_difference);
+            comparison.max_velocity_difference = comparison.max_velocity_difference.max(difference((velocity_a - velocity_b).magnitude()));
+            squared_difference_sum += position_difference as f64 * position_difference as f64;
+        }
+        let num_compared = particles_a.len().min(particles_b.len());
+        if num_compared > 0 {
+            comparison.rms_position_difference = (squared_difference_sum / num_compared as f64).sqrt() as f32;
+        }
+        comparison
+    }
+
+    pub fn within(&self, max_position_difference: f32) -> bool {
+        self.num_particles[0] == self.num_particles[1] && self.max_position_difference <= max_position_difference
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GRID_DIMENSION: wgpu::Extent3d = wgpu::Extent3d {
+        width: 16,
+        height: 16,
+        depth: 16,
+    };
+
+    fn sphere_particles(num_existing_particles: u32) -> Vec<cgmath::Point3<f32>> {
+        use cgmath::MetricSpace;
+        let center = cgmath::point3(8.0, 8.0, 8.0);
+        fluid_volume_particles(
+            GRID_DIMENSION,
+            num_existing_particles,
+            cgmath::point3(4.0, 4.0, 4.0),
+            cgmath::point3(12.0, 12.0, 12.0),
+            |p| p.distance(center) < 4.0,
+        )
+    }
+
+    #[test]
+    fn fluid_volume_particles_are_deterministic() {
+        assert_eq!(sphere_particles(0), sphere_particles(0));
+        assert_eq!(sphere_particles(100), sphere_particles(100));
+        assert_ne!(sphere_particles(0), sphere_particles(100));
+    }
+
+    #[test]
+    fn fluid_volume_particles_fill_whole_cells() {
+        let particles = fluid_volume_particles(GRID_DIMENSION, 0, cgmath::point3(2.0, 3.0, 4.0), cgmath::point3(4.0, 5.0, 6.0), |_| true);
+        assert_eq!(particles.len(), 8 * HybridFluid::PARTICLES_PER_GRID_CELL as usize);
+        for p in particles.iter() {
+            assert!(
+                p.x >= 2.0 && p.y >= 3.0 && p.z >= 4.0 && p.x <= 4.0 && p.y <= 5.0 && p.z <= 6.0,
+                "{:?}",
+                p
+            );
+        }
+
+        // The first cell is reserved for the boundary, everything past the grid is clamped away.
+        let particles = fluid_volume_particles(
+            GRID_DIMENSION,
+            0,
+            cgmath::point3(-5.0, -5.0, -5.0),
+            cgmath::point3(2.0, 2.0, 100.0),
+            |_| true,
+        );
+        assert_eq!(particles.len(), 15 * HybridFluid::PARTICLES_PER_GRID_CELL as usize);
+        assert!(particles.iter().all(|p| p.x >= 1.0 && p.y >= 1.0 && p.z >= 1.0 && p.z <= 16.0));
+    }
+
+    fn particles(positions: &[[f32; 3]]) -> Vec<(cgmath::Point3<f32>, cgmath::Vector3<f32>)> {
+        positions.iter().map(|p| ((*p).into(), cgmath::vec3(0.0, 1.0, 0.0))).collect()
+    }
+
+    #[test]
+    fn particle_comparison() {
+        let a = particles(&[[1.0, 1.0, 1.0], [2.0, 2.0, 2.0]]);
+        let comparison = ParticleComparison::new(&a, &a);
+        assert_eq!(comparison.num_particles, [2, 2]);
+        assert_eq!(comparison.max_position_difference, 0.0);
+        assert_eq!(comparison.rms_position_difference, 0.0);
+        assert_eq!(comparison.max_velocity_difference, 0.0);
+        assert!(comparison.within(0.0));
+
+        let b = particles(&[[1.0, 1.0, 1.0], [2.0, 2.0, 2.5]]);
+        let comparison = ParticleComparison::new(&a, &b);
+        assert_eq!(comparison.max_position_difference, 0.5);
+        assert_eq!(comparison.rms_position_difference, (0.125f64).sqrt() as f32);
+        assert!(comparison.within(0.5));
+        assert!(!comparison.within(0.4));
+    }
+
+    #[test]
+    fn particle_comparison_nan() {
+        let a = particles(&[[1.0, 1.0, 1.0], [2.0, 2.0, 2.0]]);
+        let b = particles(&[[1.0, 1.0, 1.0], [2.0, f32::NAN, 2.0]]);
+        let comparison = ParticleComparison::new(&a, &b);
+        assert_eq!(comparison.max_position_difference, f32::INFINITY);
+        assert!(!comparison.within(f32::MAX));
+
+        let mut c = a.clone();
+        c[0].1 = cgmath::vec3(f32::NAN, 0.0, 0.0);
+        let comparison = ParticleComparison::new(&a, &c);
+        assert_eq!(comparison.max_position_difference, 0.0);
+        assert_eq!(comparison.max_velocity_difference, f32::INFINITY);
+    }
+
+    #[test]
+    fn particle_comparison_count_mismatch() {
+        let a = particles(&[[1.0, 1.0, 1.0], [2.0, 2.0, 2.0]]);
+        let comparison = ParticleComparison::new(&a, &a[..1]);
+        assert_eq!(comparison.num_particles, [2, 1]);
+        assert_eq!(comparison.max_position_difference, 0.0);
+        assert!(!comparison.within(f32::MAX));
+        assert!(!ParticleComparison::new(&[], &a).within(f32::MAX));
+    }
+}
@@ -1,35 +1,92 @@
+use super::checkpoint::{Checkpoint, CheckpointHeader};
+use super::dynamic_solid::*;
+use super::emitter::*;
+use super::fluid_backend::fluid_volume_particles;
 use super::pressure_solver::*;
+use super::rigid_body::RigidBody;
 use crate::wgpu_utils;
 use crate::wgpu_utils::binding_builder::*;
 use crate::wgpu_utils::binding_glsl;
 use crate::wgpu_utils::pipelines::*;
+use crate::wgpu_utils::readback::*;
 use crate::wgpu_utils::shader::*;
 use crate::wgpu_utils::uniformbuffer::*;
-use rand::prelude::*;
-use std::{collections::VecDeque, path::Path, rc::Rc, time::Duration};
+use cgmath::InnerSpace;
+use serde::{Deserialize, Serialize};
+use std::{collections::VecDeque, io, path::Path, rc::Rc, time::Duration};
+use strum::IntoEnumIterator;
 
 #[repr(C)]
 #[derive(Clone, Copy)]
 struct SimulationPropertiesUniformBufferContent {
     gravity_grid: cgmath::Vector3<f32>,
     num_particles: u32,
+    transfer_scheme: u32,
+    flip_blend_factor: f32,
+    step_delta: f32,
+    viscosity: f32,
+    surface_tension: f32,
+    vorticity_confinement: f32,
+    padding: [u32; 2],
 }
 unsafe impl bytemuck::Pod for SimulationPropertiesUniformBufferContent {}
 unsafe impl bytemuck::Zeroable for SimulationPropertiesUniformBufferContent {}
 
+// How velocity is transferred between particles and grid.
+// Need to match the defines in hybrid_fluid.glsl
+#[derive(Clone, Copy, Debug, PartialEq, EnumIter, Serialize, Deserialize)]
+pub enum TransferScheme {
+    // Particles take on the grid velocity. Very stable but also very dissipative.
+    Pic = 0,
+    // Particles only take on the change of the grid velocity, blended with PIC (see flip_blend_factor).
+    // Retains a lot more detail but tends to get noisy.
+    Flip = 1,
+    // Particles take on the grid velocity plus an affine approximation of its gradient.
+    Apic = 2,
+}
+
+impl Default for TransferScheme {
+    fn default() -> Self {
+        TransferScheme::Pic
+    }
+}
+
 pub struct HybridFluid {
     grid_dimension: wgpu::Extent3d,
 
     pressure_solver: PressureSolver,
     pressure_field_from_velocity: PressureField,
     pressure_field_from_density: PressureField,
+    // Shared by all three velocity components which are solved one after another.
+    viscosity_field: PressureField,
 
     particles_position_llindex: wgpu::Buffer,
     particles_velocity_x: wgpu::Buffer,
     particles_velocity_y: wgpu::Buffer,
     particles_velocity_z: wgpu::Buffer,
+    particle_count_buffer: wgpu::Buffer,
+    particle_count_readback: ReadbackRing<()>,
+    simulation_statistics_buffer: wgpu::Buffer,
+    max_particle_speed_readback: ReadbackRing<()>,
+    max_particle_speed: f32,
+    volume_solid: wgpu::Texture,
+    transfer_scheme: TransferScheme,
+    flip_blend_factor: f32,
     simulation_properties_uniformbuffer: UniformBuffer<SimulationPropertiesUniformBufferContent>,
     simulation_properties: SimulationPropertiesUniformBufferContent,
+    dynamic_solids_uniformbuffer: UniformBuffer<DynamicSolidsUniformBufferContent>,
+    dynamic_solids: DynamicSolidsUniformBufferContent,
+    dynamic_solid_forces: wgpu::Buffer,
+    dynamic_solid_force_readback: ReadbackRing<ForceReadbackInfo>,
+    particle_sources_uniformbuffer: UniformBuffer<ParticleSourcesUniformBufferContent>,
+    emitters: Vec<EmitterState>,
+    drains: Vec<ParticleDrain>,
+    reseeding: Option<ParticleReseeding>,
+    // Whether the linked list grid still holds the particle lists written by the last step's advection.
+    particle_linked_lists_valid: bool,
+    num_steps: u32,
+    // Total simulated time, only used to timestamp solver statistics.
+    simulation_time: Duration,
 
     bind_group_uniform: wgpu::BindGroup,
     bind_group_transfer_velocity: [wgpu::BindGroup; 3],
@@ -38,6 +95,11 @@ pub struct HybridFluid {
     bind_group_advect_particles: wgpu::BindGroup,
     bind_group_density_projection_gather_error: wgpu::BindGroup,
     bind_group_density_projection_correct_particles: wgpu::BindGroup,
+    bind_group_dynamic_solid_forces: wgpu::BindGroup,
+    bind_group_particle_sources: wgpu::BindGroup,
+    bind_group_viscosity: wgpu::BindGroup,
+    bind_group_surface_tension: wgpu::BindGroup,
+    bind_group_vorticity: wgpu::BindGroup,
 
     // The interface to any renderer of the fluid. Readonly access to relevant resources
     bind_group_renderer: wgpu::BindGroup,
@@ -49,18 +111,38 @@ pub struct HybridFluid {
     pipeline_divergence_compute: ComputePipelineHandle,
     pipeline_divergence_remove: ComputePipelineHandle,
     pipeline_extrapolate_velocity: ComputePipelineHandle,
+    pipeline_viscosity_prepare: ComputePipelineHandle,
+    pipeline_viscosity_apply: ComputePipelineHandle,
+    pipeline_surface_tension_levelset: ComputePipelineHandle,
+    pipeline_surface_tension_pressure: ComputePipelineHandle,
+    pipeline_vorticity_compute: ComputePipelineHandle,
+    pipeline_vorticity_confinement_apply: ComputePipelineHandle,
     pipeline_advect_particles: ComputePipelineHandle,
     pipeline_density_projection_gather_error: ComputePipelineHandle,
     pipeline_density_projection_correct_particles: ComputePipelineHandle,
+    pipeline_dynamic_solid_forces: ComputePipelineHandle,
+    pipeline_particle_sources_emit: ComputePipelineHandle,
+    pipeline_particle_sources_drain_count: ComputePipelineHandle,
+    pipeline_particle_sources_drain_find_holes: ComputePipelineHandle,
+    pipeline_particle_sources_drain_compact: ComputePipelineHandle,
+    pipeline_particle_sources_update_count: ComputePipelineHandle,
+    pipeline_particle_sources_reseed: ComputePipelineHandle,
 
     max_num_particles: u32,
 }
 
+// Where the particle buffers ended up in a BlockingReadback.
+struct ParticleReadbackIndices {
+    count: usize,
+    position_llindex: usize,
+    velocity: [usize; 3],
+}
+
 static mut GROUP_LAYOUT_RENDERER: Option<BindGroupLayoutWithDesc> = None;
 
 #[repr(C)]
 #[derive(Clone, Copy)]
-struct ParticlePositionLl {
+pub(super) struct ParticlePositionLl {
     // Particle positions are in grid space to simplify shader computation
     // (no scaling/translation needed until we're rendering or interacting with other objects!)
     position: cgmath::Point3<f32>,
@@ -74,6 +156,22 @@ impl HybridFluid {
     // (seems to be widely accepted as the default. Houdini seems to have this configurable from 4-16, maybe worth experimenting with it! Note however, that the density error computation assumes this constant as well!)
     pub const PARTICLES_PER_GRID_CELL: u32 = 8;
 
+    // Also used by the CPU reference, so both start out with the same settings.
+    pub(super) const DEFAULT_PRESSURE_SOLVER_CONFIG_VELOCITY: SolverConfig = SolverConfig {
+        target_mse: 0.5,
+        mse_check_frequency: 4,
+        max_num_iterations: 32,
+        preconditioner: Preconditioner::IncompletePoisson,
+        method: SolverMethod::Pcg,
+    };
+    pub(super) const DEFAULT_PRESSURE_SOLVER_CONFIG_DENSITY: SolverConfig = SolverConfig {
+        target_mse: 0.05,
+        mse_check_frequency: 4,
+        max_num_iterations: 16,
+        preconditioner: Preconditioner::IncompletePoisson,
+        method: SolverMethod::Pcg,
+    };
+
     pub fn new(
         device: &wgpu::Device,
         grid_dimension: wgpu::Extent3d,
@@ -84,28 +182,62 @@ impl HybridFluid {
     ) -> Self {
         // Resources
         let simulation_properties_uniformbuffer = UniformBuffer::new(device);
+        let dynamic_solids_uniformbuffer = UniformBuffer::new(device);
+        let particle_sources_uniformbuffer = UniformBuffer::new(device);
         let particles_position_llindex = device.create_buffer(&wgpu::BufferDescriptor {
             label: Some("Buffer: Particles position & llindex"),
             size: max_num_particles as u64 * std::mem::size_of::<ParticlePositionLl>() as u64,
-            usage: wgpu::BufferUsage::STORAGE | wgpu::BufferUsage::COPY_DST,
+            usage: wgpu::BufferUsage::STORAGE | wgpu::BufferUsage::COPY_SRC | wgpu::BufferUsage::COPY_DST,
             mapped_at_creation: false,
         });
         let particles_velocity_x = device.create_buffer(&wgpu::BufferDescriptor {
             label: Some("Buffer: Particles velocity X"),
             size: max_num_particles as u64 * std::mem::size_of::<cgmath::Vector4<f32>>() as u64,
-            usage: wgpu::BufferUsage::STORAGE | wgpu::BufferUsage::COPY_DST,
+            usage: wgpu::BufferUsage::STORAGE | wgpu::BufferUsage::COPY_SRC | wgpu::BufferUsage::COPY_DST,
             mapped_at_creation: false,
         });
         let particles_velocity_y = device.create_buffer(&wgpu::BufferDescriptor {
             label: Some("Buffer: Particles velocity Y"),
             size: max_num_particles as u64 * std::mem::size_of::<cgmath::Vector4<f32>>() as u64,
-            usage: wgpu::BufferUsage::STORAGE | wgpu::BufferUsage::COPY_DST,
+            usage: wgpu::BufferUsage::STORAGE | wgpu::BufferUsage::COPY_SRC | wgpu::BufferUsage::COPY_DST,
             mapped_at_creation: false,
         });
         let particles_velocity_z = device.create_buffer(&wgpu::BufferDescriptor {
             label: Some("Buffer: Particles velocity Z"),
             size: max_num_particles as u64 * std::mem::size_of::<cgmath::Vector4<f32>>() as u64,
-            usage: wgpu::BufferUsage::STORAGE | wgpu::BufferUsage::COPY_DST,
+            usage: wgpu::BufferUsage::STORAGE | wgpu::BufferUsage::COPY_SRC | wgpu::BufferUsage::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let simulation_statistics_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Buffer: Simulation statistics"),
+            size: std::mem::size_of::<u32>() as u64,
+            usage: wgpu::BufferUsage::STORAGE | wgpu::BufferUsage::COPY_SRC,
+            mapped_at_creation: false,
+        });
+        let particle_count_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Buffer: Particle count & indirect arguments"),
+            size: std::mem::size_of::<ParticleCountBufferContent>() as u64,
+            usage: wgpu::BufferUsage::STORAGE | wgpu::BufferUsage::INDIRECT | wgpu::BufferUsage::COPY_SRC | wgpu::BufferUsage::COPY_DST,
+            mapped_at_creation: true,
+        });
+        particle_count_buffer
+            .slice(..)
+            .get_mapped_range_mut()
+            .clone_from_slice(bytemuck::bytes_of(&ParticleCountBufferContent::new(
+                0,
+                Self::COMPUTE_LOCAL_SIZE_PARTICLES,
+            )));
+        particle_count_buffer.unmap();
+        let particle_compaction_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Buffer: Particle compaction indices"),
+            size: max_num_particles as u64 * std::mem::size_of::<u32>() as u64,
+            usage: wgpu::BufferUsage::STORAGE,
+            mapped_at_creation: false,
+        });
+        let dynamic_solid_forces = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Buffer: Dynamic solid forces"),
+            size: FORCE_BUFFER_SIZE,
+            usage: wgpu::BufferUsage::STORAGE | wgpu::BufferUsage::COPY_SRC,
             mapped_at_creation: false,
         });
 
@@ -135,19 +267,49 @@ impl HybridFluid {
         let volume_velocity_x = device.create_texture(&create_volume_texture_desc("Velocity Volume X", wgpu::TextureFormat::R32Float));
         let volume_velocity_y = device.create_texture(&create_volume_texture_desc("Velocity Volume Y", wgpu::TextureFormat::R32Float));
         let volume_velocity_z = device.create_texture(&create_volume_texture_desc("Velocity Volume Z", wgpu::TextureFormat::R32Float));
+        // Velocity right after the transfer from particles, before any forces are applied. Only needed for FLIP.
+        let volume_velocity_old_x = device.create_texture(&create_volume_texture_desc("Old Velocity Volume X", wgpu::TextureFormat::R32Float));
+        let volume_velocity_old_y = device.create_texture(&create_volume_texture_desc("Old Velocity Volume Y", wgpu::TextureFormat::R32Float));
+        let volume_velocity_old_z = device.create_texture(&create_volume_texture_desc("Old Velocity Volume Z", wgpu::TextureFormat::R32Float));
         let volume_linked_lists = device.create_texture(&create_volume_texture_desc("Linked Lists Volume", wgpu::TextureFormat::R32Uint));
         let volume_marker_primary = device.create_texture(&create_volume_texture_desc("Marker Grid", wgpu::TextureFormat::R8Snorm));
+        // Marker for the staggered samples of a single velocity component, used by the viscosity solve.
+        let volume_marker_viscosity = device.create_texture(&create_volume_texture_desc("Viscosity Marker Grid", wgpu::TextureFormat::R8Snorm));
+        // Fluid level set from particles and the resulting pressure jump at the surface, only used with surface tension.
+        let volume_levelset = device.create_texture(&create_volume_texture_desc("Level Set Volume", wgpu::TextureFormat::R32Float));
+        let volume_surface_tension_pressure = device.create_texture(&create_volume_texture_desc(
+            "Surface Tension Pressure Volume",
+            wgpu::TextureFormat::R32Float,
+        ));
+        // Only used with vorticity confinement.
+        let volume_vorticity = device.create_texture(&create_volume_texture_desc("Vorticity Volume", wgpu::TextureFormat::Rgba16Float));
+        // Signed distance to static solids, computed on the CPU.
+        let volume_solid = device.create_texture(&wgpu::TextureDescriptor {
+            usage: wgpu::TextureUsage::SAMPLED | wgpu::TextureUsage::COPY_DST,
+            ..create_volume_texture_desc("Solid Distance Volume", wgpu::TextureFormat::R32Float)
+        });
 
         // Resource views
         let volume_velocity_view_x = volume_velocity_x.create_view(&Default::default());
         let volume_velocity_view_y = volume_velocity_y.create_view(&Default::default());
         let volume_velocity_view_z = volume_velocity_z.create_view(&Default::default());
+        let volume_velocity_old_view_x = volume_velocity_old_x.create_view(&Default::default());
+        let volume_velocity_old_view_y = volume_velocity_old_y.create_view(&Default::default());
+        let volume_velocity_old_view_z = volume_velocity_old_z.create_view(&Default::default());
         let volume_linked_lists_view = volume_linked_lists.create_view(&Default::default());
         let volume_marker_view = volume_marker_primary.create_view(&Default::default());
+        let volume_marker_viscosity_view = volume_marker_viscosity.create_view(&Default::default());
+        let volume_solid_view = volume_solid.create_view(&Default::default());
+        let volume_levelset_view = volume_levelset.create_view(&Default::default());
+        let volume_vorticity_view = volume_vorticity.create_view(&Default::default());
+        let volume_surface_tension_pressure_view = volume_surface_tension_pressure.create_view(&Default::default());
 
         // Layouts
         let group_layout_uniform = BindGroupLayoutBuilder::new()
             .next_binding_compute(binding_glsl::uniform())
+            .next_binding_compute(binding_glsl::texture3D()) // solid volume
+            .next_binding_compute(binding_glsl::uniform()) // dynamic solids
+            .next_binding_compute(binding_glsl::buffer(false)) // simulation statistics
             .create(device, "BindGroupLayout: HybridFluid Uniform");
         let group_layout_transfer_velocity = BindGroupLayoutBuilder::new()
             .next_binding_compute(binding_glsl::buffer(false)) // particles, position llindex
@@ -155,6 +317,7 @@ impl HybridFluid {
             .next_binding_compute(binding_glsl::uimage3D(wgpu::TextureFormat::R32Uint, false)) // linkedlist_volume
             .next_binding_compute(binding_glsl::image3D(wgpu::TextureFormat::R8Snorm, false)) // marker volume
             .next_binding_compute(binding_glsl::image3D(wgpu::TextureFormat::R32Float, false)) // velocity component
+            .next_binding_compute(binding_glsl::image3D(wgpu::TextureFormat::R32Float, false)) // velocity component before forces
             .create(device, "BindGroupLayout: Transfer velocity from Particles to Volume(s)");
         let group_layout_divergence_compute = BindGroupLayoutBuilder::new()
             .next_binding_compute(binding_glsl::texture3D()) // marker volume
@@ -162,6 +325,7 @@ impl HybridFluid {
             .next_binding_compute(binding_glsl::texture3D()) // velocityY
             .next_binding_compute(binding_glsl::texture3D()) // velocityZ
             .next_binding_compute(binding_glsl::image3D(wgpu::TextureFormat::R32Float, false)) // divergence / initial residual
+            .next_binding_compute(binding_glsl::texture3D()) // surface tension pressure
             .create(device, "BindGroupLayout: Compute Divergence");
         let group_layout_write_velocity_volume = BindGroupLayoutBuilder::new()
             .next_binding_compute(binding_glsl::texture3D()) // marker volume
@@ -169,6 +333,7 @@ impl HybridFluid {
             .next_binding_compute(binding_glsl::image3D(wgpu::TextureFormat::R32Float, false)) // velocityY
             .next_binding_compute(binding_glsl::image3D(wgpu::TextureFormat::R32Float, false)) // velocityZ
             .next_binding_compute(binding_glsl::texture3D()) // pressure
+            .next_binding_compute(binding_glsl::texture3D()) // surface tension pressure
             .create(device, "BindGroupLayout: Write to Velocity");
         let group_layout_advect_particles = BindGroupLayoutBuilder::new()
             .next_binding_compute(binding_glsl::texture2D()) // velocityX
@@ -180,6 +345,9 @@ impl HybridFluid {
             .next_binding_compute(binding_glsl::buffer(false)) // particles, velocityX
             .next_binding_compute(binding_glsl::buffer(false)) // particles, velocityY
             .next_binding_compute(binding_glsl::buffer(false)) // particles, velocityZ
+            .next_binding_compute(binding_glsl::texture3D()) // velocityX before forces
+            .next_binding_compute(binding_glsl::texture3D()) // velocityY before forces
+            .next_binding_compute(binding_glsl::texture3D()) // velocityZ before forces
             .create(device, "BindGroupLayout: Advect to Particles");
 
         let group_layout_density_projection_gather_error = BindGroupLayoutBuilder::new()
@@ -193,6 +361,48 @@ impl HybridFluid {
             .next_binding_compute(binding_glsl::texture3D()) // marker volume
             .next_binding_compute(binding_glsl::texture3D()) // pressure from density
             .create(device, "BindGroupLayout: Correct density error");
+        let group_layout_dynamic_solid_forces = BindGroupLayoutBuilder::new()
+            .next_binding_compute(binding_glsl::texture3D()) // marker volume
+            .next_binding_compute(binding_glsl::texture3D()) // pressure from velocity
+            .next_binding_compute(binding_glsl::buffer(false)) // dynamic solid forces
+            .create(device, "BindGroupLayout: Dynamic solid forces");
+        let group_layout_particle_sources = BindGroupLayoutBuilder::new()
+            .next_binding_compute(binding_glsl::buffer(false)) // particle count
+            .next_binding_compute(binding_glsl::uniform()) // emitters & drains
+            .next_binding_compute(binding_glsl::buffer(false)) // particles, position llindex
+            .next_binding_compute(binding_glsl::buffer(false)) // particles, velocityX
+            .next_binding_compute(binding_glsl::buffer(false)) // particles, velocityY
+            .next_binding_compute(binding_glsl::buffer(false)) // particles, velocityZ
+            .next_binding_compute(binding_glsl::buffer(false)) // compaction indices
+            .next_binding_compute(binding_glsl::utexture3D()) // linked list grid
+            .next_binding_compute(binding_glsl::texture3D()) // marker volume
+            .next_binding_compute(binding_glsl::texture3D()) // velocityX
+            .next_binding_compute(binding_glsl::texture3D()) // velocityY
+            .next_binding_compute(binding_glsl::texture3D()) // velocityZ
+            .create(device, "BindGroupLayout: Particle sources");
+        let group_layout_viscosity = BindGroupLayoutBuilder::new()
+            .next_binding_compute(binding_glsl::texture3D()) // marker volume
+            .next_binding_compute(binding_glsl::image3D(wgpu::TextureFormat::R32Float, false)) // velocityX
+            .next_binding_compute(binding_glsl::image3D(wgpu::TextureFormat::R32Float, false)) // velocityY
+            .next_binding_compute(binding_glsl::image3D(wgpu::TextureFormat::R32Float, false)) // velocityZ
+            .next_binding_compute(binding_glsl::image3D(wgpu::TextureFormat::R8Snorm, false)) // viscosity marker volume
+            .next_binding_compute(binding_glsl::image3D(wgpu::TextureFormat::R32Float, false)) // right hand side / initial residual
+            .next_binding_compute(binding_glsl::image3D(wgpu::TextureFormat::R32Float, false)) // viscous velocity
+            .create(device, "BindGroupLayout: Viscosity");
+        let group_layout_surface_tension = BindGroupLayoutBuilder::new()
+            .next_binding_compute(binding_glsl::buffer(true)) // particles, position llindex
+            .next_binding_compute(binding_glsl::utexture3D()) // linkedlist_volume
+            .next_binding_compute(binding_glsl::texture3D()) // marker volume
+            .next_binding_compute(binding_glsl::image3D(wgpu::TextureFormat::R32Float, false)) // level set
+            .next_binding_compute(binding_glsl::image3D(wgpu::TextureFormat::R32Float, false)) // surface tension pressure
+            .create(device, "BindGroupLayout: Surface tension");
+        let group_layout_vorticity = BindGroupLayoutBuilder::new()
+            .next_binding_compute(binding_glsl::texture3D()) // marker volume
+            .next_binding_compute(binding_glsl::image3D(wgpu::TextureFormat::R32Float, false)) // velocityX
+            .next_binding_compute(binding_glsl::image3D(wgpu::TextureFormat::R32Float, false)) // velocityY
+            .next_binding_compute(binding_glsl::image3D(wgpu::TextureFormat::R32Float, false)) // velocityZ
+            .next_binding_compute(binding_glsl::image3D(wgpu::TextureFormat::Rgba16Float, false)) // vorticity
+            .create(device, "BindGroupLayout: Vorticity");
 
         let pressure_solver = PressureSolver::new(device, grid_dimension, shader_dir, pipeline_manager, &volume_marker_view);
         let pressure_field_from_velocity = PressureField::new(
@@ -200,27 +410,36 @@ impl HybridFluid {
             device,
             grid_dimension,
             &pressure_solver,
-            SolverConfig {
-                target_mse: 0.5,
-                mse_check_frequency: 4,
-                max_num_iterations: 32,
-            },
+            Self::DEFAULT_PRESSURE_SOLVER_CONFIG_VELOCITY,
         );
         let pressure_field_from_density = PressureField::new(
             "from density",
             device,
             grid_dimension,
             &pressure_solver,
+            Self::DEFAULT_PRESSURE_SOLVER_CONFIG_DENSITY,
+        );
+        let viscosity_field = PressureField::new_viscosity(
+            "viscosity",
+            device,
+            grid_dimension,
+            &pressure_solver,
             SolverConfig {
-                target_mse: 0.05,
+                target_mse: 0.01,
                 mse_check_frequency: 4,
                 max_num_iterations: 16,
+                preconditioner: Preconditioner::IncompletePoisson,
+                method: SolverMethod::Pcg,
             },
+            &volume_marker_viscosity_view,
         );
 
         // Bind groups.
         let bind_group_uniform = BindGroupBuilder::new(&group_layout_uniform)
             .resource(simulation_properties_uniformbuffer.binding_resource())
+            .texture(&volume_solid_view)
+            .resource(dynamic_solids_uniformbuffer.binding_resource())
+            .resource(simulation_statistics_buffer.as_entire_binding())
             .create(device, "BindGroup: HybridFluid Uniform");
 
         let bind_group_transfer_velocity = [
@@ -230,6 +449,7 @@ impl HybridFluid {
                 .texture(&volume_linked_lists_view)
                 .texture(&volume_marker_view)
                 .texture(&volume_velocity_view_x)
+                .texture(&volume_velocity_old_view_x)
                 .create(device, "BindGroup: Transfer velocity to volume X"),
             BindGroupBuilder::new(&group_layout_transfer_velocity)
                 .resource(particles_position_llindex.as_entire_binding())
@@ -237,6 +457,7 @@ impl HybridFluid {
                 .texture(&volume_linked_lists_view)
                 .texture(&volume_marker_view)
                 .texture(&volume_velocity_view_y)
+                .texture(&volume_velocity_old_view_y)
                 .create(device, "BindGroup: Transfer velocity to volume Y"),
             BindGroupBuilder::new(&group_layout_transfer_velocity)
                 .resource(particles_position_llindex.as_entire_binding())
@@ -244,6 +465,7 @@ impl HybridFluid {
                 .texture(&volume_linked_lists_view)
                 .texture(&volume_marker_view)
                 .texture(&volume_velocity_view_z)
+                .texture(&volume_velocity_old_view_z)
                 .create(device, "BindGroup: Transfer velocity to volume Z"),
         ];
         let bind_group_divergence_compute = BindGroupBuilder::new(&group_layout_divergence_compute)
@@ -252,6 +474,7 @@ impl HybridFluid {
             .texture(&volume_velocity_view_y)
             .texture(&volume_velocity_view_z)
             .texture(pressure_solver.residual_view())
+            .texture(&volume_surface_tension_pressure_view)
             .create(device, "BindGroup: Compute divergence");
         let bind_group_write_velocity = BindGroupBuilder::new(&group_layout_write_velocity_volume)
             .texture(&volume_marker_view)
@@ -259,6 +482,7 @@ impl HybridFluid {
             .texture(&volume_velocity_view_y)
             .texture(&volume_velocity_view_z)
             .texture(pressure_field_from_velocity.pressure_view())
+            .texture(&volume_surface_tension_pressure_view)
             .create(device, "BindGroup: Write to Velocity Grid");
         let bind_group_advect_particles = BindGroupBuilder::new(&group_layout_advect_particles)
             .texture(&volume_velocity_view_x)
@@ -270,6 +494,9 @@ impl HybridFluid {
             .resource(particles_velocity_x.as_entire_binding())
             .resource(particles_velocity_y.as_entire_binding())
             .resource(particles_velocity_z.as_entire_binding())
+            .texture(&volume_velocity_old_view_x)
+            .texture(&volume_velocity_old_view_y)
+            .texture(&volume_velocity_old_view_z)
             .create(device, "BindGroup: Write to Particles");
         let bind_group_density_projection_gather_error = BindGroupBuilder::new(&group_layout_density_projection_gather_error)
             .resource(particles_position_llindex.as_entire_binding())
@@ -282,6 +509,48 @@ impl HybridFluid {
             .texture(&volume_marker_view)
             .texture(&pressure_field_from_density.pressure_view())
             .create(device, "BindGroup: Density projection gather");
+        let bind_group_dynamic_solid_forces = BindGroupBuilder::new(&group_layout_dynamic_solid_forces)
+            .texture(&volume_marker_view)
+            .texture(&pressure_field_from_velocity.pressure_view())
+            .resource(dynamic_solid_forces.as_entire_binding())
+            .create(device, "BindGroup: Dynamic solid forces");
+        let bind_group_particle_sources = BindGroupBuilder::new(&group_layout_particle_sources)
+            .resource(particle_count_buffer.as_entire_binding())
+            .resource(particle_sources_uniformbuffer.binding_resource())
+            .resource(particles_position_llindex.as_entire_binding())
+            .resource(particles_velocity_x.as_entire_binding())
+            .resource(particles_velocity_y.as_entire_binding())
+            .resource(particles_velocity_z.as_entire_binding())
+            .resource(particle_compaction_buffer.as_entire_binding())
+            .texture(&volume_linked_lists_view)
+            .texture(&volume_marker_view)
+            .texture(&volume_velocity_view_x)
+            .texture(&volume_velocity_view_y)
+            .texture(&volume_velocity_view_z)
+            .create(device, "BindGroup: Particle sources");
+        let bind_group_viscosity = BindGroupBuilder::new(&group_layout_viscosity)
+            .texture(&volume_marker_view)
+            .texture(&volume_velocity_view_x)
+            .texture(&volume_velocity_view_y)
+            .texture(&volume_velocity_view_z)
+            .texture(&volume_marker_viscosity_view)
+            .texture(pressure_solver.residual_view())
+            .texture(viscosity_field.pressure_view())
+            .create(device, "BindGroup: Viscosity");
+        let bind_group_surface_tension = BindGroupBuilder::new(&group_layout_surface_tension)
+            .resource(particles_position_llindex.as_entire_binding())
+            .texture(&volume_linked_lists_view)
+            .texture(&volume_marker_view)
+            .texture(&volume_levelset_view)
+            .texture(&volume_surface_tension_pressure_view)
+            .create(device, "BindGroup: Surface tension");
+        let bind_group_vorticity = BindGroupBuilder::new(&group_layout_vorticity)
+            .texture(&volume_marker_view)
+            .texture(&volume_velocity_view_x)
+            .texture(&volume_velocity_view_y)
+            .texture(&volume_velocity_view_z)
+            .texture(&volume_vorticity_view)
+            .create(device, "BindGroup: Vorticity");
 
         let bind_group_renderer = BindGroupBuilder::new(&Self::get_or_create_group_layout_renderer(device))
             .resource(particles_position_llindex.as_entire_binding())
@@ -314,7 +583,11 @@ impl HybridFluid {
         }));
         let layout_divergence_compute = Rc::new(device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
             label: Some("PipelineLayout: HybridFluid, Compute Divergence"),
-            bind_group_layouts: &[per_frame_bind_group_layout, &group_layout_divergence_compute.layout],
+            bind_group_layouts: &[
+                per_frame_bind_group_layout,
+                &group_layout_uniform.layout,
+                &group_layout_divergence_compute.layout,
+            ],
             push_constant_ranges,
         }));
         let layout_write_velocity_volume = Rc::new(device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
@@ -353,6 +626,43 @@ impl HybridFluid {
             ],
             push_constant_ranges,
         }));
+        let layout_dynamic_solid_forces = Rc::new(device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("PipelineLayout: HybridFluid, Dynamic Solid Forces"),
+            bind_group_layouts: &[
+                per_frame_bind_group_layout,
+                &group_layout_uniform.layout,
+                &group_layout_dynamic_solid_forces.layout,
+            ],
+            push_constant_ranges,
+        }));
+        let layout_particle_sources = Rc::new(device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("PipelineLayout: HybridFluid, Particle Sources"),
+            bind_group_layouts: &[
+                per_frame_bind_group_layout,
+                &group_layout_uniform.layout,
+                &group_layout_particle_sources.layout,
+            ],
+            push_constant_ranges,
+        }));
+        let layout_viscosity = Rc::new(device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("PipelineLayout: HybridFluid, Viscosity"),
+            bind_group_layouts: &[per_frame_bind_group_layout, &group_layout_uniform.layout, &group_layout_viscosity.layout],
+            push_constant_ranges,
+        }));
+        let layout_surface_tension = Rc::new(device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("PipelineLayout: HybridFluid, Surface Tension"),
+            bind_group_layouts: &[
+                per_frame_bind_group_layout,
+                &group_layout_uniform.layout,
+                &group_layout_surface_tension.layout,
+            ],
+            push_constant_ranges,
+        }));
+        let layout_vorticity = Rc::new(device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("PipelineLayout: HybridFluid, Vorticity"),
+            bind_group_layouts: &[per_frame_bind_group_layout, &group_layout_uniform.layout, &group_layout_vorticity.layout],
+            push_constant_ranges,
+        }));
 
         HybridFluid {
             grid_dimension,
@@ -360,16 +670,43 @@ impl HybridFluid {
             pressure_solver,
             pressure_field_from_velocity,
             pressure_field_from_density,
+            viscosity_field,
 
             particles_position_llindex,
             particles_velocity_x,
             particles_velocity_y,
             particles_velocity_z,
+            particle_count_buffer,
+            particle_count_readback: ReadbackRing::new(device, "Particle count", ParticleCountBufferContent::DRAW_ARGS_OFFSET, 4),
+            simulation_statistics_buffer,
+            max_particle_speed_readback: ReadbackRing::new(device, "Max particle speed", std::mem::size_of::<u32>() as u64, 4),
+            max_particle_speed: 0.0,
+            volume_solid,
             simulation_properties_uniformbuffer,
+            transfer_scheme: TransferScheme::default(),
+            flip_blend_factor: 0.95,
             simulation_properties: SimulationPropertiesUniformBufferContent {
                 num_particles: 0,
                 gravity_grid: cgmath::vec3(0.0, -9.81, 0.0),
+                transfer_scheme: TransferScheme::default() as u32,
+                flip_blend_factor: 0.0,
+                step_delta: 0.0,
+                viscosity: 0.0,
+                surface_tension: 0.0,
+                vorticity_confinement: 0.0,
+                padding: [0; 2],
             },
+            dynamic_solids_uniformbuffer,
+            dynamic_solids: DynamicSolidsUniformBufferContent::new(&[]),
+            dynamic_solid_forces,
+            dynamic_solid_force_readback: ReadbackRing::new(device, "Dynamic solid forces", FORCE_BUFFER_SIZE, 8),
+            particle_sources_uniformbuffer,
+            emitters: Vec::new(),
+            drains: Vec::new(),
+            reseeding: None,
+            particle_linked_lists_valid: false,
+            num_steps: 0,
+            simulation_time: Duration::from_secs(0),
 
             bind_group_uniform,
             bind_group_transfer_velocity,
@@ -380,6 +717,11 @@ impl HybridFluid {
 
             bind_group_density_projection_gather_error,
             bind_group_density_projection_correct_particles,
+            bind_group_dynamic_solid_forces,
+            bind_group_particle_sources,
+            bind_group_viscosity,
+            bind_group_surface_tension,
+            bind_group_vorticity,
 
             pipeline_transfer_clear: pipeline_manager.create_compute_pipeline(
                 device,
@@ -444,6 +786,60 @@ impl HybridFluid {
                     Path::new("simulation/extrapolate_velocity.comp"),
                 ),
             ),
+            pipeline_viscosity_prepare: pipeline_manager.create_compute_pipeline(
+                device,
+                shader_dir,
+                ComputePipelineCreationDesc::new(
+                    "Fluid: Viscosity, prepare",
+                    layout_viscosity.clone(),
+                    Path::new("simulation/viscosity_prepare.comp"),
+                ),
+            ),
+            pipeline_viscosity_apply: pipeline_manager.create_compute_pipeline(
+                device,
+                shader_dir,
+                ComputePipelineCreationDesc::new(
+                    "Fluid: Viscosity, apply",
+                    layout_viscosity.clone(),
+                    Path::new("simulation/viscosity_apply.comp"),
+                ),
+            ),
+            pipeline_surface_tension_levelset: pipeline_manager.create_compute_pipeline(
+                device,
+                shader_dir,
+                ComputePipelineCreationDesc::new(
+                    "Fluid: Surface tension, level set",
+                    layout_surface_tension.clone(),
+                    Path::new("simulation/surface_tension_levelset.comp"),
+                ),
+            ),
+            pipeline_surface_tension_pressure: pipeline_manager.create_compute_pipeline(
+                device,
+                shader_dir,
+                ComputePipelineCreationDesc::new(
+                    "Fluid: Surface tension, pressure",
+                    layout_surface_tension.clone(),
+                    Path::new("simulation/surface_tension_pressure.comp"),
+                ),
+            ),
+            pipeline_vorticity_compute: pipeline_manager.create_compute_pipeline(
+                device,
+                shader_dir,
+                ComputePipelineCreationDesc::new(
+                    "Fluid: Vorticity, compute",
+                    layout_vorticity.clone(),
+                    Path::new("simulation/vorticity_compute.comp"),
+                ),
+            ),
+            pipeline_vorticity_confinement_apply: pipeline_manager.create_compute_pipeline(
+                device,
+                shader_dir,
+                ComputePipelineCreationDesc::new(
+                    "Fluid: Vorticity, apply confinement",
+                    layout_vorticity.clone(),
+                    Path::new("simulation/vorticity_confinement_apply.comp"),
+                ),
+            ),
             pipeline_advect_particles: pipeline_manager.create_compute_pipeline(
                 device,
                 shader_dir,
@@ -472,71 +868,111 @@ impl HybridFluid {
                     Path::new("simulation/density_projection_correct_particles.comp"),
                 ),
             ),
+            pipeline_dynamic_solid_forces: pipeline_manager.create_compute_pipeline(
+                device,
+                shader_dir,
+                ComputePipelineCreationDesc::new(
+                    "Fluid: Dynamic solid forces",
+                    layout_dynamic_solid_forces.clone(),
+                    Path::new("simulation/dynamic_solid_forces.comp"),
+                ),
+            ),
+            pipeline_particle_sources_emit: pipeline_manager.create_compute_pipeline(
+                device,
+                shader_dir,
+                ComputePipelineCreationDesc::new(
+                    "Fluid: Particle sources, emit",
+                    layout_particle_sources.clone(),
+                    Path::new("simulation/particle_sources_emit.comp"),
+                ),
+            ),
+            pipeline_particle_sources_drain_count: pipeline_manager.create_compute_pipeline(
+                device,
+                shader_dir,
+                ComputePipelineCreationDesc::new(
+                    "Fluid: Particle sources, count drained",
+                    layout_particle_sources.clone(),
+                    Path::new("simulation/particle_sources_drain_count.comp"),
+                ),
+            ),
+            pipeline_particle_sources_drain_find_holes: pipeline_manager.create_compute_pipeline(
+                device,
+                shader_dir,
+                ComputePipelineCreationDesc::new(
+                    "Fluid: Particle sources, find holes",
+                    layout_particle_sources.clone(),
+                    Path::new("simulation/particle_sources_drain_find_holes.comp"),
+                ),
+            ),
+            pipeline_particle_sources_drain_compact: pipeline_manager.create_compute_pipeline(
+                device,
+                shader_dir,
+                ComputePipelineCreationDesc::new(
+                    "Fluid: Particle sources, compact",
+                    layout_particle_sources.clone(),
+                    Path::new("simulation/particle_sources_drain_compact.comp"),
+                ),
+            ),
+            pipeline_particle_sources_update_count: pipeline_manager.create_compute_pipeline(
+                device,
+                shader_dir,
+                ComputePipelineCreationDesc::new(
+                    "Fluid: Particle sources, update count",
+                    layout_particle_sources.clone(),
+                    Path::new("simulation/particle_sources_update_count.comp"),
+                ),
+            ),
+            pipeline_particle_sources_reseed: pipeline_manager.create_compute_pipeline(
+                device,
+                shader_dir,
+                ComputePipelineCreationDesc::new(
+                    "Fluid: Particle sources, reseed",
+                    layout_particle_sources.clone(),
+                    Path::new("simulation/particle_sources_reseed.comp"),
+                ),
+            ),
 
             max_num_particles,
         }
     }
 
-    fn clamp_to_grid(&self, grid_cor: cgmath::Point3<f32>) -> cgmath::Point3<u32> {
-        // Due to the design of the grid, the 0-1 range is reserved by solid cells and can't be filled.
-        cgmath::Point3::new(
-            self.grid_dimension.width.min(grid_cor.x as u32).max(1),
-            self.grid_dimension.height.min(grid_cor.y as u32).max(1),
-            self.grid_dimension.depth.min(grid_cor.z as u32).max(1),
-        )
-    }
-
     // Adds a cube of fluid. Coordinates are in grid space! Very slow operation!
-    pub fn add_fluid_cube(&mut self, queue: &wgpu::Queue, min_grid: cgmath::Point3<f32>, max_grid: cgmath::Point3<f32>) {
-        // align to whole cells for simplicity.
-        let min_grid = self.clamp_to_grid(min_grid);
-        let max_grid = self.clamp_to_grid(max_grid);
-        let extent_cell = max_grid - min_grid;
-
-        let mut num_new_particles = (extent_cell.x * extent_cell.y * extent_cell.z * Self::PARTICLES_PER_GRID_CELL) as u32;
+    // Meant for setting up a fluid, the particle count known on the CPU lags behind once emitters or drains are active.
+    // Adds particles to all cells between min_grid and max_grid for which `contains` is true at the particle position.
+    // All new particles start out with the given (grid space) velocity.
+    pub fn add_fluid_volume(
+        &mut self,
+        queue: &wgpu::Queue,
+        min_grid: cgmath::Point3<f32>,
+        max_grid: cgmath::Point3<f32>,
+        velocity: cgmath::Vector3<f32>,
+        contains: impl Fn(cgmath::Point3<f32>) -> bool,
+    ) {
+        let mut new_particles: Vec<ParticlePositionLl> = fluid_volume_particles(
+            self.grid_dimension,
+            self.simulation_properties.num_particles,
+            min_grid,
+            max_grid,
+            contains,
+        )
+        .into_iter()
+        .map(|position| ParticlePositionLl {
+            position,
+            linked_list_next: 0xFFFFFFFF,
+        })
+        .collect();
+
+        let mut num_new_particles = new_particles.len() as u32;
         if self.max_num_particles < num_new_particles + self.simulation_properties.num_particles {
             error!(
                 "Can't add {} particles, max is {}, current is {}",
                 num_new_particles, self.max_num_particles, self.simulation_properties.num_particles
             );
             num_new_particles = self.max_num_particles - self.simulation_properties.num_particles;
+            new_particles.truncate(num_new_particles as usize);
         }
         info!("Adding {} new particles", num_new_particles);
 
-        // Fill buffer with particle data
-        let mut rng: rand::rngs::SmallRng = rand::SeedableRng::seed_from_u64((self.simulation_properties.num_particles + num_new_particles) as u64);
-        let mut new_particles = Vec::new();
-        new_particles.resize(
-            num_new_particles as usize,
-            ParticlePositionLl {
-                position: cgmath::point3(0.0, 0.0, 0.0),
-                linked_list_next: 0xFFFFFFFF,
-            },
-        );
-        for (i, particle) in new_particles.iter_mut().enumerate() {
-            let cell = cgmath::point3(
-                (min_grid.x + i as u32 / Self::PARTICLES_PER_GRID_CELL % extent_cell.x) as f32,
-                (min_grid.y + i as u32 / Self::PARTICLES_PER_GRID_CELL / extent_cell.x % extent_cell.y) as f32,
-                (min_grid.z + i as u32 / Self::PARTICLES_PER_GRID_CELL / extent_cell.x / extent_cell.y) as f32,
-            );
-
-            let sample_idx = i as u32 % Self::PARTICLES_PER_GRID_CELL;
-
-            // pure random
-            // let offset = rng.gen::<cgmath::Vector3<f32>>();
-            // pure regular
-            // let offset = cgmath::vec3(
-            //     (sample_idx % 2) as f32 + 0.5,
-            //     (sample_idx / 2 % 2) as f32 + 0.5,
-            //     (sample_idx / 4 % 2) as f32 + 0.5,
-            // ) * 0.5;
-            // stratified
-            let offset = cgmath::vec3((sample_idx % 2) as f32, (sample_idx / 2 % 2) as f32, (sample_idx / 4 % 2) as f32) * 0.5
-                + rng.gen::<cgmath::Vector3<f32>>() * 0.5;
-
-            particle.position = cell + offset;
-        }
-
         let particle_size = std::mem::size_of::<ParticlePositionLl>() as u64;
         queue.write_buffer(
             &self.particles_position_llindex,
@@ -544,26 +980,435 @@ impl HybridFluid {
             bytemuck::cast_slice(&new_particles),
         );
 
-        // Clear velocities:
-        // wgpu-rs doesn't zero initialize yet (bug/missing feature impl)
-        // https://github.com/gfx-rs/wgpu/issues/563
+        // Initialize velocities, the affine part (APIC) starts out as zero.
+        // (Always need to write them: wgpu-rs doesn't zero initialize yet, https://github.com/gfx-rs/wgpu/issues/563)
         let offset_velocity_buffer = self.simulation_properties.num_particles as u64 * std::mem::size_of::<cgmath::Vector4<f32>>() as u64;
-        let zero_velocity = vec![0 as u8; num_new_particles as usize * std::mem::size_of::<cgmath::Vector4<f32>>()];
-        queue.write_buffer(&self.particles_velocity_x, offset_velocity_buffer, &zero_velocity);
-        queue.write_buffer(&self.particles_velocity_y, offset_velocity_buffer, &zero_velocity);
-        queue.write_buffer(&self.particles_velocity_z, offset_velocity_buffer, &zero_velocity);
+        let initial_velocity = |component: f32| vec![[0.0, 0.0, 0.0, component]; num_new_particles as usize];
+        queue.write_buffer(
+            &self.particles_velocity_x,
+            offset_velocity_buffer,
+            bytemuck::cast_slice(&initial_velocity(velocity.x)),
+        );
+        queue.write_buffer(
+            &self.particles_velocity_y,
+            offset_velocity_buffer,
+            bytemuck::cast_slice(&initial_velocity(velocity.y)),
+        );
+        queue.write_buffer(
+            &self.particles_velocity_z,
+            offset_velocity_buffer,
+            bytemuck::cast_slice(&initial_velocity(velocity.z)),
+        );
 
         self.simulation_properties.num_particles += num_new_particles;
+        // Read-back would only be available after a few steps.
+        self.max_particle_speed = self.max_particle_speed.max(velocity.magnitude());
+        queue.write_buffer(
+            &self.particle_count_buffer,
+            0,
+            bytemuck::bytes_of(&ParticleCountBufferContent::new(
+                self.simulation_properties.num_particles,
+                Self::COMPUTE_LOCAL_SIZE_PARTICLES,
+            )),
+        );
+    }
+
+    // Replaces all static solids.
+    // Expects the signed distance (in grid cells, negative inside) to the closest solid for every grid cell center.
+    pub fn set_static_solids(&mut self, queue: &wgpu::Queue, distance_field: &[f32]) {
+        assert_eq!(
+            distance_field.len(),
+            (self.grid_dimension.width * self.grid_dimension.height * self.grid_dimension.depth) as usize
+        );
+        queue.write_texture(
+            wgpu::TextureCopyView {
+                texture: &self.volume_solid,
+                mip_level: 0,
+                origin: wgpu::Origin3d::ZERO,
+            },
+            bytemuck::cast_slice(distance_field),
+            wgpu::TextureDataLayout {
+                offset: 0,
+                bytes_per_row: self.grid_dimension.width * std::mem::size_of::<f32>() as u32,
+                rows_per_image: self.grid_dimension.height,
+            },
+            self.grid_dimension,
+        );
+    }
+
+    // Sets all moving solids for the next step. Coordinates are in grid space!
+    pub fn set_dynamic_solids(&mut self, solids: &[DynamicSolid]) {
+        self.dynamic_solids = DynamicSolidsUniformBufferContent::new(solids);
+    }
+
+    // Latest available forces & torques of the fluid pressure on the dynamic solids, in the same order as passed to set_dynamic_solids.
+    // Lags a few steps behind, returns None if nothing new arrived since the last call.
+    pub fn retrieve_dynamic_solid_forces(&mut self) -> Option<Vec<FluidForce>> {
+        self.dynamic_solid_force_readback.retrieve(forces_from_impulse_buffer)
+    }
+
+    // Replaces all emitters. Coordinates are in grid space!
+    pub fn set_emitters(&mut self, emitters: &[ParticleEmitter]) {
+        if emitters.len() > MAX_NUM_EMITTERS {
+            warn!(
+                "{} emitters given, but only up to {} are supported. Ignoring the rest.",
+                emitters.len(),
+                MAX_NUM_EMITTERS
+            );
+        }
+        self.emitters = emitters
+            .iter()
+            .take(MAX_NUM_EMITTERS)
+            .map(|emitter| EmitterState::new(*emitter))
+            .collect();
+    }
+
+    // Replaces all drains. Coordinates are in grid space!
+    pub fn set_drains(&mut self, drains: &[ParticleDrain]) {
+        if drains.len() > MAX_NUM_DRAINS {
+            warn!(
+                "{} drains given, but only up to {} are supported. Ignoring the rest.",
+                drains.len(),
+                MAX_NUM_DRAINS
+            );
+        }
+        self.drains = drains.iter().take(MAX_NUM_DRAINS).cloned().collect();
+    }
+
+    // Bounds for the number of particles per cell, None disables reseeding.
+    pub fn reseeding(&self) -> Option<ParticleReseeding> {
+        self.reseeding
+    }
+
+    pub fn set_reseeding(&mut self, reseeding: Option<ParticleReseeding>) {
+        self.reseeding = reseeding.map(|reseeding| {
+            let min_particles_per_cell = reseeding.min_particles_per_cell.max(1).min(MAX_PARTICLES_PER_CELL);
+            ParticleReseeding {
+                min_particles_per_cell,
+                max_particles_per_cell: reseeding.max_particles_per_cell.max(min_particles_per_cell).min(MAX_PARTICLES_PER_CELL),
+            }
+        });
+    }
+
+    pub fn transfer_scheme(&self) -> TransferScheme {
+        self.transfer_scheme
+    }
+
+    pub fn set_transfer_scheme(&mut self, transfer_scheme: TransferScheme) {
+        self.transfer_scheme = transfer_scheme;
+    }
+
+    // Amount of FLIP in the FLIP/PIC blend, only relevant if the transfer scheme is FLIP.
+    pub fn flip_blend_factor(&self) -> f32 {
+        self.flip_blend_factor
+    }
+
+    pub fn set_flip_blend_factor(&mut self, flip_blend_factor: f32) {
+        self.flip_blend_factor = flip_blend_factor.max(0.0).min(1.0);
+    }
+
+    // Kinematic viscosity in grid cells² per second. Zero (inviscid) skips the viscosity solve entirely.
+    pub fn viscosity_grid(&self) -> f32 {
+        self.simulation_properties.viscosity
+    }
+
+    pub fn set_viscosity_grid(&mut self, viscosity: f32) {
+        self.simulation_properties.viscosity = viscosity.max(0.0);
+        self.viscosity_field.set_viscosity(self.simulation_properties.viscosity);
+    }
+
+    // Surface tension coefficient divided by fluid density in grid cells³ per second². Zero disables surface tension.
+    pub fn surface_tension_grid(&self) -> f32 {
+        self.simulation_properties.surface_tension
+    }
+
+    pub fn set_surface_tension_grid(&mut self, surface_tension: f32) {
+        self.simulation_properties.surface_tension = surface_tension.max(0.0);
+    }
+
+    // Strength of the vorticity confinement force, independent of the grid scale. Zero disables vorticity confinement.
+    pub fn vorticity_confinement(&self) -> f32 {
+        self.simulation_properties.vorticity_confinement
+    }
+
+    pub fn set_vorticity_confinement(&mut self, vorticity_confinement: f32) {
+        self.simulation_properties.vorticity_confinement = vorticity_confinement.max(0.0);
     }
 
     pub fn set_gravity_grid(&mut self, gravity: cgmath::Vector3<f32>) {
         self.simulation_properties.gravity_grid = gravity;
     }
 
+    // Particle count as last seen by the CPU. With active emitters or drains this lags a few steps behind.
     pub fn num_particles(&self) -> u32 {
         self.simulation_properties.num_particles
     }
 
+    // Draws 4 vertices (a quad strip) for every particle, using the particle count on the GPU.
+    pub fn draw_particles<'a>(&'a self, rpass: &mut wgpu::RenderPass<'a>) {
+        rpass.draw_indirect(&self.particle_count_buffer, ParticleCountBufferContent::DRAW_ARGS_OFFSET);
+    }
+
+    fn enqueue_particle_readback(&self, device: &wgpu::Device, readback: &mut BlockingReadback) -> ParticleReadbackIndices {
+        let position_buffer_size = self.max_num_particles as u64 * std::mem::size_of::<ParticlePositionLl>() as u64;
+        let velocity_buffer_size = self.max_num_particles as u64 * std::mem::size_of::<cgmath::Vector4<f32>>() as u64;
+        ParticleReadbackIndices {
+            count: readback.copy_buffer(
+                device,
+                &self.particle_count_buffer,
+                std::mem::size_of::<ParticleCountBufferContent>() as u64,
+            ),
+            position_llindex: readback.copy_buffer(device, &self.particles_position_llindex, position_buffer_size),
+            velocity: [
+                readback.copy_buffer(device, &self.particles_velocity_x, velocity_buffer_size),
+                readback.copy_buffer(device, &self.particles_velocity_y, velocity_buffer_size),
+                readback.copy_buffer(device, &self.particles_velocity_z, velocity_buffer_size),
+            ],
+        }
+    }
+
+    // Takes the particle buffers out of the read-back result and cuts them down to the number of active particles.
+    // Returns the particle count, the positions & linked list indices and the velocity buffers.
+    fn take_particle_readback(readback_results: &mut [Vec<u8>], indices: &ParticleReadbackIndices) -> (u32, Vec<u8>, [Vec<u8>; 3]) {
+        let count = &readback_results[indices.count];
+        let num_particles_offset = ParticleCountBufferContent::NUM_PARTICLES_OFFSET as usize;
+        let num_particles = u32::from_ne_bytes([
+            count[num_particles_offset],
+            count[num_particles_offset + 1],
+            count[num_particles_offset + 2],
+            count[num_particles_offset + 3],
+        ]);
+
+        let mut take = |index: usize, element_size: usize| {
+            let mut buffer = std::mem::take(&mut readback_results[index]);
+            buffer.truncate(num_particles as usize * element_size);
+            buffer
+        };
+        let velocity_size = std::mem::size_of::<cgmath::Vector4<f32>>();
+        (
+            num_particles,
+            take(indices.position_llindex, std::mem::size_of::<ParticlePositionLl>()),
+            [
+                take(indices.velocity[0], velocity_size),
+                take(indices.velocity[1], velocity_size),
+                take(indices.velocity[2], velocity_size),
+            ],
+        )
+    }
+
+    // Positions (grid space) and velocities (grid cells per second) of all particles.
+    // Waits for the GPU to finish all previously submitted work, so this is a fairly slow operation.
+    pub fn read_particles(&self, device: &wgpu::Device, queue: &wgpu::Queue) -> io::Result<Vec<(cgmath::Point3<f32>, cgmath::Vector3<f32>)>> {
+        let mut readback = BlockingReadback::new(device, "Particles");
+        let indices = self.enqueue_particle_readback(device, &mut readback);
+        let mut results = readback
+            .finish(device, queue)
+            .map_err(|_| io::Error::new(io::ErrorKind::Other, "failed to read back particles"))?;
+        let (_, positions, velocities) = Self::take_particle_readback(&mut results, &indices);
+
+        // Read-back data isn't necessarily aligned, so we can't cast the buffers directly.
+        let read_f32 =
+            |buffer: &[u8], offset: usize| f32::from_ne_bytes([buffer[offset], buffer[offset + 1], buffer[offset + 2], buffer[offset + 3]]);
+        let particle_size = std::mem::size_of::<ParticlePositionLl>();
+        let velocity_size = std::mem::size_of::<cgmath::Vector4<f32>>();
+        Ok((0..positions.len() / particle_size)
+            .map(|i| {
+                let position = cgmath::point3(
+                    read_f32(&positions, i * particle_size),
+                    read_f32(&positions, i * particle_size + 4),
+                    read_f32(&positions, i * particle_size + 8),
+                );
+                // Velocity is stored in the last component, after the affine matrix row.
+                let velocity = cgmath::vec3(
+                    read_f32(&velocities[0], i * velocity_size + 12),
+                    read_f32(&velocities[1], i * velocity_size + 12),
+                    read_f32(&velocities[2], i * velocity_size + 12),
+                );
+                (position, velocity)
+            })
+            .collect())
+    }
+
+    // Writes particles, pressure, emitters and simulation properties to a checkpoint file.
+    // Waits for the GPU to finish all previously submitted work, so this is a fairly slow operation.
+    // The simulation time and the rigid bodies coupled with the fluid are stored alongside to allow resuming time dependent scene elements.
+    pub fn save_checkpoint(
+        &self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        path: &Path,
+        simulation_time: Duration,
+        rigid_bodies: &[RigidBody],
+    ) -> io::Result<()> {
+        let mut readback = BlockingReadback::new(device, "Checkpoint");
+        let particle_indices = self.enqueue_particle_readback(device, &mut readback);
+        let pressure_from_velocity_index = readback.copy_texture(
+            device,
+            self.pressure_field_from_velocity.pressure_texture(),
+            self.grid_dimension,
+            std::mem::size_of::<f32>() as u32,
+        );
+        let pressure_from_density_index = readback.copy_texture(
+            device,
+            self.pressure_field_from_density.pressure_texture(),
+            self.grid_dimension,
+            std::mem::size_of::<f32>() as u32,
+        );
+        let mut results = readback
+            .finish(device, queue)
+            .map_err(|_| io::Error::new(io::ErrorKind::Other, "failed to read back simulation state"))?;
+        let (num_particles, particles_position_llindex, particles_velocity) = Self::take_particle_readback(&mut results, &particle_indices);
+
+        let mut header = CheckpointHeader::new();
+        header.grid_dimension = [self.grid_dimension.width, self.grid_dimension.height, self.grid_dimension.depth];
+        header.num_particles = num_particles;
+        header.num_steps = self.num_steps;
+        header.transfer_scheme = self.transfer_scheme as u32;
+        header.flip_blend_factor = self.flip_blend_factor;
+        header.gravity_grid = self.simulation_properties.gravity_grid.into();
+        header.viscosity_grid = self.simulation_properties.viscosity;
+        header.surface_tension_grid = self.simulation_properties.surface_tension;
+        header.vorticity_confinement = self.simulation_properties.vorticity_confinement;
+        if let Some(reseeding) = self.reseeding {
+            header.reseeding_min_particles_per_cell = reseeding.min_particles_per_cell;
+            header.reseeding_max_particles_per_cell = reseeding.max_particles_per_cell;
+        }
+        header.simulation_time_nanos = simulation_time.as_nanos() as u64;
+        header.num_emitters = self.emitters.len() as u32;
+        header.num_rigid_bodies = rigid_bodies.len() as u32;
+
+        let checkpoint = Checkpoint {
+            header,
+            particles_position_llindex,
+            particles_velocity,
+            pressure_from_velocity: std::mem::take(&mut results[pressure_from_velocity_index]),
+            pressure_from_density: std::mem::take(&mut results[pressure_from_density_index]),
+            emitter_fractional_particles: self.emitters.iter().map(|emitter| emitter.fractional_particles).collect(),
+            rigid_bodies: rigid_bodies.iter().map(RigidBody::checkpoint).collect(),
+        };
+        checkpoint.write(path)?;
+        info!("Wrote checkpoint with {} particles to {:?}", num_particles, path);
+        Ok(())
+    }
+
+    // Restores a checkpoint written by save_checkpoint, replacing all particles & pressure and continuing the given rigid bodies.
+    // Meant to be called on a freshly created fluid with the same grid dimension, emitters and rigid bodies.
+    // Returns the simulation time stored in the checkpoint.
+    pub fn load_checkpoint(&mut self, queue: &wgpu::Queue, path: &Path, rigid_bodies: &mut [RigidBody]) -> io::Result<Duration> {
+        let checkpoint = Checkpoint::read(path)?;
+        let header = &checkpoint.header;
+
+        let grid_dimension = [self.grid_dimension.width, self.grid_dimension.height, self.grid_dimension.depth];
+        if header.grid_dimension != grid_dimension {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "checkpoint has grid dimension {:?}, but the fluid has grid dimension {:?}",
+                    header.grid_dimension, grid_dimension
+                ),
+            ));
+        }
+        if header.num_particles > self.max_num_particles {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "checkpoint has {} particles, but the fluid supports only up to {}",
+                    header.num_particles, self.max_num_particles
+                ),
+            ));
+        }
+        if checkpoint.emitter_fractional_particles.len() != self.emitters.len() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "checkpoint has {} emitters, but the fluid has {}",
+                    checkpoint.emitter_fractional_particles.len(),
+                    self.emitters.len()
+                ),
+            ));
+        }
+        if checkpoint.rigid_bodies.len() != rigid_bodies.len() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "checkpoint has {} rigid bodies, but the scene has {}",
+                    checkpoint.rigid_bodies.len(),
+                    rigid_bodies.len()
+                ),
+            ));
+        }
+        let transfer_scheme = TransferScheme::iter()
+            .find(|scheme| *scheme as u32 == header.transfer_scheme)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, format!("unknown transfer scheme {}", header.transfer_scheme)))?;
+
+        queue.write_buffer(&self.particles_position_llindex, 0, &checkpoint.particles_position_llindex);
+        queue.write_buffer(&self.particles_velocity_x, 0, &checkpoint.particles_velocity[0]);
+        queue.write_buffer(&self.particles_velocity_y, 0, &checkpoint.particles_velocity[1]);
+        queue.write_buffer(&self.particles_velocity_z, 0, &checkpoint.particles_velocity[2]);
+        // Linked list pointers in the checkpoint don't match the current linked list grid.
+        self.particle_linked_lists_valid = false;
+        queue.write_buffer(
+            &self.particle_count_buffer,
+            0,
+            bytemuck::bytes_of(&ParticleCountBufferContent::new(header.num_particles, Self::COMPUTE_LOCAL_SIZE_PARTICLES)),
+        );
+        for (target, content) in [&self.pressure_field_from_velocity, &self.pressure_field_from_density]
+            .iter()
+            .zip([&checkpoint.pressure_from_velocity, &checkpoint.pressure_from_density].iter())
+        {
+            queue.write_texture(
+                wgpu::TextureCopyView {
+                    texture: target.pressure_texture(),
+                    mip_level: 0,
+                    origin: wgpu::Origin3d::ZERO,
+                },
+                content,
+                wgpu::TextureDataLayout {
+                    offset: 0,
+                    bytes_per_row: self.grid_dimension.width * std::mem::size_of::<f32>() as u32,
+                    rows_per_image: self.grid_dimension.height,
+                },
+                self.grid_dimension,
+            );
+        }
+
+        self.simulation_properties.num_particles = header.num_particles;
+        self.simulation_properties.gravity_grid = header.gravity_grid.into();
+        self.set_viscosity_grid(header.viscosity_grid);
+        self.set_surface_tension_grid(header.surface_tension_grid);
+        self.set_vorticity_confinement(header.vorticity_confinement);
+        self.set_reseeding(if header.reseeding_min_particles_per_cell == 0 {
+            None
+        } else {
+            Some(ParticleReseeding {
+                min_particles_per_cell: header.reseeding_min_particles_per_cell,
+                max_particles_per_cell: header.reseeding_max_particles_per_cell,
+            })
+        });
+        self.transfer_scheme = transfer_scheme;
+        self.flip_blend_factor = header.flip_blend_factor;
+        self.num_steps = header.num_steps;
+        self.simulation_time = Duration::from_nanos(header.simulation_time_nanos);
+        for (emitter, fractional_particles) in self.emitters.iter_mut().zip(checkpoint.emitter_fractional_particles.iter()) {
+            emitter.fractional_particles = *fractional_particles;
+        }
+        for (body, body_checkpoint) in rigid_bodies.iter_mut().zip(checkpoint.rigid_bodies.iter()) {
+            body.restore_checkpoint(body_checkpoint);
+        }
+        // Velocity is the .w component of every particle's vec4 (byte buffers aren't necessarily aligned for casting).
+        let velocity_component = |component: usize, particle: usize| {
+            let offset = particle * std::mem::size_of::<cgmath::Vector4<f32>>() + 3 * std::mem::size_of::<f32>();
+            let bytes = &checkpoint.particles_velocity[component][offset..offset + std::mem::size_of::<f32>()];
+            f32::from_ne_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+        };
+        self.max_particle_speed = (0..header.num_particles as usize)
+            .map(|i| cgmath::vec3(velocity_component(0, i), velocity_component(1, i), velocity_component(2, i)).magnitude())
+            .fold(0.0, f32::max);
+
+        info!("Restored checkpoint with {} particles from {:?}", header.num_particles, path);
+        Ok(Duration::from_nanos(header.simulation_time_nanos))
+    }
+
     pub fn get_or_create_group_layout_renderer(device: &wgpu::Device) -> &BindGroupLayoutWithDesc {
         unsafe {
             GROUP_LAYOUT_RENDERER.get_or_insert_with(|| {
@@ -598,6 +1443,13 @@ impl HybridFluid {
     };
     const COMPUTE_LOCAL_SIZE_PARTICLES: u32 = 64;
 
+    // Need to match the modes in particle_sources_update_count.comp
+    const PARTICLE_COUNT_MODE_RESET_COUNTERS: u32 = 0;
+    const PARTICLE_COUNT_MODE_APPLY_DRAIN: u32 = 1;
+    const PARTICLE_COUNT_MODE_APPLY_EMISSION: u32 = 2;
+    const PARTICLE_COUNT_MODE_APPLY_RESEEDING: u32 = 3;
+    const SIMULATION_PROPERTIES_NUM_PARTICLES_OFFSET: u64 = 12;
+
     pub fn pressure_solver_config_velocity(&mut self) -> &mut SolverConfig {
         &mut self.pressure_field_from_velocity.config
     }
@@ -614,11 +1466,104 @@ impl HybridFluid {
         &self.pressure_field_from_density.stats
     }
 
+    pub fn viscosity_solver_config(&mut self) -> &mut SolverConfig {
+        &mut self.viscosity_field.config
+    }
+
+    // One sample per velocity component and step.
+    pub fn viscosity_solver_stats(&self) -> &VecDeque<SolverStatisticSample> {
+        &self.viscosity_field.stats
+    }
+
+    pub fn pressure_solver_convergence_velocity(&self) -> &VecDeque<SolverConvergence> {
+        &self.pressure_field_from_velocity.convergence
+    }
+
+    pub fn pressure_solver_convergence_density(&self) -> &VecDeque<SolverConvergence> {
+        &self.pressure_field_from_density.convergence
+    }
+
+    pub fn viscosity_solver_convergence(&self) -> &VecDeque<SolverConvergence> {
+        &self.viscosity_field.convergence
+    }
+
+    pub const SOLVER_CONVERGENCE_CSV_HEADER: &'static str = "solver,simulated_time,iteration,mse";
+
+    // Writes the residual history of all recent solves as csv, one row per error check.
+    pub fn write_solver_convergence_csv(&self, path: &Path) -> io::Result<()> {
+        use std::io::Write;
+
+        if let Some(directory) = path.parent() {
+            std::fs::create_dir_all(directory)?;
+        }
+        let mut writer = io::BufWriter::new(std::fs::File::create(path)?);
+        writeln!(writer, "{}", Self::SOLVER_CONVERGENCE_CSV_HEADER)?;
+        self.write_solver_convergence_rows(&mut writer, &mut [0; 3])?;
+        writer.flush()
+    }
+
+    // Appends csv rows (see SOLVER_CONVERGENCE_CSV_HEADER) for the solves of the velocity, density and viscosity solver
+    // that were retrieved after the given number of solves and advances num_written accordingly.
+    // Returns how many of these solves were no longer in the history and got skipped.
+    pub fn write_solver_convergence_rows(&self, writer: &mut impl std::io::Write, num_written: &mut [u64; 3]) -> io::Result<u64> {
+        use std::io::Write;
+
+        let mut num_skipped = 0;
+        for ((solver, field), num_written) in [
+            ("velocity", &self.pressure_field_from_velocity),
+            ("density", &self.pressure_field_from_density),
+            ("viscosity", &self.viscosity_field),
+        ]
+        .iter()
+        .zip(num_written.iter_mut())
+        {
+            let num_new = (field.num_retrieved_solves - *num_written) as usize;
+            let num_available = num_new.min(field.convergence.len());
+            num_skipped += (num_new - num_available) as u64;
+            for convergence in field.convergence.iter().skip(field.convergence.len() - num_available) {
+                for residual in convergence.residuals.iter() {
+                    writeln!(
+                        writer,
+                        "{},{},{},{}",
+                        solver,
+                        convergence.timestamp.as_secs_f64(),
+                        residual.iteration,
+                        residual.mse
+                    )?;
+                }
+            }
+            *num_written = field.num_retrieved_solves;
+        }
+        Ok(num_skipped)
+    }
+
     // Necessary to call this to update solver statistics and config.
     // Do not call while building command buffer!
     pub fn update_statistics(&mut self) {
         self.pressure_field_from_density.start_error_buffer_readbacks();
         self.pressure_field_from_velocity.start_error_buffer_readbacks();
+        self.viscosity_field.start_error_buffer_readbacks();
+        self.dynamic_solid_force_readback.start_readbacks();
+        self.particle_count_readback.start_readbacks();
+        self.max_particle_speed_readback.start_readbacks();
+        if let Some(num_particles) = self
+            .particle_count_readback
+            .retrieve(|content, _| bytemuck::cast_slice::<u8, u32>(content)[3])
+        {
+            self.simulation_properties.num_particles = num_particles;
+        }
+        if let Some(max_particle_speed) = self
+            .max_particle_speed_readback
+            .retrieve(|content, _| f32::from_bits(bytemuck::cast_slice::<u8, u32>(content)[0]))
+        {
+            self.max_particle_speed = max_particle_speed;
+        }
+    }
+
+    // Largest particle speed in grid cells per second.
+    // Result of a step a few steps ago since it comes from an asynchronous read-back.
+    pub fn max_particle_speed_grid(&self) -> f32 {
+        self.max_particle_speed
     }
 
     pub fn step(
@@ -631,14 +1576,94 @@ impl HybridFluid {
     ) {
         wgpu_scope!(encoder, "HybridFluid.step");
 
+        // Reseeding relies on the linked lists of the last step, so it skips steps right after the particles were replaced.
+        let reseeding = if self.particle_linked_lists_valid { self.reseeding } else { None };
+        let particle_sources =
+            ParticleSourcesUniformBufferContent::new(&mut self.emitters, &self.drains, reseeding, simulation_delta, self.num_steps);
+        self.num_steps += 1;
+        self.simulation_time += simulation_delta;
+
+        self.simulation_properties.transfer_scheme = self.transfer_scheme as u32;
+        self.simulation_properties.flip_blend_factor = self.flip_blend_factor;
+        self.simulation_properties.step_delta = simulation_delta.as_secs_f32();
+
         wgpu_scope!(encoder, "update uniforms", || {
             self.pressure_field_from_density.update_uniforms(queue, simulation_delta);
             self.pressure_field_from_velocity.update_uniforms(queue, simulation_delta);
+            self.pressure_field_from_density.set_timestamp(self.simulation_time);
+            self.pressure_field_from_velocity.set_timestamp(self.simulation_time);
+            if self.simulation_properties.viscosity > 0.0 {
+                self.viscosity_field.update_uniforms(queue, simulation_delta);
+                self.viscosity_field.set_timestamp(self.simulation_time);
+            }
             self.simulation_properties_uniformbuffer.update_content(queue, self.simulation_properties);
+            self.dynamic_solids_uniformbuffer.update_content(queue, self.dynamic_solids);
+            self.particle_sources_uniformbuffer.update_content(queue, particle_sources);
         });
 
+        let removes_particles = particle_sources.num_drains() > 0 || particle_sources.is_reseeding();
+        if removes_particles || particle_sources.num_emitted_particles() > 0 {
+            let mut cpass = encoder.begin_compute_pass();
+            cpass.set_bind_group(0, per_frame_bind_group, &[]);
+            cpass.set_bind_group(1, &self.bind_group_uniform, &[]);
+            cpass.set_bind_group(2, &self.bind_group_particle_sources, &[]);
+
+            if removes_particles {
+                cpass.set_pipeline(pipeline_manager.get_compute(&self.pipeline_particle_sources_update_count));
+                cpass.set_push_constants(0, bytemuck::bytes_of(&[Self::PARTICLE_COUNT_MODE_RESET_COUNTERS]));
+                cpass.dispatch(1, 1, 1);
+            }
+            if particle_sources.is_reseeding() {
+                wgpu_scope!(cpass, "reseed particles", || {
+                    let grid_work_groups = wgpu_utils::compute_group_size(self.grid_dimension, Self::COMPUTE_LOCAL_SIZE_FLUID);
+                    cpass.set_pipeline(pipeline_manager.get_compute(&self.pipeline_particle_sources_reseed));
+                    cpass.dispatch(grid_work_groups.width, grid_work_groups.height, grid_work_groups.depth);
+                    cpass.set_pipeline(pipeline_manager.get_compute(&self.pipeline_particle_sources_update_count));
+                    cpass.set_push_constants(0, bytemuck::bytes_of(&[Self::PARTICLE_COUNT_MODE_APPLY_RESEEDING]));
+                    cpass.dispatch(1, 1, 1);
+                });
+            }
+            // Surplus particles marked by reseeding are removed along with drained ones.
+            if removes_particles {
+                wgpu_scope!(cpass, "drain particles", || {
+                    cpass.set_pipeline(pipeline_manager.get_compute(&self.pipeline_particle_sources_drain_count));
+                    cpass.dispatch_indirect(&self.particle_count_buffer, ParticleCountBufferContent::DISPATCH_ARGS_OFFSET);
+                    cpass.set_pipeline(pipeline_manager.get_compute(&self.pipeline_particle_sources_drain_find_holes));
+                    cpass.dispatch_indirect(&self.particle_count_buffer, ParticleCountBufferContent::DISPATCH_ARGS_OFFSET);
+                    // There are never more holes than particles.
+                    cpass.set_pipeline(pipeline_manager.get_compute(&self.pipeline_particle_sources_drain_compact));
+                    cpass.dispatch_indirect(&self.particle_count_buffer, ParticleCountBufferContent::DISPATCH_ARGS_OFFSET);
+                    cpass.set_pipeline(pipeline_manager.get_compute(&self.pipeline_particle_sources_update_count));
+                    cpass.set_push_constants(0, bytemuck::bytes_of(&[Self::PARTICLE_COUNT_MODE_APPLY_DRAIN]));
+                    cpass.dispatch(1, 1, 1);
+                });
+            }
+            if particle_sources.num_emitted_particles() > 0 {
+                wgpu_scope!(cpass, "emit particles", || {
+                    cpass.set_pipeline(pipeline_manager.get_compute(&self.pipeline_particle_sources_emit));
+                    cpass.dispatch(
+                        wgpu_utils::compute_group_size_1d(particle_sources.num_emitted_particles(), Self::COMPUTE_LOCAL_SIZE_PARTICLES),
+                        1,
+                        1,
+                    );
+                    cpass.set_pipeline(pipeline_manager.get_compute(&self.pipeline_particle_sources_update_count));
+                    cpass.set_push_constants(0, bytemuck::bytes_of(&[Self::PARTICLE_COUNT_MODE_APPLY_EMISSION]));
+                    cpass.dispatch(1, 1, 1);
+                });
+            }
+        }
+        // All particle passes take the particle count from the simulation properties.
+        encoder.copy_buffer_to_buffer(
+            &self.particle_count_buffer,
+            ParticleCountBufferContent::NUM_PARTICLES_OFFSET,
+            self.simulation_properties_uniformbuffer.buffer(),
+            Self::SIMULATION_PROPERTIES_NUM_PARTICLES_OFFSET,
+            std::mem::size_of::<u32>() as u64,
+        );
+        // Reads the dispatch arguments along with the count.
+        self.particle_count_readback.enqueue_copy(encoder, &self.particle_count_buffer, 0, ());
+
         let grid_work_groups = wgpu_utils::compute_group_size(self.grid_dimension, Self::COMPUTE_LOCAL_SIZE_FLUID);
-        let particle_work_groups = wgpu_utils::compute_group_size_1d(self.simulation_properties.num_particles, Self::COMPUTE_LOCAL_SIZE_PARTICLES);
 
         {
             let mut cpass = encoder.begin_compute_pass();
@@ -657,7 +1682,7 @@ impl HybridFluid {
 
                         wgpu_scope!(cpass, "create particle linked lists", || {
                             cpass.set_pipeline(pipeline_manager.get_compute(&self.pipeline_transfer_build_linkedlist));
-                            cpass.dispatch(particle_work_groups, 1, 1);
+                            cpass.dispatch_indirect(&self.particle_count_buffer, ParticleCountBufferContent::DISPATCH_ARGS_OFFSET);
                         });
 
                         if i == 0 {
@@ -674,9 +1699,27 @@ impl HybridFluid {
                     });
                 }
             });
+            if self.simulation_properties.vorticity_confinement > 0.0 {
+                wgpu_scope!(cpass, "vorticity confinement", || {
+                    cpass.set_bind_group(2, &self.bind_group_vorticity, &[]);
+                    cpass.set_pipeline(pipeline_manager.get_compute(&self.pipeline_vorticity_compute));
+                    cpass.dispatch(grid_work_groups.width, grid_work_groups.height, grid_work_groups.depth);
+                    cpass.set_pipeline(pipeline_manager.get_compute(&self.pipeline_vorticity_confinement_apply));
+                    cpass.dispatch(grid_work_groups.width, grid_work_groups.height, grid_work_groups.depth);
+                });
+            }
+            if self.simulation_properties.surface_tension > 0.0 {
+                wgpu_scope!(cpass, "surface tension", || {
+                    cpass.set_bind_group(2, &self.bind_group_surface_tension, &[]);
+                    cpass.set_pipeline(pipeline_manager.get_compute(&self.pipeline_surface_tension_levelset));
+                    cpass.dispatch(grid_work_groups.width, grid_work_groups.height, grid_work_groups.depth);
+                    cpass.set_pipeline(pipeline_manager.get_compute(&self.pipeline_surface_tension_pressure));
+                    cpass.dispatch(grid_work_groups.width, grid_work_groups.height, grid_work_groups.depth);
+                });
+            }
             wgpu_scope!(cpass, "compute divergence", || {
                 cpass.set_pipeline(pipeline_manager.get_compute(&self.pipeline_divergence_compute));
-                cpass.set_bind_group(1, &self.bind_group_divergence_compute, &[]); // Writes directly into Residual of the pressure solver.
+                cpass.set_bind_group(2, &self.bind_group_divergence_compute, &[]); // Writes directly into Residual of the pressure solver.
                 cpass.dispatch(grid_work_groups.width, grid_work_groups.height, grid_work_groups.depth);
             });
         }
@@ -690,6 +1733,14 @@ impl HybridFluid {
             cpass.set_bind_group(0, per_frame_bind_group, &[]);
             cpass.set_bind_group(1, &self.bind_group_uniform, &[]);
 
+            if self.dynamic_solids.num_solids() > 0 {
+                wgpu_scope!(cpass, "compute pressure forces on dynamic solids", || {
+                    cpass.set_bind_group(2, &self.bind_group_dynamic_solid_forces, &[]);
+                    cpass.set_pipeline(pipeline_manager.get_compute(&self.pipeline_dynamic_solid_forces));
+                    cpass.dispatch(self.dynamic_solids.num_solids(), 1, 1);
+                });
+            }
+
             {
                 cpass.set_bind_group(2, &self.bind_group_write_velocity, &[]);
 
@@ -697,6 +1748,49 @@ impl HybridFluid {
                     cpass.set_pipeline(pipeline_manager.get_compute(&self.pipeline_divergence_remove));
                     cpass.dispatch(grid_work_groups.width, grid_work_groups.height, grid_work_groups.depth);
                 });
+            }
+        }
+
+        // Implicit viscosity, solving (I - dt * viscosity * laplace) u_new = u for every velocity component.
+        // Runs before extrapolation since only samples next to fluid take part. The (small) divergence this introduces is left to the density projection.
+        if self.simulation_properties.viscosity > 0.0 {
+            for i in 0..3 {
+                wgpu_scope!(encoder, &format!("viscosity {}", ["x", "y", "z"][i]), || {
+                    {
+                        let mut cpass = encoder.begin_compute_pass();
+                        wgpu_scope!(cpass, "prepare viscosity solve", || {
+                            cpass.set_bind_group(0, per_frame_bind_group, &[]);
+                            cpass.set_bind_group(1, &self.bind_group_uniform, &[]);
+                            cpass.set_bind_group(2, &self.bind_group_viscosity, &[]);
+                            cpass.set_pipeline(pipeline_manager.get_compute(&self.pipeline_viscosity_prepare));
+                            cpass.set_push_constants(0, bytemuck::bytes_of(&[i as u32]));
+                            cpass.dispatch(grid_work_groups.width, grid_work_groups.height, grid_work_groups.depth);
+                        });
+                    }
+                    self.pressure_solver
+                        .solve(simulation_delta, &mut self.viscosity_field, &mut encoder, pipeline_manager);
+                    {
+                        let mut cpass = encoder.begin_compute_pass();
+                        wgpu_scope!(cpass, "apply viscosity solve", || {
+                            cpass.set_bind_group(0, per_frame_bind_group, &[]);
+                            cpass.set_bind_group(1, &self.bind_group_uniform, &[]);
+                            cpass.set_bind_group(2, &self.bind_group_viscosity, &[]);
+                            cpass.set_pipeline(pipeline_manager.get_compute(&self.pipeline_viscosity_apply));
+                            cpass.set_push_constants(0, bytemuck::bytes_of(&[i as u32]));
+                            cpass.dispatch(grid_work_groups.width, grid_work_groups.height, grid_work_groups.depth);
+                        });
+                    }
+                });
+            }
+        }
+
+        {
+            let mut cpass = encoder.begin_compute_pass();
+            cpass.set_bind_group(0, per_frame_bind_group, &[]);
+            cpass.set_bind_group(1, &self.bind_group_uniform, &[]);
+
+            {
+                cpass.set_bind_group(2, &self.bind_group_write_velocity, &[]);
 
                 wgpu_scope!(cpass, "extrapolate velocity grid", || {
                     cpass.set_pipeline(pipeline_manager.get_compute(&self.pipeline_extrapolate_velocity));
@@ -712,7 +1806,7 @@ impl HybridFluid {
             wgpu_scope!(cpass, "advect particles & write new linked list grid", || {
                 cpass.set_bind_group(2, &self.bind_group_advect_particles, &[]);
                 cpass.set_pipeline(pipeline_manager.get_compute(&self.pipeline_advect_particles));
-                cpass.dispatch(particle_work_groups, 1, 1);
+                cpass.dispatch_indirect(&self.particle_count_buffer, ParticleCountBufferContent::DISPATCH_ARGS_OFFSET);
             });
 
             wgpu_scope!(cpass, "density projection: set boundary marker", || {
@@ -727,6 +1821,21 @@ impl HybridFluid {
             });
         }
 
+        self.max_particle_speed_readback
+            .enqueue_copy(encoder, &self.simulation_statistics_buffer, 0, ());
+
+        if self.dynamic_solids.num_solids() > 0 {
+            self.dynamic_solid_force_readback.enqueue_copy(
+                encoder,
+                &self.dynamic_solid_forces,
+                0,
+                ForceReadbackInfo {
+                    num_solids: self.dynamic_solids.num_solids(),
+                    simulation_delta,
+                },
+            );
+        }
+
         // Compute pressure from density error.
         self.pressure_solver
             .solve(simulation_delta, &mut self.pressure_field_from_density, &mut encoder, pipeline_manager);
@@ -738,8 +1847,10 @@ impl HybridFluid {
                 cpass.set_bind_group(1, &self.bind_group_uniform, &[]);
                 cpass.set_bind_group(2, &self.bind_group_density_projection_correct_particles, &[]);
                 cpass.set_pipeline(pipeline_manager.get_compute(&self.pipeline_density_projection_correct_particles));
-                cpass.dispatch(particle_work_groups, 1, 1);
+                cpass.dispatch_indirect(&self.particle_count_buffer, ParticleCountBufferContent::DISPATCH_ARGS_OFFSET);
             });
         }
+
+        self.particle_linked_lists_valid = true;
     }
 }
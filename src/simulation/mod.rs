@@ -1,5 +1,23 @@
+mod checkpoint;
+mod cpu_fluid;
+mod cpu_pressure_solver;
+mod dynamic_solid;
+mod emitter;
+mod fluid_backend;
 mod hybrid_fluid;
+mod multigrid;
+mod pcg_solver;
 mod pressure_solver;
+mod pressure_solver_validation;
+mod relaxation_solver;
+mod rigid_body;
 
-pub use hybrid_fluid::HybridFluid;
-pub use pressure_solver::{SolverConfig, SolverStatisticSample};
+pub use cpu_fluid::CpuFluid;
+pub use cpu_pressure_solver::PressureComparison;
+pub use dynamic_solid::{DynamicSolid, DynamicSolidShape, FluidForce, MAX_NUM_DYNAMIC_SOLIDS};
+pub use emitter::{EmitterShape, ParticleDrain, ParticleEmitter, ParticleReseeding, MAX_NUM_DRAINS, MAX_NUM_EMITTERS, MAX_PARTICLES_PER_CELL};
+pub use fluid_backend::{FluidBackend, GpuStepContext, ParticleComparison};
+pub use hybrid_fluid::{HybridFluid, TransferScheme};
+pub use pressure_solver::{Preconditioner, SolverConfig, SolverConvergence, SolverMethod, SolverStatisticSample};
+pub use pressure_solver_validation::{validate_pressure_solver, SolverValidationResult, MAX_RELATIVE_PRESSURE_DIFFERENCE};
+pub use rigid_body::{RigidBody, RigidBodyShape};
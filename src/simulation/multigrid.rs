@@ -0,0 +1,434 @@
+// Multigrid preconditioner for the pressure solver, see pressure_multigrid.glsl for an overview of the method.
+// Owns the volumes of all coarser levels, on the finest level it works directly on the solver's residual and auxiliary/search volumes.
+
+use super::pressure_solver::create_volume_texture_desc;
+use crate::wgpu_utils::{self, binding_builder::*, binding_glsl, pipelines::*, shader::ShaderDirectory};
+use std::{path::Path, rc::Rc};
+
+// Volume the preconditioned residual ends up in.
+#[derive(Clone, Copy)]
+pub(super) enum MultigridTarget {
+    Auxiliary = 0,
+    Search = 1,
+}
+
+struct CoarseLevel {
+    grid_dimension: wgpu::Extent3d,
+    // Replaces the solver's general bind group (marker volume) for all passes on this level.
+    bind_group_marker: wgpu::BindGroup,
+    bind_group_restrict_marker: wgpu::BindGroup,
+    bind_group_smooth: wgpu::BindGroup,
+    // Passes between this and the next coarser level, None on the coarsest level.
+    bind_group_restrict_residual: Option<wgpu::BindGroup>,
+    bind_group_prolongate: Option<wgpu::BindGroup>,
+}
+
+pub(super) struct MultigridPreconditioner {
+    grid_dimension: wgpu::Extent3d,
+    // Finest level, one bind group per MultigridTarget.
+    bind_group_smooth: [wgpu::BindGroup; 2],
+    bind_group_restrict_residual: [wgpu::BindGroup; 2],
+    bind_group_prolongate: [wgpu::BindGroup; 2],
+    coarse_levels: Vec<CoarseLevel>,
+
+    pipeline_restrict_marker: ComputePipelineHandle,
+    pipeline_smooth: ComputePipelineHandle,
+    pipeline_restrict_residual: ComputePipelineHandle,
+    pipeline_prolongate: ComputePipelineHandle,
+}
+
+impl MultigridPreconditioner {
+    // Need to match the mode bits in multigrid_smooth.comp
+    const SMOOTH_COLOR_BLACK: u32 = 1;
+    const SMOOTH_ZERO_INITIAL_GUESS: u32 = 2;
+    const SMOOTH_START_DOTPRODUCT: u32 = 4;
+
+    // Red-black Gauss-Seidel iterations before restriction and after prolongation.
+    const NUM_SMOOTHING_ITERATIONS: u32 = 2;
+    // The coarsest level is small enough to (almost) solve it with many smoothing iterations.
+    const NUM_SMOOTHING_ITERATIONS_COARSEST: u32 = 8;
+    // Coarsening stops before any dimension of a level gets smaller than this.
+    const MIN_GRID_DIMENSION: u32 = 4;
+
+    const COMPUTE_LOCAL_SIZE_VOLUME: wgpu::Extent3d = wgpu::Extent3d {
+        width: 8,
+        height: 8,
+        depth: 1,
+    };
+
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        device: &wgpu::Device,
+        grid_dimension: wgpu::Extent3d,
+        shader_dir: &ShaderDirectory,
+        pipeline_manager: &mut PipelineManager,
+        group_layout_general: &BindGroupLayoutWithDesc,
+        group_layout_pressure_field: &BindGroupLayoutWithDesc,
+        push_constant_ranges: &[wgpu::PushConstantRange],
+        reduce_buffer: &wgpu::Buffer,
+        volume_residual_view: &wgpu::TextureView,
+        volume_target_views: [&wgpu::TextureView; 2],
+    ) -> Self {
+        let group_layout_restrict_marker = BindGroupLayoutBuilder::new()
+            .next_binding_compute(binding_glsl::image3D(wgpu::TextureFormat::R8Snorm, false))
+            .create(device, "BindGroupLayout: Multigrid restrict marker");
+        let group_layout_smooth = BindGroupLayoutBuilder::new()
+            .next_binding_compute(binding_glsl::buffer(false))
+            .next_binding_compute(binding_glsl::texture3D())
+            .next_binding_compute(binding_glsl::image3D(wgpu::TextureFormat::R32Float, false))
+            .create(device, "BindGroupLayout: Multigrid smooth");
+        let group_layout_restrict_residual = BindGroupLayoutBuilder::new()
+            .next_binding_compute(binding_glsl::texture3D())
+            .next_binding_compute(binding_glsl::texture3D())
+            .next_binding_compute(binding_glsl::image3D(wgpu::TextureFormat::R32Float, false))
+            .create(device, "BindGroupLayout: Multigrid restrict residual");
+        let group_layout_prolongate = BindGroupLayoutBuilder::new()
+            .next_binding_compute(binding_glsl::texture3D())
+            .next_binding_compute(binding_glsl::image3D(wgpu::TextureFormat::R32Float, false))
+            .create(device, "BindGroupLayout: Multigrid prolongate");
+
+        let create_pipeline_layout = |label: &str, group_layout: &BindGroupLayoutWithDesc| {
+            Rc::new(device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some(label),
+                bind_group_layouts: &[&group_layout_general.layout, &group_layout_pressure_field.layout, &group_layout.layout],
+                push_constant_ranges,
+            }))
+        };
+        let layout_restrict_marker = create_pipeline_layout("Multigrid Restrict Marker Pipeline Layout", &group_layout_restrict_marker);
+        let layout_smooth = create_pipeline_layout("Multigrid Smooth Pipeline Layout", &group_layout_smooth);
+        let layout_restrict_residual = create_pipeline_layout("Multigrid Restrict Residual Pipeline Layout", &group_layout_restrict_residual);
+        let layout_prolongate = create_pipeline_layout("Multigrid Prolongate Pipeline Layout", &group_layout_prolongate);
+
+        // Volumes of all coarser levels.
+        struct LevelVolumes {
+            grid_dimension: wgpu::Extent3d,
+            marker_view: wgpu::TextureView,
+            right_hand_side_view: wgpu::TextureView,
+            solution_view: wgpu::TextureView,
+        }
+        let mut level_volumes = Vec::new();
+        let mut level_grid_dimension = grid_dimension;
+        loop {
+            level_grid_dimension = wgpu::Extent3d {
+                width: (level_grid_dimension.width + 1) / 2,
+                height: (level_grid_dimension.height + 1) / 2,
+                depth: (level_grid_dimension.depth + 1) / 2,
+            };
+            // There is always at least one coarser level so the finest level doesn't need to be special cased.
+            let min_dimension = level_grid_dimension
+                .width
+                .min(level_grid_dimension.height)
+                .min(level_grid_dimension.depth);
+            if !level_volumes.is_empty() && min_dimension < Self::MIN_GRID_DIMENSION {
+                break;
+            }
+            let level = level_volumes.len() + 1;
+            let create_view = |name: &str, format: wgpu::TextureFormat| {
+                device
+                    .create_texture(&create_volume_texture_desc(
+                        &format!("Multigrid {} - Level {}", name, level),
+                        level_grid_dimension,
+                        format,
+                    ))
+                    .create_view(&Default::default())
+            };
+            level_volumes.push(LevelVolumes {
+                grid_dimension: level_grid_dimension,
+                marker_view: create_view("Marker", wgpu::TextureFormat::R8Snorm),
+                right_hand_side_view: create_view("Right Hand Side", wgpu::TextureFormat::R32Float),
+                solution_view: create_view("Solution", wgpu::TextureFormat::R32Float),
+            });
+        }
+        info!("Multigrid preconditioner with {} levels", level_volumes.len() + 1);
+
+        let bind_group_smooth = [
+            BindGroupBuilder::new(&group_layout_smooth)
+                .resource(reduce_buffer.as_entire_binding())
+                .texture(volume_residual_view)
+                .texture(volume_target_views[0])
+                .create(device, "BindGroup: Multigrid smooth, Level 0 to auxiliary"),
+            BindGroupBuilder::new(&group_layout_smooth)
+                .resource(reduce_buffer.as_entire_binding())
+                .texture(volume_residual_view)
+                .texture(volume_target_views[1])
+                .create(device, "BindGroup: Multigrid smooth, Level 0 to search"),
+        ];
+        let bind_group_restrict_residual = [
+            BindGroupBuilder::new(&group_layout_restrict_residual)
+                .texture(volume_residual_view)
+                .texture(volume_target_views[0])
+                .texture(&level_volumes[0].right_hand_side_view)
+                .create(device, "BindGroup: Multigrid restrict residual, Level 0 from auxiliary"),
+            BindGroupBuilder::new(&group_layout_restrict_residual)
+                .texture(volume_residual_view)
+                .texture(volume_target_views[1])
+                .texture(&level_volumes[0].right_hand_side_view)
+                .create(device, "BindGroup: Multigrid restrict residual, Level 0 from search"),
+        ];
+        let bind_group_prolongate = [
+            BindGroupBuilder::new(&group_layout_prolongate)
+                .texture(&level_volumes[0].solution_view)
+                .texture(volume_target_views[0])
+                .create(device, "BindGroup: Multigrid prolongate, Level 0 to auxiliary"),
+            BindGroupBuilder::new(&group_layout_prolongate)
+                .texture(&level_volumes[0].solution_view)
+                .texture(volume_target_views[1])
+                .create(device, "BindGroup: Multigrid prolongate, Level 0 to search"),
+        ];
+
+        let coarse_levels = level_volumes
+            .iter()
+            .enumerate()
+            .map(|(i, volumes)| {
+                let level = i + 1;
+                let coarser_volumes = level_volumes.get(i + 1);
+                CoarseLevel {
+                    grid_dimension: volumes.grid_dimension,
+                    bind_group_marker: BindGroupBuilder::new(group_layout_general)
+                        .texture(&volumes.marker_view)
+                        .create(device, &format!("BindGroup: Multigrid marker, Level {}", level)),
+                    bind_group_restrict_marker: BindGroupBuilder::new(&group_layout_restrict_marker)
+                        .texture(&volumes.marker_view)
+                        .create(device, &format!("BindGroup: Multigrid restrict marker, Level {}", level)),
+                    bind_group_smooth: BindGroupBuilder::new(&group_layout_smooth)
+                        .resource(reduce_buffer.as_entire_binding())
+                        .texture(&volumes.right_hand_side_view)
+                        .texture(&volumes.solution_view)
+                        .create(device, &format!("BindGroup: Multigrid smooth, Level {}", level)),
+                    bind_group_restrict_residual: coarser_volumes.map(|coarser_volumes| {
+                        BindGroupBuilder::new(&group_layout_restrict_residual)
+                            .texture(&volumes.right_hand_side_view)
+                            .texture(&volumes.solution_view)
+                            .texture(&coarser_volumes.right_hand_side_view)
+                            .create(device, &format!("BindGroup: Multigrid restrict residual, Level {}", level))
+                    }),
+                    bind_group_prolongate: coarser_volumes.map(|coarser_volumes| {
+                        BindGroupBuilder::new(&group_layout_prolongate)
+                            .texture(&coarser_volumes.solution_view)
+                            .texture(&volumes.solution_view)
+                            .create(device, &format!("BindGroup: Multigrid prolongate, Level {}", level))
+                    }),
+                }
+            })
+            .collect();
+
+        let shader_path = Path::new("simulation/pressure_solver");
+
+        MultigridPreconditioner {
+            grid_dimension,
+            bind_group_smooth,
+            bind_group_restrict_residual,
+            bind_group_prolongate,
+            coarse_levels,
+
+            pipeline_restrict_marker: pipeline_manager.create_compute_pipeline(
+                device,
+                shader_dir,
+                ComputePipelineCreationDesc::new(
+                    "PressureSolve: Multigrid restrict marker",
+                    layout_restrict_marker,
+                    &shader_path.join(Path::new("multigrid_restrict_marker.comp")),
+                ),
+            ),
+            pipeline_smooth: pipeline_manager.create_compute_pipeline(
+                device,
+                shader_dir,
+                ComputePipelineCreationDesc::new(
+                    "PressureSolve: Multigrid smooth",
+                    layout_smooth,
+                    &shader_path.join(Path::new("multigrid_smooth.comp")),
+                ),
+            ),
+            pipeline_restrict_residual: pipeline_manager.create_compute_pipeline(
+                device,
+                shader_dir,
+                ComputePipelineCreationDesc::new(
+                    "PressureSolve: Multigrid restrict residual",
+                    layout_restrict_residual,
+                    &shader_path.join(Path::new("multigrid_restrict_residual.comp")),
+                ),
+            ),
+            pipeline_prolongate: pipeline_manager.create_compute_pipeline(
+                device,
+                shader_dir,
+                ComputePipelineCreationDesc::new(
+                    "PressureSolve: Multigrid prolongate",
+                    layout_prolongate,
+                    &shader_path.join(Path::new("multigrid_prolongate.comp")),
+                ),
+            ),
+        }
+    }
+
+    fn num_levels(&self) -> usize {
+        self.coarse_levels.len() + 1
+    }
+
+    fn set_marker<'a, 'b: 'a>(&'b self, cpass: &mut wgpu::ComputePass<'a>, level: usize, bind_group_marker_finest: &'a wgpu::BindGroup) {
+        if level == 0 {
+            cpass.set_bind_group(0, bind_group_marker_finest, &[]);
+        } else {
+            cpass.set_bind_group(0, &self.coarse_levels[level - 1].bind_group_marker, &[]);
+        }
+    }
+
+    // Dispatches over a level. The finest level may use an indirect dispatch, so that it becomes a no-op once the solver reached its target.
+    fn dispatch<'a>(&self, cpass: &mut wgpu::ComputePass<'a>, level: usize, finest_indirect_dispatch: Option<(&'a wgpu::Buffer, u64)>) {
+        if level == 0 {
+            if let Some((buffer, offset)) = finest_indirect_dispatch {
+                cpass.dispatch_indirect(buffer, offset);
+                return;
+            }
+        }
+        let grid_dimension = if level == 0 {
+            self.grid_dimension
+        } else {
+            self.coarse_levels[level - 1].grid_dimension
+        };
+        let work_groups = wgpu_utils::compute_group_size(grid_dimension, Self::COMPUTE_LOCAL_SIZE_VOLUME);
+        cpass.dispatch(work_groups.width, work_groups.height, work_groups.depth);
+    }
+
+    // Creates the marker volumes of all coarser levels from the finest one.
+    // Needs to be called once per solve before the preconditioner is applied. Expects the pressure field's bind group to be set.
+    pub fn restrict_marker<'a, 'b: 'a>(
+        &'b self,
+        cpass: &mut wgpu::ComputePass<'a>,
+        pipeline_manager: &'a PipelineManager,
+        bind_group_marker_finest: &'a wgpu::BindGroup,
+    ) {
+        wgpu_scope!(cpass, "multigrid: restrict marker");
+        cpass.set_pipeline(pipeline_manager.get_compute(&self.pipeline_restrict_marker));
+        for level in 1..self.num_levels() {
+            self.set_marker(&mut cpass, level - 1, bind_group_marker_finest);
+            cpass.set_bind_group(2, &self.coarse_levels[level - 1].bind_group_restrict_marker, &[]);
+            self.dispatch(&mut cpass, level, None);
+        }
+        cpass.set_bind_group(0, bind_group_marker_finest, &[]);
+    }
+
+    // Red-black Gauss-Seidel iterations, reversed order is black before red.
+    #[allow(clippy::too_many_arguments)]
+    fn smooth<'a, 'b: 'a>(
+        &'b self,
+        cpass: &mut wgpu::ComputePass<'a>,
+        level: usize,
+        target: MultigridTarget,
+        num_iterations: u32,
+        reversed: bool,
+        zero_initial_guess: bool,
+        start_dotproduct: bool,
+        finest_indirect_dispatch: Option<(&'a wgpu::Buffer, u64)>,
+    ) {
+        if level == 0 {
+            cpass.set_bind_group(2, &self.bind_group_smooth[target as usize], &[]);
+        } else {
+            cpass.set_bind_group(2, &self.coarse_levels[level - 1].bind_group_smooth, &[]);
+        }
+        let colors = if reversed {
+            [Self::SMOOTH_COLOR_BLACK, 0]
+        } else {
+            [0, Self::SMOOTH_COLOR_BLACK]
+        };
+        for i in 0..num_iterations {
+            for (c, color) in colors.iter().enumerate() {
+                let mut mode = *color;
+                if zero_initial_guess && i == 0 && c == 0 {
+                    mode |= Self::SMOOTH_ZERO_INITIAL_GUESS;
+                }
+                if start_dotproduct && i == num_iterations - 1 && c == colors.len() - 1 {
+                    mode |= Self::SMOOTH_START_DOTPRODUCT;
+                }
+                cpass.set_push_constants(0, bytemuck::bytes_of(&[mode, level as u32]));
+                self.dispatch(cpass, level, finest_indirect_dispatch);
+            }
+        }
+    }
+
+    // Applies a single V-cycle to the residual (r), stores the result in the target volume and starts the dotproduct of the result with (r).
+    // Expects the pressure field's bind group to be set. Leaves the finest marker bound.
+    pub fn apply<'a, 'b: 'a>(
+        &'b self,
+        cpass: &mut wgpu::ComputePass<'a>,
+        pipeline_manager: &'a PipelineManager,
+        bind_group_marker_finest: &'a wgpu::BindGroup,
+        target: MultigridTarget,
+        finest_indirect_dispatch: Option<(&'a wgpu::Buffer, u64)>,
+    ) {
+        wgpu_scope!(cpass, "multigrid: V-cycle");
+        let coarsest_level = self.num_levels() - 1;
+
+        // Down the V, every level starts out with zero as initial guess.
+        for level in 0..coarsest_level {
+            self.set_marker(&mut cpass, level, bind_group_marker_finest);
+            cpass.set_pipeline(pipeline_manager.get_compute(&self.pipeline_smooth));
+            self.smooth(
+                &mut cpass,
+                level,
+                target,
+                Self::NUM_SMOOTHING_ITERATIONS,
+                false,
+                true,
+                false,
+                finest_indirect_dispatch,
+            );
+            cpass.set_pipeline(pipeline_manager.get_compute(&self.pipeline_restrict_residual));
+            if level == 0 {
+                cpass.set_bind_group(2, &self.bind_group_restrict_residual[target as usize], &[]);
+            } else {
+                cpass.set_bind_group(2, self.coarse_levels[level - 1].bind_group_restrict_residual.as_ref().unwrap(), &[]);
+            }
+            cpass.set_push_constants(0, bytemuck::bytes_of(&[0, level as u32]));
+            self.dispatch(&mut cpass, level + 1, None);
+        }
+
+        // Coarsest level, symmetric order of smoothing iterations to keep the V-cycle symmetric.
+        self.set_marker(&mut cpass, coarsest_level, bind_group_marker_finest);
+        cpass.set_pipeline(pipeline_manager.get_compute(&self.pipeline_smooth));
+        self.smooth(
+            &mut cpass,
+            coarsest_level,
+            target,
+            Self::NUM_SMOOTHING_ITERATIONS_COARSEST,
+            false,
+            true,
+            false,
+            finest_indirect_dispatch,
+        );
+        self.smooth(
+            &mut cpass,
+            coarsest_level,
+            target,
+            Self::NUM_SMOOTHING_ITERATIONS_COARSEST,
+            true,
+            false,
+            false,
+            finest_indirect_dispatch,
+        );
+
+        // Up the V, correct with the coarser solution and smooth in reverse order.
+        for level in (0..coarsest_level).rev() {
+            self.set_marker(&mut cpass, level, bind_group_marker_finest);
+            cpass.set_pipeline(pipeline_manager.get_compute(&self.pipeline_prolongate));
+            if level == 0 {
+                cpass.set_bind_group(2, &self.bind_group_prolongate[target as usize], &[]);
+            } else {
+                cpass.set_bind_group(2, self.coarse_levels[level - 1].bind_group_prolongate.as_ref().unwrap(), &[]);
+            }
+            cpass.set_push_constants(0, bytemuck::bytes_of(&[0, level as u32]));
+            self.dispatch(&mut cpass, level, finest_indirect_dispatch);
+
+            cpass.set_pipeline(pipeline_manager.get_compute(&self.pipeline_smooth));
+            self.smooth(
+                &mut cpass,
+                level,
+                target,
+                Self::NUM_SMOOTHING_ITERATIONS,
+                true,
+                false,
+                level == 0,
+                finest_indirect_dispatch,
+            );
+        }
+    }
+}
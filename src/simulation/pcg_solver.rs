@@ -0,0 +1,299 @@
+// Preconditioned Conjugate Gradient solver, following the description in Bridson's book.
+// For optimization various steps are collapsed as far as possible to avoid expensive buffer/texture read/writes
+// This makes the algorithm a lot faster but also a bit harder to read.
+
+use super::multigrid::{MultigridPreconditioner, MultigridTarget};
+use super::pressure_solver::*;
+use crate::wgpu_utils::{binding_builder::*, binding_glsl, pipelines::*};
+use std::{path::Path, rc::Rc};
+
+pub(super) struct PcgSolver {
+    bind_group_preconditioner: [wgpu::BindGroup; 3],
+    bind_group_apply_coeff: wgpu::BindGroup,
+    bind_group_update_pressure_and_residual: wgpu::BindGroup,
+    bind_group_update_search: wgpu::BindGroup,
+
+    multigrid: MultigridPreconditioner,
+
+    pipeline_apply_preconditioner: ComputePipelineHandle,
+    pipeline_apply_coeff: ComputePipelineHandle,
+    pipeline_update_pressure_and_residual: ComputePipelineHandle,
+    pipeline_update_search: ComputePipelineHandle,
+}
+
+impl PcgSolver {
+    pub fn new(desc: &SolverBackendDesc, pipeline_manager: &mut PipelineManager) -> Self {
+        let device = desc.device;
+
+        let group_layout_apply_coeff = BindGroupLayoutBuilder::new()
+            .next_binding_compute(binding_glsl::buffer(false))
+            .next_binding_compute(binding_glsl::texture3D())
+            .create(device, "BindGroupLayout: P. solver apply coeff matrix & start dot");
+        let group_layout_preconditioner = BindGroupLayoutBuilder::new()
+            .next_binding_compute(binding_glsl::buffer(false))
+            .next_binding_compute(binding_glsl::texture3D())
+            .next_binding_compute(binding_glsl::image3D(wgpu::TextureFormat::R32Float, false))
+            .next_binding_compute(binding_glsl::texture3D())
+            .create(device, "BindGroupLayout: Pressure solver preconditioner");
+        let group_layout_update_volume = BindGroupLayoutBuilder::new()
+            .next_binding_compute(binding_glsl::buffer(false))
+            .next_binding_compute(binding_glsl::image3D(wgpu::TextureFormat::R32Float, false))
+            .next_binding_compute(binding_glsl::texture3D())
+            .next_binding_compute(binding_glsl::uniform())
+            .create(device, "BindGroupLayout: Pressure solver generic volume update");
+
+        let layout_update_volume = Rc::new(device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Update Volume Pipeline Layout"),
+            bind_group_layouts: &[
+                &desc.group_layout_general.layout,
+                &desc.group_layout_pressure_field.layout,
+                &group_layout_update_volume.layout,
+            ],
+            push_constant_ranges: desc.push_constant_ranges,
+        }));
+        let layout_preconditioner = Rc::new(device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Pressure Solve Precondition Pipeline Layout"),
+            bind_group_layouts: &[
+                &desc.group_layout_general.layout,
+                &desc.group_layout_pressure_field.layout,
+                &group_layout_preconditioner.layout,
+            ],
+            push_constant_ranges: desc.push_constant_ranges,
+        }));
+        let layout_apply_coeff = Rc::new(device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Pressure Solve Apply Coeff Pipeline Layout"),
+            bind_group_layouts: &[
+                &desc.group_layout_general.layout,
+                &desc.group_layout_pressure_field.layout,
+                &group_layout_apply_coeff.layout,
+            ],
+            push_constant_ranges: desc.push_constant_ranges,
+        }));
+
+        let volume_search = device.create_texture(&create_volume_texture_desc(
+            "Pressure Solve Search",
+            desc.grid_dimension,
+            wgpu::TextureFormat::R32Float,
+        ));
+        let volume_search_view = volume_search.create_view(&Default::default());
+
+        let bind_group_apply_coeff = BindGroupBuilder::new(&group_layout_apply_coeff)
+            .resource(desc.reduce_buffer.as_entire_binding())
+            .texture(&volume_search_view)
+            .create(device, "BindGroup: Apply coeff matrix & start dot product");
+        let bind_group_preconditioner = [
+            BindGroupBuilder::new(&group_layout_preconditioner)
+                .resource(desc.reduce_buffer.as_entire_binding())
+                .texture(desc.volume_residual_view)
+                .texture(desc.volume_auxiliary_temp_view)
+                .texture(desc.volume_residual_view)
+                .create(device, "BindGroup: Preconditioner, Step 1"),
+            BindGroupBuilder::new(&group_layout_preconditioner)
+                .resource(desc.reduce_buffer.as_entire_binding())
+                .texture(desc.volume_residual_view)
+                .texture(desc.volume_auxiliary_view)
+                .texture(desc.volume_auxiliary_temp_view)
+                .create(device, "BindGroup: Preconditioner, Step 2"),
+            BindGroupBuilder::new(&group_layout_preconditioner)
+                .resource(desc.reduce_buffer.as_entire_binding())
+                .texture(desc.volume_residual_view)
+                .texture(&volume_search_view)
+                .texture(desc.volume_auxiliary_temp_view)
+                .create(device, "BindGroup: Preconditioner, Step 2, to search"),
+        ];
+        let bind_group_update_pressure_and_residual = BindGroupBuilder::new(&group_layout_update_volume)
+            .resource(desc.reduce_buffer.as_entire_binding())
+            .texture(desc.volume_residual_view)
+            .texture(&volume_search_view)
+            .resource(desc.reduce_result_and_dispatch_buffer.as_entire_binding())
+            .create(device, "BindGroup: Pressure update pressure and residual");
+        let bind_group_update_search = BindGroupBuilder::new(&group_layout_update_volume)
+            .resource(desc.reduce_buffer.as_entire_binding())
+            .texture(&volume_search_view)
+            .texture(desc.volume_auxiliary_view)
+            .resource(desc.reduce_result_and_dispatch_buffer.as_entire_binding())
+            .create(device, "BindGroup: Pressure update search");
+
+        let multigrid = MultigridPreconditioner::new(
+            device,
+            desc.grid_dimension,
+            desc.shader_dir,
+            pipeline_manager,
+            desc.group_layout_general,
+            desc.group_layout_pressure_field,
+            desc.push_constant_ranges,
+            desc.reduce_buffer,
+            desc.volume_residual_view,
+            [desc.volume_auxiliary_view, &volume_search_view],
+        );
+
+        let shader_path = Path::new("simulation/pressure_solver");
+
+        PcgSolver {
+            bind_group_preconditioner,
+            bind_group_apply_coeff,
+            bind_group_update_pressure_and_residual,
+            bind_group_update_search,
+
+            multigrid,
+
+            pipeline_apply_preconditioner: pipeline_manager.create_compute_pipeline(
+                device,
+                desc.shader_dir,
+                ComputePipelineCreationDesc::new(
+                    "PressureSolve: Apply preconditioner",
+                    layout_preconditioner.clone(),
+                    &shader_path.join(&Path::new("pressure_apply_preconditioner.comp")),
+                ),
+            ),
+            pipeline_apply_coeff: pipeline_manager.create_compute_pipeline(
+                device,
+                desc.shader_dir,
+                ComputePipelineCreationDesc::new(
+                    "PressureSolve: Apply coefficient matrix",
+                    layout_apply_coeff.clone(),
+                    &shader_path.join(&Path::new("pressure_apply_coeff.comp")),
+                ),
+            ),
+            pipeline_update_pressure_and_residual: pipeline_manager.create_compute_pipeline(
+                device,
+                desc.shader_dir,
+                ComputePipelineCreationDesc::new(
+                    "PressureSolve: Update pressure and residual",
+                    layout_update_volume.clone(),
+                    &shader_path.join(&Path::new("pressure_update_pressure_and_residual.comp")),
+                ),
+            ),
+            pipeline_update_search: pipeline_manager.create_compute_pipeline(
+                device,
+                desc.shader_dir,
+                ComputePipelineCreationDesc::new(
+                    "PressureSolve: Update search",
+                    layout_update_volume.clone(),
+                    &shader_path.join(&Path::new("pressure_update_search.comp")),
+                ),
+            ),
+        }
+    }
+}
+
+impl SolverBackend for PcgSolver {
+    fn solve<'a, 'b: 'a>(
+        &'b self,
+        solver: &'b PressureSolver,
+        cpass: &mut wgpu::ComputePass<'a>,
+        pipeline_manager: &'a PipelineManager,
+        config: &SolverConfig,
+        bind_group_marker: &'a wgpu::BindGroup,
+    ) {
+        const PRECONDITIONER_PASS0: u32 = 0;
+        const PRECONDITIONER_PASS1: u32 = 1;
+
+        let reduce_pass_initial_group_size = solver.reduce_pass_initial_group_size();
+        let grid_work_groups = solver.grid_work_groups();
+
+        wgpu_scope!(cpass, "init", || {
+            // Apply preconditioner on (r), store result to search vector (s) and start dotproduct of <s; r>
+            // Note that we don't use the auxillary vector here as in-between storage!
+            wgpu_scope!(cpass, "preconditioner on (r), store to auxillary (z), start dotproduct of <z; r>", || {
+                match config.preconditioner {
+                    Preconditioner::IncompletePoisson => {
+                        cpass.set_pipeline(pipeline_manager.get_compute(&self.pipeline_apply_preconditioner));
+                        cpass.set_push_constants(0, &bytemuck::bytes_of(&[0 as u32]));
+                        cpass.set_push_constants(0, &bytemuck::bytes_of(&[PRECONDITIONER_PASS0]));
+                        cpass.set_bind_group(2, &self.bind_group_preconditioner[0], &[]);
+                        cpass.dispatch(grid_work_groups.width, grid_work_groups.height, grid_work_groups.depth);
+                        cpass.set_push_constants(0, &bytemuck::bytes_of(&[PRECONDITIONER_PASS1, reduce_pass_initial_group_size]));
+                        cpass.set_bind_group(2, &self.bind_group_preconditioner[2], &[]);
+                        cpass.dispatch(grid_work_groups.width, grid_work_groups.height, grid_work_groups.depth);
+                    }
+                    Preconditioner::Multigrid => {
+                        // The marker doesn't change during the solve, so the coarse levels only need to be set up once.
+                        self.multigrid.restrict_marker(cpass, pipeline_manager, bind_group_marker);
+                        self.multigrid
+                            .apply(cpass, pipeline_manager, bind_group_marker, MultigridTarget::Search, None);
+                    }
+                }
+            });
+            // Init sigma to dotproduct of search vector (s) and residual (r)
+            solver.reduce_add(cpass, pipeline_manager, PressureSolver::REDUCE_RESULTMODE_INIT);
+        });
+
+        wgpu_scope!(cpass, "solver iterations", || {
+            let dispatch_buffer = solver.reduce_result_and_dispatch_buffer();
+            const DISPATCH_BUFFER_OFFSET: u64 = PressureSolver::INDIRECT_DISPATCH_VOLUME_OFFSET;
+
+            let mut i = 0;
+            while wgpu_scope!(cpass, &format!("iteration {}", i), || {
+                wgpu_scope!(cpass, "multiply search vector (s) with coefficients (A)", || {
+                    // The dot product is applied to the result (denoted as z in Bridson's book) and the search vector (s), i.e. compute <s; As>
+                    cpass.set_pipeline(pipeline_manager.get_compute(&self.pipeline_apply_coeff));
+                    cpass.set_bind_group(2, &self.bind_group_apply_coeff, &[]);
+                    cpass.set_push_constants(0, &bytemuck::bytes_of(&[0, reduce_pass_initial_group_size]));
+                    cpass.dispatch_indirect(dispatch_buffer, DISPATCH_BUFFER_OFFSET);
+                });
+                // finish dotproduct of auxiliary field (z) and search field (s)
+                solver.reduce_add(cpass, pipeline_manager, PressureSolver::REDUCE_RESULTMODE_ALPHA);
+
+                let iteration_with_mse_computation = config.is_mse_check_iteration(i);
+
+                wgpu_scope!(cpass, "update pressure field (p) and residual field (r)", || {
+                    const PRUPDATE_COMPUTE_MSE: u32 = 1;
+                    cpass.set_pipeline(pipeline_manager.get_compute(&self.pipeline_update_pressure_and_residual));
+                    if iteration_with_mse_computation {
+                        cpass.set_push_constants(0, &bytemuck::bytes_of(&[PRUPDATE_COMPUTE_MSE, reduce_pass_initial_group_size]));
+                    } else {
+                        cpass.set_push_constants(0, &bytemuck::bytes_of(&[0]));
+                    }
+                    cpass.set_bind_group(2, &self.bind_group_update_pressure_and_residual, &[]);
+                    cpass.dispatch_indirect(dispatch_buffer, DISPATCH_BUFFER_OFFSET);
+                });
+
+                // Time to check on mse?
+                if iteration_with_mse_computation {
+                    // Compute remaining error.
+                    // Used for statistics. If below target, makes all upcoming dispatch_indirect no-ops.
+                    solver.reduce_add(cpass, pipeline_manager, PressureSolver::REDUCE_RESULTMODE_MSE + i as u32);
+
+                    if config.max_num_iterations == i {
+                        return false;
+                    }
+                }
+
+                wgpu_scope!(cpass, "preconditioner on (r), store to auxillary (z), start dotproduct of <z; r>", || {
+                    match config.preconditioner {
+                        Preconditioner::IncompletePoisson => {
+                            cpass.set_pipeline(pipeline_manager.get_compute(&self.pipeline_apply_preconditioner));
+                            cpass.set_push_constants(0, &bytemuck::bytes_of(&[PRECONDITIONER_PASS0]));
+                            cpass.set_bind_group(2, &self.bind_group_preconditioner[0], &[]);
+                            cpass.dispatch_indirect(dispatch_buffer, DISPATCH_BUFFER_OFFSET);
+                            cpass.set_push_constants(0, &bytemuck::bytes_of(&[PRECONDITIONER_PASS1, reduce_pass_initial_group_size]));
+                            cpass.set_bind_group(2, &self.bind_group_preconditioner[1], &[]);
+                            cpass.dispatch_indirect(dispatch_buffer, DISPATCH_BUFFER_OFFSET);
+                        }
+                        // Only the passes on the finest level are indirect, coarser levels are cheap in comparison.
+                        Preconditioner::Multigrid => self.multigrid.apply(
+                            cpass,
+                            pipeline_manager,
+                            bind_group_marker,
+                            MultigridTarget::Auxiliary,
+                            Some((dispatch_buffer, DISPATCH_BUFFER_OFFSET)),
+                        ),
+                    }
+                });
+
+                // finish dotproduct of auxiliary field (z) and residual field (r)
+                solver.reduce_add(cpass, pipeline_manager, PressureSolver::REDUCE_RESULTMODE_BETA);
+
+                wgpu_scope!(cpass, "Update search vector", || {
+                    cpass.set_pipeline(pipeline_manager.get_compute(&self.pipeline_update_search));
+                    cpass.set_bind_group(2, &self.bind_group_update_search, &[]);
+                    cpass.dispatch_indirect(dispatch_buffer, DISPATCH_BUFFER_OFFSET);
+                });
+
+                i += 1;
+                true
+            }) {}
+        });
+    }
+}
@@ -1,12 +1,15 @@
+use super::pcg_solver::PcgSolver;
+use super::relaxation_solver::{RelaxationMethod, RelaxationSolver};
 use crate::wgpu_utils::{self, binding_builder::*, binding_glsl, pipelines::*, shader::ShaderDirectory};
 use futures::Future;
 use futures::*;
+use serde::{Deserialize, Serialize};
 use std::collections::VecDeque;
 use std::rc::Rc;
 use std::{path::Path, pin::Pin, time::Duration};
 use wgpu_utils::uniformbuffer::UniformBuffer;
 
-fn create_volume_texture_desc(label: &str, grid_dimension: wgpu::Extent3d, format: wgpu::TextureFormat) -> wgpu::TextureDescriptor {
+pub(super) fn create_volume_texture_desc(label: &str, grid_dimension: wgpu::Extent3d, format: wgpu::TextureFormat) -> wgpu::TextureDescriptor {
     wgpu::TextureDescriptor {
         label: Some(label),
         size: grid_dimension,
@@ -23,45 +26,142 @@ pub struct PressureSolver {
 
     bind_group_general: wgpu::BindGroup,
     bind_group_init: wgpu::BindGroup,
-    bind_group_preconditioner: [wgpu::BindGroup; 3],
-    bind_group_apply_coeff: wgpu::BindGroup,
     bind_group_dotproduct_reduce: [wgpu::BindGroup; 2],
     bind_group_dotproduct_final: [wgpu::BindGroup; 2],
-    bind_group_update_pressure_and_residual: wgpu::BindGroup,
-    bind_group_update_search: wgpu::BindGroup,
+
+    pcg: PcgSolver,
+    jacobi: RelaxationSolver,
+    gauss_seidel: RelaxationSolver,
 
     pipeline_init: ComputePipelineHandle,
-    pipeline_apply_preconditioner: ComputePipelineHandle,
     pipeline_reduce: ComputePipelineHandle,
-    pipeline_apply_coeff: ComputePipelineHandle,
-    pipeline_update_pressure_and_residual: ComputePipelineHandle,
-    pipeline_update_search: ComputePipelineHandle,
 
     dotproduct_reduce_result_and_dispatch_buffer: wgpu::Buffer,
 
+    group_layout_general: BindGroupLayoutWithDesc,
     group_layout_pressure_field: BindGroupLayoutWithDesc,
 
+    volume_residual: wgpu::Texture,
     volume_residual_view: wgpu::TextureView,
 }
 
+// Resources of the PressureSolver a backend needs to create its bind groups and pipelines.
+pub(super) struct SolverBackendDesc<'a> {
+    pub device: &'a wgpu::Device,
+    pub shader_dir: &'a ShaderDirectory,
+    pub grid_dimension: wgpu::Extent3d,
+    pub group_layout_general: &'a BindGroupLayoutWithDesc,
+    pub group_layout_pressure_field: &'a BindGroupLayoutWithDesc,
+    pub push_constant_ranges: &'a [wgpu::PushConstantRange],
+    // Input for PressureSolver::reduce_add, holds one value per thread of a volume pass.
+    pub reduce_buffer: &'a wgpu::Buffer,
+    pub reduce_result_and_dispatch_buffer: &'a wgpu::Buffer,
+    pub volume_residual_view: &'a wgpu::TextureView,
+    // Scratch volumes, shared between all backends.
+    pub volume_auxiliary_view: &'a wgpu::TextureView,
+    pub volume_auxiliary_temp_view: &'a wgpu::TextureView,
+}
+
+// Algorithm used by the PressureSolver to solve the linear system of a PressureField.
+pub(super) trait SolverBackend {
+    // Called after the initial residual (r) for the pressure from the last step was written to the residual volume.
+    // The marker and pressure field bind groups (0 and 1) are set.
+    // Needs to write statistics to the reduce result buffer via PressureSolver::reduce_add with REDUCE_RESULTMODE_MSE,
+    // latest at config.max_num_iterations. Afterwards all passes dispatched at INDIRECT_DISPATCH_VOLUME_OFFSET are skipped.
+    fn solve<'a, 'b: 'a>(
+        &'b self,
+        solver: &'b PressureSolver,
+        cpass: &mut wgpu::ComputePass<'a>,
+        pipeline_manager: &'a PipelineManager,
+        config: &SolverConfig,
+        bind_group_marker: &'a wgpu::BindGroup,
+    );
+}
+
 const NUM_PRESSURE_ERROR_BUFFER: usize = 32;
 
 struct PendingErrorBuffer {
     copy_operation: Option<Pin<Box<dyn Future<Output = std::result::Result<(), wgpu::BufferAsyncError>>>>>,
     buffer: wgpu::Buffer,
     resulting_sample: SolverStatisticSample,
+    // Step length of the solve, read-backs arrive a few (possibly differently long) steps later.
+    simulation_delta: Duration,
 }
 
+// Content of a pressure error read-back buffer: squared error & iteration count, followed by the residual history.
+const PRESSURE_ERROR_BUFFER_SIZE: u64 = 8 + PressureSolver::RESIDUAL_HISTORY_SIZE;
+
+// Algorithm used to solve the linear system, see SolverBackend
+#[derive(Clone, Copy, Debug, PartialEq, EnumIter, Serialize, Deserialize)]
+pub enum SolverMethod {
+    // Preconditioned conjugate gradient, see pcg_solver.rs
+    Pcg,
+    // Plain Jacobi iterations, slow to converge but trivially parallel.
+    Jacobi,
+    // Red-black Gauss-Seidel iterations, converges about twice as fast as Jacobi.
+    GaussSeidel,
+}
+
+impl Default for SolverMethod {
+    fn default() -> Self {
+        SolverMethod::Pcg
+    }
+}
+
+// Preconditioner used by the PCG solver.
+#[derive(Clone, Copy, Debug, PartialEq, EnumIter, Serialize, Deserialize)]
+pub enum Preconditioner {
+    // Cheap approximate inverse, needs many iterations on large domains.
+    IncompletePoisson,
+    // Single V-cycle of a geometric multigrid, more expensive per iteration but converges in a few iterations independent of the domain size.
+    Multigrid,
+}
+
+impl Default for Preconditioner {
+    fn default() -> Self {
+        Preconditioner::IncompletePoisson
+    }
+}
+
+#[derive(Clone, Copy, Serialize, Deserialize)]
 pub struct SolverConfig {
     pub target_mse: f32,
     pub max_num_iterations: i32,
     pub mse_check_frequency: i32,
+    #[serde(default)]
+    pub preconditioner: Preconditioner,
+    #[serde(default)]
+    pub method: SolverMethod,
+}
+
+impl SolverConfig {
+    // Whether the error is computed after the given iteration.
+    // The last iteration always computes the error so there are statistics for every solve.
+    pub(super) fn is_mse_check_iteration(&self, iteration: i32) -> bool {
+        self.max_num_iterations == iteration || (iteration > 0 && iteration % self.mse_check_frequency == 0)
+    }
 }
 #[derive(Default, Copy, Clone)]
 pub struct SolverStatisticSample {
     pub mse: f32,
     pub iteration_count: i32,
-    //timestamp: Duration,
+    // Simulated time at the end of the step the solve belongs to, see PressureField::set_timestamp
+    pub timestamp: Duration,
+}
+
+// Error after one of the iterations the solver checked its error in, see SolverConfig::is_mse_check_iteration
+#[derive(Default, Copy, Clone)]
+pub struct SolverResidualSample {
+    pub iteration: i32,
+    pub mse: f32,
+}
+
+// Error over the course of a single solve.
+#[derive(Default, Clone)]
+pub struct SolverConvergence {
+    pub timestamp: Duration,
+    // Only the first PressureSolver::RESIDUAL_HISTORY_MAX_LENGTH checks are recorded.
+    pub residuals: Vec<SolverResidualSample>,
 }
 
 #[repr(C)]
@@ -71,15 +171,60 @@ struct SolverConfigUniformBufferContent {
     // For easier handling with different timesteps the user facing parameter is about 'pressure * density'.
     target_mse_per_second: f32,
     max_num_iterations: u32,
+    // See SolverSystem
+    identity_scale: f32,
+    laplace_scale: f32,
+    neumann_boundary_marker: f32,
+    padding: [u32; 3],
 }
 unsafe impl bytemuck::Pod for SolverConfigUniformBufferContent {}
 unsafe impl bytemuck::Zeroable for SolverConfigUniformBufferContent {}
 
 type SolverConfigUniformBuffer = UniformBuffer<SolverConfigUniformBufferContent>;
 
+// The linear system a PressureField solves for.
+// All systems share the same sparsity pattern (7 point laplacian on the marker volume), so they can use the same solver.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SolverSystem {
+    // Poisson equation with zero gradient at solid walls and zero pressure in air.
+    Pressure,
+    // (I + dt * viscosity * laplacian) for implicit viscosity of a single velocity component, viscosity in grid cells² per second.
+    // Zero gradient at free surfaces and fixed velocity at solid walls.
+    Viscosity(f32),
+}
+
+impl SolverSystem {
+    // IdentityScale, LaplaceScale and NeumannBoundaryMarker of the system, see pressure.glsl
+    pub(super) fn coefficients(&self, simulation_delta: Duration) -> (f32, f32, f32) {
+        match *self {
+            SolverSystem::Pressure => (0.0, 1.0, CELL_SOLID),
+            SolverSystem::Viscosity(viscosity) => (1.0, simulation_delta.as_secs_f32() * viscosity, CELL_AIR),
+        }
+    }
+
+    // Factor from the squared error the solver works with to the user facing one.
+    // We currently always deal with 'pressure * density / dt', not with pressure. See also SolverConfigUniformBufferContent.
+    // Velocities are solved directly, no need to rescale the error.
+    pub(super) fn error_scale(&self, simulation_delta: Duration) -> f32 {
+        match *self {
+            SolverSystem::Pressure => simulation_delta.as_secs_f32() * simulation_delta.as_secs_f32(),
+            SolverSystem::Viscosity(_) => 1.0,
+        }
+    }
+}
+
+// Marker volume values, see hybrid_fluid.glsl
+pub(super) const CELL_SOLID: f32 = 0.0;
+pub(super) const CELL_FLUID: f32 = 1.0;
+pub(super) const CELL_AIR: f32 = -1.0;
+
 // Pressure solver instance keeps track of pressure result from last step/frame in order to speed up the solve.
 pub struct PressureField {
+    system: SolverSystem,
+    // Replaces the solver's marker volume if the field uses a different one.
+    bind_group_marker: Option<wgpu::BindGroup>,
     bind_group_pressure_field: wgpu::BindGroup,
+    volume_pressure: wgpu::Texture,
     volume_pressure_view: wgpu::TextureView,
 
     unused_error_buffers: Vec<wgpu::Buffer>,
@@ -89,7 +234,13 @@ pub struct PressureField {
     config_ubo: SolverConfigUniformBuffer,
     pub config: SolverConfig,
     pub stats: VecDeque<SolverStatisticSample>,
+    // Residual history for the same solves as stats.
+    pub convergence: VecDeque<SolverConvergence>,
+    // Number of solves retrieved so far, including those that already dropped out of stats and convergence.
+    pub num_retrieved_solves: u64,
 
+    // Simulated time the statistics of upcoming solves are marked with.
+    timestamp: Duration,
     timestamp_last_iteration: Duration,
 }
 
@@ -97,11 +248,43 @@ impl PressureField {
     const SOLVER_STATISTIC_HISTORY_LENGTH: usize = 100;
 
     pub fn new(name: &'static str, device: &wgpu::Device, grid_dimension: wgpu::Extent3d, solver: &PressureSolver, config: SolverConfig) -> Self {
-        let volume_pressure = device.create_texture(&create_volume_texture_desc(
-            &format!("Pressure Volume - {}", name),
+        Self::new_with_system(name, device, grid_dimension, solver, config, SolverSystem::Pressure, None)
+    }
+
+    // Field for the viscosity system on a separate marker volume, see SolverSystem::Viscosity
+    pub fn new_viscosity(
+        name: &'static str,
+        device: &wgpu::Device,
+        grid_dimension: wgpu::Extent3d,
+        solver: &PressureSolver,
+        config: SolverConfig,
+        volume_marker_view: &wgpu::TextureView,
+    ) -> Self {
+        Self::new_with_system(
+            name,
+            device,
             grid_dimension,
-            wgpu::TextureFormat::R32Float,
-        ));
+            solver,
+            config,
+            SolverSystem::Viscosity(0.0),
+            Some(volume_marker_view),
+        )
+    }
+
+    fn new_with_system(
+        name: &'static str,
+        device: &wgpu::Device,
+        grid_dimension: wgpu::Extent3d,
+        solver: &PressureSolver,
+        config: SolverConfig,
+        system: SolverSystem,
+        volume_marker_view: Option<&wgpu::TextureView>,
+    ) -> Self {
+        // Pressure is copied from and to for checkpoints.
+        let volume_pressure = device.create_texture(&wgpu::TextureDescriptor {
+            usage: wgpu::TextureUsage::SAMPLED | wgpu::TextureUsage::STORAGE | wgpu::TextureUsage::COPY_SRC | wgpu::TextureUsage::COPY_DST,
+            ..create_volume_texture_desc(&format!("Pressure Volume - {}", name), grid_dimension, wgpu::TextureFormat::R32Float)
+        });
         let volume_pressure_view = volume_pressure.create_view(&Default::default());
 
         let config_ubo = SolverConfigUniformBuffer::new(device);
@@ -111,10 +294,16 @@ impl PressureField {
             .resource(config_ubo.binding_resource())
             .create(device, &format!("BindGroup: Pressure - {}", name));
 
+        let bind_group_marker = volume_marker_view.map(|view| {
+            BindGroupBuilder::new(&solver.group_layout_general)
+                .texture(view)
+                .create(device, &format!("BindGroup: Pressure solve marker - {}", name))
+        });
+
         let mut unused_error_buffers = Vec::new();
         for i in 0..NUM_PRESSURE_ERROR_BUFFER {
             unused_error_buffers.push(device.create_buffer(&wgpu::BufferDescriptor {
-                size: 8,
+                size: PRESSURE_ERROR_BUFFER_SIZE,
                 usage: wgpu::BufferUsage::MAP_READ | wgpu::BufferUsage::COPY_DST,
                 label: Some(&format!("Buffer: Pressure error read-back buffer {} ({})", i, name)),
                 mapped_at_creation: false,
@@ -122,7 +311,10 @@ impl PressureField {
         }
 
         PressureField {
+            system,
+            bind_group_marker,
             bind_group_pressure_field,
+            volume_pressure,
             volume_pressure_view,
             unused_error_buffers,
             unscheduled_error_readbacks: Vec::new(),
@@ -131,37 +323,72 @@ impl PressureField {
             config_ubo,
             config,
             stats: VecDeque::new(),
+            convergence: VecDeque::new(),
+            num_retrieved_solves: 0,
 
+            timestamp: Duration::new(0, 0),
             timestamp_last_iteration: Duration::new(0, 0),
         }
     }
 
+    // Only valid for viscosity fields.
+    pub fn set_viscosity(&mut self, viscosity: f32) {
+        assert!(self.system != SolverSystem::Pressure, "Can't set viscosity on a pressure field");
+        self.system = SolverSystem::Viscosity(viscosity);
+    }
+
+    pub fn set_timestamp(&mut self, timestamp: Duration) {
+        self.timestamp = timestamp;
+    }
+
     pub fn pressure_view(&self) -> &wgpu::TextureView {
         &self.volume_pressure_view
     }
 
-    fn retrieve_new_error_samples(&mut self, simulation_delta: Duration) {
+    pub fn pressure_texture(&self) -> &wgpu::Texture {
+        &self.volume_pressure
+    }
+
+    fn retrieve_new_error_samples(&mut self) {
         // Check if there's any new data samples
         while let Some(mut readback) = self.pending_error_readbacks.pop_front() {
             if (&mut readback.copy_operation.as_mut().unwrap()).now_or_never().is_some() {
-                let mapped = readback.buffer.slice(0..8);
+                let mapped = readback.buffer.slice(..);
                 let buffer_data = mapped.get_mapped_range().to_vec();
-                let squared_error = *bytemuck::from_bytes::<f32>(&buffer_data[0..4]);
-                let iteration_count = *bytemuck::from_bytes::<f32>(&buffer_data[4..8]);
                 readback.buffer.unmap();
                 self.unused_error_buffers.push(readback.buffer);
+                // Mapped data isn't necessarily aligned, so we can't cast the buffer directly.
+                let read_f32 = |index: usize| {
+                    let bytes = &buffer_data[index * 4..index * 4 + 4];
+                    f32::from_ne_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+                };
 
-                // We currently always deal with 'pressure * density / dt', not with pressure.
                 // To make display more representative for different time, we adjust our error value accordingly.
                 // See also config.target_mse
-                let delta_sq = simulation_delta.as_secs_f32() * simulation_delta.as_secs_f32();
-                readback.resulting_sample.mse = squared_error * delta_sq;
-                readback.resulting_sample.iteration_count = iteration_count as i32;
+                let error_scale = self.system.error_scale(readback.simulation_delta);
+                readback.resulting_sample.mse = read_f32(0) * error_scale;
+                readback.resulting_sample.iteration_count = read_f32(1) as i32;
+
+                let history_length = (read_f32(2) as usize).min(PressureSolver::RESIDUAL_HISTORY_MAX_LENGTH);
+                let convergence = SolverConvergence {
+                    timestamp: readback.resulting_sample.timestamp,
+                    residuals: (0..history_length)
+                        .map(|i| SolverResidualSample {
+                            iteration: read_f32(3 + i * 2) as i32,
+                            mse: read_f32(4 + i * 2) * error_scale,
+                        })
+                        .collect(),
+                };
 
                 self.stats.push_back(readback.resulting_sample);
                 while self.stats.len() > Self::SOLVER_STATISTIC_HISTORY_LENGTH {
                     self.stats.pop_front();
                 }
+                self.convergence.push_back(convergence);
+                self.num_retrieved_solves += 1;
+                while self.convergence.len() > Self::SOLVER_STATISTIC_HISTORY_LENGTH {
+                    self.convergence.pop_front();
+                }
             } else {
                 self.pending_error_readbacks.push_front(readback);
                 break;
@@ -169,17 +396,25 @@ impl PressureField {
         }
     }
 
-    fn enqueue_error_buffer_read(&mut self, encoder: &mut wgpu::CommandEncoder, source_buffer: &wgpu::Buffer) {
+    fn enqueue_error_buffer_read(&mut self, encoder: &mut wgpu::CommandEncoder, source_buffer: &wgpu::Buffer, simulation_delta: Duration) {
         if let Some(target_buffer) = self.unused_error_buffers.pop() {
             encoder.copy_buffer_to_buffer(source_buffer, 8, &target_buffer, 0, 8);
+            encoder.copy_buffer_to_buffer(
+                source_buffer,
+                PressureSolver::RESIDUAL_HISTORY_OFFSET,
+                &target_buffer,
+                8,
+                PressureSolver::RESIDUAL_HISTORY_SIZE,
+            );
             self.unscheduled_error_readbacks.push(PendingErrorBuffer {
                 copy_operation: None, // Filled out in start_error_buffer_readbacks
                 buffer: target_buffer,
                 resulting_sample: SolverStatisticSample {
                     mse: 0.0,
                     iteration_count: 0,
-                    //timestamp: self.timestamp_last_iteration,
+                    timestamp: self.timestamp,
                 },
+                simulation_delta,
             });
         } else {
             warn!("No more error buffer available for async copy of pressure solve error");
@@ -187,14 +422,16 @@ impl PressureField {
     }
 
     pub fn update_uniforms(&mut self, queue: &wgpu::Queue, simulation_delta: Duration) {
-        let delta_sq = simulation_delta.as_secs_f32() * simulation_delta.as_secs_f32();
-        self.config_ubo.update_content(
-            queue,
-            SolverConfigUniformBufferContent {
-                target_mse_per_second: self.config.target_mse / delta_sq,
-                max_num_iterations: self.config.max_num_iterations as u32,
-            },
-        );
+        let (identity_scale, laplace_scale, neumann_boundary_marker) = self.system.coefficients(simulation_delta);
+        let content = SolverConfigUniformBufferContent {
+            target_mse_per_second: self.config.target_mse / self.system.error_scale(simulation_delta),
+            max_num_iterations: self.config.max_num_iterations as u32,
+            identity_scale,
+            laplace_scale,
+            neumann_boundary_marker,
+            padding: [0; 3],
+        };
+        self.config_ubo.update_content(queue, content);
     }
 
     // Call this once all command
@@ -208,10 +445,20 @@ impl PressureField {
 
 impl PressureSolver {
     const REDUCE_RESULTMODE_REDUCE: u32 = 0;
-    const REDUCE_RESULTMODE_INIT: u32 = 1;
-    const REDUCE_RESULTMODE_ALPHA: u32 = 2;
-    const REDUCE_RESULTMODE_BETA: u32 = 3;
-    const REDUCE_RESULTMODE_MSE: u32 = 4;
+    pub(super) const REDUCE_RESULTMODE_INIT: u32 = 1;
+    pub(super) const REDUCE_RESULTMODE_ALPHA: u32 = 2;
+    pub(super) const REDUCE_RESULTMODE_BETA: u32 = 3;
+    pub(super) const REDUCE_RESULTMODE_MSE: u32 = 4;
+
+    // Offset of the dispatch command for a pass over the entire volume in the reduce result & dispatch buffer.
+    // Zeroed out once the target error is reached, so all passes dispatched with it turn into no-ops.
+    pub(super) const INDIRECT_DISPATCH_VOLUME_OFFSET: u64 = 4 * 4;
+
+    // Residual history written by the reduce pass at every error check (length followed by iteration & squared error pairs).
+    // Need to match RESIDUAL_HISTORY_OFFSET and RESIDUAL_HISTORY_MAX_LENGTH in pressure.glsl
+    const RESIDUAL_HISTORY_OFFSET: u64 = 16 * 4;
+    const RESIDUAL_HISTORY_MAX_LENGTH: usize = 128;
+    const RESIDUAL_HISTORY_SIZE: u64 = (1 + 2 * Self::RESIDUAL_HISTORY_MAX_LENGTH as u64) * 4;
 
     const COMPUTE_LOCAL_SIZE_VOLUME: wgpu::Extent3d = wgpu::Extent3d {
         width: 8,
@@ -240,26 +487,10 @@ impl PressureSolver {
             .next_binding_compute(binding_glsl::image3D(wgpu::TextureFormat::R32Float, false))
             .next_binding_compute(binding_glsl::buffer(false))
             .create(device, "BindGroupLayout: Pressure solver init");
-        let group_layout_apply_coeff = BindGroupLayoutBuilder::new()
-            .next_binding_compute(binding_glsl::buffer(false))
-            .next_binding_compute(binding_glsl::texture3D())
-            .create(device, "BindGroupLayout: P. solver apply coeff matrix & start dot");
         let group_layout_reduce = BindGroupLayoutBuilder::new()
             .next_binding_compute(binding_glsl::buffer(true)) // source
             .next_binding_compute(binding_glsl::buffer(false)) // dest
             .create(device, "BindGroupLayout: Pressure solver dot product reduce");
-        let group_layout_preconditioner = BindGroupLayoutBuilder::new()
-            .next_binding_compute(binding_glsl::buffer(false))
-            .next_binding_compute(binding_glsl::texture3D())
-            .next_binding_compute(binding_glsl::image3D(wgpu::TextureFormat::R32Float, false))
-            .next_binding_compute(binding_glsl::texture3D())
-            .create(device, "BindGroupLayout: Pressure solver preconditioner");
-        let group_layout_update_volume = BindGroupLayoutBuilder::new()
-            .next_binding_compute(binding_glsl::buffer(false))
-            .next_binding_compute(binding_glsl::image3D(wgpu::TextureFormat::R32Float, false))
-            .next_binding_compute(binding_glsl::texture3D())
-            .next_binding_compute(binding_glsl::uniform())
-            .create(device, "BindGroupLayout: Pressure solver generic volume update");
 
         // Use same push constant range for all pipelines to improve internal Vulkan pipeline compatibility.
         let push_constant_ranges = &[wgpu::PushConstantRange {
@@ -267,24 +498,6 @@ impl PressureSolver {
             range: 0..8,
         }];
 
-        let layout_update_volume = Rc::new(device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
-            label: Some("Update Volume Pipeline Layout"),
-            bind_group_layouts: &[
-                &group_layout_general.layout,
-                &group_layout_pressure_field.layout,
-                &group_layout_update_volume.layout,
-            ],
-            push_constant_ranges,
-        }));
-        let layout_preconditioner = Rc::new(device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
-            label: Some("Pressure Solve Precondition Pipeline Layout"),
-            bind_group_layouts: &[
-                &group_layout_general.layout,
-                &group_layout_pressure_field.layout,
-                &group_layout_preconditioner.layout,
-            ],
-            push_constant_ranges,
-        }));
         let layout_init = Rc::new(device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
             label: Some("Pressure Solve Init Pipeline Layout"),
             bind_group_layouts: &[
@@ -294,16 +507,6 @@ impl PressureSolver {
             ],
             push_constant_ranges,
         }));
-
-        let layout_apply_coeff = Rc::new(device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
-            label: Some("Pressure Solve Apply Coeff Pipeline Layout"),
-            bind_group_layouts: &[
-                &group_layout_general.layout,
-                &group_layout_pressure_field.layout,
-                &group_layout_apply_coeff.layout,
-            ],
-            push_constant_ranges,
-        }));
         let layout_reduce = Rc::new(device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
             label: Some("Pressure Solve Reduce Pipeline Layout"),
             bind_group_layouts: &[
@@ -314,11 +517,11 @@ impl PressureSolver {
             push_constant_ranges,
         }));
 
-        let volume_residual = device.create_texture(&create_volume_texture_desc(
-            "Pressure Solve Residual",
-            grid_dimension,
-            wgpu::TextureFormat::R32Float,
-        ));
+        // Right hand side is uploaded directly for validation, see pressure_solver_validation.rs
+        let volume_residual = device.create_texture(&wgpu::TextureDescriptor {
+            usage: wgpu::TextureUsage::SAMPLED | wgpu::TextureUsage::STORAGE | wgpu::TextureUsage::COPY_DST,
+            ..create_volume_texture_desc("Pressure Solve Residual", grid_dimension, wgpu::TextureFormat::R32Float)
+        });
         let volume_auxiliary = device.create_texture(&create_volume_texture_desc(
             "Pressure Solve Auxiliary",
             grid_dimension,
@@ -329,11 +532,6 @@ impl PressureSolver {
             grid_dimension,
             wgpu::TextureFormat::R32Float,
         ));
-        let volume_search = device.create_texture(&create_volume_texture_desc(
-            "Pressure Solve Search",
-            grid_dimension,
-            wgpu::TextureFormat::R32Float,
-        ));
 
         let num_cells = (grid_dimension.width * grid_dimension.height * grid_dimension.depth) as u64;
         let dotproduct_reduce_step_buffers = [
@@ -352,7 +550,7 @@ impl PressureSolver {
         ];
         let dotproduct_reduce_result_and_dispatch_buffer = device.create_buffer(&wgpu::BufferDescriptor {
             label: Some("Buffer: DotProduct Result & IndirectDispatch buffer"),
-            size: 16 * std::mem::size_of::<f32>() as u64,
+            size: Self::RESIDUAL_HISTORY_OFFSET + Self::RESIDUAL_HISTORY_SIZE,
             usage: wgpu::BufferUsage::INDIRECT | wgpu::BufferUsage::STORAGE | wgpu::BufferUsage::UNIFORM | wgpu::BufferUsage::COPY_SRC,
             mapped_at_creation: false,
         });
@@ -360,7 +558,6 @@ impl PressureSolver {
         let volume_residual_view = volume_residual.create_view(&Default::default());
         let volume_auxiliary_view = volume_auxiliary.create_view(&Default::default());
         let volume_auxiliary_temp_view = volume_auxiliary_temp.create_view(&Default::default());
-        let volume_search_view = volume_search.create_view(&Default::default());
 
         let bind_group_general = BindGroupBuilder::new(&group_layout_general)
             .texture(&volume_marker_view)
@@ -369,30 +566,6 @@ impl PressureSolver {
             .texture(&volume_residual_view)
             .resource(dotproduct_reduce_result_and_dispatch_buffer.as_entire_binding())
             .create(device, "BindGroup: Compute initial residual");
-        let bind_group_apply_coeff = BindGroupBuilder::new(&group_layout_apply_coeff)
-            .resource(dotproduct_reduce_step_buffers[0].as_entire_binding())
-            .texture(&volume_search_view)
-            .create(device, "BindGroup: Apply coeff matrix & start dot product");
-        let bind_group_preconditioner = [
-            BindGroupBuilder::new(&group_layout_preconditioner)
-                .resource(dotproduct_reduce_step_buffers[0].as_entire_binding())
-                .texture(&volume_residual_view)
-                .texture(&volume_auxiliary_temp_view)
-                .texture(&volume_residual_view)
-                .create(device, "BindGroup: Preconditioner, Step 1"),
-            BindGroupBuilder::new(&group_layout_preconditioner)
-                .resource(dotproduct_reduce_step_buffers[0].as_entire_binding())
-                .texture(&volume_residual_view)
-                .texture(&volume_auxiliary_view)
-                .texture(&volume_auxiliary_temp_view)
-                .create(device, "BindGroup: Preconditioner, Step 2"),
-            BindGroupBuilder::new(&group_layout_preconditioner)
-                .resource(dotproduct_reduce_step_buffers[0].as_entire_binding())
-                .texture(&volume_residual_view)
-                .texture(&volume_search_view)
-                .texture(&volume_auxiliary_temp_view)
-                .create(device, "BindGroup: Preconditioner, Step 2, to search"),
-        ];
         let bind_group_dotproduct_reduce = [
             BindGroupBuilder::new(&group_layout_reduce)
                 .resource(dotproduct_reduce_step_buffers[0].as_entire_binding())
@@ -414,18 +587,22 @@ impl PressureSolver {
                 .create(device, "BindGroup: Pressure Solve, Reduce Final 1"),
         ];
 
-        let bind_group_update_pressure_and_residual = BindGroupBuilder::new(&group_layout_update_volume)
-            .resource(dotproduct_reduce_step_buffers[0].as_entire_binding())
-            .texture(&volume_residual_view)
-            .texture(&volume_search_view)
-            .resource(dotproduct_reduce_result_and_dispatch_buffer.as_entire_binding())
-            .create(device, "BindGroup: Pressure update pressure and residual");
-        let bind_group_update_search = BindGroupBuilder::new(&group_layout_update_volume)
-            .resource(dotproduct_reduce_step_buffers[0].as_entire_binding())
-            .texture(&volume_search_view)
-            .texture(&volume_auxiliary_view)
-            .resource(dotproduct_reduce_result_and_dispatch_buffer.as_entire_binding())
-            .create(device, "BindGroup: Pressure update search");
+        let backend_desc = SolverBackendDesc {
+            device,
+            shader_dir,
+            grid_dimension,
+            group_layout_general: &group_layout_general,
+            group_layout_pressure_field: &group_layout_pressure_field,
+            push_constant_ranges,
+            reduce_buffer: &dotproduct_reduce_step_buffers[0],
+            reduce_result_and_dispatch_buffer: &dotproduct_reduce_result_and_dispatch_buffer,
+            volume_residual_view: &volume_residual_view,
+            volume_auxiliary_view: &volume_auxiliary_view,
+            volume_auxiliary_temp_view: &volume_auxiliary_temp_view,
+        };
+        let pcg = PcgSolver::new(&backend_desc, pipeline_manager);
+        let jacobi = RelaxationSolver::new(&backend_desc, pipeline_manager, RelaxationMethod::Jacobi);
+        let gauss_seidel = RelaxationSolver::new(&backend_desc, pipeline_manager, RelaxationMethod::GaussSeidel);
 
         let shader_path = Path::new("simulation/pressure_solver");
 
@@ -434,12 +611,12 @@ impl PressureSolver {
 
             bind_group_general,
             bind_group_init,
-            bind_group_preconditioner,
-            bind_group_apply_coeff,
             bind_group_dotproduct_reduce,
             bind_group_dotproduct_final,
-            bind_group_update_pressure_and_residual,
-            bind_group_update_search,
+
+            pcg,
+            jacobi,
+            gauss_seidel,
 
             pipeline_init: pipeline_manager.create_compute_pipeline(
                 device,
@@ -450,15 +627,6 @@ impl PressureSolver {
                     &shader_path.join(Path::new("pressure_init.comp")),
                 ),
             ),
-            pipeline_apply_preconditioner: pipeline_manager.create_compute_pipeline(
-                device,
-                shader_dir,
-                ComputePipelineCreationDesc::new(
-                    "PressureSolve: Apply preconditioner",
-                    layout_preconditioner.clone(),
-                    &shader_path.join(&Path::new("pressure_apply_preconditioner.comp")),
-                ),
-            ),
             pipeline_reduce: pipeline_manager.create_compute_pipeline(
                 device,
                 shader_dir,
@@ -468,38 +636,13 @@ impl PressureSolver {
                     &shader_path.join(&Path::new("pressure_reduce.comp")),
                 ),
             ),
-            pipeline_apply_coeff: pipeline_manager.create_compute_pipeline(
-                device,
-                shader_dir,
-                ComputePipelineCreationDesc::new(
-                    "PressureSolve: Apply coefficient matrix",
-                    layout_apply_coeff.clone(),
-                    &shader_path.join(&Path::new("pressure_apply_coeff.comp")),
-                ),
-            ),
-            pipeline_update_pressure_and_residual: pipeline_manager.create_compute_pipeline(
-                device,
-                shader_dir,
-                ComputePipelineCreationDesc::new(
-                    "PressureSolve: Update pressure and residual",
-                    layout_update_volume.clone(),
-                    &shader_path.join(&Path::new("pressure_update_pressure_and_residual.comp")),
-                ),
-            ),
-            pipeline_update_search: pipeline_manager.create_compute_pipeline(
-                device,
-                shader_dir,
-                ComputePipelineCreationDesc::new(
-                    "PressureSolve: Update search",
-                    layout_update_volume.clone(),
-                    &shader_path.join(&Path::new("pressure_update_search.comp")),
-                ),
-            ),
 
+            group_layout_general,
             group_layout_pressure_field,
 
             dotproduct_reduce_result_and_dispatch_buffer,
 
+            volume_residual,
             volume_residual_view,
         }
     }
@@ -508,7 +651,30 @@ impl PressureSolver {
         &self.volume_residual_view
     }
 
-    fn reduce_add<'a, 'b: 'a>(&'b self, cpass: &mut wgpu::ComputePass<'a>, pipeline_manager: &'a PipelineManager, result_mode: u32) {
+    // Holds the right hand side before the solve.
+    pub(super) fn residual_texture(&self) -> &wgpu::Texture {
+        &self.volume_residual
+    }
+
+    // Holds the reduce results (see pressure_reduce.comp) and the indirect dispatch commands.
+    pub(super) fn reduce_result_and_dispatch_buffer(&self) -> &wgpu::Buffer {
+        &self.dotproduct_reduce_result_and_dispatch_buffer
+    }
+
+    // Number of work groups for a pass over the entire volume that is not dispatched indirectly.
+    pub(super) fn grid_work_groups(&self) -> wgpu::Extent3d {
+        wgpu_utils::compute_group_size(self.grid_dimension, Self::COMPUTE_LOCAL_SIZE_VOLUME)
+    }
+
+    // Size of the reduce buffer written by passes that start a dotproduct, needs to be passed as second push constant.
+    pub(super) fn reduce_pass_initial_group_size(&self) -> u32 {
+        wgpu_utils::compute_group_size_1d(
+            (self.grid_dimension.width * self.grid_dimension.height * self.grid_dimension.depth) as u32 / Self::REDUCE_READS_PER_THREAD,
+            Self::COMPUTE_LOCAL_SIZE_REDUCE,
+        )
+    }
+
+    pub(super) fn reduce_add<'a, 'b: 'a>(&'b self, cpass: &mut wgpu::ComputePass<'a>, pipeline_manager: &'a PipelineManager, result_mode: u32) {
         wgpu_scope!(cpass, &format!("PressureSolver.reduce_add - mode {}", result_mode));
 
         let mut num_entries_remaining = (self.grid_dimension.width * self.grid_dimension.height * self.grid_dimension.depth) as u32;
@@ -551,6 +717,14 @@ impl PressureSolver {
         cpass.dispatch(1, 1, 1);
     }
 
+    fn backend(&self, method: SolverMethod) -> &dyn SolverBackend {
+        match method {
+            SolverMethod::Pcg => &self.pcg,
+            SolverMethod::Jacobi => &self.jacobi,
+            SolverMethod::GaussSeidel => &self.gauss_seidel,
+        }
+    }
+
     pub fn solve<'a, 'b: 'a>(
         &'b self,
         simulation_delta: Duration,
@@ -562,23 +736,14 @@ impl PressureSolver {
 
         let mut cpass = encoder.begin_compute_pass();
 
-        const PRECONDITIONER_PASS0: u32 = 0;
-        const PRECONDITIONER_PASS1: u32 = 1;
-
-        pressure_field.retrieve_new_error_samples(simulation_delta);
-
-        let reduce_pass_initial_group_size = wgpu_utils::compute_group_size_1d(
-            (self.grid_dimension.width * self.grid_dimension.height * self.grid_dimension.depth) as u32 / Self::REDUCE_READS_PER_THREAD,
-            Self::COMPUTE_LOCAL_SIZE_REDUCE,
-        );
+        pressure_field.retrieve_new_error_samples();
 
-        cpass.set_bind_group(0, &self.bind_group_general, &[]);
+        let bind_group_marker = pressure_field.bind_group_marker.as_ref().unwrap_or(&self.bind_group_general);
+        cpass.set_bind_group(0, bind_group_marker, &[]);
         cpass.set_bind_group(1, &pressure_field.bind_group_pressure_field, &[]);
 
-        // For optimization various steps are collapsed as far as possible to avoid expensive buffer/texture read/writes
-        // This makes the algorithm a lot faster but also a bit harder to read.
-        wgpu_scope!(cpass, "init", || {
-            let grid_work_groups = wgpu_utils::compute_group_size(self.grid_dimension, Self::COMPUTE_LOCAL_SIZE_VOLUME);
+        wgpu_scope!(cpass, "compute initial residual", || {
+            let grid_work_groups = self.grid_work_groups();
 
             // We use pressure from last frame, but set explicitly set all pressure values to zero wherever there is not fluid right now.
             // This is done in order to prevent having results from many frames ago influence results for upcoming frames.
@@ -597,90 +762,16 @@ impl PressureSolver {
             }
             cpass.set_bind_group(2, &self.bind_group_init, &[]);
             cpass.dispatch(grid_work_groups.width, grid_work_groups.height, grid_work_groups.depth);
-
-            // Apply preconditioner on (r), store result to search vector (s) and start dotproduct of <s; r>
-            // Note that we don't use the auxillary vector here as in-between storage!
-            wgpu_scope!(cpass, "preconditioner on (r), store to auxillary (z), start dotproduct of <z; r>", || {
-                cpass.set_pipeline(pipeline_manager.get_compute(&self.pipeline_apply_preconditioner));
-                cpass.set_push_constants(0, &bytemuck::bytes_of(&[0 as u32]));
-                cpass.set_push_constants(0, &bytemuck::bytes_of(&[PRECONDITIONER_PASS0]));
-                cpass.set_bind_group(2, &self.bind_group_preconditioner[0], &[]);
-                cpass.dispatch(grid_work_groups.width, grid_work_groups.height, grid_work_groups.depth);
-                cpass.set_push_constants(0, &bytemuck::bytes_of(&[PRECONDITIONER_PASS1, reduce_pass_initial_group_size]));
-                cpass.set_bind_group(2, &self.bind_group_preconditioner[2], &[]);
-                cpass.dispatch(grid_work_groups.width, grid_work_groups.height, grid_work_groups.depth);
-            });
-            // Init sigma to dotproduct of search vector (s) and residual (r)
-            self.reduce_add(&mut cpass, pipeline_manager, Self::REDUCE_RESULTMODE_INIT);
         });
 
-        wgpu_scope!(cpass, "solver iterations", || {
-            const DISPATCH_BUFFER_OFFSET: u64 = 4 * 4;
-
-            let mut i = 0;
-            while wgpu_scope!(cpass, &format!("iteration {}", i), || {
-                wgpu_scope!(cpass, "multiply search vector (s) with coefficients (A)", || {
-                    // The dot product is applied to the result (denoted as z in Bridson's book) and the search vector (s), i.e. compute <s; As>
-                    cpass.set_pipeline(pipeline_manager.get_compute(&self.pipeline_apply_coeff));
-                    cpass.set_bind_group(2, &self.bind_group_apply_coeff, &[]);
-                    cpass.set_push_constants(0, &bytemuck::bytes_of(&[0, reduce_pass_initial_group_size]));
-                    cpass.dispatch_indirect(&self.dotproduct_reduce_result_and_dispatch_buffer, DISPATCH_BUFFER_OFFSET);
-                });
-                // finish dotproduct of auxiliary field (z) and search field (s)
-                self.reduce_add(&mut cpass, pipeline_manager, Self::REDUCE_RESULTMODE_ALPHA);
-
-                let iteration_with_mse_computation =
-                    pressure_field.config.max_num_iterations == i || (i > 0 && i % pressure_field.config.mse_check_frequency == 0);
-
-                wgpu_scope!(cpass, "update pressure field (p) and residual field (r)", || {
-                    const PRUPDATE_COMPUTE_MSE: u32 = 1;
-                    cpass.set_pipeline(pipeline_manager.get_compute(&self.pipeline_update_pressure_and_residual));
-                    if iteration_with_mse_computation {
-                        cpass.set_push_constants(0, &bytemuck::bytes_of(&[PRUPDATE_COMPUTE_MSE, reduce_pass_initial_group_size]));
-                    } else {
-                        cpass.set_push_constants(0, &bytemuck::bytes_of(&[0]));
-                    }
-                    cpass.set_bind_group(2, &self.bind_group_update_pressure_and_residual, &[]);
-                    cpass.dispatch_indirect(&self.dotproduct_reduce_result_and_dispatch_buffer, DISPATCH_BUFFER_OFFSET);
-                });
-
-                // Time to check on mse?
-                if iteration_with_mse_computation {
-                    // Compute remaining error.
-                    // Used for statistics. If below target, makes all upcoming dispatch_indirect no-ops.
-                    self.reduce_add(&mut cpass, pipeline_manager, Self::REDUCE_RESULTMODE_MSE + i as u32);
-
-                    if pressure_field.config.max_num_iterations == i {
-                        return false;
-                    }
-                }
-
-                wgpu_scope!(cpass, "preconditioner on (r), store to auxillary (z), start dotproduct of <z; r>", || {
-                    cpass.set_pipeline(pipeline_manager.get_compute(&self.pipeline_apply_preconditioner));
-                    cpass.set_push_constants(0, &bytemuck::bytes_of(&[PRECONDITIONER_PASS0]));
-                    cpass.set_bind_group(2, &self.bind_group_preconditioner[0], &[]);
-                    cpass.dispatch_indirect(&self.dotproduct_reduce_result_and_dispatch_buffer, DISPATCH_BUFFER_OFFSET);
-                    cpass.set_push_constants(0, &bytemuck::bytes_of(&[PRECONDITIONER_PASS1, reduce_pass_initial_group_size]));
-                    cpass.set_bind_group(2, &self.bind_group_preconditioner[1], &[]);
-                    cpass.dispatch_indirect(&self.dotproduct_reduce_result_and_dispatch_buffer, DISPATCH_BUFFER_OFFSET);
-                });
-
-                // finish dotproduct of auxiliary field (z) and residual field (r)
-                self.reduce_add(&mut cpass, pipeline_manager, Self::REDUCE_RESULTMODE_BETA);
-
-                wgpu_scope!(cpass, "Update search vector", || {
-                    cpass.set_pipeline(pipeline_manager.get_compute(&self.pipeline_update_search));
-                    cpass.set_bind_group(2, &self.bind_group_update_search, &[]);
-                    cpass.dispatch_indirect(&self.dotproduct_reduce_result_and_dispatch_buffer, DISPATCH_BUFFER_OFFSET);
-                });
-
-                i += 1;
-                true
-            }) {}
+        let config = pressure_field.config;
+        wgpu_scope!(cpass, &format!("{:?}", config.method), || {
+            self.backend(config.method)
+                .solve(self, &mut cpass, pipeline_manager, &config, bind_group_marker);
         });
 
         drop(cpass);
         pressure_field.timestamp_last_iteration += simulation_delta;
-        pressure_field.enqueue_error_buffer_read(&mut *encoder, &self.dotproduct_reduce_result_and_dispatch_buffer);
+        pressure_field.enqueue_error_buffer_read(&mut *encoder, &self.dotproduct_reduce_result_and_dispatch_buffer, simulation_delta);
     }
 }
@@ -0,0 +1,254 @@
+// Runs the GPU pressure solver on generated test problems and compares the results against the CPU reference (cpu_pressure_solver.rs).
+// Every case uses a fresh PressureField, so both sides start out with zero pressure.
+
+use super::cpu_pressure_solver::{CpuPressureSystem, PressureComparison};
+use super::pressure_solver::*;
+use crate::wgpu_utils::{pipelines::PipelineManager, readback::BlockingReadback, shader::ShaderDirectory};
+use std::time::Duration;
+
+// Needs to be large enough for the reduce passes of the solver, see PressureSolver::reduce_add.
+// Different size on every axis to catch mixed up coordinates.
+const GRID_DIMENSION: wgpu::Extent3d = wgpu::Extent3d {
+    width: 48,
+    height: 32,
+    depth: 40,
+};
+const SIMULATION_DELTA: Duration = Duration::from_micros(1_000_000 / 120);
+// Viscosity in grid cells² per second, chosen so that a single step diffuses by about one cell.
+const VISCOSITY: f32 = 120.0;
+
+// Largest tolerated difference to the reference relative to the largest pressure value.
+// The solvers stop at a target error, so the solutions are never quite the same.
+pub const MAX_RELATIVE_PRESSURE_DIFFERENCE: f32 = 0.01;
+
+pub struct SolverValidationResult {
+    pub name: &'static str,
+    pub gpu: SolverStatisticSample,
+    pub reference: SolverStatisticSample,
+    // Error of the GPU solution as computed by the CPU, should be close to gpu.mse
+    pub gpu_solution_error: f32,
+    pub comparison: PressureComparison,
+}
+
+impl SolverValidationResult {
+    pub fn passed(&self) -> bool {
+        self.comparison.relative_difference() <= MAX_RELATIVE_PRESSURE_DIFFERENCE
+    }
+}
+
+struct SolverValidationCase {
+    name: &'static str,
+    system: SolverSystem,
+    config: SolverConfig,
+}
+
+// A pool of fluid with a wavy surface and a solid sphere in it, surrounded by the (solid) domain boundary.
+fn generate_marker() -> Vec<f32> {
+    let mut marker = Vec::new();
+    for z in 0..GRID_DIMENSION.depth {
+        for y in 0..GRID_DIMENSION.height {
+            for x in 0..GRID_DIMENSION.width {
+                let (x, y, z) = (x as f32 + 0.5, y as f32 + 0.5, z as f32 + 0.5);
+                let (width, height, depth) = (GRID_DIMENSION.width as f32, GRID_DIMENSION.height as f32, GRID_DIMENSION.depth as f32);
+                let surface_height = height * (0.6 + 0.1 * (x / width * 6.0).sin() * (z / depth * 4.0).cos());
+                let sphere_radius = 0.2 * depth;
+                let sphere_distance = ((x - 0.5 * width).powi(2) + (y - 0.3 * height).powi(2) + (z - 0.5 * depth).powi(2)).sqrt() - sphere_radius;
+
+                marker.push(if sphere_distance < 0.0 {
+                    CELL_SOLID
+                } else if y < surface_height {
+                    CELL_FLUID
+                } else {
+                    CELL_AIR
+                });
+            }
+        }
+    }
+    marker
+}
+
+// Pseudo random values in [-1, 1] in the fluid, deterministic so results of different runs can be compared.
+fn generate_rhs(marker: &[f32]) -> Vec<f32> {
+    marker
+        .iter()
+        .enumerate()
+        .map(|(index, marker)| {
+            if *marker != CELL_FLUID {
+                return 0.0;
+            }
+            let mut hash = index as u32;
+            hash = (hash ^ 61) ^ (hash >> 16);
+            hash = hash.wrapping_mul(9);
+            hash ^= hash >> 4;
+            hash = hash.wrapping_mul(0x27d4eb2d);
+            hash ^= hash >> 15;
+            hash as f32 / u32::MAX as f32 * 2.0 - 1.0
+        })
+        .collect()
+}
+
+fn validation_cases() -> Vec<SolverValidationCase> {
+    let pressure_config = |method, preconditioner, max_num_iterations| SolverConfig {
+        target_mse: 1.0e-8,
+        max_num_iterations,
+        mse_check_frequency: 4,
+        preconditioner,
+        method,
+    };
+    let viscosity_config = |method, max_num_iterations| SolverConfig {
+        target_mse: 1.0e-4,
+        max_num_iterations,
+        mse_check_frequency: 4,
+        preconditioner: Preconditioner::IncompletePoisson,
+        method,
+    };
+
+    vec![
+        SolverValidationCase {
+            name: "pressure, pcg with incomplete poisson",
+            system: SolverSystem::Pressure,
+            config: pressure_config(SolverMethod::Pcg, Preconditioner::IncompletePoisson, 512),
+        },
+        SolverValidationCase {
+            name: "pressure, pcg with multigrid",
+            system: SolverSystem::Pressure,
+            config: pressure_config(SolverMethod::Pcg, Preconditioner::Multigrid, 128),
+        },
+        // Relaxation methods take far too many iterations for the pressure system, but do fine on the well conditioned viscosity system.
+        SolverValidationCase {
+            name: "viscosity, pcg with incomplete poisson",
+            system: SolverSystem::Viscosity(VISCOSITY),
+            config: viscosity_config(SolverMethod::Pcg, 128),
+        },
+        SolverValidationCase {
+            name: "viscosity, jacobi",
+            system: SolverSystem::Viscosity(VISCOSITY),
+            config: viscosity_config(SolverMethod::Jacobi, 256),
+        },
+        SolverValidationCase {
+            name: "viscosity, gauss-seidel",
+            system: SolverSystem::Viscosity(VISCOSITY),
+            config: viscosity_config(SolverMethod::GaussSeidel, 256),
+        },
+    ]
+}
+
+// Blocks until all cases ran on the GPU.
+pub fn validate_pressure_solver(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    shader_dir: &ShaderDirectory,
+    pipeline_manager: &mut PipelineManager,
+) -> Result<Vec<SolverValidationResult>, wgpu::BufferAsyncError> {
+    let marker = generate_marker();
+    let rhs = generate_rhs(&marker);
+
+    let volume_marker = device.create_texture(&wgpu::TextureDescriptor {
+        label: Some("Pressure Solver Validation Marker"),
+        size: GRID_DIMENSION,
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D3,
+        format: wgpu::TextureFormat::R8Snorm,
+        usage: wgpu::TextureUsage::SAMPLED | wgpu::TextureUsage::COPY_DST,
+    });
+    let marker_snorm: Vec<i8> = marker.iter().map(|marker| (marker * 127.0) as i8).collect();
+    queue.write_texture(
+        wgpu::TextureCopyView {
+            texture: &volume_marker,
+            mip_level: 0,
+            origin: wgpu::Origin3d::ZERO,
+        },
+        bytemuck::cast_slice(&marker_snorm),
+        wgpu::TextureDataLayout {
+            offset: 0,
+            bytes_per_row: GRID_DIMENSION.width,
+            rows_per_image: GRID_DIMENSION.height,
+        },
+        GRID_DIMENSION,
+    );
+    let volume_marker_view = volume_marker.create_view(&Default::default());
+
+    let solver = PressureSolver::new(device, GRID_DIMENSION, shader_dir, pipeline_manager, &volume_marker_view);
+
+    let mut results = Vec::new();
+    for case in validation_cases() {
+        let mut pressure_field = match case.system {
+            SolverSystem::Pressure => PressureField::new(case.name, device, GRID_DIMENSION, &solver, case.config),
+            SolverSystem::Viscosity(viscosity) => {
+                let mut field = PressureField::new_viscosity(case.name, device, GRID_DIMENSION, &solver, case.config, &volume_marker_view);
+                field.set_viscosity(viscosity);
+                field
+            }
+        };
+        pressure_field.update_uniforms(queue, SIMULATION_DELTA);
+        queue.write_texture(
+            wgpu::TextureCopyView {
+                texture: solver.residual_texture(),
+                mip_level: 0,
+                origin: wgpu::Origin3d::ZERO,
+            },
+            bytemuck::cast_slice(&rhs),
+            wgpu::TextureDataLayout {
+                offset: 0,
+                bytes_per_row: GRID_DIMENSION.width * std::mem::size_of::<f32>() as u32,
+                rows_per_image: GRID_DIMENSION.height,
+            },
+            GRID_DIMENSION,
+        );
+
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Encoder: Pressure solver validation"),
+        });
+        solver.solve(SIMULATION_DELTA, &mut pressure_field, &mut encoder, pipeline_manager);
+        queue.submit(Some(encoder.finish()));
+
+        let mut readback = BlockingReadback::new(device, "Pressure solver validation");
+        let pressure_index = readback.copy_texture(
+            device,
+            pressure_field.pressure_texture(),
+            GRID_DIMENSION,
+            std::mem::size_of::<f32>() as u32,
+        );
+        // Squared error and iteration count, see pressure_reduce.comp
+        let reduce_result_index = readback.copy_buffer(device, solver.reduce_result_and_dispatch_buffer(), 4 * std::mem::size_of::<f32>() as u64);
+        let readback_results = readback.finish(device, queue)?;
+        // Read-back data isn't necessarily aligned, so we can't cast the buffers directly.
+        let read_f32s = |buffer: &[u8]| -> Vec<f32> {
+            buffer
+                .chunks_exact(4)
+                .map(|bytes| f32::from_ne_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+                .collect()
+        };
+        let gpu_pressure = read_f32s(&readback_results[pressure_index]);
+        let reduce_result = read_f32s(&readback_results[reduce_result_index]);
+
+        let system = CpuPressureSystem::new(GRID_DIMENSION, &marker, case.system, SIMULATION_DELTA);
+        let mut reference_pressure = vec![0.0; marker.len()];
+        // Solve the reference a lot more accurately than the GPU, so the difference is dominated by the GPU solver's error.
+        let reference = system.solve_pcg(
+            &rhs,
+            &mut reference_pressure,
+            &SolverConfig {
+                target_mse: case.config.target_mse * 1.0e-4,
+                max_num_iterations: 4096,
+                mse_check_frequency: 1,
+                ..case.config
+            },
+        );
+
+        results.push(SolverValidationResult {
+            name: case.name,
+            gpu: SolverStatisticSample {
+                mse: reduce_result[2] * case.system.error_scale(SIMULATION_DELTA),
+                iteration_count: reduce_result[3] as i32,
+                ..Default::default()
+            },
+            reference,
+            gpu_solution_error: system.solution_error(&rhs, &gpu_pressure),
+            comparison: system.compare(&gpu_pressure, &reference_pressure),
+        });
+    }
+
+    Ok(results)
+}
@@ -0,0 +1,215 @@
+// Simple relaxation methods as alternative pressure solver backends.
+// Both solve for the correction (e) of the initial pressure guess, i.e. A e = r with r being the initial residual,
+// which allows starting with a zero guess and keeps the residual volume untouched for computing the error.
+// They need a lot more iterations than the PCG solver but are much cheaper per iteration and serve as a reference.
+
+use super::pressure_solver::*;
+use crate::wgpu_utils::{binding_builder::*, binding_glsl, pipelines::*};
+use std::{path::Path, rc::Rc};
+
+#[derive(Clone, Copy, PartialEq)]
+pub(super) enum RelaxationMethod {
+    // Ping-pongs between the auxiliary volumes, every iteration consists of two sweeps so the result always ends up in the same volume.
+    Jacobi,
+    // Red-black ordering, every iteration updates all red and then all black cells in place.
+    GaussSeidel,
+}
+
+pub(super) struct RelaxationSolver {
+    method: RelaxationMethod,
+
+    // Jacobi: auxiliary -> auxiliary temp, auxiliary temp -> auxiliary
+    // Gauss-Seidel: auxiliary in place
+    bind_group_sweep: Vec<wgpu::BindGroup>,
+    bind_group_compute_mse: wgpu::BindGroup,
+    bind_group_apply_correction: wgpu::BindGroup,
+
+    pipeline_sweep: ComputePipelineHandle,
+    pipeline_compute_mse: ComputePipelineHandle,
+    pipeline_apply_correction: ComputePipelineHandle,
+}
+
+impl RelaxationSolver {
+    // Need to match the mode bits in relaxation_jacobi.comp
+    const JACOBI_ZERO_INITIAL_GUESS: u32 = 1;
+    // Need to match the mode bits in multigrid_smooth.comp
+    const GAUSS_SEIDEL_COLOR_BLACK: u32 = 1;
+    const GAUSS_SEIDEL_ZERO_INITIAL_GUESS: u32 = 2;
+
+    pub fn new(desc: &SolverBackendDesc, pipeline_manager: &mut PipelineManager, method: RelaxationMethod) -> Self {
+        let device = desc.device;
+
+        let group_layout_sweep = match method {
+            RelaxationMethod::Jacobi => BindGroupLayoutBuilder::new()
+                .next_binding_compute(binding_glsl::texture3D())
+                .next_binding_compute(binding_glsl::texture3D())
+                .next_binding_compute(binding_glsl::image3D(wgpu::TextureFormat::R32Float, false))
+                .create(device, "BindGroupLayout: Jacobi sweep"),
+            RelaxationMethod::GaussSeidel => BindGroupLayoutBuilder::new()
+                .next_binding_compute(binding_glsl::buffer(false))
+                .next_binding_compute(binding_glsl::texture3D())
+                .next_binding_compute(binding_glsl::image3D(wgpu::TextureFormat::R32Float, false))
+                .create(device, "BindGroupLayout: Gauss-Seidel sweep"),
+        };
+        let group_layout_compute_mse = BindGroupLayoutBuilder::new()
+            .next_binding_compute(binding_glsl::buffer(false))
+            .next_binding_compute(binding_glsl::texture3D())
+            .next_binding_compute(binding_glsl::texture3D())
+            .create(device, "BindGroupLayout: Relaxation compute mse");
+        let group_layout_apply_correction = BindGroupLayoutBuilder::new()
+            .next_binding_compute(binding_glsl::texture3D())
+            .create(device, "BindGroupLayout: Relaxation apply correction");
+
+        let create_pipeline_layout = |label: &str, group_layout: &BindGroupLayoutWithDesc| {
+            Rc::new(device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some(label),
+                bind_group_layouts: &[
+                    &desc.group_layout_general.layout,
+                    &desc.group_layout_pressure_field.layout,
+                    &group_layout.layout,
+                ],
+                push_constant_ranges: desc.push_constant_ranges,
+            }))
+        };
+        let layout_sweep = create_pipeline_layout("Relaxation Sweep Pipeline Layout", &group_layout_sweep);
+        let layout_compute_mse = create_pipeline_layout("Relaxation Compute MSE Pipeline Layout", &group_layout_compute_mse);
+        let layout_apply_correction = create_pipeline_layout("Relaxation Apply Correction Pipeline Layout", &group_layout_apply_correction);
+
+        let bind_group_sweep = match method {
+            RelaxationMethod::Jacobi => vec![
+                BindGroupBuilder::new(&group_layout_sweep)
+                    .texture(desc.volume_residual_view)
+                    .texture(desc.volume_auxiliary_view)
+                    .texture(desc.volume_auxiliary_temp_view)
+                    .create(device, "BindGroup: Jacobi sweep 0"),
+                BindGroupBuilder::new(&group_layout_sweep)
+                    .texture(desc.volume_residual_view)
+                    .texture(desc.volume_auxiliary_temp_view)
+                    .texture(desc.volume_auxiliary_view)
+                    .create(device, "BindGroup: Jacobi sweep 1"),
+            ],
+            RelaxationMethod::GaussSeidel => vec![BindGroupBuilder::new(&group_layout_sweep)
+                .resource(desc.reduce_buffer.as_entire_binding())
+                .texture(desc.volume_residual_view)
+                .texture(desc.volume_auxiliary_view)
+                .create(device, "BindGroup: Gauss-Seidel sweep")],
+        };
+        let bind_group_compute_mse = BindGroupBuilder::new(&group_layout_compute_mse)
+            .resource(desc.reduce_buffer.as_entire_binding())
+            .texture(desc.volume_residual_view)
+            .texture(desc.volume_auxiliary_view)
+            .create(device, "BindGroup: Relaxation compute mse");
+        let bind_group_apply_correction = BindGroupBuilder::new(&group_layout_apply_correction)
+            .texture(desc.volume_auxiliary_view)
+            .create(device, "BindGroup: Relaxation apply correction");
+
+        let shader_path = Path::new("simulation/pressure_solver");
+        let (sweep_name, sweep_shader) = match method {
+            RelaxationMethod::Jacobi => ("PressureSolve: Jacobi sweep", "relaxation_jacobi.comp"),
+            RelaxationMethod::GaussSeidel => ("PressureSolve: Gauss-Seidel sweep", "multigrid_smooth.comp"),
+        };
+
+        RelaxationSolver {
+            method,
+
+            bind_group_sweep,
+            bind_group_compute_mse,
+            bind_group_apply_correction,
+
+            pipeline_sweep: pipeline_manager.create_compute_pipeline(
+                device,
+                desc.shader_dir,
+                ComputePipelineCreationDesc::new(sweep_name, layout_sweep, &shader_path.join(Path::new(sweep_shader))),
+            ),
+            pipeline_compute_mse: pipeline_manager.create_compute_pipeline(
+                device,
+                desc.shader_dir,
+                ComputePipelineCreationDesc::new(
+                    "PressureSolve: Relaxation compute mse",
+                    layout_compute_mse,
+                    &shader_path.join(Path::new("relaxation_compute_mse.comp")),
+                ),
+            ),
+            pipeline_apply_correction: pipeline_manager.create_compute_pipeline(
+                device,
+                desc.shader_dir,
+                ComputePipelineCreationDesc::new(
+                    "PressureSolve: Relaxation apply correction",
+                    layout_apply_correction,
+                    &shader_path.join(Path::new("relaxation_apply_correction.comp")),
+                ),
+            ),
+        }
+    }
+
+    fn sweep<'a, 'b: 'a>(&'b self, cpass: &mut wgpu::ComputePass<'a>, dispatch_buffer: &'a wgpu::Buffer, first_iteration: bool) {
+        match self.method {
+            RelaxationMethod::Jacobi => {
+                for (i, bind_group) in self.bind_group_sweep.iter().enumerate() {
+                    let mode = if first_iteration && i == 0 { Self::JACOBI_ZERO_INITIAL_GUESS } else { 0 };
+                    cpass.set_push_constants(0, bytemuck::bytes_of(&[mode, 0]));
+                    cpass.set_bind_group(2, bind_group, &[]);
+                    cpass.dispatch_indirect(dispatch_buffer, PressureSolver::INDIRECT_DISPATCH_VOLUME_OFFSET);
+                }
+            }
+            RelaxationMethod::GaussSeidel => {
+                cpass.set_bind_group(2, &self.bind_group_sweep[0], &[]);
+                // Second push constant is the multigrid level.
+                let red_mode = if first_iteration { Self::GAUSS_SEIDEL_ZERO_INITIAL_GUESS } else { 0 };
+                cpass.set_push_constants(0, bytemuck::bytes_of(&[red_mode, 0]));
+                cpass.dispatch_indirect(dispatch_buffer, PressureSolver::INDIRECT_DISPATCH_VOLUME_OFFSET);
+                cpass.set_push_constants(0, bytemuck::bytes_of(&[Self::GAUSS_SEIDEL_COLOR_BLACK, 0]));
+                cpass.dispatch_indirect(dispatch_buffer, PressureSolver::INDIRECT_DISPATCH_VOLUME_OFFSET);
+            }
+        }
+    }
+}
+
+impl SolverBackend for RelaxationSolver {
+    fn solve<'a, 'b: 'a>(
+        &'b self,
+        solver: &'b PressureSolver,
+        cpass: &mut wgpu::ComputePass<'a>,
+        pipeline_manager: &'a PipelineManager,
+        config: &SolverConfig,
+        _bind_group_marker: &'a wgpu::BindGroup,
+    ) {
+        let reduce_pass_initial_group_size = solver.reduce_pass_initial_group_size();
+        let dispatch_buffer = solver.reduce_result_and_dispatch_buffer();
+
+        wgpu_scope!(cpass, "solver iterations", || {
+            let mut i = 0;
+            while wgpu_scope!(cpass, &format!("iteration {}", i), || {
+                cpass.set_pipeline(pipeline_manager.get_compute(&self.pipeline_sweep));
+                self.sweep(cpass, dispatch_buffer, i == 0);
+
+                // Time to check on mse?
+                if config.is_mse_check_iteration(i) {
+                    wgpu_scope!(cpass, "compute residual of correction (e)", || {
+                        cpass.set_pipeline(pipeline_manager.get_compute(&self.pipeline_compute_mse));
+                        cpass.set_push_constants(0, bytemuck::bytes_of(&[0, reduce_pass_initial_group_size]));
+                        cpass.set_bind_group(2, &self.bind_group_compute_mse, &[]);
+                        cpass.dispatch_indirect(dispatch_buffer, PressureSolver::INDIRECT_DISPATCH_VOLUME_OFFSET);
+                    });
+                    // Used for statistics. If below target, makes all upcoming dispatch_indirect no-ops.
+                    solver.reduce_add(cpass, pipeline_manager, PressureSolver::REDUCE_RESULTMODE_MSE + i as u32);
+
+                    if config.max_num_iterations == i {
+                        return false;
+                    }
+                }
+
+                i += 1;
+                true
+            }) {}
+        });
+
+        // Not indirect, the correction needs to be applied no matter in which iteration the solver stopped.
+        wgpu_scope!(cpass, "add correction (e) to pressure (p)", || {
+            let grid_work_groups = solver.grid_work_groups();
+            cpass.set_pipeline(pipeline_manager.get_compute(&self.pipeline_apply_correction));
+            cpass.set_bind_group(2, &self.bind_group_apply_correction, &[]);
+            cpass.dispatch(grid_work_groups.width, grid_work_groups.height, grid_work_groups.depth);
+        });
+    }
+}
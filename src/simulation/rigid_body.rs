@@ -0,0 +1,153 @@
+// Rigid bodies that are pushed around by the fluid and in turn act as moving boundary for it.
+//
+// Integrated on the CPU in world space. Fluid forces are read back from the GPU and thus lag a few steps behind,
+// the bodies themselves are handed to the fluid as dynamic solids every step.
+
+use super::checkpoint::RigidBodyCheckpoint;
+use super::dynamic_solid::{DynamicSolid, DynamicSolidShape, FluidForce};
+use cgmath::{InnerSpace, Rotation};
+
+#[derive(Clone, Copy)]
+pub enum RigidBodyShape {
+    Box { half_extent: cgmath::Vector3<f32> },
+    Sphere { radius: f32 },
+}
+
+pub struct RigidBody {
+    pub shape: RigidBodyShape,
+    mass: f32,
+    // Fraction of velocity that is lost every second.
+    damping: f32,
+    // Diagonal of the inertia tensor in body space.
+    inertia: cgmath::Vector3<f32>,
+
+    pub position: cgmath::Point3<f32>,
+    pub rotation: cgmath::Quaternion<f32>,
+    pub linear_velocity: cgmath::Vector3<f32>,
+    pub angular_velocity: cgmath::Vector3<f32>,
+
+    // Last known force & torque (around the center of mass) the fluid exerts on the body.
+    fluid_force: cgmath::Vector3<f32>,
+    fluid_torque: cgmath::Vector3<f32>,
+}
+
+impl RigidBody {
+    // Fraction of normal velocity that is kept when bouncing off the domain walls.
+    const WALL_RESTITUTION: f32 = 0.3;
+
+    pub fn new(shape: RigidBodyShape, density: f32, damping: f32, position: cgmath::Point3<f32>, rotation: cgmath::Quaternion<f32>) -> Self {
+        let (mass, inertia) = match shape {
+            RigidBodyShape::Box { half_extent: h } => {
+                let mass = density * 8.0 * h.x * h.y * h.z;
+                let inertia = cgmath::vec3(h.y * h.y + h.z * h.z, h.x * h.x + h.z * h.z, h.x * h.x + h.y * h.y) * (mass / 3.0);
+                (mass, inertia)
+            }
+            RigidBodyShape::Sphere { radius } => {
+                let mass = density * 4.0 / 3.0 * std::f32::consts::PI * radius * radius * radius;
+                let inertia = 0.4 * mass * radius * radius;
+                (mass, cgmath::vec3(inertia, inertia, inertia))
+            }
+        };
+
+        RigidBody {
+            shape,
+            mass,
+            damping,
+            inertia,
+            position,
+            rotation,
+            linear_velocity: cgmath::vec3(0.0, 0.0, 0.0),
+            angular_velocity: cgmath::vec3(0.0, 0.0, 0.0),
+            fluid_force: cgmath::vec3(0.0, 0.0, 0.0),
+            fluid_torque: cgmath::vec3(0.0, 0.0, 0.0),
+        }
+    }
+
+    // Radius of a sphere around the center that fully contains the body.
+    pub fn bounding_radius(&self) -> f32 {
+        match self.shape {
+            RigidBodyShape::Box { half_extent } => half_extent.magnitude(),
+            RigidBodyShape::Sphere { radius } => radius,
+        }
+    }
+
+    // Takes a force as computed by the fluid simulation and converts it to world units.
+    pub fn set_fluid_force(&mut self, fluid_force: &FluidForce, fluid_density: f32, grid_to_world_scale: f32) {
+        let scale = fluid_density * grid_to_world_scale.powi(4);
+        self.fluid_force = fluid_force.force * scale;
+        self.fluid_torque = fluid_force.torque * (scale * grid_to_world_scale);
+    }
+
+    fn apply_world_inverse_inertia(&self, v: cgmath::Vector3<f32>) -> cgmath::Vector3<f32> {
+        let local = self.rotation.invert().rotate_vector(v);
+        let local = cgmath::vec3(local.x / self.inertia.x, local.y / self.inertia.y, local.z / self.inertia.z);
+        self.rotation.rotate_vector(local)
+    }
+
+    // Semi-implicit Euler step. Bodies are kept within the given (world space) domain bounds.
+    // (ignores gyroscopic effects and collisions with other solids)
+    pub fn integrate(&mut self, delta: f32, gravity: cgmath::Vector3<f32>, domain_min: cgmath::Point3<f32>, domain_max: cgmath::Point3<f32>) {
+        let damping = (1.0 - self.damping * delta).max(0.0);
+
+        self.linear_velocity += (gravity + self.fluid_force / self.mass) * delta;
+        self.linear_velocity *= damping;
+        self.position += self.linear_velocity * delta;
+
+        self.angular_velocity += self.apply_world_inverse_inertia(self.fluid_torque) * delta;
+        self.angular_velocity *= damping;
+        let spin = cgmath::Quaternion::from_sv(0.0, self.angular_velocity) * self.rotation * (0.5 * delta);
+        self.rotation = (self.rotation + spin).normalize();
+
+        let radius = self.bounding_radius();
+        for axis in 0..3 {
+            let min = domain_min[axis] + radius;
+            let max = (domain_max[axis] - radius).max(min);
+            if self.position[axis] < min {
+                self.position[axis] = min;
+                self.linear_velocity[axis] = self.linear_velocity[axis].abs() * Self::WALL_RESTITUTION;
+            } else if self.position[axis] > max {
+                self.position[axis] = max;
+                self.linear_velocity[axis] = -self.linear_velocity[axis].abs() * Self::WALL_RESTITUTION;
+            }
+        }
+    }
+
+    pub(super) fn checkpoint(&self) -> RigidBodyCheckpoint {
+        RigidBodyCheckpoint {
+            position: self.position.into(),
+            rotation: self.rotation.into(),
+            linear_velocity: self.linear_velocity.into(),
+            angular_velocity: self.angular_velocity.into(),
+            fluid_force: self.fluid_force.into(),
+            fluid_torque: self.fluid_torque.into(),
+        }
+    }
+
+    // Continues the motion stored in a checkpoint. Shape, mass & damping are taken from the scene config, not the checkpoint.
+    pub(super) fn restore_checkpoint(&mut self, checkpoint: &RigidBodyCheckpoint) {
+        self.position = checkpoint.position.into();
+        self.rotation = checkpoint.rotation.into();
+        self.linear_velocity = checkpoint.linear_velocity.into();
+        self.angular_velocity = checkpoint.angular_velocity.into();
+        self.fluid_force = checkpoint.fluid_force.into();
+        self.fluid_torque = checkpoint.fluid_torque.into();
+    }
+
+    pub fn dynamic_solid(&self, grid_to_world_scale: f32) -> DynamicSolid {
+        let shape = match self.shape {
+            RigidBodyShape::Box { half_extent } => DynamicSolidShape::Box {
+                half_extent: half_extent / grid_to_world_scale,
+            },
+            RigidBodyShape::Sphere { radius } => DynamicSolidShape::Sphere {
+                radius: radius / grid_to_world_scale,
+            },
+        };
+        DynamicSolid {
+            shape,
+            center: self.position / grid_to_world_scale,
+            rotation: self.rotation,
+            linear_velocity: self.linear_velocity / grid_to_world_scale,
+            angular_velocity: self.angular_velocity,
+        }
+    }
+}
@@ -3,6 +3,7 @@ use crate::{
     timer::{SimulationStepResult, Timer},
     wgpu_utils::pipelines::PipelineManager,
 };
+use serde::{Deserialize, Serialize};
 use std::time::{Duration, Instant};
 
 // The simulation controller orchestrates simulation steps.
@@ -15,6 +16,27 @@ pub enum SimulationControllerStatus {
     Paused,
 }
 
+// Adaptive time stepping chooses the length of every step from the largest particle speed,
+// keeping the CFL number (number of grid cells a particle moves in a single step) below a bound.
+#[derive(Clone, Copy, Serialize, Deserialize)]
+pub struct AdaptiveTimeStepConfig {
+    pub enabled: bool,
+    pub max_cfl_number: f32,
+    // The regular steps per second act as the lower limit.
+    pub max_simulation_steps_per_second: u64,
+}
+
+impl Default for AdaptiveTimeStepConfig {
+    fn default() -> Self {
+        AdaptiveTimeStepConfig {
+            enabled: false,
+            // Particle advection only looks at the velocities of the cell it starts in.
+            max_cfl_number: 1.0,
+            max_simulation_steps_per_second: 1000,
+        }
+    }
+}
+
 pub struct SimulationController {
     timer: Timer,
     computation_time_last_fast_forward: Duration,
@@ -22,6 +44,7 @@ pub struct SimulationController {
     status: SimulationControllerStatus,
     pub simulation_stop_time: Duration,
     pub time_scale: f32,
+    adaptive_time_step: AdaptiveTimeStepConfig,
 }
 
 const MIN_REALTIME_FPS: f64 = 20.0;
@@ -41,6 +64,7 @@ impl SimulationController {
             timer: Timer::new(delta_from_steps_per_second(DEFAULT_SIMULATION_STEPS_PER_SECOND)),
             computation_time_last_fast_forward: Default::default(),
             time_scale: 1.0,
+            adaptive_time_step: Default::default(),
         }
     }
 
@@ -86,6 +110,17 @@ impl SimulationController {
             .set_simulation_delta(delta_from_steps_per_second(self.simulation_steps_per_second));
     }
 
+    pub fn adaptive_time_step(&self) -> AdaptiveTimeStepConfig {
+        self.adaptive_time_step
+    }
+
+    pub fn set_adaptive_time_step(&mut self, adaptive_time_step: AdaptiveTimeStepConfig) {
+        self.adaptive_time_step = adaptive_time_step;
+        // Adaptive steps are picked before every step, but we need to go back to the fixed step length if it was disabled.
+        self.timer
+            .set_simulation_delta(delta_from_steps_per_second(self.simulation_steps_per_second));
+    }
+
     pub fn restart(&mut self) {
         self.timer = Timer::new(delta_from_steps_per_second(self.simulation_steps_per_second));
     }
@@ -184,6 +219,21 @@ impl SimulationController {
         return true;
     }
 
+    // Longest step for which no particle moves further than the CFL number allows.
+    // Uses the speed of a few steps ago, so it's not a guarantee, but violent changes are slow enough to catch up.
+    fn adaptive_simulation_delta(&self, scene: &Scene) -> Duration {
+        let min_delta = delta_from_steps_per_second(
+            self.adaptive_time_step
+                .max_simulation_steps_per_second
+                .max(self.simulation_steps_per_second),
+        );
+        let max_delta = delta_from_steps_per_second(self.simulation_steps_per_second);
+        // Particles live in grid space, so their speed is in cells per second.
+        let delta = self.adaptive_time_step.max_cfl_number / scene.fluid().max_particle_speed_grid();
+        // (f32::max/min ignore NaN)
+        Duration::from_secs_f32(delta.min(max_delta.as_secs_f32()).max(min_delta.as_secs_f32()))
+    }
+
     fn single_step<'a>(
         &mut self,
         scene: &'a mut Scene,
@@ -199,6 +249,10 @@ impl SimulationController {
             Duration::from_secs(u64::MAX)
         };
 
+        if self.adaptive_time_step.enabled {
+            self.timer.set_simulation_delta(self.adaptive_simulation_delta(scene));
+        }
+
         if self.timer.total_simulated_time() + self.timer.simulation_delta() > self.simulation_stop_time {
             self.status = SimulationControllerStatus::Paused;
             return false;
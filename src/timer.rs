@@ -31,6 +31,7 @@ pub struct Timer {
     simulation_delta: Duration,
     num_simulation_steps: u32,
     num_simulation_steps_this_frame: u32,
+    simulated_time_this_frame: Duration,
     total_simulated_time: Duration,
     accepted_simulation_to_render_lag: Duration, // time lost that we don't plan on catching up anymore
 }
@@ -59,6 +60,7 @@ impl Timer {
             simulation_delta,
             num_simulation_steps: 0,
             num_simulation_steps_this_frame: 0,
+            simulated_time_this_frame: Duration::from_millis(0),
             total_simulated_time: Duration::from_millis(0),
             accepted_simulation_to_render_lag: Duration::from_millis(0),
         }
@@ -84,6 +86,7 @@ impl Timer {
 
         self.timestamp_last_frame = std::time::Instant::now();
         self.num_simulation_steps_this_frame = 0;
+        self.simulated_time_this_frame = Duration::from_millis(0);
         self.num_frames_rendered += 1;
     }
 
@@ -107,7 +110,8 @@ impl Timer {
         }
 
         // Did we hit a maximum of simulation steps and want to introduce lag instead?
-        if self.num_simulation_steps_this_frame * self.simulation_delta > max_total_step_per_frame {
+        // (steps may vary in length, so we can't just count them)
+        if self.simulated_time_this_frame > max_total_step_per_frame {
             // We heuristically don't drop all lost simulation frames. This avoids oscillating between realtime and offline
             // which is caused by our frame deltas being influenced by work from a couple of cpu frames ago (due gpu/cpu sync)
             self.accepted_simulation_to_render_lag += residual_time.mul_f32(0.8);
@@ -120,6 +124,7 @@ impl Timer {
         }
 
         self.num_simulation_steps_this_frame += 1;
+        self.simulated_time_this_frame += self.simulation_delta;
         self.num_simulation_steps += 1;
         self.total_simulated_time += self.simulation_delta;
         SimulationStepResult::PerformStepAndCallAgain
@@ -129,6 +134,7 @@ impl Timer {
         self.simulation_delta
     }
 
+    // Can be changed between any two steps.
     pub fn set_simulation_delta(&mut self, delta: Duration) {
         self.simulation_delta = delta;
     }
@@ -179,5 +185,5 @@ pub struct FrameTimeUniformBufferContent {
     pub total_passed: f32,         // How much time has passed on the rendering clock since rendering started.
     pub frame_delta: f32,          // How long a previous frame took in seconds.
     pub total_simulated_time: f32, // How much time has passed in the simulation *excluding any steps in the current frame*
-    pub simulation_delta: f32,     // How much we're advancing the simulation for each step in the current frame.
+    pub simulation_delta: f32,     // Length of the next simulation step at the start of the current frame.
 }
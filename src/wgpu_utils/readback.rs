@@ -0,0 +1,186 @@
+use futures::*;
+use std::{collections::VecDeque, pin::Pin};
+
+struct PendingReadback<Meta> {
+    copy_operation: Option<Pin<Box<dyn Future<Output = std::result::Result<(), wgpu::BufferAsyncError>>>>>,
+    buffer: wgpu::Buffer,
+    meta: Meta,
+}
+
+// Ring of read-back buffers for retrieving small amounts of data from the GPU every frame without stalling.
+// Every copy can carry some metadata describing the circumstances under which the data was produced.
+// Results arrive a few frames late. If all buffers are in flight, new copies are skipped.
+pub struct ReadbackRing<Meta> {
+    size: u64,
+    unused_buffers: Vec<wgpu::Buffer>,
+    unscheduled_readback: Option<PendingReadback<Meta>>,
+    pending_readbacks: VecDeque<PendingReadback<Meta>>,
+}
+
+impl<Meta> ReadbackRing<Meta> {
+    pub fn new(device: &wgpu::Device, label: &str, size: u64, num_buffers: usize) -> Self {
+        let unused_buffers = (0..num_buffers)
+            .map(|i| {
+                device.create_buffer(&wgpu::BufferDescriptor {
+                    size,
+                    usage: wgpu::BufferUsage::MAP_READ | wgpu::BufferUsage::COPY_DST,
+                    label: Some(&format!("Buffer: {} read-back buffer {}", label, i)),
+                    mapped_at_creation: false,
+                })
+            })
+            .collect();
+
+        ReadbackRing {
+            size,
+            unused_buffers,
+            unscheduled_readback: None,
+            pending_readbacks: VecDeque::new(),
+        }
+    }
+
+    pub fn enqueue_copy(&mut self, encoder: &mut wgpu::CommandEncoder, source_buffer: &wgpu::Buffer, source_offset: u64, meta: Meta) {
+        if let Some(buffer) = self.unused_buffers.pop() {
+            encoder.copy_buffer_to_buffer(source_buffer, source_offset, &buffer, 0, self.size);
+            self.unscheduled_readback = Some(PendingReadback {
+                copy_operation: None, // Filled out in start_readbacks
+                buffer,
+                meta,
+            });
+        }
+    }
+
+    // Call this once all command buffers referencing the buffers are submitted.
+    pub fn start_readbacks(&mut self) {
+        if let Some(mut readback) = self.unscheduled_readback.take() {
+            readback.copy_operation = Some(readback.buffer.slice(..).map_async(wgpu::MapMode::Read).boxed());
+            self.pending_readbacks.push_back(readback);
+        }
+    }
+
+    // Processes the most recent read-back that became available since the last call.
+    pub fn retrieve<R>(&mut self, mut process: impl FnMut(&[u8], &Meta) -> R) -> Option<R> {
+        let mut result = None;
+        while let Some(mut readback) = self.pending_readbacks.pop_front() {
+            if (&mut readback.copy_operation.as_mut().unwrap()).now_or_never().is_some() {
+                result = Some(process(&readback.buffer.slice(..).get_mapped_range(), &readback.meta));
+                readback.buffer.unmap();
+                self.unused_buffers.push(readback.buffer);
+            } else {
+                self.pending_readbacks.push_front(readback);
+                break;
+            }
+        }
+        result
+    }
+}
+
+struct BlockingReadbackEntry {
+    buffer: wgpu::Buffer,
+    size: u64,
+    // Texture copies need rows padded to COPY_BYTES_PER_ROW_ALIGNMENT, the padding is removed again on read.
+    bytes_per_row: u32,
+    bytes_per_padded_row: u32,
+}
+
+// Reads data back from the GPU immediately, stalling until all previously submitted work is done.
+// Only meant for rare operations like writing simulation state to disk.
+pub struct BlockingReadback {
+    encoder: wgpu::CommandEncoder,
+    entries: Vec<BlockingReadbackEntry>,
+}
+
+impl BlockingReadback {
+    pub fn new(device: &wgpu::Device, label: &str) -> Self {
+        BlockingReadback {
+            encoder: device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some(&format!("Encoder: {} read-back", label)),
+            }),
+            entries: Vec::new(),
+        }
+    }
+
+    fn create_buffer(&self, device: &wgpu::Device, size: u64) -> wgpu::Buffer {
+        device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some(&format!("Buffer: Blocking read-back {}", self.entries.len())),
+            size,
+            usage: wgpu::BufferUsage::MAP_READ | wgpu::BufferUsage::COPY_DST,
+            mapped_at_creation: false,
+        })
+    }
+
+    // Returns the index of the result in the list returned by finish.
+    pub fn copy_buffer(&mut self, device: &wgpu::Device, source: &wgpu::Buffer, size: u64) -> usize {
+        let buffer = self.create_buffer(device, size);
+        self.encoder.copy_buffer_to_buffer(source, 0, &buffer, 0, size);
+        self.entries.push(BlockingReadbackEntry {
+            buffer,
+            size,
+            bytes_per_row: size as u32,
+            bytes_per_padded_row: size as u32,
+        });
+        self.entries.len() - 1
+    }
+
+    // Returns the index of the result in the list returned by finish.
+    pub fn copy_texture(&mut self, device: &wgpu::Device, source: &wgpu::Texture, extent: wgpu::Extent3d, bytes_per_texel: u32) -> usize {
+        let bytes_per_row = extent.width * bytes_per_texel;
+        let bytes_per_padded_row =
+            (bytes_per_row + wgpu::COPY_BYTES_PER_ROW_ALIGNMENT - 1) / wgpu::COPY_BYTES_PER_ROW_ALIGNMENT * wgpu::COPY_BYTES_PER_ROW_ALIGNMENT;
+        let size = (bytes_per_padded_row * extent.height * extent.depth) as u64;
+        let buffer = self.create_buffer(device, size);
+        self.encoder.copy_texture_to_buffer(
+            wgpu::TextureCopyView {
+                texture: source,
+                mip_level: 0,
+                origin: wgpu::Origin3d::ZERO,
+            },
+            wgpu::BufferCopyView {
+                buffer: &buffer,
+                layout: wgpu::TextureDataLayout {
+                    offset: 0,
+                    bytes_per_row: bytes_per_padded_row,
+                    rows_per_image: extent.height,
+                },
+            },
+            extent,
+        );
+        self.entries.push(BlockingReadbackEntry {
+            buffer,
+            size,
+            bytes_per_row,
+            bytes_per_padded_row,
+        });
+        self.entries.len() - 1
+    }
+
+    // Submits all copies and waits for the results.
+    pub fn finish(self, device: &wgpu::Device, queue: &wgpu::Queue) -> Result<Vec<Vec<u8>>, wgpu::BufferAsyncError> {
+        queue.submit(Some(self.encoder.finish()));
+
+        let mappings: Vec<_> = self
+            .entries
+            .iter()
+            .map(|entry| entry.buffer.slice(..entry.size).map_async(wgpu::MapMode::Read))
+            .collect();
+        device.poll(wgpu::Maintain::Wait);
+        for mapping in mappings {
+            futures::executor::block_on(mapping)?;
+        }
+
+        Ok(self
+            .entries
+            .iter()
+            .map(|entry| {
+                let content = entry
+                    .buffer
+                    .slice(..entry.size)
+                    .get_mapped_range()
+                    .chunks(entry.bytes_per_padded_row as usize)
+                    .flat_map(|row| row[..entry.bytes_per_row as usize].iter().cloned())
+                    .collect();
+                entry.buffer.unmap();
+                content
+            })
+            .collect())
+    }
+}
@@ -61,6 +61,10 @@ impl<Content: bytemuck::Pod> UniformBuffer<Content> {
     pub fn binding_resource(&self) -> wgpu::BindingResource {
         self.buffer.as_entire_binding()
     }
+
+    pub fn buffer(&self) -> &wgpu::Buffer {
+        &self.buffer
+    }
 }
 
 #[derive(Clone, Copy)]